@@ -1,24 +1,33 @@
-use std::path::PathBuf;
-use std::time::Duration;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 
 use clap::{Parser, Subcommand};
 use dialoguer::{Confirm, Input};
 use gaveloc_adapters::configuration;
-use gaveloc_adapters::patch::{FileVersionRepository, HttpPatchDownloader, SquareEnixPatchServer};
+use gaveloc_adapters::patch::{
+    FileVersionRepository, HttpPatchDownloader, SquareEnixPatchHistory, SquareEnixPatchServer,
+};
 use gaveloc_adapters::runner::{LinuxRunnerDetector, LinuxRunnerManager};
 use gaveloc_adapters::telemetry;
 use gaveloc_adapters::{
     FileAccountRepository, GoatcorpIntegrityChecker, HttpOtpListener, KeyringCredentialStore,
-    SquareEnixAuthenticator, ZiPatchParser,
+    LocalDiskSpace, SquareEnixAuthenticator, ZiPatchParser,
 };
-use gaveloc_core::config::Region;
+use gaveloc_core::config::{PatchSettings, Region, Settings};
+use gaveloc_core::control::UpdateControl;
 use gaveloc_core::entities::{
-    Account, AccountId, CachedSession, Credentials, IntegrityStatus, Repository,
+    Account, AccountId, CachedSession, Credentials, GameVersion, IntegrityCheckMode,
+    IntegrityReport, IntegrityStatus, PatchEntry, Repository,
 };
+use gaveloc_core::error::Error;
 use gaveloc_core::ports::{
     AccountRepository, Authenticator, CredentialStore, IntegrityChecker, OtpListener,
     PatchDownloader, PatchServer, RunnerDetector, RunnerManager, VersionRepository, ZiPatchApplier,
 };
+use gaveloc_core::report::ReportFormat;
+use gaveloc_core::use_cases::DiskSpaceEstimate;
+use gaveloc_core::zipatch::{patch_journal_path, PatchInspection};
 use indicatif::{ProgressBar, ProgressStyle};
 use tracing::error;
 
@@ -130,7 +139,30 @@ enum Commands {
         #[arg(long, default_value = "false")]
         problems_only: bool,
 
-        /// Export full report to JSON file
+        /// Export the full report; written as CSV or HTML for .csv and .html
+        /// files, JSON otherwise
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Hash every file, even those unchanged since the last check
+        #[arg(long, default_value = "false")]
+        full: bool,
+
+        /// Also extract every file inside the SqPack archives to find the
+        /// damaged ones
+        #[arg(long, default_value = "false")]
+        deep: bool,
+    },
+
+    /// Compare two JSON integrity reports saved by verify
+    DiffReports {
+        /// Older report
+        old: PathBuf,
+
+        /// Newer report
+        new: PathBuf,
+
+        /// Export the differences to a JSON file
         #[arg(short, long)]
         output: Option<PathBuf>,
     },
@@ -144,6 +176,30 @@ enum Commands {
         /// Skip confirmation prompt
         #[arg(short, long, default_value = "false")]
         yes: bool,
+
+        /// Hash every file, even those unchanged since the last check
+        #[arg(long, default_value = "false")]
+        full: bool,
+
+        /// Repair the damaged files inside SqPack archives instead of whole
+        /// files, rewriting only their part of the dat files
+        #[arg(long, default_value = "false")]
+        deep: bool,
+    },
+
+    /// Generate an integrity manifest from a known-good installation
+    GenerateManifest {
+        /// Path to game installation
+        #[arg(short, long)]
+        game_path: PathBuf,
+
+        /// Write the manifest to this JSON file
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// Game version to record (defaults to the installed version)
+        #[arg(long)]
+        game_version: Option<String>,
     },
 
     /// Update boot files (no login required)
@@ -156,6 +212,14 @@ enum Commands {
         #[arg(long, default_value = "false")]
         keep_patches: bool,
 
+        /// Cap download speed in KiB/s, overriding the configured limit (0 for unlimited)
+        #[arg(long)]
+        max_download_rate: Option<u64>,
+
+        /// Only download between these local times, e.g. 01:00-07:00 ("any" for no window)
+        #[arg(long)]
+        download_window: Option<String>,
+
         /// Skip confirmation prompt
         #[arg(short, long, default_value = "false")]
         yes: bool,
@@ -179,10 +243,164 @@ enum Commands {
         #[arg(long, default_value = "false")]
         keep_patches: bool,
 
+        /// Cap download speed in KiB/s, overriding the configured limit (0 for unlimited)
+        #[arg(long)]
+        max_download_rate: Option<u64>,
+
+        /// Only download between these local times, e.g. 01:00-07:00 ("any" for no window)
+        #[arg(long)]
+        download_window: Option<String>,
+
         /// Skip confirmation prompt
         #[arg(short, long, default_value = "false")]
         yes: bool,
     },
+
+    /// Work with patch files
+    Patch {
+        #[command(subcommand)]
+        command: PatchCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum PatchCommand {
+    /// Show what a patch file contains and which files it writes, without
+    /// applying it
+    Inspect {
+        /// Patch file to inspect
+        file: PathBuf,
+
+        /// Print the inspection as JSON
+        #[arg(long, default_value = "false")]
+        json: bool,
+    },
+}
+
+/// Apply download limit overrides from the command line to the configured patch settings
+fn patch_settings(
+    settings: &Settings,
+    max_download_rate: Option<u64>,
+    download_window: Option<&str>,
+) -> anyhow::Result<PatchSettings> {
+    let mut patch = settings.patch.clone();
+    if let Some(rate) = max_download_rate {
+        patch.max_download_rate = Some(rate);
+    }
+    match download_window {
+        Some("any") => patch.download_window = None,
+        Some(window) => patch.download_window = Some(window.parse()?),
+        None => {}
+    }
+    Ok(patch)
+}
+
+/// Patch history for repairs, using the default account's session if it has
+/// a valid one
+///
+/// Game files need a login session to fetch their patch history.
+async fn repair_history() -> anyhow::Result<SquareEnixPatchHistory> {
+    let account_repo = FileAccountRepository::new(get_config_dir());
+    let credential_store = KeyringCredentialStore::new();
+    let mut session_id = None;
+    if let Some(account) = account_repo.get_default_account().await? {
+        if let Ok(Some(session)) = credential_store.get_session(&account.id).await {
+            if session.is_valid() {
+                session_id = Some(session.unique_id);
+            }
+        }
+    }
+
+    Ok(match session_id {
+        Some(session_id) => SquareEnixPatchHistory::with_session(session_id)?,
+        None => {
+            println!("No valid session, only boot files can be repaired.");
+            println!("Use 'login' first to repair game files.");
+            SquareEnixPatchHistory::new()?
+        }
+    })
+}
+
+/// Integrity check mode selected by `--full`
+fn check_mode(full: bool) -> IntegrityCheckMode {
+    if full {
+        IntegrityCheckMode::Full
+    } else {
+        IntegrityCheckMode::Incremental
+    }
+}
+
+/// Cancel `control` when the user presses Ctrl-C
+///
+/// Downloads then stop after the current chunk instead of killing the process
+/// mid-write.
+fn cancel_on_ctrl_c(control: &UpdateControl) {
+    let control = control.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            control.cancel();
+        }
+    });
+}
+
+/// Print the disk space an update needs, and whether it is available
+///
+/// Returns false if the patch or game directory is too small for it.
+async fn update_fits(
+    applier: &Arc<ZiPatchParser>,
+    patch_dir: &Path,
+    game_path: &Path,
+    patches: &[PatchEntry],
+) -> Result<bool, Error> {
+    let estimate = DiskSpaceEstimate::for_patches(applier, patch_dir, patches).await?;
+    println!(
+        "Disk space needed: {:.2} MB",
+        estimate.total() as f64 / 1024.0 / 1024.0
+    );
+
+    match estimate.ensure_fits(&LocalDiskSpace::new(), patch_dir, game_path) {
+        Ok(()) => Ok(true),
+        Err(Error::InsufficientDiskSpace {
+            path,
+            needed,
+            available,
+        }) => {
+            println!();
+            println!(
+                "Not enough disk space in {}: {:.2} MB needed, {:.2} MB free.",
+                path.display(),
+                needed as f64 / 1024.0 / 1024.0,
+                available as f64 / 1024.0 / 1024.0
+            );
+            Ok(false)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Apply a downloaded patch to its repository, journaling every write
+///
+/// An apply interrupted through `control` keeps its journal so the next run
+/// resumes it; a failed one is rolled back.
+fn apply_journaled(
+    applier: &ZiPatchParser,
+    patch: &PatchEntry,
+    patch_path: &Path,
+    game_path: &Path,
+    control: &UpdateControl,
+) -> Result<(), Error> {
+    let journal_path = patch_journal_path(patch_path);
+    let install_path = game_path.join(patch.repository.install_dir());
+    applier
+        .apply_patch_journaled(patch_path, &install_path, &journal_path, control)
+        .inspect_err(|e| {
+            if matches!(e, Error::Cancelled) {
+                return;
+            }
+            if let Err(e) = applier.rollback_patch(&journal_path) {
+                error!("rollback failed: {}", e);
+            }
+        })
 }
 
 fn get_config_dir() -> PathBuf {
@@ -202,7 +420,7 @@ async fn main() -> anyhow::Result<()> {
 
     let _guard = telemetry::init_subscriber("gaveloc_cli", "info");
 
-    let _settings = match configuration::get_configuration() {
+    let settings = match configuration::get_configuration() {
         Ok(s) => s,
         Err(e) => {
             error!(?e, "failed to load configuration");
@@ -623,9 +841,9 @@ async fn main() -> anyhow::Result<()> {
             // Check expansions
             for exp in 1..=5 {
                 if let Some(repo) = Repository::from_expansion(exp) {
-                    match version_repo.get_version(game_path, repo).await {
-                        Ok(v) => println!("  {}:   {}", repo, v),
-                        Err(_) => {} // Silently skip missing expansions
+                    // Silently skip missing expansions
+                    if let Ok(v) = version_repo.get_version(game_path, repo).await {
+                        println!("  {}:   {}", repo, v);
                     }
                 }
             }
@@ -693,8 +911,10 @@ async fn main() -> anyhow::Result<()> {
 
         Commands::Verify {
             game_path,
-            problems_only,
+            problems_only: _,
             output,
+            full,
+            deep,
         } => {
             if !game_path.exists() {
                 println!("Game path does not exist: {}", game_path.display());
@@ -702,7 +922,8 @@ async fn main() -> anyhow::Result<()> {
             }
 
             let version_repo = FileVersionRepository;
-            let integrity_checker = GoatcorpIntegrityChecker::with_default_client();
+            let integrity_checker = GoatcorpIntegrityChecker::with_default_client()
+                .with_integrity_settings(&settings.integrity);
 
             // Get current game version
             let game_version = match version_repo.get_version(game_path, Repository::Ffxiv).await {
@@ -745,8 +966,10 @@ async fn main() -> anyhow::Result<()> {
             };
 
             // Run integrity check
+            let started_at = SystemTime::now();
+            let started = Instant::now();
             let results = match integrity_checker
-                .check_integrity(game_path, &manifest, progress)
+                .check_integrity(game_path, &manifest, check_mode(*full), progress)
                 .await
             {
                 Ok(r) => r,
@@ -759,6 +982,40 @@ async fn main() -> anyhow::Result<()> {
 
             pb.finish_and_clear();
 
+            let mut report = IntegrityReport::new(
+                &manifest,
+                check_mode(*full),
+                started_at,
+                started.elapsed(),
+                results,
+            );
+
+            if *deep {
+                println!("Checking the files inside SqPack archives...");
+                let pb = ProgressBar::new(0);
+                pb.set_style(
+                    ProgressStyle::default_bar()
+                        .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({percent}%)")
+                        .unwrap()
+                        .progress_chars("#>-"),
+                );
+                let pb_clone = pb.clone();
+                let progress = move |progress: gaveloc_core::entities::IntegrityProgress| {
+                    pb_clone.set_length(progress.total_files as u64);
+                    pb_clone.set_position(progress.files_checked as u64);
+                };
+
+                let damaged = integrity_checker
+                    .check_sqpack_files(game_path, progress)
+                    .await;
+                pb.finish_and_clear();
+                match damaged {
+                    Ok(damaged) => report.sqpack_files = damaged,
+                    Err(e) => println!("SqPack check failed: {}", e),
+                }
+            }
+            let results = &report.files;
+
             // Summarize results
             let valid_count = results.iter().filter(|r| r.status == IntegrityStatus::Valid).count();
             let mismatch_count = results.iter().filter(|r| r.status == IntegrityStatus::Mismatch).count();
@@ -782,47 +1039,95 @@ async fn main() -> anyhow::Result<()> {
                 .filter(|r| r.status != IntegrityStatus::Valid)
                 .collect();
 
-            if *problems_only || !problems.is_empty() {
-                if !problems.is_empty() {
-                    println!();
-                    println!("Files with problems:");
-                    for result in problems.iter().take(50) {
-                        println!("  [{}] {}", result.status, result.relative_path);
-                    }
-                    if problems.len() > 50 {
-                        println!("  ... and {} more", problems.len() - 50);
-                    }
+            if !problems.is_empty() {
+                println!();
+                println!("Files with problems:");
+                for result in problems.iter().take(50) {
+                    println!("  [{}] {}", result.status, result.relative_path);
+                }
+                if problems.len() > 50 {
+                    println!("  ... and {} more", problems.len() - 50);
+                }
 
-                    println!();
-                    println!("Run 'gaveloc_cli repair --game-path {}' to fix these files.", game_path.display());
+                println!();
+                println!("Run 'gaveloc_cli repair --game-path {}' to fix these files.", game_path.display());
+            }
+
+            if !report.sqpack_files.is_empty() {
+                println!();
+                println!("Damaged files inside SqPack archives:");
+                for file in report.sqpack_files.iter().take(50) {
+                    println!("  {}: {}", file, file.error);
                 }
+                if report.sqpack_files.len() > 50 {
+                    println!("  ... and {} more", report.sqpack_files.len() - 50);
+                }
+
+                println!();
+                println!(
+                    "Run 'gaveloc_cli repair --deep --game-path {}' to fix these files.",
+                    game_path.display()
+                );
             }
 
             // Export report if requested
             if let Some(output_path) = output {
-                let report = serde_json::json!({
-                    "game_version": game_version.as_str(),
-                    "total_files": results.len(),
-                    "valid": valid_count,
-                    "mismatch": mismatch_count,
-                    "missing": missing_count,
-                    "unreadable": unreadable_count,
-                    "problems": problems.iter().map(|r| {
-                        serde_json::json!({
-                            "path": r.relative_path,
-                            "status": format!("{}", r.status),
-                            "expected": r.expected_hash,
-                            "actual": r.actual_hash,
-                        })
-                    }).collect::<Vec<_>>(),
-                });
-                tokio::fs::write(&output_path, serde_json::to_string_pretty(&report)?).await?;
+                let format = ReportFormat::from_path(output_path).unwrap_or(ReportFormat::Json);
+                tokio::fs::write(&output_path, report.render(format)?).await?;
                 println!();
                 println!("Report saved to: {}", output_path.display());
             }
         }
 
-        Commands::Repair { game_path, yes } => {
+        Commands::DiffReports { old, new, output } => {
+            let old_report = IntegrityReport::from_json(&tokio::fs::read_to_string(old).await?)?;
+            let new_report = IntegrityReport::from_json(&tokio::fs::read_to_string(new).await?)?;
+            let diff = old_report.diff(&new_report);
+
+            println!(
+                "Comparing {} ({}) with {} ({})",
+                old.display(),
+                diff.old_game_version,
+                new.display(),
+                diff.new_game_version
+            );
+            println!();
+
+            if diff.changes.is_empty() {
+                println!("No differences.");
+            } else {
+                let status = |status: Option<IntegrityStatus>| {
+                    status.map_or("not checked".to_string(), |s| s.to_string())
+                };
+                for change in &diff.changes {
+                    println!(
+                        "  {}: {} -> {}",
+                        change.relative_path,
+                        status(change.status_before()),
+                        status(change.status_after())
+                    );
+                }
+                println!();
+                println!(
+                    "{} files changed, {} newly damaged",
+                    diff.changes.len(),
+                    diff.regressions().count()
+                );
+            }
+
+            if let Some(output_path) = output {
+                tokio::fs::write(&output_path, serde_json::to_string_pretty(&diff)?).await?;
+                println!();
+                println!("Differences saved to: {}", output_path.display());
+            }
+        }
+
+        Commands::Repair {
+            game_path,
+            yes,
+            full,
+            deep,
+        } => {
             if !game_path.exists() {
                 println!("Game path does not exist: {}", game_path.display());
                 return Ok(());
@@ -838,7 +1143,84 @@ async fn main() -> anyhow::Result<()> {
             tokio::fs::remove_file(&test_file).await.ok();
 
             let version_repo = FileVersionRepository;
-            let integrity_checker = GoatcorpIntegrityChecker::with_default_client();
+            let integrity_checker = GoatcorpIntegrityChecker::with_default_client()
+                .with_integrity_settings(&settings.integrity)
+                .with_patch_dir(configuration::patch_dir(&settings))
+                .with_patch_settings(&settings.patch);
+
+            if *deep {
+                println!("Checking the files inside SqPack archives...");
+                let damaged = match integrity_checker
+                    .check_sqpack_files(game_path, |_| {})
+                    .await
+                {
+                    Ok(damaged) => damaged,
+                    Err(e) => {
+                        println!("SqPack check failed: {}", e);
+                        return Ok(());
+                    }
+                };
+
+                if damaged.is_empty() {
+                    println!("All files inside SqPack archives are valid. Nothing to repair.");
+                    return Ok(());
+                }
+
+                println!();
+                println!("Found {} damaged files:", damaged.len());
+                for file in damaged.iter().take(10) {
+                    println!("  {}", file);
+                }
+                if damaged.len() > 10 {
+                    println!("  ... and {} more", damaged.len() - 10);
+                }
+
+                let confirmed = if *yes {
+                    true
+                } else {
+                    println!();
+                    println!("Warning: Ensure the game launcher is not running.");
+                    println!("Repair rebuilds these files from the game's patch history.");
+                    println!("This can download several gigabytes of patches.");
+                    Confirm::new()
+                        .with_prompt("Proceed with repair?")
+                        .default(false)
+                        .interact()?
+                };
+                if !confirmed {
+                    println!("Cancelled.");
+                    return Ok(());
+                }
+
+                let history = repair_history().await?;
+                let control = UpdateControl::new();
+                cancel_on_ctrl_c(&control);
+                println!();
+                println!("Repairing files...");
+                let repairs = match integrity_checker
+                    .repair_sqpack_files(game_path, &damaged, &history, &control)
+                    .await
+                {
+                    Ok(repairs) => repairs,
+                    Err(e) => {
+                        println!("Repair failed: {}", e);
+                        return Ok(());
+                    }
+                };
+
+                let repaired = repairs.iter().filter(|r| r.outcome.is_repaired()).count();
+                for repair in repairs.iter().filter(|r| !r.outcome.is_repaired()) {
+                    println!("  [{}] {}", repair.outcome, repair.file);
+                }
+
+                println!();
+                println!(
+                    "Repair complete: {} files repaired, {} not repaired",
+                    repaired,
+                    repairs.len() - repaired
+                );
+                return Ok(());
+            }
 
             // Get current game version
             let game_version = match version_repo.get_version(game_path, Repository::Ffxiv).await {
@@ -865,7 +1247,7 @@ async fn main() -> anyhow::Result<()> {
 
             // Run integrity check (silently)
             let results = match integrity_checker
-                .check_integrity(game_path, &manifest, |_| {})
+                .check_integrity(game_path, &manifest, check_mode(*full), |_| {})
                 .await
             {
                 Ok(r) => r,
@@ -925,8 +1307,8 @@ async fn main() -> anyhow::Result<()> {
             } else {
                 println!();
                 println!("Warning: Ensure the game launcher is not running.");
-                println!("Repair will delete corrupted files.");
-                println!("You will need to run the launcher to re-download them.");
+                println!("Repair rebuilds these files from the game's patch history.");
+                println!("This can download several gigabytes of patches.");
                 Confirm::new()
                     .with_prompt("Proceed with repair?")
                     .default(false)
@@ -938,30 +1320,105 @@ async fn main() -> anyhow::Result<()> {
                 return Ok(());
             }
 
-            // Repair files in parallel
+            let history = repair_history().await?;
+            let control = UpdateControl::new();
+            cancel_on_ctrl_c(&control);
+
             println!();
             println!("Repairing files...");
-            let (repaired, errors) = match integrity_checker
-                .repair_files(game_path, &problems)
+            let repairs = match integrity_checker
+                .repair_files(game_path, &manifest, &problems, &history, &control)
                 .await
             {
-                Ok(counts) => counts,
+                Ok(repairs) => repairs,
                 Err(e) => {
                     println!("Repair failed: {}", e);
                     return Ok(());
                 }
             };
 
+            let repaired = repairs.iter().filter(|r| r.outcome.is_repaired()).count();
+            for repair in repairs.iter().filter(|r| !r.outcome.is_repaired()) {
+                println!("  [{}] {}", repair.outcome, repair.relative_path);
+            }
+
             println!();
-            println!("Repair complete: {} files removed, {} errors", repaired, errors);
-            if repaired > 0 {
-                println!("Run the launcher to re-download the removed files.");
+            println!(
+                "Repair complete: {} files repaired, {} not repaired",
+                repaired,
+                repairs.len() - repaired
+            );
+        }
+
+        Commands::GenerateManifest {
+            game_path,
+            output,
+            game_version,
+        } => {
+            if !game_path.exists() {
+                println!("Game path does not exist: {}", game_path.display());
+                return Ok(());
             }
+
+            let game_version = match game_version {
+                Some(version) => GameVersion::parse(version)?,
+                None => match FileVersionRepository
+                    .get_version(game_path, Repository::Ffxiv)
+                    .await
+                {
+                    Ok(v) => v,
+                    Err(e) => {
+                        println!("Failed to read game version: {}", e);
+                        return Ok(());
+                    }
+                },
+            };
+
+            println!(
+                "Generating manifest for version {}...",
+                game_version.as_str()
+            );
+
+            let pb = ProgressBar::new(0);
+            pb.set_style(
+                ProgressStyle::default_bar()
+                    .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({percent}%)")
+                    .unwrap()
+                    .progress_chars("#>-"),
+            );
+
+            let pb_clone = pb.clone();
+            let progress = move |progress: gaveloc_core::entities::IntegrityProgress| {
+                pb_clone.set_length(progress.total_files as u64);
+                pb_clone.inc(1);
+            };
+
+            let integrity_checker = GoatcorpIntegrityChecker::with_default_client()
+                .with_integrity_settings(&settings.integrity);
+            let manifest = match integrity_checker
+                .generate_manifest(game_path, game_version.as_str(), progress)
+                .await
+            {
+                Ok(m) => m,
+                Err(e) => {
+                    pb.finish_and_clear();
+                    println!("Manifest generation failed: {}", e);
+                    return Ok(());
+                }
+            };
+
+            pb.finish_and_clear();
+
+            tokio::fs::write(output, serde_json::to_string_pretty(&manifest)?).await?;
+            println!("Hashed {} files", manifest.hashes.len());
+            println!("Manifest saved to: {}", output.display());
         }
 
         Commands::Update {
             game_path,
             keep_patches,
+            max_download_rate,
+            download_window,
             yes,
         } => {
             if !game_path.exists() {
@@ -971,8 +1428,10 @@ async fn main() -> anyhow::Result<()> {
 
             let version_repo = FileVersionRepository;
             let patch_server = SquareEnixPatchServer::new()?;
-            let patch_downloader = HttpPatchDownloader::new()?;
-            let patch_applier = ZiPatchParser::new();
+            let patch_settings =
+                patch_settings(&settings, *max_download_rate, download_window.as_deref())?;
+            let patch_downloader = HttpPatchDownloader::new()?.with_patch_settings(&patch_settings);
+            let patch_applier = Arc::new(ZiPatchParser::new());
 
             // Get current boot version
             let boot_version = match version_repo.get_version(game_path, Repository::Boot).await {
@@ -1016,6 +1475,13 @@ async fn main() -> anyhow::Result<()> {
             }
             println!("Total download: {:.2} MB", total_size_mb);
 
+            // Partial downloads stay in the patch directory and resume next run
+            let patch_dir = configuration::patch_dir(&settings);
+            tokio::fs::create_dir_all(&patch_dir).await?;
+            if !update_fits(&patch_applier, &patch_dir, game_path, &patches).await? {
+                return Ok(());
+            }
+
             // Confirm update
             let confirmed = if *yes {
                 true
@@ -1031,9 +1497,8 @@ async fn main() -> anyhow::Result<()> {
                 return Ok(());
             }
 
-            // Create temp directory for patches
-            let temp_dir = tempfile::tempdir()?;
-            let patch_dir = temp_dir.path();
+            let control = UpdateControl::new();
+            cancel_on_ctrl_c(&control);
 
             println!();
 
@@ -1063,29 +1528,58 @@ async fn main() -> anyhow::Result<()> {
                     pb_clone.set_position(downloaded);
                 };
 
-                if let Err(e) = patch_downloader
-                    .download_patch(patch, &patch_path, None, progress)
+                match patch_downloader
+                    .download_patch(patch, &patch_path, None, &control, progress)
                     .await
                 {
-                    pb.finish_and_clear();
-                    println!("  Download failed: {}", e);
-                    return Ok(());
+                    Ok(()) => {}
+                    Err(Error::Cancelled) => {
+                        pb.finish_and_clear();
+                        println!("  Download cancelled.");
+                        return Ok(());
+                    }
+                    Err(e) => {
+                        pb.finish_and_clear();
+                        println!("  Download failed: {}", e);
+                        return Ok(());
+                    }
                 }
                 pb.finish_and_clear();
 
                 // Verify
                 print!("  Verifying... ");
-                if !patch_downloader.verify_patch(patch, &patch_path).await? {
-                    println!("FAILED");
-                    println!("  Patch verification failed. Please try again.");
+                match patch_downloader
+                    .verify_patch_blocks(patch, &patch_path, &control)
+                    .await
+                {
+                    Ok(report) if report.is_valid() => println!("OK"),
+                    Ok(_) => {
+                        println!("FAILED");
+                        println!("  Patch verification failed. Please try again.");
+                        return Ok(());
+                    }
+                    Err(Error::Cancelled) => {
+                        println!("cancelled");
+                        return Ok(());
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+
+                // Don't start applying once cancelled
+                if control.checkpoint().await.is_err() {
+                    println!("  Update cancelled.");
                     return Ok(());
                 }
-                println!("OK");
 
                 // Apply
                 print!("  Applying... ");
-                match patch_applier.apply_patch(&patch_path, game_path) {
+                match apply_journaled(&patch_applier, patch, &patch_path, game_path, &control) {
                     Ok(()) => println!("OK"),
+                    Err(Error::Cancelled) => {
+                        println!("cancelled");
+                        println!("  The patch resumes on the next update.");
+                        return Ok(());
+                    }
                     Err(e) => {
                         println!("FAILED");
                         println!("  Failed to apply patch: {}", e);
@@ -1097,6 +1591,9 @@ async fn main() -> anyhow::Result<()> {
                 version_repo
                     .set_version(game_path, Repository::Boot, &patch.version_id)
                     .await?;
+                patch_applier
+                    .discard_journal(&patch_journal_path(&patch_path))
+                    .ok();
 
                 // Clean up patch file unless keeping
                 if !keep_patches {
@@ -1118,6 +1615,8 @@ async fn main() -> anyhow::Result<()> {
             username,
             max_expansion,
             keep_patches,
+            max_download_rate,
+            download_window,
             yes,
         } => {
             if !game_path.exists() {
@@ -1131,8 +1630,10 @@ async fn main() -> anyhow::Result<()> {
             let authenticator = SquareEnixAuthenticator::new()?;
             let version_repo = FileVersionRepository;
             let patch_server = SquareEnixPatchServer::new()?;
-            let patch_downloader = HttpPatchDownloader::new()?;
-            let patch_applier = ZiPatchParser::new();
+            let patch_settings =
+                patch_settings(&settings, *max_download_rate, download_window.as_deref())?;
+            let patch_downloader = HttpPatchDownloader::new()?.with_patch_settings(&patch_settings);
+            let patch_applier = Arc::new(ZiPatchParser::new());
 
             // Determine which account to use
             let account = if let Some(username) = username {
@@ -1221,6 +1722,13 @@ async fn main() -> anyhow::Result<()> {
             }
             println!("Total download: {:.2} MB", total_size_mb);
 
+            // Partial downloads stay in the patch directory and resume next run
+            let patch_dir = configuration::patch_dir(&settings);
+            tokio::fs::create_dir_all(&patch_dir).await?;
+            if !update_fits(&patch_applier, &patch_dir, game_path, &patches).await? {
+                return Ok(());
+            }
+
             // Confirm update
             let confirmed = if *yes {
                 true
@@ -1236,9 +1744,8 @@ async fn main() -> anyhow::Result<()> {
                 return Ok(());
             }
 
-            // Create temp directory for patches
-            let temp_dir = tempfile::tempdir()?;
-            let patch_dir = temp_dir.path();
+            let control = UpdateControl::new();
+            cancel_on_ctrl_c(&control);
 
             println!();
 
@@ -1269,29 +1776,58 @@ async fn main() -> anyhow::Result<()> {
                     pb_clone.set_position(downloaded);
                 };
 
-                if let Err(e) = patch_downloader
-                    .download_patch(patch, &patch_path, Some(&unique_id), progress)
+                match patch_downloader
+                    .download_patch(patch, &patch_path, Some(&unique_id), &control, progress)
                     .await
                 {
-                    pb.finish_and_clear();
-                    println!("  Download failed: {}", e);
-                    return Ok(());
+                    Ok(()) => {}
+                    Err(Error::Cancelled) => {
+                        pb.finish_and_clear();
+                        println!("  Download cancelled.");
+                        return Ok(());
+                    }
+                    Err(e) => {
+                        pb.finish_and_clear();
+                        println!("  Download failed: {}", e);
+                        return Ok(());
+                    }
                 }
                 pb.finish_and_clear();
 
                 // Verify
                 print!("  Verifying... ");
-                if !patch_downloader.verify_patch(patch, &patch_path).await? {
-                    println!("FAILED");
-                    println!("  Patch verification failed. Please try again.");
+                match patch_downloader
+                    .verify_patch_blocks(patch, &patch_path, &control)
+                    .await
+                {
+                    Ok(report) if report.is_valid() => println!("OK"),
+                    Ok(_) => {
+                        println!("FAILED");
+                        println!("  Patch verification failed. Please try again.");
+                        return Ok(());
+                    }
+                    Err(Error::Cancelled) => {
+                        println!("cancelled");
+                        return Ok(());
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+
+                // Don't start applying once cancelled
+                if control.checkpoint().await.is_err() {
+                    println!("  Update cancelled.");
                     return Ok(());
                 }
-                println!("OK");
 
                 // Apply
                 print!("  Applying... ");
-                match patch_applier.apply_patch(&patch_path, game_path) {
+                match apply_journaled(&patch_applier, patch, &patch_path, game_path, &control) {
                     Ok(()) => println!("OK"),
+                    Err(Error::Cancelled) => {
+                        println!("cancelled");
+                        println!("  The patch resumes on the next update.");
+                        return Ok(());
+                    }
                     Err(e) => {
                         println!("FAILED");
                         println!("  Failed to apply patch: {}", e);
@@ -1303,6 +1839,9 @@ async fn main() -> anyhow::Result<()> {
                 version_repo
                     .set_version(game_path, patch.repository, &patch.version_id)
                     .await?;
+                patch_applier
+                    .discard_journal(&patch_journal_path(&patch_path))
+                    .ok();
 
                 // Clean up patch file unless keeping
                 if !keep_patches {
@@ -1321,6 +1860,91 @@ async fn main() -> anyhow::Result<()> {
                 }
             }
         }
+
+        Commands::Patch {
+            command: PatchCommand::Inspect { file, json },
+        } => {
+            let chunks = match ZiPatchParser::new().parse_patch(file) {
+                Ok(chunks) => chunks,
+                Err(e) => {
+                    println!("Failed to read patch: {}", e);
+                    return Ok(());
+                }
+            };
+            let inspection = PatchInspection::from_chunks(&chunks);
+
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&inspection)?);
+                return Ok(());
+            }
+
+            let mb = |bytes: u64| bytes as f64 / 1024.0 / 1024.0;
+            println!("Patch: {}", file.display());
+            if let Some(header) = &inspection.header {
+                println!("  Format version: {}", header.version);
+                println!("  Type:           {}", header.patch_type);
+                println!("  Entry files:    {}", header.entry_files);
+            }
+            if let Some(target) = &inspection.target_info {
+                println!();
+                println!("Target:");
+                println!("  Platform:       {}", target.platform);
+                println!("  Region:         {:#06x}", target.region);
+                println!(
+                    "  Debug:          {}",
+                    if target.is_debug { "yes" } else { "no" }
+                );
+                println!("  Version:        {}", target.version);
+                println!("  Deleted data:   {:.2} MB", mb(target.deleted_data_size));
+                println!("  Seek count:     {}", target.seek_count);
+            }
+            if let Some(info) = &inspection.patch_info {
+                println!();
+                println!("Patch info:");
+                println!("  Status:         {}", info.status);
+                println!("  Version:        {}", info.version);
+                println!("  Install size:   {:.2} MB", mb(info.install_size));
+            }
+
+            println!();
+            println!("Chunks:");
+            for (chunk_type, count) in &inspection.chunk_counts {
+                println!("  {:<6} {:>10}", chunk_type, count);
+            }
+            if !inspection.sqpk_counts.is_empty() {
+                println!();
+                println!("SQPK commands:");
+                for (command, count) in &inspection.sqpk_counts {
+                    println!("  {:<6} {:>10}", command, count);
+                }
+            }
+
+            println!();
+            println!("Affected files ({}):", inspection.files.len());
+            for (path, change) in &inspection.files {
+                let mut details = Vec::new();
+                if change.bytes_written > 0 {
+                    details.push(format!("{:.2} MB written", mb(change.bytes_written)));
+                }
+                if change.bytes_deleted > 0 {
+                    details.push(format!("{:.2} MB deleted", mb(change.bytes_deleted)));
+                }
+                if change.removed {
+                    details.push("removed".to_string());
+                }
+                details.push(format!("{} commands", change.commands));
+                println!("  [{:?}] {}: {}", change.kind, path, details.join(", "));
+            }
+
+            let written: u64 = inspection.files.values().map(|c| c.bytes_written).sum();
+            let deleted: u64 = inspection.files.values().map(|c| c.bytes_deleted).sum();
+            println!();
+            println!(
+                "Total: {:.2} MB written, {:.2} MB deleted",
+                mb(written),
+                mb(deleted)
+            );
+        }
     }
 
     Ok(())
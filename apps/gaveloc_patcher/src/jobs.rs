@@ -0,0 +1,241 @@
+//! Queue of patch jobs requested by the launcher
+//!
+//! Jobs run one at a time in the order they were started. The launcher can
+//! pause, resume or cancel them and query their status while one is running,
+//! so the queue is shared between the request loop and the job worker.
+
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard};
+
+use tokio::sync::Notify;
+
+use gaveloc_adapters::ipc::{PatcherResponse, ProgressReport};
+use gaveloc_core::config::PatchSettings;
+use gaveloc_core::control::UpdateControl;
+use gaveloc_core::entities::PatchEntry;
+
+/// A batch of patches requested by the launcher
+pub struct PatchJob {
+    pub job_id: u64,
+    pub patches: Vec<PatchEntry>,
+    pub game_path: PathBuf,
+    pub patch_dir: PathBuf,
+    pub unique_id: Option<String>,
+    pub settings: PatchSettings,
+    pub keep_patches: bool,
+}
+
+#[derive(Default)]
+struct Jobs {
+    paused: bool,
+    /// Running job and the control it checks
+    current: Option<(u64, UpdateControl)>,
+    /// Latest progress of the running job
+    progress: Option<ProgressReport>,
+    queued: VecDeque<PatchJob>,
+    /// Set when the patcher is exiting, so no further job starts
+    closed: bool,
+}
+
+/// Jobs waiting for and being run by the job worker
+#[derive(Default)]
+pub struct JobQueue {
+    jobs: Mutex<Jobs>,
+    queued: Notify,
+}
+
+impl JobQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Jobs> {
+        self.jobs.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Queue a job behind the running and already queued ones
+    pub fn push(&self, job: PatchJob) {
+        self.lock().queued.push_back(job);
+        self.queued.notify_one();
+    }
+
+    /// Wait for the next job to run, `None` once the queue is closed
+    ///
+    /// The job's control starts out paused while patching is paused.
+    pub async fn next(&self) -> Option<(PatchJob, UpdateControl)> {
+        loop {
+            {
+                let mut jobs = self.lock();
+                if jobs.closed {
+                    return None;
+                }
+                if let Some(job) = jobs.queued.pop_front() {
+                    let control = UpdateControl::new();
+                    if jobs.paused {
+                        control.pause();
+                    }
+                    jobs.current = Some((job.job_id, control.clone()));
+                    jobs.progress = None;
+                    return Some((job, control));
+                }
+            }
+            self.queued.notified().await;
+        }
+    }
+
+    /// Mark the running job as finished
+    pub fn finish(&self) {
+        let mut jobs = self.lock();
+        jobs.current = None;
+        jobs.progress = None;
+    }
+
+    /// Record the running job's latest progress for status queries
+    pub fn report(&self, progress: ProgressReport) {
+        self.lock().progress = Some(progress);
+    }
+
+    /// Pause the running job and hold back queued ones
+    pub fn pause(&self) {
+        let mut jobs = self.lock();
+        jobs.paused = true;
+        if let Some((_, control)) = &jobs.current {
+            control.pause();
+        }
+    }
+
+    pub fn resume(&self) {
+        let mut jobs = self.lock();
+        jobs.paused = false;
+        if let Some((_, control)) = &jobs.current {
+            control.resume();
+        }
+    }
+
+    /// Cancel the running job and drop the queued ones
+    ///
+    /// The running job reports its own cancellation once it reaches a safe
+    /// point; the IDs of the dropped jobs are returned.
+    pub fn cancel_all(&self) -> Vec<u64> {
+        let mut jobs = self.lock();
+        if let Some((_, control)) = &jobs.current {
+            control.cancel();
+        }
+        jobs.queued.drain(..).map(|job| job.job_id).collect()
+    }
+
+    /// Cancel everything and stop the worker once the running job stopped
+    pub fn close(&self) -> Vec<u64> {
+        self.lock().closed = true;
+        // The permit wakes the worker even if it is not waiting yet
+        self.queued.notify_one();
+        self.cancel_all()
+    }
+
+    /// Answer to a status query
+    pub fn status(&self) -> PatcherResponse {
+        let jobs = self.lock();
+        PatcherResponse::Status {
+            paused: jobs.paused,
+            current: jobs.progress.clone(),
+            queued_jobs: jobs.queued.iter().map(|job| job.job_id).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::*;
+
+    fn job(job_id: u64) -> PatchJob {
+        PatchJob {
+            job_id,
+            patches: vec![],
+            game_path: PathBuf::from("/game"),
+            patch_dir: PathBuf::from("/patches"),
+            unique_id: None,
+            settings: PatchSettings::default(),
+            keep_patches: false,
+        }
+    }
+
+    fn queued_jobs(queue: &JobQueue) -> Vec<u64> {
+        match queue.status() {
+            PatcherResponse::Status { queued_jobs, .. } => queued_jobs,
+            other => panic!("expected Status, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_job_started_while_paused_starts_paused() {
+        let queue = JobQueue::new();
+        queue.pause();
+        queue.push(job(1));
+
+        let (job, control) = queue.next().await.unwrap();
+        assert_eq!(job.job_id, 1);
+        assert!(control.is_paused());
+
+        queue.resume();
+        assert!(!control.is_paused());
+    }
+
+    #[tokio::test]
+    async fn test_cancel_all_drops_queued_and_cancels_running() {
+        let queue = JobQueue::new();
+        queue.push(job(1));
+        queue.push(job(2));
+        queue.push(job(3));
+        let (_, control) = queue.next().await.unwrap();
+
+        assert_eq!(queue.cancel_all(), vec![2, 3]);
+        assert!(control.is_cancelled());
+        assert!(queued_jobs(&queue).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_close_wakes_waiting_worker() {
+        let queue = Arc::new(JobQueue::new());
+        let worker = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.next().await.map(|(job, _)| job.job_id) }
+        });
+        // Let the worker start waiting
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        queue.close();
+        let next = tokio::time::timeout(Duration::from_secs(1), worker)
+            .await
+            .expect("close should wake the worker")
+            .unwrap();
+        assert_eq!(next, None);
+    }
+
+    #[tokio::test]
+    async fn test_status_reports_queued_ids() {
+        let queue = JobQueue::new();
+        queue.push(job(1));
+        queue.push(job(2));
+        queue.push(job(3));
+        assert_eq!(queued_jobs(&queue), vec![1, 2, 3]);
+
+        // The running job is no longer queued
+        queue.next().await.unwrap();
+        match queue.status() {
+            PatcherResponse::Status {
+                paused,
+                current,
+                queued_jobs,
+            } => {
+                assert!(!paused);
+                assert!(current.is_none());
+                assert_eq!(queued_jobs, vec![2, 3]);
+            }
+            other => panic!("expected Status, got {:?}", other),
+        }
+    }
+}
@@ -1,30 +1,47 @@
 //! Gaveloc Patcher - Isolated patch application process
 //!
-//! This binary is spawned by the main launcher to apply ZiPatch files to the
-//! game installation. It communicates with the launcher via Unix domain sockets.
+//! This binary is spawned by the main launcher to download, verify and apply
+//! ZiPatch files to the game installation, updating its version files as each
+//! patch completes. It communicates with the launcher via Unix domain sockets
+//! and runs the jobs it is sent one at a time, answering pause, resume, cancel
+//! and status requests while a job runs.
 //!
 //! Usage: gaveloc_patcher <socket_path>
 
-use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use anyhow::{Context, Result};
 use interprocess::local_socket::tokio::prelude::*;
+use interprocess::local_socket::tokio::{SendHalf, Stream};
 use interprocess::local_socket::{GenericFilePath, ToFsName};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
 use tracing::{error, info, warn, Level};
 use tracing_subscriber::EnvFilter;
 
 use gaveloc_adapters::ipc::{
-    deserialize_message, serialize_message, PatcherRequest, PatcherResponse, MAX_MESSAGE_SIZE,
-    MESSAGE_HEADER_SIZE,
+    deserialize_message, serialize_message, Capabilities, PatcherRequest, PatcherResponse,
+    ProgressReport, MAX_MESSAGE_SIZE, MESSAGE_HEADER_SIZE,
 };
-use gaveloc_adapters::ZiPatchParser;
+use gaveloc_adapters::{
+    FileVersionRepository, HttpPatchDownloader, LocalDiskSpace, SquareEnixPatchServer,
+    ZiPatchParser,
+};
+use gaveloc_core::control::UpdateControl;
 use gaveloc_core::entities::{PatchEntry, PatchState};
-use gaveloc_core::ports::ZiPatchApplier;
+use gaveloc_core::error::Error;
+use gaveloc_core::use_cases::{DownloadPhase, UpdateGameUseCase, UpdateProgress, UpdateStage};
+
+use crate::jobs::{JobQueue, PatchJob};
+use crate::watchdog::ParentWatchdog;
 
-/// Flag to signal cancellation
-static CANCELLED: AtomicBool = AtomicBool::new(false);
+mod jobs;
+mod watchdog;
+
+/// Minimum time between download progress messages
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -52,69 +69,127 @@ async fn main() -> Result<()> {
         .to_fs_name::<GenericFilePath>()
         .context("invalid socket path")?;
 
-    let mut stream = interprocess::local_socket::tokio::Stream::connect(socket_name)
+    let stream = Stream::connect(socket_name)
         .await
         .context("failed to connect to launcher")?;
 
     info!("connected to launcher");
 
+    // Requests keep being read while a job runs, so responses from the
+    // request loop and the job worker go through a single writer task
+    let (mut reader, writer) = stream.split();
+    let (responses, pending_responses) = mpsc::unbounded_channel();
+    let writer = tokio::spawn(write_responses(writer, pending_responses));
+
     // Send Ready message
-    send_message(&mut stream, &PatcherResponse::Ready).await?;
+    let _ = responses.send(PatcherResponse::Ready {
+        capabilities: Capabilities::SUPPORTED,
+    });
+
+    let jobs = Arc::new(JobQueue::new());
+    let worker = tokio::spawn(run_jobs(jobs.clone(), responses.clone()));
+    // Watches the launcher once it introduced itself
+    let mut watchdog = None;
 
     // Main message loop
     loop {
-        let request = match recv_message(&mut stream).await {
-            Ok(req) => req,
-            Err(e) => {
-                // Connection closed or error - exit gracefully
-                warn!("connection error, exiting: {}", e);
+        let request = tokio::select! {
+            request = recv_message(&mut reader) => match request {
+                Ok(req) => req,
+                Err(e) => match e.downcast_ref::<Error>() {
+                    // Sent by a newer launcher; the rest of the connection is
+                    // still usable
+                    Some(Error::IpcUnknownMessage(kind)) => {
+                        warn!("unsupported request {}", kind);
+                        let _ = responses.send(PatcherResponse::Error {
+                            job_id: None,
+                            message: format!("unsupported request: {}", kind),
+                        });
+                        continue;
+                    }
+                    Some(e) => {
+                        error!("incompatible launcher, exiting: {}", e);
+                        let _ = responses.send(PatcherResponse::Error {
+                            job_id: None,
+                            message: e.to_string(),
+                        });
+                        break;
+                    }
+                    None => {
+                        // Connection closed or error - exit gracefully
+                        warn!("connection error, exiting: {}", e);
+                        break;
+                    }
+                },
+            },
+            _ = watchdog::parent_exited(watchdog) => {
+                // Nobody is left to report to, so stop at the next safe
+                // point; an interrupted patch keeps its journal
+                warn!("launcher exited, exiting");
                 break;
             }
         };
 
         match request {
-            PatcherRequest::Hello { parent_pid } => {
-                info!("received Hello from parent PID {}", parent_pid);
-                // Could monitor parent process here, but for simplicity just acknowledge
+            PatcherRequest::Hello {
+                parent_pid,
+                capabilities,
+            } => {
+                info!(
+                    "received Hello from parent PID {} (capabilities {:#x})",
+                    parent_pid,
+                    capabilities.bits()
+                );
+                watchdog = Some(ParentWatchdog::new(parent_pid));
             }
 
             PatcherRequest::StartPatch {
+                job_id,
                 patches,
                 game_path,
+                patch_dir,
+                unique_id,
+                settings,
                 keep_patches,
             } => {
                 info!(
-                    "received StartPatch: {} patches for {:?}",
+                    "received StartPatch: job {} with {} patches for {:?}",
+                    job_id,
                     patches.len(),
                     game_path
                 );
-                CANCELLED.store(false, Ordering::SeqCst);
-
-                match apply_patches(&mut stream, patches, &game_path, keep_patches).await {
-                    Ok(()) => {
-                        if CANCELLED.load(Ordering::SeqCst) {
-                            send_message(&mut stream, &PatcherResponse::Cancelled).await?;
-                        } else {
-                            send_message(&mut stream, &PatcherResponse::AllCompleted).await?;
-                        }
-                    }
-                    Err(e) => {
-                        error!("patch application failed: {}", e);
-                        send_message(
-                            &mut stream,
-                            &PatcherResponse::Error {
-                                message: e.to_string(),
-                            },
-                        )
-                        .await?;
-                    }
-                }
+                jobs.push(PatchJob {
+                    job_id,
+                    patches,
+                    game_path,
+                    patch_dir,
+                    unique_id,
+                    settings,
+                    keep_patches,
+                });
             }
 
             PatcherRequest::Cancel => {
                 info!("received Cancel request");
-                CANCELLED.store(true, Ordering::SeqCst);
-                // The apply_patches loop will check CANCELLED and exit
+                // The running job stops at its next safe point and reports
+                // its own cancellation
+                for job_id in jobs.cancel_all() {
+                    let _ = responses.send(PatcherResponse::JobCancelled { job_id });
+                }
+            }
+
+            PatcherRequest::Pause => {
+                info!("received Pause request");
+                jobs.pause();
+            }
+
+            PatcherRequest::Resume => {
+                info!("received Resume request");
+                jobs.resume();
+            }
+
+            PatcherRequest::QueryStatus => {
+                let _ = responses.send(jobs.status());
             }
 
             PatcherRequest::Shutdown => {
@@ -124,31 +199,49 @@ async fn main() -> Result<()> {
         }
     }
 
+    // Stop the running job at its next safe point before exiting
+    for job_id in jobs.close() {
+        let _ = responses.send(PatcherResponse::JobCancelled { job_id });
+    }
+    if let Err(e) = worker.await {
+        error!("job worker panicked: {}", e);
+    }
+    drop(responses);
+    let _ = writer.await;
+
     info!("patcher exiting");
     Ok(())
 }
 
+/// Write responses to the launcher until every sender is gone
+async fn write_responses(
+    mut writer: SendHalf,
+    mut responses: mpsc::UnboundedReceiver<PatcherResponse>,
+) {
+    while let Some(response) = responses.recv().await {
+        if let Err(e) = send_message(&mut writer, &response).await {
+            warn!("failed to send response: {:#}", e);
+            break;
+        }
+    }
+}
+
 /// Send a message to the launcher
-async fn send_message(
-    stream: &mut interprocess::local_socket::tokio::Stream,
-    msg: &PatcherResponse,
-) -> Result<()> {
+async fn send_message<W: AsyncWrite + Unpin>(writer: &mut W, msg: &PatcherResponse) -> Result<()> {
     let bytes = serialize_message(msg).context("failed to serialize message")?;
-    stream
+    writer
         .write_all(&bytes)
         .await
         .context("failed to write message")?;
-    stream.flush().await.context("failed to flush")?;
+    writer.flush().await.context("failed to flush")?;
     Ok(())
 }
 
 /// Receive a message from the launcher
-async fn recv_message(
-    stream: &mut interprocess::local_socket::tokio::Stream,
-) -> Result<PatcherRequest> {
+async fn recv_message<R: AsyncRead + Unpin>(reader: &mut R) -> Result<PatcherRequest> {
     // Read length header
     let mut header = [0u8; MESSAGE_HEADER_SIZE];
-    stream
+    reader
         .read_exact(&mut header)
         .await
         .context("failed to read header")?;
@@ -160,7 +253,7 @@ async fn recv_message(
 
     // Read payload
     let mut payload = vec![0u8; len as usize];
-    stream
+    reader
         .read_exact(&mut payload)
         .await
         .context("failed to read payload")?;
@@ -171,100 +264,342 @@ async fn recv_message(
     Ok(request)
 }
 
-/// Apply a batch of patches
-async fn apply_patches(
-    stream: &mut interprocess::local_socket::tokio::Stream,
-    patches: Vec<PatchEntry>,
-    game_path: &Path,
-    _keep_patches: bool,
-) -> Result<()> {
-    let total_patches = patches.len();
+/// Run queued jobs one at a time until the queue is closed
+async fn run_jobs(jobs: Arc<JobQueue>, responses: mpsc::UnboundedSender<PatcherResponse>) {
+    // Jobs share the HTTP client and its connections
+    let downloader = HttpPatchDownloader::new();
+
+    while let Some((job, control)) = jobs.next().await {
+        let job_id = job.job_id;
+        let reporter = JobReporter::new(jobs.clone(), responses.clone(), &job);
+        let result = match &downloader {
+            Ok(downloader) => {
+                let downloader = downloader.clone().with_patch_settings(&job.settings);
+                apply_patches(reporter, job, downloader, &control).await
+            }
+            Err(e) => Err(anyhow::anyhow!("failed to create patch downloader: {}", e)),
+        };
+        jobs.finish();
 
-    for (idx, patch) in patches.into_iter().enumerate() {
-        if CANCELLED.load(Ordering::SeqCst) {
-            info!("patch operation cancelled");
-            return Ok(());
-        }
+        let response = match result {
+            Ok(()) => {
+                info!("job {} completed", job_id);
+                PatcherResponse::JobCompleted { job_id }
+            }
+            Err(e) if matches!(e.downcast_ref(), Some(Error::Cancelled)) => {
+                info!("job {} cancelled", job_id);
+                PatcherResponse::JobCancelled { job_id }
+            }
+            Err(e) => {
+                error!("job {} failed: {:#}", job_id, e);
+                PatcherResponse::Error {
+                    job_id: Some(job_id),
+                    message: format!("{:#}", e),
+                }
+            }
+        };
+        let _ = responses.send(response);
+    }
+}
 
-        let version_id = patch.version_id.clone();
-        let repository = patch.repository;
+/// Forwards the progress of a running job to the launcher
+///
+/// A closed connection is noticed by the request loop, which then cancels the
+/// job, so failing to report is not an error here.
+#[derive(Clone)]
+struct JobReporter {
+    jobs: Arc<JobQueue>,
+    responses: mpsc::UnboundedSender<PatcherResponse>,
+    job_id: u64,
+    patches: Arc<[PatchEntry]>,
+    sent: Arc<Mutex<SentProgress>>,
+}
 
-        info!(
-            "applying patch {}/{}: {} for {:?}",
-            idx + 1,
-            total_patches,
-            version_id,
-            repository
-        );
+/// What a [`JobReporter`] already told the launcher
+#[derive(Default)]
+struct SentProgress {
+    patches_completed: usize,
+    /// Patch whose installation was reported last
+    installing: Option<usize>,
+    /// Download step reported last
+    download: Option<DownloadStep>,
+}
 
-        // Send progress: Installing
-        send_message(
-            stream,
-            &PatcherResponse::Progress {
-                patch_index: idx,
-                total_patches,
-                version_id: version_id.clone(),
-                repository,
-                state: PatchState::Installing,
-                bytes_processed: 0,
-                bytes_total: patch.length,
-            },
-        )
-        .await?;
+/// Downloading or verifying one patch
+struct DownloadStep {
+    patch_index: usize,
+    state: PatchState,
+    started: Instant,
+    /// Bytes already on disk when the download (re)started
+    resumed_at: Option<u64>,
+    next_report: Instant,
+}
 
-        // The patch file should already be downloaded to a temp location
-        // The URL contains the patch file path for now
-        // In production, patches are downloaded first, then passed to patcher
-        let patch_path = PathBuf::from(&patch.url);
-
-        if !patch_path.exists() {
-            // For now, just log and continue - in production the patch would be downloaded first
-            warn!(
-                "patch file not found: {:?} - skipping (this is expected in test mode)",
-                patch_path
-            );
-
-            // Send completion anyway for testing
-            send_message(
-                stream,
-                &PatcherResponse::PatchCompleted {
-                    patch_index: idx,
-                    version_id: version_id.clone(),
-                },
-            )
-            .await?;
-            continue;
+impl JobReporter {
+    fn new(
+        jobs: Arc<JobQueue>,
+        responses: mpsc::UnboundedSender<PatcherResponse>,
+        job: &PatchJob,
+    ) -> Self {
+        Self {
+            jobs,
+            responses,
+            job_id: job.job_id,
+            patches: job.patches.clone().into(),
+            sent: Arc::default(),
         }
+    }
 
-        // Apply the patch in a blocking task
-        let game_path_owned = game_path.to_path_buf();
-        let patch_path_owned = patch_path.clone();
-        let apply_result = tokio::task::spawn_blocking(move || {
-            let parser = ZiPatchParser::new();
-            parser.apply_patch(&patch_path_owned, &game_path_owned)
-        })
-        .await
-        .context("patch task panicked")?;
+    /// Forward an update of the job's progress
+    ///
+    /// The start of each step and each completed patch are always sent,
+    /// download progress at most every [`PROGRESS_INTERVAL`].
+    fn report(&self, progress: &UpdateProgress) {
+        let mut sent = self.sent.lock().unwrap_or_else(|e| e.into_inner());
+
+        while sent.patches_completed < progress.patches_applied {
+            let patch_index = sent.patches_completed;
+            self.send(PatcherResponse::PatchCompleted {
+                job_id: self.job_id,
+                patch_index,
+                version_id: self.patches[patch_index].version_id.clone(),
+            });
+            sent.patches_completed += 1;
+        }
 
-        match apply_result {
-            Ok(()) => {
-                info!("patch {} applied successfully", version_id);
-
-                send_message(
-                    stream,
-                    &PatcherResponse::PatchCompleted {
-                        patch_index: idx,
-                        version_id,
-                    },
-                )
-                .await?;
+        let UpdateStage::Patching { download, apply } = &progress.stage else {
+            return;
+        };
+
+        if let Some(download) = download {
+            let patch_index = download.patch.patch_index - 1;
+            let state = match download.phase {
+                DownloadPhase::Downloading => PatchState::Downloading,
+                DownloadPhase::Verifying => PatchState::Verifying,
+            };
+            let now = Instant::now();
+            let step = match sent.download.take() {
+                Some(mut step) if step.patch_index == patch_index && step.state == state => {
+                    step.resumed_at.get_or_insert(progress.bytes_downloaded);
+                    step
+                }
+                _ => DownloadStep {
+                    patch_index,
+                    state,
+                    started: now,
+                    resumed_at: None,
+                    next_report: now,
+                },
+            };
+            let step = sent.download.insert(step);
+
+            if now >= step.next_report {
+                step.next_report = now + PROGRESS_INTERVAL;
+                let elapsed = step.started.elapsed().as_secs_f64();
+                let speed = match step.resumed_at {
+                    Some(resumed_at) if elapsed > 0.0 => {
+                        progress.bytes_downloaded.saturating_sub(resumed_at) as f64 / elapsed
+                    }
+                    _ => 0.0,
+                };
+                self.progress(patch_index, state, progress.bytes_downloaded, speed);
             }
-            Err(e) => {
-                error!("failed to apply patch {}: {}", version_id, e);
-                return Err(e.into());
+        }
+
+        if let Some(apply) = apply {
+            let patch_index = apply.patch_index - 1;
+            if sent.installing != Some(patch_index) {
+                sent.installing = Some(patch_index);
+                self.progress(patch_index, PatchState::Installing, 0, 0.0);
             }
         }
     }
 
+    fn progress(&self, patch_index: usize, state: PatchState, bytes_processed: u64, speed: f64) {
+        let patch = &self.patches[patch_index];
+        let report = ProgressReport {
+            job_id: self.job_id,
+            patch_index,
+            total_patches: self.patches.len(),
+            version_id: patch.version_id.clone(),
+            repository: patch.repository,
+            state,
+            bytes_processed,
+            bytes_total: patch.length,
+            bytes_per_sec: speed,
+        };
+        self.jobs.report(report.clone());
+        self.send(PatcherResponse::Progress(report));
+    }
+
+    fn send(&self, response: PatcherResponse) {
+        let _ = self.responses.send(response);
+    }
+}
+
+/// Download, verify and apply the patches of a job
+///
+/// The update use case downloads the next patch while the previous one is
+/// applied, refuses a job that does not fit on disk before downloading
+/// anything, and reuses downloads and journals left by a previous run. Fails
+/// with [`Error::Cancelled`] once `control` is cancelled, keeping the partial
+/// download and the journal of an interrupted patch for the next run.
+async fn apply_patches(
+    reporter: JobReporter,
+    job: PatchJob,
+    downloader: HttpPatchDownloader,
+    control: &UpdateControl,
+) -> Result<()> {
+    // A job started while paused waits here, even one without patches
+    control.checkpoint().await?;
+
+    tokio::fs::create_dir_all(&job.patch_dir)
+        .await
+        .with_context(|| format!("failed to create patch directory {:?}", job.patch_dir))?;
+
+    // The launcher sends the patch list, so the patch server is never queried
+    let update = UpdateGameUseCase::new(
+        Arc::new(SquareEnixPatchServer::new()?),
+        Arc::new(downloader),
+        Arc::new(ZiPatchParser::new()),
+        Arc::new(FileVersionRepository::new()),
+        Arc::new(LocalDiskSpace::new()),
+        job.patch_dir,
+    )
+    .with_keep_patches(job.keep_patches);
+
+    update
+        .apply_patches(
+            &job.patches,
+            &job.game_path,
+            job.unique_id.as_deref(),
+            control,
+            move |progress| reporter.report(&progress),
+        )
+        .await?;
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gaveloc_core::entities::Repository;
+    use gaveloc_core::use_cases::{DownloadActivity, PatchActivity};
+
+    fn test_job(versions: &[&str]) -> PatchJob {
+        PatchJob {
+            job_id: 7,
+            patches: versions
+                .iter()
+                .map(|version| PatchEntry {
+                    version_id: version.to_string(),
+                    url: format!("http://example.com/{}.patch", version),
+                    length: 100,
+                    hash_type: None,
+                    hash_block_size: None,
+                    hashes: None,
+                    repository: Repository::Ffxiv,
+                })
+                .collect(),
+            game_path: PathBuf::from("/game"),
+            patch_dir: PathBuf::from("/patches"),
+            unique_id: None,
+            settings: Default::default(),
+            keep_patches: false,
+        }
+    }
+
+    fn activity(patch_index: usize, version: &str) -> PatchActivity {
+        PatchActivity {
+            patch_index,
+            total_patches: 2,
+            repository: Repository::Ffxiv,
+            version: version.to_string(),
+        }
+    }
+
+    fn patching(
+        download: Option<(usize, &str, DownloadPhase)>,
+        apply: Option<(usize, &str)>,
+        bytes_downloaded: u64,
+        patches_applied: usize,
+    ) -> UpdateProgress {
+        UpdateProgress {
+            stage: UpdateStage::Patching {
+                download: download.map(|(index, version, phase)| DownloadActivity {
+                    patch: activity(index, version),
+                    phase,
+                }),
+                apply: apply.map(|(index, version)| activity(index, version)),
+            },
+            bytes_downloaded,
+            bytes_total: 100,
+            overall_progress: 0.0,
+            patches_applied,
+        }
+    }
+
+    /// Summarize a response as the state or event it reports
+    fn describe(response: PatcherResponse) -> String {
+        match response {
+            PatcherResponse::Progress(report) => format!(
+                "{:?} {} {}",
+                report.state, report.patch_index, report.bytes_processed
+            ),
+            PatcherResponse::PatchCompleted {
+                patch_index,
+                version_id,
+                ..
+            } => format!("completed {} {}", patch_index, version_id),
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_job_reporter_forwards_use_case_progress() {
+        let (responses, mut sent) = mpsc::unbounded_channel();
+        let reporter = JobReporter::new(
+            Arc::new(JobQueue::new()),
+            responses,
+            &test_job(&["p1", "p2"]),
+        );
+
+        let downloading = Some((1, "p1", DownloadPhase::Downloading));
+        reporter.report(&patching(downloading, None, 0, 0));
+        // Throttled, as it follows the start of the download immediately
+        reporter.report(&patching(downloading, None, 50, 0));
+        reporter.report(&patching(
+            Some((1, "p1", DownloadPhase::Verifying)),
+            None,
+            100,
+            0,
+        ));
+        // The second patch downloads while the first is applied; the
+        // installation is announced once
+        let downloading = Some((2, "p2", DownloadPhase::Downloading));
+        reporter.report(&patching(downloading, Some((1, "p1")), 0, 0));
+        reporter.report(&patching(downloading, Some((1, "p1")), 0, 0));
+        reporter.report(&patching(None, None, 0, 2));
+        reporter.report(&UpdateProgress {
+            stage: UpdateStage::Completed,
+            ..patching(None, None, 0, 2)
+        });
+
+        let mut events = Vec::new();
+        while let Ok(response) = sent.try_recv() {
+            events.push(describe(response));
+        }
+        assert_eq!(
+            events,
+            [
+                "Downloading 0 0",
+                "Verifying 0 100",
+                "Downloading 1 0",
+                "Installing 0 0",
+                "completed 0 p1",
+                "completed 1 p2",
+            ]
+        );
+    }
+}
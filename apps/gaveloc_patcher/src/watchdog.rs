@@ -0,0 +1,89 @@
+//! Parent process monitoring
+//!
+//! A patcher whose launcher crashed must not keep writing to the game
+//! directory. The watchdog notices the launcher exiting so the patcher can stop
+//! at the next safe point and exit.
+
+use std::path::Path;
+use std::time::Duration;
+
+/// How often the parent process is checked
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Watches the launcher process that spawned the patcher
+#[derive(Debug, Clone, Copy)]
+pub struct ParentWatchdog {
+    parent_pid: u32,
+    /// Whether `parent_pid` is our actual parent process
+    direct_parent: bool,
+}
+
+impl ParentWatchdog {
+    pub fn new(parent_pid: u32) -> Self {
+        Self {
+            parent_pid,
+            direct_parent: std::os::unix::process::parent_id() == parent_pid,
+        }
+    }
+
+    /// Whether the launcher is still running
+    ///
+    /// When the launcher is our parent, being re-parented means it exited,
+    /// which unlike `/proc` is not fooled by the PID being reused.
+    pub fn parent_alive(&self) -> bool {
+        if self.direct_parent {
+            std::os::unix::process::parent_id() == self.parent_pid
+        } else {
+            Path::new(&format!("/proc/{}", self.parent_pid)).exists()
+        }
+    }
+
+    /// Resolves once the launcher has exited
+    pub async fn parent_exited(&self) {
+        while self.parent_alive() {
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+}
+
+/// Resolves once the watched launcher has exited, never without a watchdog
+pub async fn parent_exited(watchdog: Option<ParentWatchdog>) {
+    match watchdog {
+        Some(watchdog) => watchdog.parent_exited().await,
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parent_alive_for_current_parent() {
+        let watchdog = ParentWatchdog::new(std::os::unix::process::parent_id());
+        assert!(watchdog.direct_parent);
+        assert!(watchdog.parent_alive());
+    }
+
+    #[test]
+    fn test_parent_alive_false_for_unused_pid() {
+        // Linux PIDs never exceed 2^22, so nothing runs under this one
+        let watchdog = ParentWatchdog::new(u32::MAX - 1);
+        assert!(!watchdog.direct_parent);
+        assert!(!watchdog.parent_alive());
+    }
+
+    #[tokio::test]
+    async fn test_parent_exited_resolves_for_unused_pid() {
+        let watchdog = ParentWatchdog::new(u32::MAX - 1);
+        tokio::time::timeout(Duration::from_millis(100), parent_exited(Some(watchdog)))
+            .await
+            .expect("parent_exited should resolve once the parent is gone");
+    }
+
+    #[tokio::test]
+    async fn test_parent_exited_without_watchdog_never_resolves() {
+        let result = tokio::time::timeout(Duration::from_millis(100), parent_exited(None)).await;
+        assert!(result.is_err());
+    }
+}
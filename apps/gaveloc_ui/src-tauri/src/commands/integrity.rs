@@ -7,8 +7,14 @@ use std::sync::Arc;
 use serde::{Deserialize, Serialize};
 use tauri::{AppHandle, Emitter, State};
 
-use gaveloc_core::entities::{FileIntegrityResult, IntegrityProgress, IntegrityStatus, Repository};
-use gaveloc_core::ports::{IntegrityChecker, VersionRepository};
+use gaveloc_adapters::SquareEnixPatchHistory;
+use gaveloc_core::control::UpdateControl;
+use gaveloc_core::entities::{
+    FileIntegrityResult, IntegrityCheckMode, IntegrityProgress, IntegrityStatus, Repository,
+};
+use gaveloc_core::ports::{
+    AccountRepository, CredentialStore, IntegrityChecker, VersionRepository,
+};
 
 use crate::state::AppState;
 
@@ -44,6 +50,13 @@ pub struct FileToRepairDto {
 pub struct RepairResultDto {
     pub success_count: u32,
     pub failure_count: u32,
+    pub failures: Vec<FileRepairFailureDto>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileRepairFailureDto {
+    pub relative_path: String,
+    pub reason: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -74,6 +87,9 @@ pub struct IntegrityProgressEvent {
 pub struct IntegrityState {
     pub is_checking: bool,
     pub cancel_requested: Arc<AtomicBool>,
+    pub is_repairing: bool,
+    /// Cancels the patch downloads and replay of a running repair
+    pub repair_control: UpdateControl,
     pub current_file: Option<String>,
     pub files_checked: u32,
     pub total_files: u32,
@@ -86,6 +102,8 @@ impl Default for IntegrityState {
         Self {
             is_checking: false,
             cancel_requested: Arc::new(AtomicBool::new(false)),
+            is_repairing: false,
+            repair_control: UpdateControl::new(),
             current_file: None,
             files_checked: 0,
             total_files: 0,
@@ -139,6 +157,7 @@ async fn get_game_version(state: &AppState) -> Result<String, String> {
 pub async fn verify_integrity(
     state: State<'_, AppState>,
     app_handle: AppHandle,
+    full: Option<bool>,
 ) -> Result<IntegrityResultDto, String> {
     // Check if already checking
     {
@@ -209,10 +228,15 @@ pub async fn verify_integrity(
         });
     };
 
-    // Run integrity check
+    // Run integrity check, reusing the hashes of unchanged files unless asked not to
+    let mode = if full.unwrap_or(false) {
+        IntegrityCheckMode::Full
+    } else {
+        IntegrityCheckMode::Incremental
+    };
     let results = state
         .integrity_checker
-        .check_integrity(&game_path, &manifest, progress_callback)
+        .check_integrity(&game_path, &manifest, mode, progress_callback)
         .await
         .map_err(|e| {
             // Reset state on error
@@ -274,7 +298,7 @@ pub async fn verify_integrity(
     Ok(result)
 }
 
-/// Repair corrupted/missing files (deletes them so patching system can restore)
+/// Repair corrupted/missing files by rebuilding them from the patch history
 #[tauri::command]
 pub async fn repair_files(
     state: State<'_, AppState>,
@@ -286,9 +310,33 @@ pub async fn repair_files(
         return Ok(RepairResultDto {
             success_count: 0,
             failure_count: 0,
+            failures: Vec::new(),
         });
     }
 
+    // Rebuilt files are checked against the same manifest the check used
+    let game_version = get_game_version(&state).await?;
+    let manifest = state
+        .integrity_checker
+        .fetch_manifest(&game_version)
+        .await
+        .map_err(|e| format!("Failed to fetch integrity manifest: {}", e))?;
+
+    // Game files need a login session to fetch their patch history
+    let mut session_id = None;
+    if let Ok(Some(account)) = state.accounts.get_default_account().await {
+        if let Ok(Some(session)) = state.credentials.get_session(&account.id).await {
+            if session.is_valid() {
+                session_id = Some(session.unique_id);
+            }
+        }
+    }
+    let history = match session_id {
+        Some(session_id) => SquareEnixPatchHistory::with_session(session_id),
+        None => SquareEnixPatchHistory::new(),
+    }
+    .map_err(|e| format!("Failed to create patch history client: {}", e))?;
+
     // Convert DTOs to FileIntegrityResult for the repair method
     let file_results: Vec<FileIntegrityResult> = files
         .into_iter()
@@ -300,22 +348,43 @@ pub async fn repair_files(
         })
         .collect();
 
-    let (success, failure) = state
+    let control = {
+        let mut integrity_state = state.integrity_state.write().await;
+        integrity_state.is_repairing = true;
+        integrity_state.repair_control = UpdateControl::new();
+        integrity_state.repair_control.clone()
+    };
+    let repairs = state
         .integrity_checker
-        .repair_files(&game_path, &file_results)
-        .await
-        .map_err(|e| format!("Failed to repair files: {}", e))?;
+        .repair_files(&game_path, &manifest, &file_results, &history, &control)
+        .await;
+    state.integrity_state.write().await.is_repairing = false;
+    let repairs = repairs.map_err(|e| format!("Failed to repair files: {}", e))?;
+
+    let failures: Vec<FileRepairFailureDto> = repairs
+        .into_iter()
+        .filter(|r| !r.outcome.is_repaired())
+        .map(|r| FileRepairFailureDto {
+            relative_path: r.relative_path,
+            reason: r.outcome.to_string(),
+        })
+        .collect();
 
     Ok(RepairResultDto {
-        success_count: success,
-        failure_count: failure,
+        success_count: (file_results.len() - failures.len()) as u32,
+        failure_count: failures.len() as u32,
+        failures,
     })
 }
 
-/// Cancel ongoing integrity check
+/// Cancel ongoing integrity check or repair
 #[tauri::command]
 pub async fn cancel_integrity_check(state: State<'_, AppState>) -> Result<(), String> {
     let integrity_state = state.integrity_state.read().await;
+    if integrity_state.is_repairing {
+        integrity_state.repair_control.cancel();
+        return Ok(());
+    }
     if !integrity_state.is_checking {
         return Err("No integrity check in progress".to_string());
     }
@@ -1,11 +1,11 @@
 use std::path::PathBuf;
-use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::Instant;
 
 use gaveloc_adapters::ipc::UnixSocketPatcherIpc;
-use gaveloc_core::entities::{AccountId, PatchEntry, Repository};
-use gaveloc_core::ports::{CredentialStore, PatchDownloader, PatcherIpc, PatchServer, VersionRepository};
+use gaveloc_core::config::PatchSettings;
+use gaveloc_core::control::{ControlState, UpdateControl};
+use gaveloc_core::entities::{AccountId, PatchEntry, PatcherEvent, Repository};
+use gaveloc_core::ports::{CredentialStore, PatcherIpc, PatchServer, VersionRepository};
 use serde::{Deserialize, Serialize};
 use tauri::{AppHandle, Emitter, State};
 use tokio::sync::RwLock;
@@ -36,6 +36,7 @@ pub struct PatchStatusDto {
     pub bytes_downloaded: u64,
     pub bytes_total: u64,
     pub speed_bytes_per_sec: f64,
+    pub is_paused: bool,
 }
 
 /// Patch progress event
@@ -77,7 +78,8 @@ pub struct PatchingState {
     pub bytes_processed: u64,
     pub bytes_total: u64,
     pub speed: f64,
-    pub cancel_requested: Arc<AtomicBool>,
+    /// Pauses or cancels the running operation between download chunks
+    pub control: UpdateControl,
 }
 
 impl Default for PatchingState {
@@ -92,7 +94,7 @@ impl Default for PatchingState {
             bytes_processed: 0,
             bytes_total: 0,
             speed: 0.0,
-            cancel_requested: Arc::new(AtomicBool::new(false)),
+            control: UpdateControl::new(),
         }
     }
 }
@@ -164,9 +166,8 @@ pub async fn start_boot_patch(
     drop(version_repo_guard);
 
     // Start patching in background
-    let patch_downloader = state.patch_downloader.clone();
+    let patch_settings = state.settings.read().await.patch.clone();
     let patch_state = state.patch_state.clone();
-    let version_repo = state.version_repo.clone();
 
     tokio::spawn(async move {
         run_patching(
@@ -174,9 +175,8 @@ pub async fn start_boot_patch(
             patches,
             game_path,
             None, // No unique_id for boot patches
-            patch_downloader,
+            patch_settings,
             patch_state,
-            version_repo,
         )
         .await;
     });
@@ -228,9 +228,8 @@ pub async fn start_game_patch(
     }
 
     // Start patching in background
-    let patch_downloader = state.patch_downloader.clone();
+    let patch_settings = state.settings.read().await.patch.clone();
     let patch_state = state.patch_state.clone();
-    let version_repo = state.version_repo.clone();
 
     tokio::spawn(async move {
         run_patching(
@@ -238,9 +237,8 @@ pub async fn start_game_patch(
             patches,
             game_path,
             Some(unique_id),
-            patch_downloader,
+            patch_settings,
             patch_state,
-            version_repo,
         )
         .await;
     });
@@ -256,7 +254,24 @@ pub async fn cancel_patch(state: State<'_, AppState>) -> Result<(), String> {
         return Ok(());
     }
 
-    patch_state.cancel_requested.store(true, Ordering::SeqCst);
+    patch_state.control.cancel();
+    Ok(())
+}
+
+/// Pause downloading after the current chunk
+#[tauri::command]
+pub async fn pause_patch(state: State<'_, AppState>) -> Result<(), String> {
+    let patch_state = state.patch_state.read().await;
+    if patch_state.is_patching {
+        patch_state.control.pause();
+    }
+    Ok(())
+}
+
+/// Resume a paused download
+#[tauri::command]
+pub async fn resume_patch(state: State<'_, AppState>) -> Result<(), String> {
+    state.patch_state.read().await.control.resume();
     Ok(())
 }
 
@@ -275,159 +290,35 @@ pub async fn get_patch_status(state: State<'_, AppState>) -> Result<PatchStatusD
         bytes_downloaded: patch_state.bytes_processed,
         bytes_total: patch_state.bytes_total,
         speed_bytes_per_sec: patch_state.speed,
+        is_paused: patch_state.control.is_paused(),
     })
 }
 
 /// Run the patching process
+///
+/// The patcher process downloads, verifies and applies the patches and
+/// updates the version files; this relays its progress to the UI.
 async fn run_patching(
     app_handle: AppHandle,
     patches: Vec<PatchEntry>,
     game_path: PathBuf,
     unique_id: Option<String>,
-    patch_downloader: Arc<gaveloc_adapters::patch::HttpPatchDownloader>,
+    patch_settings: PatchSettings,
     patch_state: Arc<RwLock<PatchingState>>,
-    version_repo: Arc<RwLock<Option<gaveloc_adapters::FileVersionRepository>>>,
 ) {
     let total_patches = patches.len();
-    let temp_dir = std::env::temp_dir().join("gaveloc_patches");
+    let patch_dir = std::env::temp_dir().join("gaveloc_patches");
 
     // Initialize state
-    {
+    let control = {
         let mut state = patch_state.write().await;
         state.is_patching = true;
         state.phase = PatchPhase::Downloading;
         state.current_index = 0;
         state.total_patches = total_patches;
-        state.cancel_requested.store(false, Ordering::SeqCst);
-    }
-
-    // Create temp directory
-    if let Err(e) = tokio::fs::create_dir_all(&temp_dir).await {
-        emit_error(&app_handle, &patch_state, format!("Failed to create temp dir: {}", e), false).await;
-        return;
-    }
-
-    // Download all patches first
-    let mut downloaded_patches: Vec<(PatchEntry, PathBuf)> = Vec::new();
-
-    for (index, patch) in patches.iter().enumerate() {
-        // Check for cancellation
-        if patch_state.read().await.cancel_requested.load(Ordering::SeqCst) {
-            emit_cancelled(&app_handle, &patch_state).await;
-            return;
-        }
-
-        let patch_file = temp_dir.join(format!("{}_{}.patch",
-            repository_name(patch.repository),
-            patch.version_id.replace('.', "_")
-        ));
-
-        // Update state
-        {
-            let mut state = patch_state.write().await;
-            state.phase = PatchPhase::Downloading;
-            state.current_index = index;
-            state.current_version_id = Some(patch.version_id.clone());
-            state.current_repository = Some(repository_name(patch.repository).to_string());
-            state.bytes_processed = 0;
-            state.bytes_total = patch.length;
-        }
-
-        // Emit progress event
-        let _ = app_handle.emit("patch_progress", PatchProgressEvent {
-            phase: PatchPhase::Downloading,
-            current_index: index,
-            total_patches,
-            version_id: patch.version_id.clone(),
-            repository: repository_name(patch.repository).to_string(),
-            bytes_processed: 0,
-            bytes_total: patch.length,
-            speed_bytes_per_sec: 0.0,
-        });
-
-        // Download with progress
-        let patch_state_clone = patch_state.clone();
-        let app_handle_clone = app_handle.clone();
-        let version_id = patch.version_id.clone();
-        let repo_name = repository_name(patch.repository).to_string();
-        let start_time = Instant::now();
-
-        let progress_callback = move |downloaded: u64, total: u64| {
-            let elapsed = start_time.elapsed().as_secs_f64();
-            let speed = if elapsed > 0.0 { downloaded as f64 / elapsed } else { 0.0 };
-
-            // Update state (spawn a task since we're in a sync callback)
-            let patch_state = patch_state_clone.clone();
-            let app_handle = app_handle_clone.clone();
-            let version_id = version_id.clone();
-            let repo_name = repo_name.clone();
-
-            tokio::spawn(async move {
-                {
-                    let mut state = patch_state.write().await;
-                    state.bytes_processed = downloaded;
-                    state.speed = speed;
-                }
-
-                let _ = app_handle.emit("patch_progress", PatchProgressEvent {
-                    phase: PatchPhase::Downloading,
-                    current_index: index,
-                    total_patches,
-                    version_id,
-                    repository: repo_name,
-                    bytes_processed: downloaded,
-                    bytes_total: total,
-                    speed_bytes_per_sec: speed,
-                });
-            });
-        };
-
-        // Download
-        if let Err(e) = patch_downloader
-            .download_patch(patch, &patch_file, unique_id.as_deref(), progress_callback)
-            .await
-        {
-            emit_error(&app_handle, &patch_state, format!("Download failed: {}", e), true).await;
-            return;
-        }
-
-        // Verify
-        {
-            let mut state = patch_state.write().await;
-            state.phase = PatchPhase::Verifying;
-        }
-
-        let _ = app_handle.emit("patch_progress", PatchProgressEvent {
-            phase: PatchPhase::Verifying,
-            current_index: index,
-            total_patches,
-            version_id: patch.version_id.clone(),
-            repository: repository_name(patch.repository).to_string(),
-            bytes_processed: patch.length,
-            bytes_total: patch.length,
-            speed_bytes_per_sec: 0.0,
-        });
-
-        match patch_downloader.verify_patch(patch, &patch_file).await {
-            Ok(true) => {
-                downloaded_patches.push((patch.clone(), patch_file));
-            }
-            Ok(false) => {
-                emit_error(&app_handle, &patch_state, "Patch verification failed".to_string(), true).await;
-                return;
-            }
-            Err(e) => {
-                emit_error(&app_handle, &patch_state, format!("Verification error: {}", e), true).await;
-                return;
-            }
-        }
-    }
-
-    // Now apply patches using the patcher process
-    {
-        let mut state = patch_state.write().await;
-        state.phase = PatchPhase::Applying;
-    }
+        state.control = UpdateControl::new();
+        state.control.clone()
+    };
 
     // Spawn patcher
     let patcher = match UnixSocketPatcherIpc::spawn().await {
@@ -438,35 +329,37 @@ async fn run_patching(
         }
     };
 
-    // Prepare patches with local file paths
-    let patches_for_patcher: Vec<PatchEntry> = downloaded_patches
-        .iter()
-        .map(|(patch, path)| {
-            let mut p = patch.clone();
-            p.url = path.to_string_lossy().to_string();
-            p
-        })
-        .collect();
-
     // Start patching
-    if let Err(e) = patcher.start_patch(patches_for_patcher, &game_path).await {
-        emit_error(&app_handle, &patch_state, format!("Failed to start patching: {}", e), false).await;
-        let _ = patcher.shutdown().await;
-        return;
-    }
-
-    // Poll for progress
-    loop {
-        // Check for cancellation
-        if patch_state.read().await.cancel_requested.load(Ordering::SeqCst) {
-            let _ = patcher.cancel().await;
-            emit_cancelled(&app_handle, &patch_state).await;
+    let job_id = match patcher
+        .start_patch(patches.clone(), &game_path, &patch_dir, unique_id.as_deref(), &patch_settings)
+        .await
+    {
+        Ok(job_id) => job_id,
+        Err(e) => {
+            emit_error(&app_handle, &patch_state, format!("Failed to start patching: {}", e), false).await;
             let _ = patcher.shutdown().await;
             return;
         }
+    };
 
-        match patcher.receive_progress().await {
-            Ok(Some(progress)) => {
+    // Relay events, forwarding pause, resume and cancel requests from the UI
+    let mut control_state = control.subscribe();
+    loop {
+        if control_state.has_changed().unwrap_or(false) {
+            // A lost connection is reported by receive_event below
+            let requested = *control_state.borrow_and_update();
+            let _ = match requested {
+                ControlState::Paused => patcher.pause().await,
+                ControlState::Running => patcher.resume().await,
+                // The patcher stops at its next safe point and reports the
+                // job as cancelled
+                ControlState::Cancelled => patcher.cancel().await,
+            };
+        }
+
+        match patcher.receive_event().await {
+            Ok(Some(PatcherEvent::Progress(job))) if job.job_id == job_id => {
+                let progress = job.progress;
                 let phase = match progress.state {
                     gaveloc_core::entities::PatchState::Pending => PatchPhase::Applying,
                     gaveloc_core::entities::PatchState::Downloading => PatchPhase::Downloading,
@@ -475,12 +368,7 @@ async fn run_patching(
                     gaveloc_core::entities::PatchState::Completed => PatchPhase::Completed,
                     gaveloc_core::entities::PatchState::Failed => PatchPhase::Failed,
                 };
-
-                // Find current index based on version_id
-                let current_index = downloaded_patches
-                    .iter()
-                    .position(|(p, _)| p.version_id == progress.patch.version_id)
-                    .unwrap_or(0);
+                let current_index = job.patch_index;
 
                 {
                     let mut state = patch_state.write().await;
@@ -490,6 +378,7 @@ async fn run_patching(
                     state.current_repository = Some(repository_name(progress.patch.repository).to_string());
                     state.bytes_processed = progress.bytes_downloaded;
                     state.bytes_total = progress.bytes_total;
+                    state.speed = progress.speed_bytes_per_sec;
                 }
 
                 let _ = app_handle.emit("patch_progress", PatchProgressEvent {
@@ -502,58 +391,48 @@ async fn run_patching(
                     bytes_total: progress.bytes_total,
                     speed_bytes_per_sec: progress.speed_bytes_per_sec,
                 });
-
-                // If completed, emit patch_completed
-                if progress.state == gaveloc_core::entities::PatchState::Completed {
-                    let _ = app_handle.emit("patch_completed", PatchCompletedEvent {
-                        index: current_index,
-                        version_id: progress.patch.version_id,
-                        repository: repository_name(progress.patch.repository).to_string(),
-                    });
-                }
             }
-            Ok(None) => {
-                // All completed
+            Ok(Some(PatcherEvent::PatchCompleted {
+                job_id: completed_job,
+                patch_index,
+                version_id,
+            })) if completed_job == job_id => {
+                let repository = patches
+                    .get(patch_index)
+                    .map(|p| repository_name(p.repository).to_string())
+                    .unwrap_or_default();
+                let _ = app_handle.emit("patch_completed", PatchCompletedEvent {
+                    index: patch_index,
+                    version_id,
+                    repository,
+                });
+            }
+            Ok(Some(PatcherEvent::JobCompleted { job_id: completed_job })) if completed_job == job_id => {
                 break;
             }
-            Err(gaveloc_core::error::Error::Cancelled) => {
+            Ok(Some(PatcherEvent::JobCancelled { job_id: cancelled_job })) if cancelled_job == job_id => {
                 emit_cancelled(&app_handle, &patch_state).await;
                 let _ = patcher.shutdown().await;
                 return;
             }
+            Ok(Some(PatcherEvent::JobFailed { job_id: failed_job, message })) if failed_job == job_id => {
+                emit_error(&app_handle, &patch_state, format!("Patching failed: {}", message), true).await;
+                let _ = patcher.shutdown().await;
+                return;
+            }
+            // Events of other jobs, or nothing yet
+            Ok(_) => {}
             Err(e) => {
-                emit_error(&app_handle, &patch_state, format!("Patch application error: {}", e), false).await;
+                emit_error(&app_handle, &patch_state, format!("Patching failed: {}", e), true).await;
                 let _ = patcher.shutdown().await;
                 return;
             }
         }
-
-        // Small delay to avoid busy polling
-        tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
     }
 
     // Shutdown patcher
     let _ = patcher.shutdown().await;
-
-    // Update version files
-    let version_repo_guard = version_repo.read().await;
-    if let Some(repo) = version_repo_guard.as_ref() {
-        for (patch, _) in &downloaded_patches {
-            if let Err(e) = repo.set_version(
-                &game_path,
-                patch.repository,
-                &patch.version_id,
-            ).await {
-                eprintln!("Failed to update version for {:?}: {}", patch.repository, e);
-            }
-        }
-    }
-
-    // Clean up temp files
-    for (_, path) in &downloaded_patches {
-        let _ = tokio::fs::remove_file(path).await;
-    }
-    let _ = tokio::fs::remove_dir(&temp_dir).await;
+    let _ = tokio::fs::remove_dir(&patch_dir).await;
 
     // Mark as completed
     {
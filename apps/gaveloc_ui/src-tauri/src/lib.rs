@@ -52,6 +52,8 @@ pub fn run() {
             commands::patching::start_boot_patch,
             commands::patching::start_game_patch,
             commands::patching::cancel_patch,
+            commands::patching::pause_patch,
+            commands::patching::resume_patch,
             commands::patching::get_patch_status,
             commands::integrity::verify_integrity,
             commands::integrity::repair_files,
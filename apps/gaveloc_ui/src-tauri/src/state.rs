@@ -2,11 +2,12 @@ use std::path::PathBuf;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
+use gaveloc_adapters::configuration::patch_dir;
 use gaveloc_adapters::{
     configuration::get_configuration,
     FileAccountRepository, FileVersionRepository, GoatcorpIntegrityChecker,
     HttpOtpListener, KeyringCredentialStore, SquareEnixAuthenticator,
-    patch::{HttpPatchDownloader, SquareEnixPatchServer},
+    patch::SquareEnixPatchServer,
     prefix::LinuxPrefixManager,
     process::LinuxProcessLauncher,
     runner::LinuxRunnerDetector,
@@ -34,8 +35,6 @@ pub struct AppState {
     pub authenticator: Arc<RwLock<Option<SquareEnixAuthenticator>>>,
     /// Patch server for checking updates
     pub patch_server: Arc<SquareEnixPatchServer>,
-    /// Patch downloader
-    pub patch_downloader: Arc<HttpPatchDownloader>,
     /// Version repository for reading/writing game versions
     pub version_repo: Arc<RwLock<Option<FileVersionRepository>>>,
     /// Integrity checker
@@ -72,10 +71,12 @@ impl AppState {
         let patch_server = Arc::new(
             SquareEnixPatchServer::new().expect("Failed to create patch server client"),
         );
-        let patch_downloader = Arc::new(
-            HttpPatchDownloader::new().expect("Failed to create patch downloader client"),
+        let integrity_checker = Arc::new(
+            GoatcorpIntegrityChecker::with_default_client()
+                .with_integrity_settings(&settings.integrity)
+                .with_patch_dir(patch_dir(&settings))
+                .with_patch_settings(&settings.patch),
         );
-        let integrity_checker = Arc::new(GoatcorpIntegrityChecker::with_default_client());
         let runner_detector = Arc::new(LinuxRunnerDetector::new());
         let otp_listener = Arc::new(HttpOtpListener::new());
         let process_launcher = Arc::new(LinuxProcessLauncher::new());
@@ -93,7 +94,6 @@ impl AppState {
             credentials,
             authenticator,
             patch_server,
-            patch_downloader,
             version_repo,
             integrity_checker,
             runner_detector,
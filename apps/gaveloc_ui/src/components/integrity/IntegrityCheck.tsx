@@ -1,6 +1,6 @@
 import { useState } from 'react';
 import { useIntegrityStore } from '../../stores/integrityStore';
-import { useGameStore } from '../../stores/gameStore';
+import type { FileRepairFailure } from '../../types';
 import './IntegrityCheck.css';
 
 function formatBytes(bytes: number): string {
@@ -25,12 +25,10 @@ export function IntegrityCheck() {
     reset,
   } = useIntegrityStore();
 
-  const { checkBootUpdates } = useGameStore();
-
   const [showProblems, setShowProblems] = useState(false);
   const [repairResult, setRepairResult] = useState<{
     success: number;
-    failure: number;
+    failures: FileRepairFailure[];
   } | null>(null);
 
   const handleVerify = async () => {
@@ -58,18 +56,16 @@ export function IntegrityCheck() {
       const repairRes = await repairFiles(repairableFiles);
       setRepairResult({
         success: repairRes.success_count,
-        failure: repairRes.failure_count,
+        failures: repairRes.failures,
       });
-      // Clear the result so user knows they need to check updates
       reset();
     } catch (e) {
       // Error is handled by the store
     }
   };
 
-  const handleCheckUpdatesAfterRepair = async () => {
+  const handleCloseRepairResult = () => {
     setRepairResult(null);
-    await checkBootUpdates();
   };
 
   // Calculate repairable file count
@@ -87,19 +83,25 @@ export function IntegrityCheck() {
         </div>
         <div className="integrity-repair-result">
           <p className="text-success">
-            Successfully removed {repairResult.success} corrupted file(s).
+            Successfully repaired {repairResult.success} file(s).
           </p>
-          {repairResult.failure > 0 && (
-            <p className="text-error">
-              Failed to remove {repairResult.failure} file(s).
-            </p>
+          {repairResult.failures.length > 0 && (
+            <>
+              <p className="text-error">
+                Failed to repair {repairResult.failures.length} file(s):
+              </p>
+              <ul className="text-secondary mt-sm">
+                {repairResult.failures.map((f) => (
+                  <li key={f.relative_path}>
+                    {f.relative_path}: {f.reason}
+                  </li>
+                ))}
+              </ul>
+            </>
           )}
-          <p className="text-secondary mt-sm">
-            Click "Check for Updates" to download and restore the removed files.
-          </p>
         </div>
-        <button className="primary mt-md" onClick={handleCheckUpdatesAfterRepair}>
-          Check for Updates
+        <button className="primary mt-md" onClick={handleCloseRepairResult}>
+          Done
         </button>
       </div>
     );
@@ -264,8 +266,8 @@ export function IntegrityCheck() {
                     : `Repair ${repairableCount} file(s)`}
                 </button>
                 <p className="integrity-repair-warning">
-                  Repair will delete corrupted files. Run "Check for Updates"
-                  after to restore them.
+                  Repair rebuilds these files from the game's patch history,
+                  which can download several gigabytes of patches.
                 </p>
               </div>
             )}
@@ -24,8 +24,11 @@ export function PatchProgress() {
     bytesProcessed,
     bytesTotal,
     speedBytesPerSec,
+    isPaused,
     error,
     cancelPatch,
+    pausePatch,
+    resumePatch,
     reset,
   } = usePatchStore();
 
@@ -38,6 +41,9 @@ export function PatchProgress() {
 
   // Get phase display text
   const getPhaseText = () => {
+    if (isPaused && phase === 'Downloading') {
+      return 'Paused';
+    }
     switch (phase) {
       case 'Downloading':
         return 'Downloading';
@@ -138,6 +144,11 @@ export function PatchProgress() {
       </div>
 
       <div className="patch-progress-actions">
+        {phase === 'Downloading' && (
+          <button className="secondary" onClick={isPaused ? resumePatch : pausePatch}>
+            {isPaused ? 'Resume' : 'Pause'}
+          </button>
+        )}
         <button
           className="secondary"
           onClick={cancelPatch}
@@ -17,7 +17,7 @@ interface IntegrityState {
   error: string | null;
 
   // Actions
-  startVerify: () => Promise<IntegrityResult>;
+  startVerify: (full?: boolean) => Promise<IntegrityResult>;
   cancelVerify: () => Promise<void>;
   repairFiles: (files: FileIntegrityResult[]) => Promise<RepairResult>;
   updateProgress: (progress: IntegrityProgress) => void;
@@ -35,11 +35,11 @@ export const useIntegrityStore = create<IntegrityState>((set) => ({
   result: null,
   error: null,
 
-  // Start integrity verification
-  startVerify: async () => {
+  // Start integrity verification; a full check rehashes unchanged files too
+  startVerify: async (full = false) => {
     set({ isChecking: true, error: null, result: null, progress: null });
     try {
-      const result = await invoke<IntegrityResult>('verify_integrity');
+      const result = await invoke<IntegrityResult>('verify_integrity', { full });
       set({ result, isChecking: false });
       return result;
     } catch (e) {
@@ -58,7 +58,7 @@ export const useIntegrityStore = create<IntegrityState>((set) => ({
     }
   },
 
-  // Repair files (deletes them so patching can restore)
+  // Repair files (rebuilds them from the patch history)
   repairFiles: async (files: FileIntegrityResult[]) => {
     set({ isRepairing: true, error: null });
 
@@ -17,6 +17,7 @@ interface PatchState {
   bytesProcessed: number;
   bytesTotal: number;
   speedBytesPerSec: number;
+  isPaused: boolean;
   error: string | null;
   completedPatches: string[];
 
@@ -24,6 +25,8 @@ interface PatchState {
   startBootPatch: () => Promise<void>;
   startGamePatch: (accountId: string) => Promise<void>;
   cancelPatch: () => Promise<void>;
+  pausePatch: () => Promise<void>;
+  resumePatch: () => Promise<void>;
   getStatus: () => Promise<PatchStatus>;
   updateProgress: (event: PatchProgressEvent) => void;
   markCompleted: (event: PatchCompletedEvent) => void;
@@ -41,6 +44,7 @@ export const usePatchStore = create<PatchState>((set) => ({
   bytesProcessed: 0,
   bytesTotal: 0,
   speedBytesPerSec: 0,
+  isPaused: false,
   error: null,
   completedPatches: [],
 
@@ -48,6 +52,7 @@ export const usePatchStore = create<PatchState>((set) => ({
     set({
       isPatching: true,
       phase: 'Downloading',
+      isPaused: false,
       error: null,
       completedPatches: [],
     });
@@ -68,6 +73,7 @@ export const usePatchStore = create<PatchState>((set) => ({
     set({
       isPatching: true,
       phase: 'Downloading',
+      isPaused: false,
       error: null,
       completedPatches: [],
     });
@@ -92,6 +98,24 @@ export const usePatchStore = create<PatchState>((set) => ({
     }
   },
 
+  pausePatch: async (): Promise<void> => {
+    try {
+      await invoke('pause_patch');
+      set({ isPaused: true });
+    } catch (err) {
+      console.error('Failed to pause patch:', err);
+    }
+  },
+
+  resumePatch: async (): Promise<void> => {
+    try {
+      await invoke('resume_patch');
+      set({ isPaused: false });
+    } catch (err) {
+      console.error('Failed to resume patch:', err);
+    }
+  },
+
   getStatus: async (): Promise<PatchStatus> => {
     try {
       const status = await invoke<PatchStatus>('get_patch_status');
@@ -105,6 +129,7 @@ export const usePatchStore = create<PatchState>((set) => ({
         bytesProcessed: status.bytes_downloaded,
         bytesTotal: status.bytes_total,
         speedBytesPerSec: status.speed_bytes_per_sec,
+        isPaused: status.is_paused,
       });
       return status;
     } catch (err) {
@@ -118,6 +143,7 @@ export const usePatchStore = create<PatchState>((set) => ({
         bytes_downloaded: 0,
         bytes_total: 0,
         speed_bytes_per_sec: 0,
+        is_paused: false,
       };
     }
   },
@@ -161,6 +187,7 @@ export const usePatchStore = create<PatchState>((set) => ({
       bytesProcessed: 0,
       bytesTotal: 0,
       speedBytesPerSec: 0,
+      isPaused: false,
       error: null,
       completedPatches: [],
     });
@@ -1,6 +1,6 @@
 import { create } from 'zustand';
 import { invoke } from '@tauri-apps/api/core';
-import type { Settings, GameSettings, WineSettings } from '../types';
+import type { Settings, GameSettings, WineSettings, PatchSettings } from '../types';
 
 interface ValidationResult {
   valid: boolean;
@@ -20,6 +20,7 @@ interface SettingsState {
   saveSettings: (settings: Settings) => Promise<void>;
   updateGameSettings: (gameSettings: Partial<GameSettings>) => void;
   updateWineSettings: (wineSettings: Partial<WineSettings>) => void;
+  updatePatchSettings: (patchSettings: Partial<PatchSettings>) => void;
   validateGamePath: (path: string) => Promise<ValidationResult>;
   detectGameInstall: () => Promise<string | null>;
   getDefaultInstallPath: () => Promise<string>;
@@ -51,6 +52,15 @@ const defaultSettings: Settings = {
     winesync: false,
     dxvk_hud: null,
   },
+  patch: {
+    max_download_rate: null,
+    download_window: null,
+  },
+  integrity: {
+    manifest_sources: [{ type: 'goatcorp' }],
+    pinned_manifests: {},
+    manifest_cache_dir: null,
+  },
   log_level: 'info',
 };
 
@@ -108,6 +118,18 @@ export const useSettingsStore = create<SettingsState>((set, get) => ({
     });
   },
 
+  updatePatchSettings: (patchSettings: Partial<PatchSettings>) => {
+    const { settings } = get();
+    if (!settings) return;
+
+    set({
+      settings: {
+        ...settings,
+        patch: { ...settings.patch, ...patchSettings },
+      },
+    });
+  },
+
   validateGamePath: async (path: string) => {
     try {
       const result = await invoke<ValidationResult>('validate_game_path', { path });
@@ -48,6 +48,8 @@ export interface NewsArticle {
 export interface Settings {
   game: GameSettings;
   wine: WineSettings;
+  patch: PatchSettings;
+  integrity: IntegritySettings;
   log_level: string;
 }
 
@@ -69,6 +71,32 @@ export interface WineSettings {
   dxvk_hud: string | null;
 }
 
+export interface PatchSettings {
+  /** Download rate cap in KiB/s (null for unlimited) */
+  max_download_rate: number | null;
+  /** Only download between these local times ("HH:MM") */
+  download_window: DownloadWindow | null;
+}
+
+export interface DownloadWindow {
+  start: string;
+  end: string;
+}
+
+export interface IntegritySettings {
+  /** Where manifests are read from, tried in order */
+  manifest_sources: ManifestSource[];
+  /** Expected SHA-256 of the manifest JSON, by game version */
+  pinned_manifests: Record<string, string>;
+  /** Where fetched manifests are cached (null for the user cache directory) */
+  manifest_cache_dir: string | null;
+}
+
+export type ManifestSource =
+  | { type: 'goatcorp' }
+  | { type: 'mirror'; base_url: string }
+  | { type: 'directory'; path: string };
+
 export interface GamescopeSettings {
   width: number | null;
   height: number | null;
@@ -146,6 +174,7 @@ export interface PatchStatus {
   bytes_downloaded: number;
   bytes_total: number;
   speed_bytes_per_sec: number;
+  is_paused: boolean;
 }
 
 // Authentication types
@@ -241,6 +270,12 @@ export interface FileToRepair {
 export interface RepairResult {
   success_count: number;
   failure_count: number;
+  failures: FileRepairFailure[];
+}
+
+export interface FileRepairFailure {
+  relative_path: string;
+  reason: string;
 }
 
 // Game version types
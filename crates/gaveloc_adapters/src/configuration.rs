@@ -50,6 +50,18 @@ pub fn get_configuration() -> Result<Settings, config::ConfigError> {
     get_configuration_with_paths(None, None)
 }
 
+/// Directory patches are downloaded to, the user cache directory by default
+///
+/// Unlike the temporary directory, the cache directory is not a size-limited
+/// tmpfs on many systems.
+pub fn patch_dir(settings: &Settings) -> PathBuf {
+    settings.patch_dir.clone().unwrap_or_else(|| {
+        ProjectDirs::from("com", "gaveloc", "gaveloc")
+            .map(|d| d.cache_dir().join("patches"))
+            .unwrap_or_else(|| std::env::temp_dir().join("gaveloc_patches"))
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1 +1,55 @@
-// File system adapter (stub)
+//! File system adapter
+//!
+//! Free space queries backed by `statvfs`.
+
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use gaveloc_core::entities::FilesystemSpace;
+use gaveloc_core::error::Error;
+use gaveloc_core::ports::DiskSpaceProvider;
+
+/// Reports free space of local filesystems
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LocalDiskSpace;
+
+impl LocalDiskSpace {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl DiskSpaceProvider for LocalDiskSpace {
+    fn filesystem_space(&self, path: &Path) -> Result<FilesystemSpace, Error> {
+        // Directories such as the patch cache may not have been created yet
+        let existing = path
+            .ancestors()
+            .find(|ancestor| ancestor.exists())
+            .ok_or_else(|| Error::Other(format!("no existing parent for {:?}", path)))?;
+
+        let stats = rustix::fs::statvfs(existing).map_err(std::io::Error::from)?;
+        let device = std::fs::metadata(existing)?.dev();
+
+        Ok(FilesystemSpace {
+            device,
+            available_bytes: stats.f_bavail.saturating_mul(stats.f_frsize),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_filesystem_space_of_missing_path() {
+        let dir = tempdir().unwrap();
+        let missing = dir.path().join("patches/boot");
+
+        let provider = LocalDiskSpace::new();
+        let space = provider.filesystem_space(&missing).unwrap();
+        assert_eq!(space, provider.filesystem_space(dir.path()).unwrap());
+        assert!(space.available_bytes > 0);
+    }
+}
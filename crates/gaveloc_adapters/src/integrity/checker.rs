@@ -1,148 +1,248 @@
 //! GoatcorpIntegrityChecker implementation
 //!
-//! Verifies game file integrity against the goatcorp community manifest.
-//! Uses SHA1 hashes with parallel file checking via rayon.
+//! Verifies game file integrity against the goatcorp community manifest, or a
+//! mirror of it.
+//! Uses SHA1 hashes with parallel file checking via rayon. Damaged files are
+//! rebuilt by replaying their repository's patch history.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::io::Read;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, MutexGuard};
 use std::time::Duration;
 
 use async_trait::async_trait;
 use rayon::prelude::*;
 use sha1::{Digest, Sha1};
+use sha2::Sha256;
 
+use gaveloc_core::config::{IntegritySettings, PatchSettings};
+use gaveloc_core::control::UpdateControl;
 use gaveloc_core::entities::{
-    FileIntegrityResult, IntegrityManifest, IntegrityProgress, IntegrityStatus,
+    FileIntegrityResult, FileRepairResult, IntegrityCheckMode, IntegrityManifest,
+    IntegrityProgress, IntegrityStatus, PatchChain, RepairOutcome, Repository, SqPackFileDamage,
+    SqPackRepairResult,
 };
 use gaveloc_core::error::Error;
-use gaveloc_core::ports::IntegrityChecker;
+use gaveloc_core::ports::{
+    IntegrityChecker, ManifestSource, PatchDownloader, PatchHistory, VersionRepository,
+};
+use gaveloc_core::use_cases::DiskSpaceEstimate;
+
+use super::deep::{check_sqpack_parallel, install_rebuilt_file};
+use super::hash_cache::{FileStamp, InstallHashes};
+use super::sources::{manifest_source, HttpManifestSource};
+use crate::configuration::patch_dir;
+use crate::fs::LocalDiskSpace;
+use crate::patch::{FileVersionRepository, HttpPatchDownloader};
+use crate::zipatch::ZiPatchParser;
 
-const MANIFEST_BASE_URL: &str = "https://goatcorp.github.io/integrity";
-const MANIFEST_TIMEOUT: Duration = Duration::from_secs(30);
-const MAX_RETRIES: u32 = 3;
 const CACHE_TTL_SECS: u64 = 86400; // 24 hours
+/// Directory inside the installation where files are rebuilt
+const REPAIR_STAGING_DIR: &str = ".gaveloc_repair";
 
 /// Integrity checker using goatcorp community manifest
 pub struct GoatcorpIntegrityChecker {
     client: reqwest::Client,
+    /// Tried in order until one has the manifest
+    sources: Vec<Box<dyn ManifestSource>>,
+    /// Expected SHA-256 of the manifest JSON, by game version
+    pinned_manifests: HashMap<String, String>,
+    cache_dir: PathBuf,
+    /// Where patches downloaded for repairs are kept while they are replayed
+    patch_dir: PathBuf,
+    /// Rate cap and download window for repair downloads
+    patch_settings: PatchSettings,
 }
 
 impl GoatcorpIntegrityChecker {
-    /// Create a new integrity checker with the given HTTP client
+    /// Create a new integrity checker reading goatcorp manifests with the given HTTP client
     pub fn new(client: reqwest::Client) -> Self {
-        Self { client }
+        Self {
+            sources: vec![Box::new(HttpManifestSource::goatcorp(client.clone()))],
+            client,
+            pinned_manifests: HashMap::new(),
+            cache_dir: default_cache_dir(),
+            patch_dir: patch_dir(&Default::default()),
+            patch_settings: PatchSettings::default(),
+        }
     }
 
     /// Create a new integrity checker with default HTTP client
     pub fn with_default_client() -> Self {
-        Self {
-            client: reqwest::Client::new(),
+        Self::new(reqwest::Client::new())
+    }
+
+    /// Apply the configured manifest sources, pins and cache directory
+    pub fn with_integrity_settings(mut self, settings: &IntegritySettings) -> Self {
+        self.sources = settings
+            .manifest_sources
+            .iter()
+            .map(|source| manifest_source(source, &self.client))
+            .collect();
+        self.pinned_manifests = settings
+            .pinned_manifests
+            .iter()
+            .map(|(version, hash)| (version.clone(), hash.to_lowercase()))
+            .collect();
+        if let Some(cache_dir) = &settings.manifest_cache_dir {
+            self.cache_dir = cache_dir.clone();
         }
+        self
     }
 
-    /// Get the cache file path for a manifest version
-    fn cache_path(game_version: &str) -> PathBuf {
-        directories::ProjectDirs::from("com", "gaveloc", "gaveloc")
-            .map(|d| {
-                d.cache_dir()
-                    .join("manifests")
-                    .join(format!("{}.json", game_version))
-            })
-            .unwrap_or_else(|| PathBuf::from(format!("/tmp/gaveloc_manifest_{}.json", game_version)))
+    /// Read manifests from `sources`, in priority order
+    pub fn with_sources(mut self, sources: Vec<Box<dyn ManifestSource>>) -> Self {
+        self.sources = sources;
+        self
     }
 
-    /// Fetch manifest from network with retry logic
-    async fn fetch_manifest_from_network(
-        &self,
-        game_version: &str,
-    ) -> Result<IntegrityManifest, Error> {
-        let url = format!("{}/{}.json", MANIFEST_BASE_URL, game_version);
+    /// Only accept the manifest for `game_version` if its JSON has this SHA-256
+    pub fn with_pinned_manifest(mut self, game_version: &str, sha256: &str) -> Self {
+        self.pinned_manifests
+            .insert(game_version.to_string(), sha256.to_lowercase());
+        self
+    }
 
-        for attempt in 0..MAX_RETRIES {
-            let result = tokio::time::timeout(MANIFEST_TIMEOUT, self.client.get(&url).send()).await;
+    /// Cache fetched manifests in `cache_dir`
+    pub fn with_cache_dir(mut self, cache_dir: impl Into<PathBuf>) -> Self {
+        self.cache_dir = cache_dir.into();
+        self
+    }
 
-            match result {
-                Ok(Ok(response)) => {
-                    if response.status() == reqwest::StatusCode::NOT_FOUND {
-                        return Err(Error::IntegrityManifestNotFound(game_version.to_string()));
-                    }
-                    if response.status().is_server_error() && attempt < MAX_RETRIES - 1 {
-                        tokio::time::sleep(Duration::from_secs(2u64.pow(attempt))).await;
-                        continue;
-                    }
-                    if !response.status().is_success() {
-                        return Err(Error::Network(format!(
-                            "manifest request failed: {}",
-                            response.status()
-                        )));
-                    }
-                    return response.json().await.map_err(|e| {
-                        Error::Network(format!("failed to parse integrity manifest: {}", e))
-                    });
-                }
-                Ok(Err(e)) => {
-                    if attempt < MAX_RETRIES - 1 {
-                        tokio::time::sleep(Duration::from_secs(2u64.pow(attempt))).await;
-                        continue;
-                    }
-                    return Err(Error::Network(format!(
-                        "failed to fetch integrity manifest: {}",
-                        e
-                    )));
-                }
-                Err(_) => {
-                    if attempt < MAX_RETRIES - 1 {
-                        continue;
-                    }
-                    return Err(Error::Network("manifest fetch timeout".into()));
-                }
+    /// Download the patches replayed by repairs to `patch_dir`
+    pub fn with_patch_dir(mut self, patch_dir: impl Into<PathBuf>) -> Self {
+        self.patch_dir = patch_dir.into();
+        self
+    }
+
+    /// Download the patches replayed by repairs with the rate cap and download
+    /// window from `settings`
+    pub fn with_patch_settings(mut self, settings: &PatchSettings) -> Self {
+        self.patch_settings = settings.clone();
+        self
+    }
+
+    /// Get the cache file path for a manifest version read from `source`
+    ///
+    /// Each source has its own cache file, so a manifest from one source is
+    /// never served once it is no longer configured.
+    fn cache_path(&self, game_version: &str, source: &dyn ManifestSource) -> PathBuf {
+        let source_hash = hex::encode(Sha256::digest(source.describe().as_bytes()));
+        self.cache_dir
+            .join(format!("{}.{}.json", game_version, &source_hash[..16]))
+    }
+
+    /// Check a manifest against its pinned checksum and parse it
+    fn parse_manifest(&self, game_version: &str, data: &[u8]) -> Result<IntegrityManifest, Error> {
+        if let Some(expected) = self.pinned_manifests.get(game_version) {
+            let actual = hex::encode(Sha256::digest(data));
+            if actual != *expected {
+                return Err(Error::IntegrityMismatch(format!(
+                    "manifest for {} has SHA-256 {}, expected {}",
+                    game_version, actual, expected
+                )));
             }
         }
-        unreachable!()
+
+        serde_json::from_slice(data)
+            .map_err(|e| Error::Other(format!("failed to parse integrity manifest: {}", e)))
     }
+
+    /// Cached manifest from the first source that has one that is fresh
+    /// (24-hour TTL) and still passes its pin
+    async fn cached_manifest(&self, game_version: &str) -> Option<IntegrityManifest> {
+        for source in &self.sources {
+            if let Some(manifest) = self
+                .cached_manifest_from(game_version, source.as_ref())
+                .await
+            {
+                return Some(manifest);
+            }
+        }
+        None
+    }
+
+    async fn cached_manifest_from(
+        &self,
+        game_version: &str,
+        source: &dyn ManifestSource,
+    ) -> Option<IntegrityManifest> {
+        let cache_path = self.cache_path(game_version, source);
+        let modified = tokio::fs::metadata(&cache_path)
+            .await
+            .ok()?
+            .modified()
+            .ok()?;
+        if modified.elapsed().unwrap_or(Duration::MAX) >= Duration::from_secs(CACHE_TTL_SECS) {
+            return None;
+        }
+
+        let data = tokio::fs::read(&cache_path).await.ok()?;
+        let mut manifest = self.parse_manifest(game_version, &data).ok()?;
+        manifest.source = Some(format!(
+            "cached copy of {} at {}",
+            source.describe(),
+            cache_path.display()
+        ));
+        Some(manifest)
+    }
+}
+
+/// Per-user cache directory for manifests
+fn default_cache_dir() -> PathBuf {
+    directories::ProjectDirs::from("com", "gaveloc", "gaveloc")
+        .map(|d| d.cache_dir().join("manifests"))
+        .unwrap_or_else(|| std::env::temp_dir().join("gaveloc_manifests"))
 }
 
 #[async_trait]
 impl IntegrityChecker for GoatcorpIntegrityChecker {
     async fn fetch_manifest(&self, game_version: &str) -> Result<IntegrityManifest, Error> {
-        let cache_path = Self::cache_path(game_version);
-
-        // Check cache (24-hour TTL)
-        if let Ok(metadata) = tokio::fs::metadata(&cache_path).await {
-            if let Ok(modified) = metadata.modified() {
-                if modified.elapsed().unwrap_or(Duration::MAX)
-                    < Duration::from_secs(CACHE_TTL_SECS)
-                {
-                    if let Ok(data) = tokio::fs::read_to_string(&cache_path).await {
-                        if let Ok(manifest) = serde_json::from_str(&data) {
-                            return Ok(manifest);
-                        }
-                    }
-                }
-            }
+        if let Some(manifest) = self.cached_manifest(game_version).await {
+            return Ok(manifest);
         }
 
-        // Fetch from network
-        let manifest = self.fetch_manifest_from_network(game_version).await?;
+        // A source failing or serving a manifest that does not match its pin
+        // falls through to the next one
+        let mut error = None;
+        for source in &self.sources {
+            let result = match source.fetch_manifest(game_version).await {
+                Ok(data) => self
+                    .parse_manifest(game_version, &data)
+                    .map(|manifest| (data, manifest)),
+                Err(e) => Err(e),
+            };
 
-        // Save to cache
-        if let Some(parent) = cache_path.parent() {
-            tokio::fs::create_dir_all(parent).await.ok();
-        }
-        if let Ok(json) = serde_json::to_string(&manifest) {
-            tokio::fs::write(&cache_path, json).await.ok();
+            match result {
+                Ok((data, mut manifest)) => {
+                    // Save the manifest as served, so the pin still applies
+                    tokio::fs::create_dir_all(&self.cache_dir).await.ok();
+                    tokio::fs::write(self.cache_path(game_version, source.as_ref()), data)
+                        .await
+                        .ok();
+                    manifest.source = Some(source.describe());
+                    return Ok(manifest);
+                }
+                Err(Error::IntegrityManifestNotFound(_)) => {
+                    tracing::debug!("no manifest for {} in {}", game_version, source.describe());
+                }
+                Err(e) => {
+                    tracing::warn!("manifest source {} failed: {}", source.describe(), e);
+                    error.get_or_insert(e);
+                }
+            }
         }
 
-        Ok(manifest)
+        Err(error.unwrap_or_else(|| Error::IntegrityManifestNotFound(game_version.to_string())))
     }
 
     async fn check_integrity<F>(
         &self,
         game_path: &Path,
         manifest: &IntegrityManifest,
+        mode: IntegrityCheckMode,
         progress: F,
     ) -> Result<Vec<FileIntegrityResult>, Error>
     where
@@ -155,62 +255,491 @@ impl IntegrityChecker for GoatcorpIntegrityChecker {
 
         // Run CPU-bound hashing in blocking task with rayon
         tokio::task::spawn_blocking(move || {
-            check_files_parallel(&game_path, &hashes, progress, &cancelled)
+            // A full check still records the hashes for the next incremental one
+            let hash_cache = Mutex::new(match mode {
+                IntegrityCheckMode::Incremental => InstallHashes::load(&game_path),
+                IntegrityCheckMode::Full => InstallHashes::empty(&game_path),
+            });
+            let results =
+                check_files_parallel(&game_path, &hashes, Some(&hash_cache), progress, &cancelled);
+            lock(&hash_cache).save();
+            results
         })
         .await
         .map_err(|e| Error::Other(format!("integrity check task panicked: {}", e)))?
     }
 
+    async fn generate_manifest<F>(
+        &self,
+        game_path: &Path,
+        game_version: &str,
+        progress: F,
+    ) -> Result<IntegrityManifest, Error>
+    where
+        F: Fn(IntegrityProgress) + Send + Sync + 'static,
+    {
+        let game_path = game_path.to_path_buf();
+        let game_version = game_version.to_string();
+        let progress = Arc::new(progress);
+        let cancelled = Arc::new(AtomicBool::new(false));
+
+        tokio::task::spawn_blocking(move || {
+            // Without expected hashes the check just records what it reads
+            let files: HashMap<String, String> = list_install_files(&game_path)?
+                .into_iter()
+                .map(|path| (path, String::new()))
+                .collect();
+            let results = check_files_parallel(&game_path, &files, None, progress, &cancelled)?;
+
+            let mut hashes = HashMap::with_capacity(results.len());
+            for result in results {
+                let hash = result.actual_hash.ok_or_else(|| {
+                    Error::Other(format!(
+                        "cannot hash {}: {}",
+                        result.relative_path, result.status
+                    ))
+                })?;
+                hashes.insert(result.relative_path, format_manifest_hash(&hash));
+            }
+
+            Ok(IntegrityManifest {
+                hashes,
+                game_version,
+                last_game_version: None,
+                source: None,
+            })
+        })
+        .await
+        .map_err(|e| Error::Other(format!("manifest generation task panicked: {}", e)))?
+    }
+
     async fn repair_file(
         &self,
         game_path: &Path,
+        manifest: &IntegrityManifest,
         relative_path: &str,
-        _expected_hash: &str,
-    ) -> Result<(), Error> {
-        // Simple strategy: delete the corrupted file
-        // The patching system will restore it on next update check
-        let file_path = normalize_path(game_path, relative_path)?;
-
-        if file_path.exists() {
-            tokio::fs::remove_file(&file_path)
-                .await
-                .map_err(Error::Io)?;
-        }
-
-        Ok(())
+        history: &dyn PatchHistory,
+        control: &UpdateControl,
+    ) -> Result<FileRepairResult, Error> {
+        let mut results = self
+            .repair_paths(game_path, manifest, &[relative_path], history, control)
+            .await;
+        Ok(results.remove(0))
     }
 
     async fn repair_files(
         &self,
         game_path: &Path,
+        manifest: &IntegrityManifest,
         files: &[FileIntegrityResult],
-    ) -> Result<(u32, u32), Error> {
+        history: &dyn PatchHistory,
+        control: &UpdateControl,
+    ) -> Result<Vec<FileRepairResult>, Error> {
+        let paths: Vec<&str> = files.iter().map(|f| f.relative_path.as_str()).collect();
+        Ok(self
+            .repair_paths(game_path, manifest, &paths, history, control)
+            .await)
+    }
+
+    async fn check_sqpack_files<F>(
+        &self,
+        game_path: &Path,
+        progress: F,
+    ) -> Result<Vec<SqPackFileDamage>, Error>
+    where
+        F: Fn(IntegrityProgress) + Send + Sync + 'static,
+    {
         let game_path = game_path.to_path_buf();
-        let paths: Vec<PathBuf> = files
+        let progress = Arc::new(progress);
+        let cancelled = Arc::new(AtomicBool::new(false));
+
+        tokio::task::spawn_blocking(move || check_sqpack_parallel(&game_path, progress, &cancelled))
+            .await
+            .map_err(|e| Error::Other(format!("SqPack check task panicked: {}", e)))?
+    }
+
+    async fn repair_sqpack_files(
+        &self,
+        game_path: &Path,
+        files: &[SqPackFileDamage],
+        history: &dyn PatchHistory,
+        control: &UpdateControl,
+    ) -> Result<Vec<SqPackRepairResult>, Error> {
+        let mut outcomes: Vec<Option<RepairOutcome>> = vec![None; files.len()];
+        // Index of each file and its dat file, relative to the repository
+        let mut by_repository: HashMap<Repository, Vec<(usize, String)>> = HashMap::new();
+
+        for (i, file) in files.iter().enumerate() {
+            match repository_path(&file.dat_path) {
+                Ok((repository, patch_path)) => by_repository
+                    .entry(repository)
+                    .or_default()
+                    .push((i, patch_path)),
+                Err(e) => {
+                    outcomes[i] = Some(RepairOutcome::Failed {
+                        message: e.to_string(),
+                    })
+                }
+            }
+        }
+
+        for (repository, targets) in by_repository {
+            let dat_files = targets.iter().map(|(_, path)| path.clone()).collect();
+            let result = self
+                .rebuild_repository(
+                    game_path,
+                    repository,
+                    dat_files,
+                    history,
+                    control,
+                    |staging, install_path, touched| {
+                        targets
+                            .iter()
+                            .map(|(i, path)| {
+                                let outcome = install_rebuilt_file(
+                                    staging,
+                                    install_path,
+                                    path,
+                                    &files[*i],
+                                    touched,
+                                );
+                                (*i, outcome)
+                            })
+                            .collect::<Vec<_>>()
+                    },
+                )
+                .await;
+
+            match result {
+                Ok(repaired) => {
+                    for (i, outcome) in repaired {
+                        outcomes[i] = Some(outcome);
+                    }
+                }
+                Err(e) => {
+                    tracing::warn!("failed to repair {} SqPack files: {}", repository, e);
+                    for (i, _) in &targets {
+                        outcomes[*i] = Some(RepairOutcome::Failed {
+                            message: e.to_string(),
+                        });
+                    }
+                }
+            }
+        }
+        tokio::fs::remove_dir(game_path.join(REPAIR_STAGING_DIR))
+            .await
+            .ok();
+
+        Ok(files
             .iter()
-            .filter_map(|f| normalize_path(&game_path, &f.relative_path).ok())
-            .collect();
+            .zip(outcomes)
+            .map(|(file, outcome)| SqPackRepairResult {
+                file: file.clone(),
+                outcome: outcome.unwrap_or(RepairOutcome::NotInPatches),
+            })
+            .collect())
+    }
+}
+
+/// A file to rebuild, located both in the manifest and in its repository
+struct RepairTarget {
+    /// Path as listed in the manifest
+    relative_path: String,
+    /// Path relative to the repository's install directory, as patches name it
+    patch_path: String,
+    /// Manifest hash, normalized
+    expected_hash: String,
+}
+
+impl RepairTarget {
+    fn new(manifest: &IntegrityManifest, relative_path: &str) -> Result<(Repository, Self), Error> {
+        let expected_hash = manifest
+            .hashes
+            .get(relative_path)
+            .map(|hash| normalize_manifest_hash(hash))
+            .ok_or_else(|| {
+                Error::Other(format!(
+                    "{} is not in the integrity manifest",
+                    relative_path
+                ))
+            })?;
+
+        let (repository, patch_path) = repository_path(relative_path)?;
+        Ok((
+            repository,
+            Self {
+                relative_path: relative_path.to_string(),
+                patch_path,
+                expected_hash,
+            },
+        ))
+    }
+}
+
+/// Repository of a manifest path, and the path relative to the repository's
+/// install directory, as patches name it
+fn repository_path(relative_path: &str) -> Result<(Repository, String), Error> {
+    // Also rejects traversal attempts
+    let path = normalize_path(Path::new(""), relative_path)?;
+    let repository = Repository::for_game_file(relative_path);
+    let patch_path = path
+        .strip_prefix(repository.install_dir())
+        .map_err(|_| {
+            Error::Other(format!(
+                "{} is outside the {} directory",
+                relative_path,
+                repository.install_dir()
+            ))
+        })?
+        .to_string_lossy()
+        .into_owned();
+
+    Ok((repository, patch_path))
+}
 
-        // Run deletions in parallel using rayon
-        let results: Vec<bool> = tokio::task::spawn_blocking(move || {
-            paths
-                .par_iter()
-                .map(|path| {
-                    if path.exists() {
-                        std::fs::remove_file(path).is_ok()
-                    } else {
-                        true // Already deleted counts as success
+impl GoatcorpIntegrityChecker {
+    /// Rebuild files given by manifest path, one result per path in order
+    ///
+    /// Failures are reported per file, so this never fails as a whole.
+    async fn repair_paths(
+        &self,
+        game_path: &Path,
+        manifest: &IntegrityManifest,
+        paths: &[&str],
+        history: &dyn PatchHistory,
+        control: &UpdateControl,
+    ) -> Vec<FileRepairResult> {
+        let mut outcomes: HashMap<String, RepairOutcome> = HashMap::new();
+        let mut by_repository: HashMap<Repository, Vec<RepairTarget>> = HashMap::new();
+
+        for path in paths {
+            match RepairTarget::new(manifest, path) {
+                Ok((repository, target)) => {
+                    by_repository.entry(repository).or_default().push(target)
+                }
+                Err(e) => {
+                    outcomes.insert(
+                        path.to_string(),
+                        RepairOutcome::Failed {
+                            message: e.to_string(),
+                        },
+                    );
+                }
+            }
+        }
+
+        // Each repository's chain is replayed once for all of its files
+        for (repository, targets) in by_repository {
+            match self
+                .repair_repository(game_path, repository, &targets, history, control)
+                .await
+            {
+                Ok(repaired) => outcomes.extend(repaired),
+                Err(e) => {
+                    tracing::warn!("failed to repair {} files: {}", repository, e);
+                    for target in targets {
+                        outcomes.insert(
+                            target.relative_path,
+                            RepairOutcome::Failed {
+                                message: e.to_string(),
+                            },
+                        );
                     }
-                })
-                .collect()
-        })
+                }
+            }
+        }
+        tokio::fs::remove_dir(game_path.join(REPAIR_STAGING_DIR))
+            .await
+            .ok();
+
+        paths
+            .iter()
+            .map(|path| FileRepairResult {
+                relative_path: path.to_string(),
+                outcome: outcomes
+                    .get(*path)
+                    .cloned()
+                    .unwrap_or(RepairOutcome::NotInPatches),
+            })
+            .collect()
+    }
+
+    /// Rebuild files of one repository by replaying its patch chain into a
+    /// staging directory, and put the ones that verify in place
+    async fn repair_repository(
+        &self,
+        game_path: &Path,
+        repository: Repository,
+        targets: &[RepairTarget],
+        history: &dyn PatchHistory,
+        control: &UpdateControl,
+    ) -> Result<HashMap<String, RepairOutcome>, Error> {
+        let files = targets.iter().map(|t| t.patch_path.clone()).collect();
+        self.rebuild_repository(
+            game_path,
+            repository,
+            files,
+            history,
+            control,
+            |staging, install_path, touched| {
+                install_rebuilt(staging, install_path, targets, touched)
+            },
+        )
         .await
-        .map_err(|e| Error::Other(format!("repair task panicked: {}", e)))?;
+    }
+
+    /// Replay the patch chain of `repository` into a staging directory,
+    /// writing only `files` (relative to the repository's install directory)
+    ///
+    /// `install` gets the staging and install directories and the files the
+    /// chain wrote, before the staging directory is removed.
+    async fn rebuild_repository<T>(
+        &self,
+        game_path: &Path,
+        repository: Repository,
+        files: HashSet<String>,
+        history: &dyn PatchHistory,
+        control: &UpdateControl,
+        install: impl FnOnce(&Path, &Path, &HashSet<String>) -> T,
+    ) -> Result<T, Error> {
+        let version = FileVersionRepository::new()
+            .get_version(game_path, repository)
+            .await?;
+        let chain = history.patch_chain(repository, &version).await?;
+        let install_path = game_path.join(repository.install_dir());
+
+        // Patches are removed once replayed, so only the largest is on disk at once
+        let estimate = DiskSpaceEstimate {
+            patch_dir_bytes: chain.patches.iter().map(|p| p.length).max().unwrap_or(0),
+            game_dir_bytes: staged_size(&install_path, &files).await,
+        };
+        estimate.ensure_fits(&LocalDiskSpace::new(), &self.patch_dir, game_path)?;
+
+        // Staging inside the installation keeps the final rename on one filesystem
+        let staging = game_path
+            .join(REPAIR_STAGING_DIR)
+            .join(repository.patch_id());
+        if staging.exists() {
+            tokio::fs::remove_dir_all(&staging).await?;
+        }
+        tokio::fs::create_dir_all(&staging).await?;
+        tokio::fs::create_dir_all(&self.patch_dir).await?;
+
+        let result = self
+            .replay_chain(&chain, &staging, files, control)
+            .await
+            .map(|touched| install(&staging, &install_path, &touched));
+
+        tokio::fs::remove_dir_all(&staging).await.ok();
+        result
+    }
+
+    /// Download and replay each patch of a chain, returning the files it wrote
+    async fn replay_chain(
+        &self,
+        chain: &PatchChain,
+        staging: &Path,
+        files: HashSet<String>,
+        control: &UpdateControl,
+    ) -> Result<HashSet<String>, Error> {
+        let downloader = HttpPatchDownloader::new()?.with_patch_settings(&self.patch_settings);
+        let files = Arc::new(files);
+        let mut touched = HashSet::new();
+
+        for (index, patch) in chain.patches.iter().enumerate() {
+            let patch_path = self.patch_dir.join(
+                patch
+                    .filename()
+                    .unwrap_or(&format!("patch_{}.patch", index)),
+            );
+            tracing::info!("replaying {} for repair", patch.version_id);
+
+            downloader
+                .download_patch(
+                    patch,
+                    &patch_path,
+                    chain.unique_id.as_deref(),
+                    control,
+                    |_, _| {},
+                )
+                .await?;
+
+            let replayed = {
+                let patch_path = patch_path.clone();
+                let staging = staging.to_path_buf();
+                let files = files.clone();
+                let control = control.clone();
+                tokio::task::spawn_blocking(move || {
+                    ZiPatchParser::new().replay_files(&patch_path, &staging, &files, &control)
+                })
+                .await
+                .map_err(|e| Error::Other(format!("repair task panicked: {}", e)))?
+            };
+            tokio::fs::remove_file(&patch_path).await.ok();
+            touched.extend(replayed?);
+        }
+
+        Ok(touched)
+    }
+}
 
-        let success = results.iter().filter(|&&r| r).count() as u32;
-        let failed = results.len() as u32 - success;
-        Ok((success, failed))
+/// Space the rebuilt copies of `files` take in the staging directory
+///
+/// Each is assumed to be as large as the damaged file it replaces; missing
+/// files cannot be sized before they are rebuilt.
+async fn staged_size(install_path: &Path, files: &HashSet<String>) -> u64 {
+    let mut size = 0;
+    for file in files {
+        if let Ok(metadata) = tokio::fs::metadata(install_path.join(file)).await {
+            size += metadata.len();
+        }
     }
+    size
+}
+
+/// Check rebuilt files against the manifest and move the matching ones over
+/// the damaged files
+fn install_rebuilt(
+    staging: &Path,
+    install_path: &Path,
+    targets: &[RepairTarget],
+    touched: &HashSet<String>,
+) -> HashMap<String, RepairOutcome> {
+    targets
+        .iter()
+        .map(|target| {
+            let outcome = if !touched.contains(&target.patch_path) {
+                RepairOutcome::NotInPatches
+            } else {
+                let rebuilt = staging.join(&target.patch_path);
+                match compute_file_hash(&rebuilt) {
+                    Ok(actual) if actual == target.expected_hash => {
+                        let dest = install_path.join(&target.patch_path);
+                        let installed = dest
+                            .parent()
+                            .map_or(Ok(()), std::fs::create_dir_all)
+                            .and_then(|_| std::fs::rename(&rebuilt, &dest));
+                        match installed {
+                            Ok(()) => RepairOutcome::Repaired,
+                            Err(e) => RepairOutcome::Failed {
+                                message: format!("failed to replace file: {}", e),
+                            },
+                        }
+                    }
+                    Ok(actual) => RepairOutcome::StillMismatched {
+                        actual_hash: actual,
+                    },
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => RepairOutcome::Failed {
+                        message: "the patches delete this file".to_string(),
+                    },
+                    Err(e) => RepairOutcome::Failed {
+                        message: e.to_string(),
+                    },
+                }
+            };
+            (target.relative_path.clone(), outcome)
+        })
+        .collect()
 }
 
 /// Convert manifest hash format to comparable format
@@ -220,6 +749,22 @@ fn normalize_manifest_hash(hash: &str) -> String {
     hash.split_whitespace().collect::<String>().to_lowercase()
 }
 
+/// Convert a hex hash to manifest format
+/// Input: "a0a1a2a3..." (lowercase hex)
+/// Output: "A0 A1 A2 A3..." (space-separated uppercase)
+fn format_manifest_hash(hex: &str) -> String {
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| String::from_utf8_lossy(pair).to_uppercase())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Lock the hash cache shared by the hashing threads
+fn lock(hash_cache: &Mutex<InstallHashes>) -> MutexGuard<'_, InstallHashes> {
+    hash_cache.lock().unwrap_or_else(|e| e.into_inner())
+}
+
 /// Compute SHA1 hash of a file using streaming to avoid OOM on large files
 fn compute_file_hash(path: &Path) -> Result<String, std::io::Error> {
     let file = std::fs::File::open(path)?;
@@ -257,10 +802,60 @@ fn normalize_path(game_path: &Path, manifest_path: &str) -> Result<PathBuf, Erro
     Ok(game_path.join(relative))
 }
 
+/// List the files of an installation as manifest paths
+/// Output: ["\boot\ffxivboot.exe", "\game\sqpack\ffxiv\000000.win32.dat0", ...]
+/// Hidden entries (like the repair staging directory) and version file
+/// backups are not part of the installation and are skipped.
+fn list_install_files(game_path: &Path) -> Result<Vec<String>, Error> {
+    let mut files = Vec::new();
+
+    for dir in [
+        Repository::Boot.install_dir(),
+        Repository::Ffxiv.install_dir(),
+    ] {
+        let root = game_path.join(dir);
+        if !root.exists() {
+            continue;
+        }
+
+        let walker = walkdir::WalkDir::new(root)
+            .into_iter()
+            .filter_entry(|entry| !entry.file_name().to_string_lossy().starts_with('.'));
+        for entry in walker {
+            let entry =
+                entry.map_err(|e| Error::Other(format!("failed to list game files: {}", e)))?;
+            if !entry.file_type().is_file()
+                || entry.path().extension().is_some_and(|ext| ext == "bck")
+            {
+                continue;
+            }
+
+            let relative = entry
+                .path()
+                .strip_prefix(game_path)
+                .map_err(|e| Error::Other(e.to_string()))?;
+            let components: Vec<_> = relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect();
+            files.push(format!("\\{}", components.join("\\")));
+        }
+    }
+
+    if files.is_empty() {
+        return Err(Error::Other(format!(
+            "no game files found in {}",
+            game_path.display()
+        )));
+    }
+    Ok(files)
+}
+
 /// Check files in parallel using rayon
 fn check_files_parallel(
     game_path: &Path,
     hashes: &HashMap<String, String>,
+    hash_cache: Option<&Mutex<InstallHashes>>,
     progress: Arc<dyn Fn(IntegrityProgress) + Send + Sync>,
     cancelled: &AtomicBool,
 ) -> Result<Vec<FileIntegrityResult>, Error> {
@@ -302,7 +897,32 @@ fn check_files_parallel(
                     status: IntegrityStatus::Missing,
                 }
             } else {
-                match compute_file_hash(&file_path) {
+                // Metadata is taken before reading, so a file changing while it
+                // is hashed does not match its cache entry afterwards
+                let stamp = std::fs::metadata(&file_path)
+                    .ok()
+                    .map(|m| FileStamp::of(&m));
+                let cached = match (hash_cache, &stamp) {
+                    (Some(cache), Some(stamp)) => lock(cache).get(manifest_path, stamp),
+                    _ => None,
+                };
+                let hashed = match cached {
+                    Some(hash) => Ok(hash),
+                    None => {
+                        let hashed = compute_file_hash(&file_path);
+                        if let Some(cache) = hash_cache {
+                            match (&hashed, stamp) {
+                                (Ok(hash), Some(stamp)) => {
+                                    lock(cache).insert(manifest_path, stamp, hash.clone())
+                                }
+                                _ => lock(cache).remove(manifest_path),
+                            }
+                        }
+                        hashed
+                    }
+                };
+
+                match hashed {
                     Ok(actual) => {
                         let status = if actual == expected_normalized {
                             IntegrityStatus::Valid
@@ -359,6 +979,12 @@ fn check_files_parallel(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::integrity::{DirectoryManifestSource, GOATCORP_MANIFEST_URL};
+    use crate::zipatch::ZiPatchWriter;
+    use gaveloc_core::config::ManifestSourceSettings;
+    use gaveloc_core::entities::{GameVersion, PatchEntry};
+    use gaveloc_core::ports::ZiPatchApplier;
+    use gaveloc_core::zipatch::{FileHeaderChunk, ZiPatchChunk};
     use tempfile::TempDir;
 
     #[test]
@@ -464,17 +1090,17 @@ mod tests {
         let mut hashes = HashMap::new();
         hashes.insert(
             r"\game\sqpack\test1.dat".to_string(),
-            format_as_manifest_hash(&hash1),
+            format_manifest_hash(&hash1),
         );
         hashes.insert(
             r"\game\sqpack\test2.dat".to_string(),
-            format_as_manifest_hash(&hash2),
+            format_manifest_hash(&hash2),
         );
 
         let progress = Arc::new(|_: IntegrityProgress| {});
         let cancelled = AtomicBool::new(false);
 
-        let results = check_files_parallel(game_path, &hashes, progress, &cancelled).unwrap();
+        let results = check_files_parallel(game_path, &hashes, None, progress, &cancelled).unwrap();
 
         assert_eq!(results.len(), 2);
         assert!(results.iter().all(|r| r.status == IntegrityStatus::Valid));
@@ -494,7 +1120,7 @@ mod tests {
         let progress = Arc::new(|_: IntegrityProgress| {});
         let cancelled = AtomicBool::new(false);
 
-        let results = check_files_parallel(game_path, &hashes, progress, &cancelled).unwrap();
+        let results = check_files_parallel(game_path, &hashes, None, progress, &cancelled).unwrap();
 
         assert_eq!(results.len(), 1);
         assert_eq!(results[0].status, IntegrityStatus::Missing);
@@ -518,7 +1144,7 @@ mod tests {
         let progress = Arc::new(|_: IntegrityProgress| {});
         let cancelled = AtomicBool::new(false);
 
-        let results = check_files_parallel(game_path, &hashes, progress, &cancelled).unwrap();
+        let results = check_files_parallel(game_path, &hashes, None, progress, &cancelled).unwrap();
 
         assert_eq!(results.len(), 1);
         assert_eq!(results[0].status, IntegrityStatus::Mismatch);
@@ -536,7 +1162,7 @@ mod tests {
         let progress = Arc::new(|_: IntegrityProgress| {});
         let cancelled = AtomicBool::new(true); // Already cancelled
 
-        let result = check_files_parallel(game_path, &hashes, progress, &cancelled);
+        let result = check_files_parallel(game_path, &hashes, None, progress, &cancelled);
 
         assert!(result.is_err());
         if let Err(Error::Cancelled) = result {
@@ -546,52 +1172,474 @@ mod tests {
         }
     }
 
-    /// Helper to format a lowercase hex hash as manifest format (space-separated uppercase)
-    fn format_as_manifest_hash(hex: &str) -> String {
-        hex.as_bytes()
-            .chunks(2)
-            .map(|chunk| {
-                let s = std::str::from_utf8(chunk).unwrap();
-                s.to_uppercase()
-            })
-            .collect::<Vec<_>>()
-            .join(" ")
+    /// Directory holding `manifest` as the manifest for `version`
+    fn manifest_dir(version: &str, manifest: &str) -> TempDir {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(dir.path().join(format!("{}.json", version)), manifest).unwrap();
+        dir
     }
 
+    const MANIFEST_JSON: &str = r#"{"Hashes":{"\\game\\ffxiv_dx11.exe":"AA BB"},"GameVersion":"2024.07.23.0000.0001","LastGameVersion":null}"#;
+
     #[tokio::test]
-    async fn test_repair_file_deletes_corrupted() {
-        let temp_dir = TempDir::new().unwrap();
-        let game_path = temp_dir.path();
+    async fn test_fetch_manifest_tries_sources_in_order() {
+        let cache = TempDir::new().unwrap();
+        let empty = TempDir::new().unwrap();
+        let mirror = manifest_dir("2024.07.23.0000.0001", MANIFEST_JSON);
+        let checker = GoatcorpIntegrityChecker::with_default_client()
+            .with_sources(vec![
+                Box::new(DirectoryManifestSource::new(empty.path())),
+                Box::new(DirectoryManifestSource::new(mirror.path())),
+            ])
+            .with_cache_dir(cache.path());
+
+        let manifest = checker
+            .fetch_manifest("2024.07.23.0000.0001")
+            .await
+            .unwrap();
+        assert_eq!(manifest.hashes[r"\game\ffxiv_dx11.exe"], "AA BB");
+        assert_eq!(
+            manifest.source.as_deref(),
+            Some(mirror.path().to_str().unwrap())
+        );
+        // Cached as served
+        let mirror_source = DirectoryManifestSource::new(mirror.path());
+        assert_eq!(
+            std::fs::read_to_string(checker.cache_path("2024.07.23.0000.0001", &mirror_source))
+                .unwrap(),
+            MANIFEST_JSON
+        );
+        let cached = checker
+            .fetch_manifest("2024.07.23.0000.0001")
+            .await
+            .unwrap();
+        assert!(cached.source.unwrap().starts_with("cached copy of"));
 
-        // Create test file
-        std::fs::create_dir_all(game_path.join("game")).unwrap();
-        let file_path = game_path.join("game/corrupted.dat");
-        std::fs::write(&file_path, "corrupted content").unwrap();
+        let err = checker
+            .fetch_manifest("2024.08.01.0000.0000")
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::IntegrityManifestNotFound(_)));
+    }
 
-        assert!(file_path.exists());
+    #[tokio::test]
+    async fn test_fetch_manifest_enforces_pinned_checksum() {
+        let cache = TempDir::new().unwrap();
+        let tampered = manifest_dir(
+            "2024.07.23.0000.0001",
+            &MANIFEST_JSON.replace("AA BB", "CC DD"),
+        );
+        let genuine = manifest_dir("2024.07.23.0000.0001", MANIFEST_JSON);
+        let pin = hex::encode(Sha256::digest(MANIFEST_JSON.as_bytes()));
+
+        // The tampered copy is skipped in favour of the next source
+        let checker = GoatcorpIntegrityChecker::with_default_client()
+            .with_sources(vec![
+                Box::new(DirectoryManifestSource::new(tampered.path())),
+                Box::new(DirectoryManifestSource::new(genuine.path())),
+            ])
+            .with_pinned_manifest("2024.07.23.0000.0001", &pin.to_uppercase())
+            .with_cache_dir(cache.path());
+        let manifest = checker
+            .fetch_manifest("2024.07.23.0000.0001")
+            .await
+            .unwrap();
+        assert_eq!(manifest.hashes[r"\game\ffxiv_dx11.exe"], "AA BB");
+
+        // Only a tampered copy: the mismatch is reported, and nothing is cached
+        let cache = TempDir::new().unwrap();
+        let checker = GoatcorpIntegrityChecker::with_default_client()
+            .with_sources(vec![Box::new(DirectoryManifestSource::new(
+                tampered.path(),
+            ))])
+            .with_pinned_manifest("2024.07.23.0000.0001", &pin)
+            .with_cache_dir(cache.path());
+        let err = checker
+            .fetch_manifest("2024.07.23.0000.0001")
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::IntegrityMismatch(_)));
+        assert!(!checker
+            .cache_path(
+                "2024.07.23.0000.0001",
+                &DirectoryManifestSource::new(tampered.path())
+            )
+            .exists());
+    }
 
-        let checker = GoatcorpIntegrityChecker::with_default_client();
-        checker
-            .repair_file(game_path, r"\game\corrupted.dat", "expected_hash")
+    #[tokio::test]
+    async fn test_cached_manifest_requires_its_source() {
+        let cache = TempDir::new().unwrap();
+        let empty = TempDir::new().unwrap();
+        let mirror = manifest_dir("2024.07.23.0000.0001", MANIFEST_JSON);
+        GoatcorpIntegrityChecker::with_default_client()
+            .with_sources(vec![Box::new(DirectoryManifestSource::new(mirror.path()))])
+            .with_cache_dir(cache.path())
+            .fetch_manifest("2024.07.23.0000.0001")
             .await
             .unwrap();
 
-        assert!(!file_path.exists());
+        // Another source is configured now, so the mirror's copy is not served
+        let err = GoatcorpIntegrityChecker::with_default_client()
+            .with_sources(vec![Box::new(DirectoryManifestSource::new(empty.path()))])
+            .with_cache_dir(cache.path())
+            .fetch_manifest("2024.07.23.0000.0001")
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::IntegrityManifestNotFound(_)));
+    }
+
+    #[test]
+    fn test_with_integrity_settings() {
+        let settings = IntegritySettings {
+            manifest_sources: vec![
+                ManifestSourceSettings::Directory {
+                    path: PathBuf::from("/srv/manifests"),
+                },
+                ManifestSourceSettings::Goatcorp,
+            ],
+            pinned_manifests: [("2024.07.23.0000.0001".to_string(), "ABC".to_string())].into(),
+            manifest_cache_dir: Some(PathBuf::from("/cache")),
+        };
+
+        let checker =
+            GoatcorpIntegrityChecker::with_default_client().with_integrity_settings(&settings);
+        let sources: Vec<_> = checker.sources.iter().map(|s| s.describe()).collect();
+        assert_eq!(sources, vec!["/srv/manifests", GOATCORP_MANIFEST_URL]);
+        assert_eq!(checker.pinned_manifests["2024.07.23.0000.0001"], "abc");
+        assert_eq!(checker.cache_dir, PathBuf::from("/cache"));
     }
 
     #[tokio::test]
-    async fn test_repair_file_missing_is_ok() {
+    async fn test_generate_manifest_hashes_install() {
         let temp_dir = TempDir::new().unwrap();
         let game_path = temp_dir.path();
+        std::fs::create_dir_all(game_path.join("boot")).unwrap();
+        std::fs::create_dir_all(game_path.join("game/sqpack/ffxiv")).unwrap();
+        std::fs::create_dir_all(game_path.join("game").join(REPAIR_STAGING_DIR)).unwrap();
+        std::fs::write(game_path.join("boot/ffxivboot.exe"), "boot").unwrap();
+        std::fs::write(game_path.join("game/ffxivgame.ver"), "2024.07.23.0000.0001").unwrap();
+        std::fs::write(game_path.join("game/ffxivgame.bck"), "2024.07.01.0000.0000").unwrap();
+        std::fs::write(
+            game_path.join("game/sqpack/ffxiv/000000.win32.index"),
+            "index",
+        )
+        .unwrap();
+        std::fs::write(
+            game_path.join("game").join(REPAIR_STAGING_DIR).join("x"),
+            "staged",
+        )
+        .unwrap();
 
         let checker = GoatcorpIntegrityChecker::with_default_client();
+        let manifest = checker
+            .generate_manifest(game_path, "2024.07.23.0000.0001", |_| {})
+            .await
+            .unwrap();
+
+        let mut paths: Vec<_> = manifest.hashes.keys().map(String::as_str).collect();
+        paths.sort();
+        assert_eq!(
+            paths,
+            vec![
+                r"\boot\ffxivboot.exe",
+                r"\game\ffxivgame.ver",
+                r"\game\sqpack\ffxiv\000000.win32.index"
+            ]
+        );
+        assert_eq!(
+            manifest.hashes[r"\boot\ffxivboot.exe"],
+            format_manifest_hash(&hex::encode(Sha1::digest(b"boot")))
+        );
+        assert_eq!(manifest.game_version, "2024.07.23.0000.0001");
+
+        // Same format as goatcorp manifests, and the install checks clean
+        let json = serde_json::to_value(&manifest).unwrap();
+        assert!(json["Hashes"].is_object());
+        assert_eq!(json["GameVersion"], "2024.07.23.0000.0001");
+        let results = checker
+            .check_integrity(game_path, &manifest, IntegrityCheckMode::Full, |_| {})
+            .await
+            .unwrap();
+        assert!(results.iter().all(|r| r.status == IntegrityStatus::Valid));
+    }
+
+    #[tokio::test]
+    async fn test_generate_manifest_empty_install_fails() {
+        let temp_dir = TempDir::new().unwrap();
 
-        // Should not error if file doesn't exist
+        let checker = GoatcorpIntegrityChecker::with_default_client();
         let result = checker
-            .repair_file(game_path, r"\game\nonexistent.dat", "expected_hash")
+            .generate_manifest(temp_dir.path(), "2024.07.23.0000.0001", |_| {})
             .await;
+        assert!(result.is_err());
+    }
+
+    /// Patch history serving a fixed chain, or failing like a missing session
+    struct FakeHistory(Result<PatchChain, String>);
+
+    #[async_trait]
+    impl PatchHistory for FakeHistory {
+        async fn patch_chain(
+            &self,
+            _repository: Repository,
+            _version: &GameVersion,
+        ) -> Result<PatchChain, Error> {
+            self.0.clone().map_err(Error::PatchServer)
+        }
+    }
+
+    /// Build a patch adding each file at offset 0
+    fn build_add_files_patch(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = ZiPatchWriter::new(Vec::new()).unwrap();
+        writer
+            .write_chunk(&ZiPatchChunk::FileHeader(FileHeaderChunk {
+                version: 3,
+                patch_type: "DIFF".to_string(),
+                entry_files: files.len() as u32,
+                offset: 0,
+            }))
+            .unwrap();
+        for (path, content) in files {
+            writer.add_file(0, path, 0, content).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    /// Installation at a known version with a damaged `game/ffxiv_dx11.exe`,
+    /// and a checker whose patch directory already holds the chain's only patch
+    fn setup_repair(
+        temp_dir: &TempDir,
+        patch: &[u8],
+    ) -> (PathBuf, GoatcorpIntegrityChecker, FakeHistory) {
+        let game_path = temp_dir.path().join("install");
+        std::fs::create_dir_all(game_path.join("game")).unwrap();
+        std::fs::write(game_path.join("game/ffxivgame.ver"), "2024.07.23.0000.0001").unwrap();
+        std::fs::write(game_path.join("game/ffxiv_dx11.exe"), "damaged").unwrap();
+
+        let patch_dir = temp_dir.path().join("patches");
+        std::fs::create_dir_all(&patch_dir).unwrap();
+        std::fs::write(patch_dir.join("H2017.06.06.0000.0001a.patch"), patch).unwrap();
+
+        let history = FakeHistory(Ok(PatchChain {
+            patches: vec![PatchEntry {
+                version_id: "H2017.06.06.0000.0001a".to_string(),
+                url: "http://127.0.0.1:9/game/H2017.06.06.0000.0001a.patch".to_string(),
+                length: patch.len() as u64,
+                hash_type: None,
+                hash_block_size: None,
+                hashes: None,
+                repository: Repository::Ffxiv,
+            }],
+            unique_id: Some("unique".to_string()),
+        }));
+        let checker = GoatcorpIntegrityChecker::with_default_client().with_patch_dir(patch_dir);
+        (game_path, checker, history)
+    }
+
+    fn manifest(hashes: &[(&str, &str)]) -> IntegrityManifest {
+        IntegrityManifest {
+            hashes: hashes
+                .iter()
+                .map(|(path, hash)| (path.to_string(), format_manifest_hash(hash)))
+                .collect(),
+            game_version: "2024.07.23.0000.0001".to_string(),
+            last_game_version: None,
+            source: None,
+        }
+    }
+
+    fn sha1_hex(data: &[u8]) -> String {
+        hex::encode(Sha1::digest(data))
+    }
+
+    #[tokio::test]
+    async fn test_repair_files_rebuilds_from_patch_chain() {
+        let temp_dir = TempDir::new().unwrap();
+        let patch = build_add_files_patch(&[
+            ("ffxiv_dx11.exe", b"original"),
+            ("ffxivgame.dll", b"unrelated"),
+        ]);
+        let (game_path, checker, history) = setup_repair(&temp_dir, &patch);
+        std::fs::write(game_path.join("game/movie.bk2"), "damaged").unwrap();
+        let manifest = manifest(&[
+            (r"\game\ffxiv_dx11.exe", &sha1_hex(b"original")),
+            (r"\game\movie.bk2", &sha1_hex(b"movie")),
+        ]);
+        let files: Vec<FileIntegrityResult> = [r"\game\ffxiv_dx11.exe", r"\game\movie.bk2"]
+            .iter()
+            .map(|path| FileIntegrityResult {
+                relative_path: path.to_string(),
+                expected_hash: String::new(),
+                actual_hash: None,
+                status: IntegrityStatus::Mismatch,
+            })
+            .collect();
+
+        let results = checker
+            .repair_files(
+                &game_path,
+                &manifest,
+                &files,
+                &history,
+                &UpdateControl::new(),
+            )
+            .await
+            .unwrap();
 
-        assert!(result.is_ok());
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].relative_path, r"\game\ffxiv_dx11.exe");
+        assert_eq!(results[0].outcome, RepairOutcome::Repaired);
+        assert_eq!(results[1].outcome, RepairOutcome::NotInPatches);
+        assert_eq!(
+            std::fs::read(game_path.join("game/ffxiv_dx11.exe")).unwrap(),
+            b"original"
+        );
+        // Only the requested files are written, and nothing is left behind
+        assert_eq!(
+            std::fs::read(game_path.join("game/movie.bk2")).unwrap(),
+            b"damaged"
+        );
+        assert!(!game_path.join("game/ffxivgame.dll").exists());
+        assert!(!game_path.join(REPAIR_STAGING_DIR).exists());
+        assert!(!temp_dir
+            .path()
+            .join("patches/H2017.06.06.0000.0001a.patch")
+            .exists());
+    }
+
+    #[tokio::test]
+    async fn test_repair_file_keeps_file_when_rebuild_mismatches() {
+        let temp_dir = TempDir::new().unwrap();
+        let patch = build_add_files_patch(&[("ffxiv_dx11.exe", b"outdated")]);
+        let (game_path, checker, history) = setup_repair(&temp_dir, &patch);
+        let manifest = manifest(&[(r"\game\ffxiv_dx11.exe", &sha1_hex(b"original"))]);
+
+        let result = checker
+            .repair_file(
+                &game_path,
+                &manifest,
+                r"\game\ffxiv_dx11.exe",
+                &history,
+                &UpdateControl::new(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            result.outcome,
+            RepairOutcome::StillMismatched {
+                actual_hash: sha1_hex(b"outdated")
+            }
+        );
+        assert_eq!(
+            std::fs::read(game_path.join("game/ffxiv_dx11.exe")).unwrap(),
+            b"damaged"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_repair_file_checks_disk_space_first() {
+        let temp_dir = TempDir::new().unwrap();
+        let patch = build_add_files_patch(&[("ffxiv_dx11.exe", b"original")]);
+        let (game_path, checker, mut history) = setup_repair(&temp_dir, &patch);
+        if let Ok(chain) = &mut history.0 {
+            chain.patches[0].length = u64::MAX / 2;
+        }
+        let manifest = manifest(&[(r"\game\ffxiv_dx11.exe", &sha1_hex(b"original"))]);
+
+        let result = checker
+            .repair_file(
+                &game_path,
+                &manifest,
+                r"\game\ffxiv_dx11.exe",
+                &history,
+                &UpdateControl::new(),
+            )
+            .await
+            .unwrap();
+
+        assert!(
+            matches!(&result.outcome, RepairOutcome::Failed { message } if message.contains("not enough disk space"))
+        );
+        assert_eq!(
+            std::fs::read(game_path.join("game/ffxiv_dx11.exe")).unwrap(),
+            b"damaged"
+        );
+        assert!(!game_path.join(REPAIR_STAGING_DIR).exists());
+    }
+
+    #[tokio::test]
+    async fn test_repair_file_stops_when_cancelled() {
+        let temp_dir = TempDir::new().unwrap();
+        let patch = build_add_files_patch(&[("ffxiv_dx11.exe", b"original")]);
+        let (game_path, checker, history) = setup_repair(&temp_dir, &patch);
+        let manifest = manifest(&[(r"\game\ffxiv_dx11.exe", &sha1_hex(b"original"))]);
+        let control = UpdateControl::new();
+        control.cancel();
+
+        let result = checker
+            .repair_file(
+                &game_path,
+                &manifest,
+                r"\game\ffxiv_dx11.exe",
+                &history,
+                &control,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            result.outcome,
+            RepairOutcome::Failed {
+                message: Error::Cancelled.to_string()
+            }
+        );
+        assert_eq!(
+            std::fs::read(game_path.join("game/ffxiv_dx11.exe")).unwrap(),
+            b"damaged"
+        );
+        // The downloaded patch is kept for the next attempt
+        assert!(temp_dir
+            .path()
+            .join("patches/H2017.06.06.0000.0001a.patch")
+            .exists());
+    }
+
+    #[tokio::test]
+    async fn test_repair_file_reports_failures_per_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let (game_path, checker, _) = setup_repair(&temp_dir, &[]);
+        let history = FakeHistory(Err("no login session".to_string()));
+        let manifest = manifest(&[(r"\game\ffxiv_dx11.exe", &sha1_hex(b"original"))]);
+
+        let result = checker
+            .repair_file(
+                &game_path,
+                &manifest,
+                r"\game\ffxiv_dx11.exe",
+                &history,
+                &UpdateControl::new(),
+            )
+            .await
+            .unwrap();
+        assert!(
+            matches!(&result.outcome, RepairOutcome::Failed { message } if message.contains("no login session"))
+        );
+        // The damaged file is kept rather than deleted
+        assert!(game_path.join("game/ffxiv_dx11.exe").exists());
+
+        let result = checker
+            .repair_file(
+                &game_path,
+                &manifest,
+                r"\game\..\..\etc\passwd",
+                &history,
+                &UpdateControl::new(),
+            )
+            .await
+            .unwrap();
+        assert!(matches!(result.outcome, RepairOutcome::Failed { .. }));
     }
 
     #[test]
@@ -603,8 +1651,21 @@ mod tests {
 
     #[test]
     fn test_cache_path() {
-        let path = GoatcorpIntegrityChecker::cache_path("2024.01.01.0000.0000");
-        assert!(path.to_string_lossy().contains("2024.01.01.0000.0000.json"));
+        let checker = GoatcorpIntegrityChecker::with_default_client().with_cache_dir("/cache");
+        let first = checker.cache_path(
+            "2024.01.01.0000.0000",
+            &DirectoryManifestSource::new("/srv/first"),
+        );
+        let second = checker.cache_path(
+            "2024.01.01.0000.0000",
+            &DirectoryManifestSource::new("/srv/second"),
+        );
+
+        assert_eq!(first.parent(), Some(Path::new("/cache")));
+        let name = first.file_name().unwrap().to_str().unwrap();
+        assert!(name.starts_with("2024.01.01.0000.0000."));
+        assert!(name.ends_with(".json"));
+        assert_ne!(first, second);
     }
 
     #[test]
@@ -622,7 +1683,7 @@ mod tests {
         let mut hashes = HashMap::new();
         hashes.insert(
             r"\game\test.dat".to_string(),
-            format_as_manifest_hash(&actual_hash),
+            format_manifest_hash(&actual_hash),
         );
 
         let progress_called = Arc::new(AtomicBool::new(false));
@@ -632,10 +1693,110 @@ mod tests {
         });
         let cancelled = AtomicBool::new(false);
 
-        let results = check_files_parallel(game_path, &hashes, progress, &cancelled).unwrap();
+        let results = check_files_parallel(game_path, &hashes, None, progress, &cancelled).unwrap();
 
         assert_eq!(results.len(), 1);
         assert_eq!(results[0].status, IntegrityStatus::Valid);
         assert!(progress_called.load(Ordering::SeqCst));
     }
+
+    /// Rewrite a file without changing its size or modification time
+    fn overwrite_keeping_mtime(path: &Path, contents: &[u8]) {
+        let modified = std::fs::metadata(path).unwrap().modified().unwrap();
+        std::fs::write(path, contents).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+    }
+
+    async fn check_status(
+        checker: &GoatcorpIntegrityChecker,
+        game_path: &Path,
+        manifest: &IntegrityManifest,
+        mode: IntegrityCheckMode,
+    ) -> IntegrityStatus {
+        let results = checker
+            .check_integrity(game_path, manifest, mode, |_| {})
+            .await
+            .unwrap();
+        results[0].status
+    }
+
+    #[tokio::test]
+    async fn test_incremental_check_reuses_unchanged_hashes() {
+        let temp_dir = TempDir::new().unwrap();
+        let game_path = temp_dir.path();
+        std::fs::create_dir_all(game_path.join("game")).unwrap();
+        let file_path = game_path.join("game/ffxiv_dx11.exe");
+        std::fs::write(&file_path, "original").unwrap();
+
+        let checker = GoatcorpIntegrityChecker::with_default_client();
+        let manifest = manifest(&[(r"\game\ffxiv_dx11.exe", &sha1_hex(b"original"))]);
+        let incremental = IntegrityCheckMode::Incremental;
+        assert_eq!(
+            check_status(&checker, game_path, &manifest, incremental).await,
+            IntegrityStatus::Valid
+        );
+
+        // Metadata unchanged, so the cached hash is trusted
+        overwrite_keeping_mtime(&file_path, b"damaged!");
+        assert_eq!(
+            check_status(&checker, game_path, &manifest, incremental).await,
+            IntegrityStatus::Valid
+        );
+
+        // A full check reads the file again and refreshes the cache
+        assert_eq!(
+            check_status(&checker, game_path, &manifest, IntegrityCheckMode::Full).await,
+            IntegrityStatus::Mismatch
+        );
+        assert_eq!(
+            check_status(&checker, game_path, &manifest, incremental).await,
+            IntegrityStatus::Mismatch
+        );
+    }
+
+    #[tokio::test]
+    async fn test_patching_invalidates_cached_hashes() {
+        let temp_dir = TempDir::new().unwrap();
+        let game_path = temp_dir.path().join("install");
+        std::fs::create_dir_all(game_path.join("game")).unwrap();
+        let file_path = game_path.join("game/ffxiv_dx11.exe");
+        std::fs::write(&file_path, "original").unwrap();
+
+        let checker = GoatcorpIntegrityChecker::with_default_client();
+        let manifest = manifest(&[(r"\game\ffxiv_dx11.exe", &sha1_hex(b"original"))]);
+        let incremental = IntegrityCheckMode::Incremental;
+        assert_eq!(
+            check_status(&checker, &game_path, &manifest, incremental).await,
+            IntegrityStatus::Valid
+        );
+
+        // Even if the patch leaves size and modification time as they were
+        let patch_path = temp_dir.path().join("D2024.08.01.0000.0000.patch");
+        std::fs::write(
+            &patch_path,
+            build_add_files_patch(&[("ffxiv_dx11.exe", b"patched!")]),
+        )
+        .unwrap();
+        let modified = std::fs::metadata(&file_path).unwrap().modified().unwrap();
+        ZiPatchParser::new()
+            .apply_patch(&patch_path, &game_path.join("game"))
+            .unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&file_path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+
+        assert_eq!(std::fs::read(&file_path).unwrap(), b"patched!");
+        assert_eq!(
+            check_status(&checker, &game_path, &manifest, incremental).await,
+            IntegrityStatus::Mismatch
+        );
+    }
 }
@@ -0,0 +1,401 @@
+//! Verification of the files inside SqPack archives
+//!
+//! Manifests hash whole dat files, so a single damaged block makes a 2 GB dat
+//! file mismatch. Extracting every file listed in the `.index` files shows
+//! which of the files inside are broken, and lets repairs rewrite just those.
+//! Dat blocks carry no checksums: damage shows as an invalid entry or block
+//! header, or a block that does not decompress to its declared size.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use rayon::prelude::*;
+
+use gaveloc_core::entities::{IntegrityProgress, RepairOutcome, SqPackFileDamage};
+use gaveloc_core::error::Error;
+use gaveloc_core::sqpack::{DatFile, SqPackIndex};
+
+/// Files extracted by each parallel task, which opens their dat file once
+const FILES_PER_TASK: usize = 256;
+
+/// Files listed by an index in one of its dat files
+struct DatContents {
+    index_path: String,
+    dat_path: PathBuf,
+    /// Manifest path of the dat file
+    dat_manifest_path: String,
+    /// Path hash, offset and size of each file, by offset
+    files: Vec<(u64, u64, u64)>,
+}
+
+/// Extract every file listed in the installation's SqPack indexes, returning
+/// the ones that fail
+pub(crate) fn check_sqpack_parallel(
+    game_path: &Path,
+    progress: Arc<dyn Fn(IntegrityProgress) + Send + Sync>,
+    cancelled: &AtomicBool,
+) -> Result<Vec<SqPackFileDamage>, Error> {
+    let mut dats = Vec::new();
+    for index_path in list_index_files(&game_path.join("game/sqpack"))? {
+        match read_index(game_path, &index_path) {
+            Ok(contents) => dats.extend(contents.into_iter().map(Arc::new)),
+            // The index itself is covered by the manifest check
+            Err(e) => tracing::warn!("skipping unreadable index {:?}: {}", index_path, e),
+        }
+    }
+
+    let total_files = dats.iter().map(|dat| dat.files.len()).sum::<usize>() as u32;
+    let total_bytes = dats
+        .iter()
+        .flat_map(|dat| &dat.files)
+        .map(|&(_, _, size)| size)
+        .sum();
+    let files_checked = AtomicU32::new(0);
+    let bytes_processed = AtomicU64::new(0);
+
+    let tasks: Vec<(Arc<DatContents>, usize)> = dats
+        .iter()
+        .flat_map(|dat| {
+            (0..dat.files.len())
+                .step_by(FILES_PER_TASK)
+                .map(move |start| (dat.clone(), start))
+        })
+        .collect();
+
+    let mut damaged = tasks
+        .par_iter()
+        .flat_map_iter(|(dat, start)| {
+            let files = &dat.files[*start..(start + FILES_PER_TASK).min(dat.files.len())];
+            let mut reader = DatFile::open(&dat.dat_path);
+            let mut damaged = Vec::new();
+
+            for &(hash, offset, size) in files {
+                if cancelled.load(Ordering::Relaxed) {
+                    break;
+                }
+
+                let result = match &mut reader {
+                    Ok(reader) => reader.read_file(offset).map(|_| ()),
+                    Err(e) => Err(Error::SqPack(e.to_string())),
+                };
+                if let Err(e) = result {
+                    damaged.push(SqPackFileDamage {
+                        index_path: dat.index_path.clone(),
+                        dat_path: dat.dat_manifest_path.clone(),
+                        hash: format_hash(hash),
+                        offset,
+                        size,
+                        error: e.to_string(),
+                    });
+                }
+
+                let checked = files_checked.fetch_add(1, Ordering::Relaxed) + 1;
+                let bytes = bytes_processed.fetch_add(size, Ordering::Relaxed) + size;
+                progress(IntegrityProgress {
+                    current_file: dat.dat_manifest_path.clone(),
+                    files_checked: checked,
+                    total_files,
+                    bytes_processed: bytes,
+                    total_bytes,
+                });
+            }
+            damaged
+        })
+        .collect::<Vec<_>>();
+
+    if cancelled.load(Ordering::Relaxed) {
+        return Err(Error::Cancelled);
+    }
+
+    damaged.sort_by(|a, b| (&a.dat_path, a.offset).cmp(&(&b.dat_path, b.offset)));
+    Ok(damaged)
+}
+
+/// `.index` files below `sqpack_dir`, sorted; `.index2` files list the same
+/// files by another hash
+fn list_index_files(sqpack_dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    if !sqpack_dir.exists() {
+        return Err(Error::Other(format!(
+            "no SqPack archives found in {}",
+            sqpack_dir.display()
+        )));
+    }
+
+    let mut index_files = Vec::new();
+    for entry in walkdir::WalkDir::new(sqpack_dir).sort_by_file_name() {
+        let entry = entry.map_err(|e| Error::Other(format!("failed to list archives: {}", e)))?;
+        if entry.file_type().is_file() && entry.path().extension().is_some_and(|e| e == "index") {
+            index_files.push(entry.into_path());
+        }
+    }
+    Ok(index_files)
+}
+
+/// Files listed by an index, grouped by dat file, with the size each takes up
+/// to the next file
+fn read_index(game_path: &Path, index_path: &Path) -> Result<Vec<DatContents>, Error> {
+    let index = SqPackIndex::open(index_path)?;
+    // `0a0000.win32`, shared by the index and dat files
+    let archive = index_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let mut by_dat: BTreeMap<u8, Vec<(u64, u64)>> = BTreeMap::new();
+    for entry in index.entries() {
+        // Synonyms point into the index's own collision table, not a dat file
+        if !entry.is_synonym {
+            by_dat
+                .entry(entry.data_file_id)
+                .or_default()
+                .push((entry.hash, entry.offset));
+        }
+    }
+
+    let index_manifest_path = manifest_path(game_path, index_path);
+    Ok(by_dat
+        .into_iter()
+        .map(|(data_file_id, mut entries)| {
+            let dat_path = index_path.with_file_name(format!("{}.dat{}", archive, data_file_id));
+            let dat_len = std::fs::metadata(&dat_path).map_or(0, |m| m.len());
+
+            entries.sort_by_key(|&(_, offset)| offset);
+            let offsets: Vec<u64> = entries.iter().map(|&(_, offset)| offset).collect();
+            let files = entries
+                .iter()
+                .map(|&(hash, offset)| {
+                    let next = offsets[offsets.partition_point(|&o| o <= offset)..]
+                        .first()
+                        .copied()
+                        .unwrap_or(dat_len);
+                    (hash, offset, next.saturating_sub(offset))
+                })
+                .collect();
+
+            DatContents {
+                index_path: index_manifest_path.clone(),
+                dat_manifest_path: manifest_path(game_path, &dat_path),
+                dat_path,
+                files,
+            }
+        })
+        .collect())
+}
+
+/// Rewrite the bytes of a damaged file in `install_path` with those of its dat
+/// file rebuilt in `staging`
+///
+/// `patch_path` is the dat file relative to both directories.
+pub(crate) fn install_rebuilt_file(
+    staging: &Path,
+    install_path: &Path,
+    patch_path: &str,
+    file: &SqPackFileDamage,
+    touched: &HashSet<String>,
+) -> RepairOutcome {
+    if !touched.contains(patch_path) {
+        return RepairOutcome::NotInPatches;
+    }
+
+    match copy_file_bytes(
+        &staging.join(patch_path),
+        &install_path.join(patch_path),
+        file,
+    ) {
+        Ok(()) => RepairOutcome::Repaired,
+        Err(e) => RepairOutcome::Failed {
+            message: e.to_string(),
+        },
+    }
+}
+
+fn copy_file_bytes(rebuilt: &Path, dest: &Path, file: &SqPackFileDamage) -> Result<(), Error> {
+    // The rebuilt dat file matches the installed index, so the file is at the
+    // same offset in it
+    DatFile::open(rebuilt)?
+        .read_file(file.offset)
+        .map_err(|e| Error::SqPack(format!("the rebuilt file is damaged too: {}", e)))?;
+
+    let mut source = File::open(rebuilt)?;
+    if source.metadata()?.len() < file.offset + file.size {
+        return Err(Error::SqPack(
+            "the rebuilt dat file is shorter than the installed one".to_string(),
+        ));
+    }
+    source.seek(SeekFrom::Start(file.offset))?;
+
+    let mut dest_file = OpenOptions::new().write(true).open(dest)?;
+    dest_file.seek(SeekFrom::Start(file.offset))?;
+    std::io::copy(&mut source.take(file.size), &mut dest_file)?;
+    dest_file.sync_all()?;
+
+    DatFile::open(dest)?.read_file(file.offset)?;
+    Ok(())
+}
+
+/// Path hash of an `.index` entry: folder and file name CRCs
+fn format_hash(hash: u64) -> String {
+    format!("{:08x}/{:08x}", hash >> 32, hash as u32)
+}
+
+/// Manifest path of a file in the installation
+/// Input: "{game_path}/game/sqpack/ffxiv/000000.win32.dat0"
+/// Output: "\game\sqpack\ffxiv\000000.win32.dat0"
+fn manifest_path(game_path: &Path, path: &Path) -> String {
+    let relative = path.strip_prefix(game_path).unwrap_or(path);
+    let components: Vec<_> = relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect();
+    format!("\\{}", components.join("\\"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gaveloc_core::sqpack::index_hash;
+    use tempfile::TempDir;
+
+    /// SqPack file header of the given type (1 for dat, 2 for index files)
+    fn sqpack_header(kind: u32) -> Vec<u8> {
+        let mut header = vec![0u8; 1024];
+        header[..6].copy_from_slice(b"SqPack");
+        header[0x0C..0x10].copy_from_slice(&1024u32.to_le_bytes());
+        header[0x14..0x18].copy_from_slice(&kind.to_le_bytes());
+        header
+    }
+
+    /// Standard file with its contents in a single uncompressed block
+    fn standard_file(contents: &[u8]) -> Vec<u8> {
+        let mut file = Vec::new();
+        for value in [128, 2, contents.len() as u32, 0, 0, 1, 0] {
+            file.extend_from_slice(&value.to_le_bytes());
+        }
+        file.resize(128, 0);
+        for value in [16, 0, 32000, contents.len() as u32] {
+            file.extend_from_slice(&value.to_le_bytes());
+        }
+        file.extend_from_slice(contents);
+        file.resize(file.len().next_multiple_of(128), 0);
+        file
+    }
+
+    /// Index and dat file of an archive holding `files`
+    fn build_archive(files: &[(&str, &[u8])]) -> (Vec<u8>, Vec<u8>, Vec<u64>) {
+        let mut dat = sqpack_header(1);
+        dat.resize(2048, 0);
+        let mut entries = Vec::new();
+        let mut offsets = Vec::new();
+        for (path, contents) in files {
+            offsets.push(dat.len() as u64);
+            entries.extend_from_slice(&index_hash(path).to_le_bytes());
+            entries.extend_from_slice(&((dat.len() / 8) as u32).to_le_bytes());
+            entries.extend_from_slice(&[0; 4]);
+            dat.extend_from_slice(&standard_file(contents));
+        }
+
+        let mut index = sqpack_header(2);
+        let mut index_header = vec![0u8; 1024];
+        index_header[0x08..0x0C].copy_from_slice(&2048u32.to_le_bytes());
+        index_header[0x0C..0x10].copy_from_slice(&(entries.len() as u32).to_le_bytes());
+        index.extend_from_slice(&index_header);
+        index.extend_from_slice(&entries);
+        (index, dat, offsets)
+    }
+
+    fn check(game_path: &Path) -> Vec<SqPackFileDamage> {
+        check_sqpack_parallel(game_path, Arc::new(|_| {}), &AtomicBool::new(false)).unwrap()
+    }
+
+    #[test]
+    fn test_check_reports_damaged_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let archive = temp_dir.path().join("game/sqpack/ffxiv/0a0000.win32");
+        std::fs::create_dir_all(archive.parent().unwrap()).unwrap();
+        let (index, mut dat, offsets) =
+            build_archive(&[("exd/root.exl", b"EXLT"), ("exd/item.exh", b"EXHF")]);
+        std::fs::write(archive.with_extension("win32.index"), &index).unwrap();
+        std::fs::write(archive.with_extension("win32.dat0"), &dat).unwrap();
+        assert!(check(temp_dir.path()).is_empty());
+
+        // Make the block of the second file claim more data than it holds
+        let block = offsets[1] as usize + 128;
+        dat[block + 12..block + 16].copy_from_slice(&64u32.to_le_bytes());
+        std::fs::write(archive.with_extension("win32.dat0"), &dat).unwrap();
+
+        let damaged = check(temp_dir.path());
+        assert_eq!(damaged.len(), 1);
+        let item = index_hash("exd/item.exh");
+        assert_eq!(
+            damaged[0].hash,
+            format!("{:08x}/{:08x}", item >> 32, item as u32)
+        );
+        assert_eq!(
+            damaged[0].index_path,
+            r"\game\sqpack\ffxiv\0a0000.win32.index"
+        );
+        assert_eq!(damaged[0].dat_path, r"\game\sqpack\ffxiv\0a0000.win32.dat0");
+        assert_eq!(damaged[0].offset, offsets[1]);
+        assert_eq!(damaged[0].size, dat.len() as u64 - offsets[1]);
+    }
+
+    #[test]
+    fn test_check_reports_files_of_missing_dat() {
+        let temp_dir = TempDir::new().unwrap();
+        let archive = temp_dir.path().join("game/sqpack/ex1/020100.win32");
+        std::fs::create_dir_all(archive.parent().unwrap()).unwrap();
+        let (index, _, _) = build_archive(&[("bg/ex1/level.lvb", b"LVB1")]);
+        std::fs::write(archive.with_extension("win32.index"), &index).unwrap();
+
+        let damaged = check(temp_dir.path());
+        assert_eq!(damaged.len(), 1);
+        assert_eq!(damaged[0].dat_path, r"\game\sqpack\ex1\020100.win32.dat0");
+        assert_eq!(damaged[0].size, 0);
+    }
+
+    #[test]
+    fn test_install_rebuilt_file_rewrites_only_its_bytes() {
+        let temp_dir = TempDir::new().unwrap();
+        let patch_path = "sqpack/ffxiv/0a0000.win32.dat0";
+        let (_, good, offsets) =
+            build_archive(&[("exd/root.exl", b"EXLT"), ("exd/item.exh", b"EXHF")]);
+        let staging = temp_dir.path().join("staging");
+        let install = temp_dir.path().join("install");
+        for dir in [&staging, &install] {
+            std::fs::create_dir_all(dir.join("sqpack/ffxiv")).unwrap();
+        }
+        std::fs::write(staging.join(patch_path), &good).unwrap();
+
+        // Both files are damaged, only the second one is repaired
+        let mut damaged = good.clone();
+        for offset in &offsets {
+            damaged[*offset as usize + 128 + 16] = b'X';
+        }
+        std::fs::write(install.join(patch_path), &damaged).unwrap();
+        let file = SqPackFileDamage {
+            index_path: r"\game\sqpack\ffxiv\0a0000.win32.index".to_string(),
+            dat_path: r"\game\sqpack\ffxiv\0a0000.win32.dat0".to_string(),
+            hash: String::new(),
+            offset: offsets[1],
+            size: good.len() as u64 - offsets[1],
+            error: String::new(),
+        };
+
+        let touched = HashSet::from([patch_path.to_string()]);
+        let outcome = install_rebuilt_file(&staging, &install, patch_path, &file, &touched);
+        assert_eq!(outcome, RepairOutcome::Repaired);
+        let repaired = std::fs::read(install.join(patch_path)).unwrap();
+        assert_eq!(repaired[offsets[1] as usize..], good[offsets[1] as usize..]);
+        assert_eq!(
+            repaired[..offsets[1] as usize],
+            damaged[..offsets[1] as usize]
+        );
+
+        let outcome = install_rebuilt_file(&staging, &install, patch_path, &file, &HashSet::new());
+        assert_eq!(outcome, RepairOutcome::NotInPatches);
+    }
+}
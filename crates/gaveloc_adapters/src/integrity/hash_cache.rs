@@ -0,0 +1,310 @@
+//! Hashes of installed files kept between integrity checks
+//!
+//! Each patched directory (`boot/`, `game/`) keeps the SHA1 of its files along
+//! with the size, modification time and inode they had when hashed. A file
+//! whose metadata still matches is not read again, so checking an untouched
+//! installation takes seconds. Applying or rolling back a patch drops the
+//! entries of every file it wrote, so patched files are always hashed again.
+
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use gaveloc_core::entities::Repository;
+use gaveloc_core::error::Error;
+
+/// Name of the cache file inside each patched directory
+pub(crate) const HASH_CACHE_FILE: &str = ".gaveloc_hashes.json";
+
+/// Bumped when the file format changes; older caches are discarded
+const HASH_CACHE_VERSION: u32 = 1;
+
+/// Metadata that changes whenever a file is rewritten or replaced
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct FileStamp {
+    size: u64,
+    mtime: i64,
+    mtime_nsec: i64,
+    inode: u64,
+}
+
+impl FileStamp {
+    pub fn of(metadata: &Metadata) -> Self {
+        Self {
+            size: metadata.len(),
+            mtime: metadata.mtime(),
+            mtime_nsec: metadata.mtime_nsec(),
+            inode: metadata.ino(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct CachedHash {
+    #[serde(flatten)]
+    stamp: FileStamp,
+    sha1: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct HashCacheFile {
+    version: u32,
+    /// Keyed by path relative to the directory, separated by `/`
+    files: HashMap<String, CachedHash>,
+}
+
+/// Cached hashes of the files in one directory
+#[derive(Debug)]
+struct HashCache {
+    dir: PathBuf,
+    files: HashMap<String, CachedHash>,
+    changed: bool,
+}
+
+impl HashCache {
+    fn empty(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            files: HashMap::new(),
+            changed: false,
+        }
+    }
+
+    /// Cache of the files in `dir`; a missing, corrupt or outdated cache is empty
+    fn load(dir: PathBuf) -> Self {
+        let path = dir.join(HASH_CACHE_FILE);
+        let files = match std::fs::read(&path) {
+            Ok(data) => match serde_json::from_slice::<HashCacheFile>(&data) {
+                Ok(cache) if cache.version == HASH_CACHE_VERSION => cache.files,
+                Ok(_) => HashMap::new(),
+                Err(e) => {
+                    tracing::warn!("ignoring unreadable hash cache {:?}: {}", path, e);
+                    HashMap::new()
+                }
+            },
+            Err(_) => HashMap::new(),
+        };
+
+        Self {
+            files,
+            ..Self::empty(dir)
+        }
+    }
+
+    /// Forget `paths` and everything below them
+    fn remove_below(&mut self, paths: &[String]) {
+        let before = self.files.len();
+        self.files.retain(|file, _| {
+            !paths.iter().any(|path| {
+                file == path
+                    || (file.starts_with(path.as_str()) && file[path.len()..].starts_with('/'))
+            })
+        });
+        self.changed |= self.files.len() != before;
+    }
+
+    /// Write the cache if it changed, replacing the previous one atomically
+    fn save(&self) -> Result<(), Error> {
+        if !self.changed || !self.dir.is_dir() {
+            return Ok(());
+        }
+
+        let data = serde_json::to_vec(&HashCacheFile {
+            version: HASH_CACHE_VERSION,
+            files: self.files.clone(),
+        })
+        .map_err(|e| Error::Other(format!("failed to serialize hash cache: {}", e)))?;
+
+        let path = self.dir.join(HASH_CACHE_FILE);
+        let temp_path = path.with_extension("json.tmp");
+        std::fs::write(&temp_path, data)?;
+        std::fs::rename(&temp_path, &path)?;
+        Ok(())
+    }
+}
+
+/// Cached hashes of an installation's patched directories, by manifest path
+#[derive(Debug)]
+pub(crate) struct InstallHashes {
+    /// Directory name (`boot`, `game`) and its cache
+    caches: Vec<(&'static str, HashCache)>,
+}
+
+impl InstallHashes {
+    /// Hashes cached for the installation at `game_path`
+    pub fn load(game_path: &Path) -> Self {
+        Self::with_caches(game_path, HashCache::load)
+    }
+
+    /// Start over for the installation at `game_path`, reading every file again
+    pub fn empty(game_path: &Path) -> Self {
+        let mut hashes = Self::with_caches(game_path, HashCache::empty);
+        // An empty cache replaces the stored one even if nothing is hashed
+        for (_, cache) in &mut hashes.caches {
+            cache.changed = true;
+        }
+        hashes
+    }
+
+    fn with_caches(game_path: &Path, cache: impl Fn(PathBuf) -> HashCache) -> Self {
+        let caches = [Repository::Boot, Repository::Ffxiv]
+            .iter()
+            .map(|repo| repo.install_dir())
+            .map(|dir| (dir, cache(game_path.join(dir))))
+            .collect();
+        Self { caches }
+    }
+
+    /// Index of the cache holding a manifest path, and the path relative to
+    /// its directory
+    fn locate(&self, manifest_path: &str) -> Option<(usize, String)> {
+        let relative = manifest_path.trim_start_matches('\\').replace('\\', "/");
+        let (dir, file) = relative.split_once('/')?;
+        let index = self.caches.iter().position(|(name, _)| *name == dir)?;
+        Some((index, file.to_string()))
+    }
+
+    /// Cached hash of a file, if it is unchanged since it was hashed
+    pub fn get(&self, manifest_path: &str, stamp: &FileStamp) -> Option<String> {
+        let (index, file) = self.locate(manifest_path)?;
+        self.caches[index]
+            .1
+            .files
+            .get(&file)
+            .filter(|cached| cached.stamp == *stamp)
+            .map(|cached| cached.sha1.clone())
+    }
+
+    /// Remember the hash of a file with the metadata it had before it was read
+    pub fn insert(&mut self, manifest_path: &str, stamp: FileStamp, sha1: String) {
+        if let Some((index, file)) = self.locate(manifest_path) {
+            let cache = &mut self.caches[index].1;
+            let entry = CachedHash { stamp, sha1 };
+            if cache.files.get(&file) != Some(&entry) {
+                cache.files.insert(file, entry);
+                cache.changed = true;
+            }
+        }
+    }
+
+    /// Forget a file that could not be hashed
+    pub fn remove(&mut self, manifest_path: &str) {
+        if let Some((index, file)) = self.locate(manifest_path) {
+            let cache = &mut self.caches[index].1;
+            cache.changed |= cache.files.remove(&file).is_some();
+        }
+    }
+
+    /// Write the changed caches; a failure only costs rehashing next time
+    pub fn save(&self) {
+        for (_, cache) in &self.caches {
+            if let Err(e) = cache.save() {
+                tracing::warn!("failed to save hash cache in {:?}: {}", cache.dir, e);
+            }
+        }
+    }
+}
+
+/// Forget the cached hashes of `paths` (and of everything below them) in `dir`,
+/// a directory a patch was applied to
+pub(crate) fn invalidate_hashes<'a>(
+    dir: &Path,
+    paths: impl IntoIterator<Item = &'a Path>,
+) -> Result<(), Error> {
+    if !dir.join(HASH_CACHE_FILE).exists() {
+        return Ok(());
+    }
+
+    let paths: Vec<String> = paths
+        .into_iter()
+        .filter_map(|path| path.strip_prefix(dir).ok())
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect();
+    if paths.is_empty() {
+        return Ok(());
+    }
+
+    let mut cache = HashCache::load(dir.to_path_buf());
+    cache.remove_below(&paths);
+    cache.save()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn stamp(path: &Path) -> FileStamp {
+        FileStamp::of(&std::fs::metadata(path).unwrap())
+    }
+
+    #[test]
+    fn test_hashes_survive_reload_until_file_changes() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("game/sqpack/ffxiv/000000.win32.dat0");
+        std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+        std::fs::write(&file, b"original").unwrap();
+
+        let mut hashes = InstallHashes::load(dir.path());
+        let manifest_path = "\\game\\sqpack\\ffxiv\\000000.win32.dat0";
+        hashes.insert(manifest_path, stamp(&file), "abc".to_string());
+        hashes.save();
+
+        let mut hashes = InstallHashes::load(dir.path());
+        assert_eq!(
+            hashes.get(manifest_path, &stamp(&file)).as_deref(),
+            Some("abc")
+        );
+
+        // Same size, but rewritten
+        std::fs::write(&file, b"modified").unwrap();
+        let mut changed = stamp(&file);
+        changed.mtime_nsec += 1;
+        assert_eq!(hashes.get(manifest_path, &changed), None);
+
+        // Outside the patched directories nothing is cached
+        hashes.insert("\\readme.txt", stamp(&file), "def".to_string());
+        assert_eq!(hashes.get("\\readme.txt", &stamp(&file)), None);
+    }
+
+    #[test]
+    fn test_invalidate_hashes_below_paths() {
+        let dir = TempDir::new().unwrap();
+        let game = dir.path().join("game");
+        std::fs::create_dir_all(&game).unwrap();
+        let file = game.join("ffxivgame.ver");
+        std::fs::write(&file, b"2024.07.23.0000.0001").unwrap();
+
+        let mut hashes = InstallHashes::load(dir.path());
+        for manifest_path in [
+            "\\game\\ffxivgame.ver",
+            "\\game\\sqpack\\ex1\\020000.win32.dat0",
+            "\\game\\sqpack\\ex10\\020000.win32.dat0",
+        ] {
+            hashes.insert(manifest_path, stamp(&file), "abc".to_string());
+        }
+        hashes.save();
+
+        invalidate_hashes(
+            &game,
+            [
+                game.join("ffxivgame.ver").as_path(),
+                &game.join("sqpack/ex1"),
+            ],
+        )
+        .unwrap();
+
+        let hashes = InstallHashes::load(dir.path());
+        assert_eq!(hashes.get("\\game\\ffxivgame.ver", &stamp(&file)), None);
+        assert_eq!(
+            hashes.get("\\game\\sqpack\\ex1\\020000.win32.dat0", &stamp(&file)),
+            None
+        );
+        assert!(hashes
+            .get("\\game\\sqpack\\ex10\\020000.win32.dat0", &stamp(&file))
+            .is_some());
+    }
+}
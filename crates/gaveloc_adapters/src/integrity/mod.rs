@@ -1,5 +1,10 @@
 //! Integrity checking module for verifying game files against community manifest
 
 mod checker;
+mod deep;
+mod hash_cache;
+mod sources;
 
 pub use checker::GoatcorpIntegrityChecker;
+pub(crate) use hash_cache::invalidate_hashes;
+pub use sources::{DirectoryManifestSource, HttpManifestSource, GOATCORP_MANIFEST_URL};
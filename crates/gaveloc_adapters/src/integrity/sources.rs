@@ -0,0 +1,208 @@
+//! Integrity manifest sources
+//!
+//! Manifests are named `<game version>.json`, whether they are served by
+//! goatcorp, an HTTP mirror, or kept in a local directory for machines without
+//! network access.
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use gaveloc_core::config::ManifestSourceSettings;
+use gaveloc_core::error::Error;
+use gaveloc_core::ports::ManifestSource;
+
+/// Base URL of the goatcorp community manifests
+pub const GOATCORP_MANIFEST_URL: &str = "https://goatcorp.github.io/integrity";
+
+const MANIFEST_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_RETRIES: u32 = 3;
+
+/// Manifests served over HTTP at `<base_url>/<version>.json`
+pub struct HttpManifestSource {
+    client: reqwest::Client,
+    base_url: String,
+}
+
+impl HttpManifestSource {
+    /// Read manifests from a mirror
+    pub fn new(client: reqwest::Client, base_url: impl Into<String>) -> Self {
+        Self {
+            client,
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// Read manifests from goatcorp
+    pub fn goatcorp(client: reqwest::Client) -> Self {
+        Self::new(client, GOATCORP_MANIFEST_URL)
+    }
+}
+
+#[async_trait]
+impl ManifestSource for HttpManifestSource {
+    fn describe(&self) -> String {
+        self.base_url.clone()
+    }
+
+    /// Fetch with retry logic; server errors and timeouts are retried
+    async fn fetch_manifest(&self, game_version: &str) -> Result<Vec<u8>, Error> {
+        let url = format!("{}/{}.json", self.base_url, game_version);
+
+        for attempt in 0..MAX_RETRIES {
+            let result = tokio::time::timeout(MANIFEST_TIMEOUT, self.client.get(&url).send()).await;
+
+            match result {
+                Ok(Ok(response)) => {
+                    if response.status() == reqwest::StatusCode::NOT_FOUND {
+                        return Err(Error::IntegrityManifestNotFound(game_version.to_string()));
+                    }
+                    if response.status().is_server_error() && attempt < MAX_RETRIES - 1 {
+                        tokio::time::sleep(Duration::from_secs(2u64.pow(attempt))).await;
+                        continue;
+                    }
+                    if !response.status().is_success() {
+                        return Err(Error::Network(format!(
+                            "manifest request failed: {}",
+                            response.status()
+                        )));
+                    }
+                    return response.bytes().await.map(|b| b.to_vec()).map_err(|e| {
+                        Error::Network(format!("failed to read integrity manifest: {}", e))
+                    });
+                }
+                Ok(Err(e)) => {
+                    if attempt < MAX_RETRIES - 1 {
+                        tokio::time::sleep(Duration::from_secs(2u64.pow(attempt))).await;
+                        continue;
+                    }
+                    return Err(Error::Network(format!(
+                        "failed to fetch integrity manifest: {}",
+                        e
+                    )));
+                }
+                Err(_) => {
+                    if attempt < MAX_RETRIES - 1 {
+                        continue;
+                    }
+                    return Err(Error::Network("manifest fetch timeout".into()));
+                }
+            }
+        }
+        unreachable!()
+    }
+}
+
+/// Manifests kept in a local directory as `<version>.json`
+pub struct DirectoryManifestSource {
+    dir: PathBuf,
+}
+
+impl DirectoryManifestSource {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+#[async_trait]
+impl ManifestSource for DirectoryManifestSource {
+    fn describe(&self) -> String {
+        self.dir.display().to_string()
+    }
+
+    async fn fetch_manifest(&self, game_version: &str) -> Result<Vec<u8>, Error> {
+        // A version is a single file name, never a path
+        if game_version.contains(['/', '\\']) || game_version.contains("..") {
+            return Err(Error::IntegrityManifestNotFound(game_version.to_string()));
+        }
+
+        match tokio::fs::read(self.dir.join(format!("{}.json", game_version))).await {
+            Ok(data) => Ok(data),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                Err(Error::IntegrityManifestNotFound(game_version.to_string()))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Create the source described by the settings
+pub fn manifest_source(
+    settings: &ManifestSourceSettings,
+    client: &reqwest::Client,
+) -> Box<dyn ManifestSource> {
+    match settings {
+        ManifestSourceSettings::Goatcorp => Box::new(HttpManifestSource::goatcorp(client.clone())),
+        ManifestSourceSettings::Mirror { base_url } => {
+            Box::new(HttpManifestSource::new(client.clone(), base_url.clone()))
+        }
+        ManifestSourceSettings::Directory { path } => {
+            Box::new(DirectoryManifestSource::new(path.clone()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// Serve `body` at `/<version>.json` for one version, 404 otherwise
+    async fn serve_manifest(version: &'static str, body: &'static str) -> String {
+        let app = axum::Router::new().route(
+            "/integrity/:file",
+            axum::routing::get(
+                move |axum::extract::Path(file): axum::extract::Path<String>| async move {
+                    if file == format!("{}.json", version) {
+                        (axum::http::StatusCode::OK, body)
+                    } else {
+                        (axum::http::StatusCode::NOT_FOUND, "")
+                    }
+                },
+            ),
+        );
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+        format!("http://{}/integrity/", addr)
+    }
+
+    #[tokio::test]
+    async fn test_http_source_fetches_from_mirror() {
+        let base_url = serve_manifest("2024.07.23.0000.0001", r#"{"Hashes":{}}"#).await;
+        let source = HttpManifestSource::new(reqwest::Client::new(), base_url);
+
+        let data = source.fetch_manifest("2024.07.23.0000.0001").await.unwrap();
+        assert_eq!(data, br#"{"Hashes":{}}"#);
+
+        let err = source
+            .fetch_manifest("2024.08.01.0000.0000")
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::IntegrityManifestNotFound(_)));
+    }
+
+    #[tokio::test]
+    async fn test_directory_source() {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(dir.path().join("2024.07.23.0000.0001.json"), "{}").unwrap();
+        let source = DirectoryManifestSource::new(dir.path());
+
+        assert_eq!(
+            source.fetch_manifest("2024.07.23.0000.0001").await.unwrap(),
+            b"{}"
+        );
+        assert!(matches!(
+            source.fetch_manifest("2024.08.01.0000.0000").await,
+            Err(Error::IntegrityManifestNotFound(_))
+        ));
+        assert!(matches!(
+            source.fetch_manifest("../secrets").await,
+            Err(Error::IntegrityManifestNotFound(_))
+        ));
+    }
+}
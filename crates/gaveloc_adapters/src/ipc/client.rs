@@ -3,27 +3,31 @@
 //! The UnixSocketPatcherIpc spawns the patcher binary and manages the socket
 //! connection for bidirectional communication.
 
+use std::collections::VecDeque;
+use std::io::ErrorKind;
 use std::path::{Path, PathBuf};
 use std::process::{Child, Command, Stdio};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
 use async_trait::async_trait;
 use interprocess::local_socket::tokio::prelude::*;
-use interprocess::local_socket::tokio::Stream;
+use interprocess::local_socket::tokio::{RecvHalf, SendHalf, Stream};
 use interprocess::local_socket::{GenericFilePath, ListenerOptions, ToFsName};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::sync::Mutex;
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
 use tracing::{debug, error, info, warn};
 
-use gaveloc_core::entities::{PatchEntry, PatchProgress, PatchState};
+use gaveloc_core::config::PatchSettings;
+use gaveloc_core::entities::{PatchEntry, PatcherEvent, PatcherStatus};
 use gaveloc_core::error::Error;
 use gaveloc_core::ports::PatcherIpc;
 
 use super::protocol::{
-    deserialize_message, serialize_message, PatcherRequest, PatcherResponse, MAX_MESSAGE_SIZE,
-    MESSAGE_HEADER_SIZE,
+    deserialize_message, serialize_message, Capabilities, PatcherRequest, PatcherResponse,
+    MAX_MESSAGE_SIZE, MESSAGE_HEADER_SIZE,
 };
 
 /// Socket path prefix for patcher IPC
@@ -32,20 +36,49 @@ const SOCKET_PATH_PREFIX: &str = "/tmp/gaveloc_patcher_";
 /// Timeout for initial connection to patcher
 const CONNECTION_TIMEOUT: Duration = Duration::from_secs(10);
 
-/// Timeout for receiving a single message (5 minutes for long patch operations)
-const RECV_TIMEOUT: Duration = Duration::from_secs(300);
+/// Timeout for the patcher's Ready message
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long `receive_event` waits for an event
+const POLL_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Timeout for the answer to a status query
+const STATUS_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait for the patcher to exit after it closed the connection
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+const EXIT_POLL_ATTEMPTS: u32 = 10;
+
+/// A decoded message, or the error that ended the connection
+type Received = Result<PatcherResponse, Error>;
 
 /// Internal state that requires interior mutability
 struct IpcState {
-    stream: Option<Stream>,
+    writer: Option<SendHalf>,
     child_process: Option<Child>,
 }
 
+/// Messages received from the patcher
+struct Inbox {
+    /// Filled by the reader task
+    responses: mpsc::UnboundedReceiver<Received>,
+    /// Events read while waiting for a status answer
+    pending: VecDeque<PatcherResponse>,
+}
+
 /// IPC client that communicates with the patcher process over Unix domain sockets
+///
+/// A background task reads the patcher's messages as they arrive, so polling
+/// for events never loses part of a message to a timeout.
 pub struct UnixSocketPatcherIpc {
     socket_path: PathBuf,
     state: Arc<Mutex<IpcState>>,
+    inbox: Mutex<Inbox>,
+    reader: JoinHandle<()>,
     is_running: Arc<AtomicBool>,
+    /// Features supported by both the launcher and the patcher
+    capabilities: Capabilities,
+    next_job_id: AtomicU64,
 }
 
 impl UnixSocketPatcherIpc {
@@ -113,12 +146,13 @@ impl UnixSocketPatcherIpc {
             .create_tokio()
             .map_err(|e| Error::Ipc(format!("failed to create socket listener: {}", e)))?;
 
-        // Spawn the patcher process
+        // Spawn the patcher process; its log goes to our output, since a pipe
+        // nobody reads would eventually block it
         let child = Command::new(patcher_binary)
             .arg(&socket_path)
             .stdin(Stdio::null())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit())
             .spawn()
             .map_err(|e| Error::Ipc(format!("failed to spawn patcher: {}", e)))?;
 
@@ -133,20 +167,22 @@ impl UnixSocketPatcherIpc {
 
         debug!("patcher connected");
 
-        let ipc = Self {
-            socket_path,
-            state: Arc::new(Mutex::new(IpcState {
-                stream: Some(stream),
-                child_process: Some(child),
-            })),
-            is_running: Arc::new(AtomicBool::new(true)),
-        };
+        let mut ipc = Self::connect(socket_path, stream, Some(child));
 
-        // Wait for Ready message
-        let response = ipc.recv_internal(RECV_TIMEOUT).await?;
+        // Wait for Ready message; a patcher speaking another protocol version
+        // is rejected here and killed when `ipc` is dropped
+        let response = ipc
+            .recv_internal(HANDSHAKE_TIMEOUT)
+            .await?
+            .ok_or_else(|| Error::Ipc("patcher did not become ready".into()))?;
         match response {
-            PatcherResponse::Ready => {
-                info!("patcher is ready");
+            PatcherResponse::Ready { capabilities } => {
+                ipc.capabilities = Capabilities::SUPPORTED.intersection(capabilities);
+                info!(
+                    "patcher is ready (capabilities {:#x}, using {:#x})",
+                    capabilities.bits(),
+                    ipc.capabilities.bits()
+                );
             }
             other => {
                 return Err(Error::Ipc(format!(
@@ -158,91 +194,142 @@ impl UnixSocketPatcherIpc {
 
         // Send Hello handshake
         let parent_pid = std::process::id();
-        ipc.send_internal(&PatcherRequest::Hello { parent_pid })
-            .await?;
+        ipc.send_internal(&PatcherRequest::Hello {
+            parent_pid,
+            capabilities: Capabilities::SUPPORTED,
+        })
+        .await?;
 
         Ok(ipc)
     }
 
+    /// Wrap an established connection, starting the task that reads from it
+    fn connect(socket_path: PathBuf, stream: Stream, child: Option<Child>) -> Self {
+        let (reader, writer) = stream.split();
+        let (responses_tx, responses) = mpsc::unbounded_channel();
+
+        Self {
+            socket_path,
+            state: Arc::new(Mutex::new(IpcState {
+                writer: Some(writer),
+                child_process: child,
+            })),
+            inbox: Mutex::new(Inbox {
+                responses,
+                pending: VecDeque::new(),
+            }),
+            reader: tokio::spawn(read_responses(reader, responses_tx)),
+            is_running: Arc::new(AtomicBool::new(true)),
+            capabilities: Capabilities::NONE,
+            next_job_id: AtomicU64::new(1),
+        }
+    }
+
     /// Send a request to the patcher (internal method)
     async fn send_internal(&self, request: &PatcherRequest) -> Result<(), Error> {
-        let bytes =
-            serialize_message(request).map_err(|e| Error::Ipc(format!("serialize error: {}", e)))?;
+        let bytes = serialize_message(request)?;
 
         let mut state = self.state.lock().await;
-        let stream = state
-            .stream
+        let writer = state
+            .writer
             .as_mut()
             .ok_or_else(|| Error::Ipc("stream closed".into()))?;
 
-        stream
-            .write_all(&bytes)
-            .await
-            .map_err(|e| Error::Ipc(format!("write error: {}", e)))?;
+        if let Err(e) = writer.write_all(&bytes).await {
+            drop(state);
+            return Err(self.connection_error("write error", e).await);
+        }
 
-        stream
-            .flush()
-            .await
-            .map_err(|e| Error::Ipc(format!("flush error: {}", e)))?;
+        if let Err(e) = writer.flush().await {
+            drop(state);
+            return Err(self.connection_error("flush error", e).await);
+        }
 
         Ok(())
     }
 
-    /// Receive a response from the patcher (internal method)
-    async fn recv_internal(&self, timeout: Duration) -> Result<PatcherResponse, Error> {
-        let mut state = self.state.lock().await;
-        let stream = state
-            .stream
-            .as_mut()
-            .ok_or_else(|| Error::Ipc("stream closed".into()))?;
+    /// Receive a response from the patcher, `None` if none arrived in time
+    async fn recv_internal(&self, timeout: Duration) -> Result<Option<PatcherResponse>, Error> {
+        let mut inbox = self.inbox.lock().await;
+        self.next_response(&mut inbox, timeout).await
+    }
 
-        // Read length header
-        let mut header = [0u8; MESSAGE_HEADER_SIZE];
-        tokio::time::timeout(timeout, stream.read_exact(&mut header))
-            .await
-            .map_err(|_| Error::Ipc("recv timeout".into()))?
-            .map_err(|e| Error::Ipc(format!("read header error: {}", e)))?;
+    /// Take the next response from the reader task
+    async fn next_response(
+        &self,
+        inbox: &mut Inbox,
+        timeout: Duration,
+    ) -> Result<Option<PatcherResponse>, Error> {
+        let received = match tokio::time::timeout(timeout, inbox.responses.recv()).await {
+            Ok(received) => received,
+            Err(_) => return Ok(None),
+        };
 
-        let len = u32::from_be_bytes(header);
+        match received {
+            Some(Ok(response)) => Ok(Some(response)),
+            Some(Err(Error::Io(e))) => Err(self.connection_error("read error", e).await),
+            Some(Err(e)) => Err(e),
+            // The reader task stopped after reporting the error that ended
+            // the connection
+            None => Err(self
+                .connection_error("read error", ErrorKind::UnexpectedEof.into())
+                .await),
+        }
+    }
 
-        if len > MAX_MESSAGE_SIZE {
-            return Err(Error::Ipc(format!(
-                "message too large: {} bytes (max {})",
-                len, MAX_MESSAGE_SIZE
-            )));
+    /// Exit status of the patcher process, `None` while it is running
+    async fn exit_status(&self) -> Option<String> {
+        let mut state = self.state.lock().await;
+        let child = state.child_process.as_mut()?;
+        match child.try_wait() {
+            Ok(None) => None,
+            Ok(Some(status)) => {
+                error!("patcher process exited unexpectedly: {}", status);
+                self.is_running.store(false, Ordering::SeqCst);
+                Some(status.to_string())
+            }
+            Err(e) => {
+                error!("error checking patcher status: {}", e);
+                None
+            }
         }
+    }
 
-        // Read payload
-        let mut payload = vec![0u8; len as usize];
-        tokio::time::timeout(timeout, stream.read_exact(&mut payload))
-            .await
-            .map_err(|_| Error::Ipc("recv payload timeout".into()))?
-            .map_err(|e| Error::Ipc(format!("read payload error: {}", e)))?;
+    /// Error for a failed read or write on the patcher connection
+    ///
+    /// Reported as [`Error::PatcherExited`] if the patcher is gone. A closed
+    /// connection usually means it is exiting, so its exit is waited for
+    /// briefly before giving up.
+    async fn connection_error(&self, context: &str, e: std::io::Error) -> Error {
+        let closed = matches!(
+            e.kind(),
+            ErrorKind::UnexpectedEof | ErrorKind::BrokenPipe | ErrorKind::ConnectionReset
+        );
+        let attempts = if closed { EXIT_POLL_ATTEMPTS } else { 1 };
 
-        let response: PatcherResponse = deserialize_message(&payload)
-            .map_err(|e| Error::Ipc(format!("deserialize error: {}", e)))?;
+        for attempt in 0..attempts {
+            if attempt > 0 {
+                tokio::time::sleep(EXIT_POLL_INTERVAL).await;
+            }
+            if let Some(status) = self.exit_status().await {
+                return Error::PatcherExited(status);
+            }
+        }
 
-        Ok(response)
+        Error::Ipc(format!("{}: {}", context, e))
     }
 
-    /// Check if the child process is still alive
-    async fn check_child_alive(&self) -> bool {
-        let mut state = self.state.lock().await;
-        if let Some(ref mut child) = state.child_process {
-            match child.try_wait() {
-                Ok(None) => true, // Still running
-                Ok(Some(status)) => {
-                    error!("patcher process exited unexpectedly: {:?}", status);
-                    self.is_running.store(false, Ordering::SeqCst);
-                    false
-                }
-                Err(e) => {
-                    error!("error checking patcher status: {}", e);
-                    false
-                }
-            }
+    /// Features supported by both the launcher and the patcher
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    /// Fail unless the patcher supports `capability`
+    fn require(&self, capability: Capabilities, feature: &str) -> Result<(), Error> {
+        if self.capabilities.contains(capability) {
+            Ok(())
         } else {
-            false
+            Err(Error::Ipc(format!("patcher does not support {}", feature)))
         }
     }
 
@@ -256,17 +343,16 @@ impl UnixSocketPatcherIpc {
         self.send_internal(&PatcherRequest::Shutdown).await?;
         self.is_running.store(false, Ordering::SeqCst);
 
-        // Close the stream and wait for child
+        // Close the stream and wait for child; the patcher cancels its jobs at
+        // the next safe point before exiting
         let mut state = self.state.lock().await;
-        state.stream = None;
+        state.writer = None;
 
         if let Some(mut child) = state.child_process.take() {
             drop(state); // Release lock before blocking
 
             match tokio::time::timeout(Duration::from_secs(5), async {
-                tokio::task::spawn_blocking(move || child.wait())
-                    .await
-                    .ok()
+                tokio::task::spawn_blocking(move || child.wait()).await.ok()
             })
             .await
             {
@@ -296,6 +382,8 @@ impl UnixSocketPatcherIpc {
 
 impl Drop for UnixSocketPatcherIpc {
     fn drop(&mut self) {
+        self.reader.abort();
+
         // Clean up socket file on drop
         if self.socket_path.exists() {
             std::fs::remove_file(&self.socket_path).ok();
@@ -311,90 +399,158 @@ impl Drop for UnixSocketPatcherIpc {
     }
 }
 
+/// Read messages from the patcher until the connection fails
+async fn read_responses(mut reader: RecvHalf, responses: mpsc::UnboundedSender<Received>) {
+    loop {
+        let received = read_response(&mut reader).await;
+        // An unknown message from a newer patcher leaves the connection usable
+        let fatal = matches!(&received, Err(e) if !matches!(e, Error::IpcUnknownMessage(_)));
+        if responses.send(received).is_err() || fatal {
+            break;
+        }
+    }
+}
+
+/// Read one length-prefixed message
+async fn read_response(reader: &mut RecvHalf) -> Result<PatcherResponse, Error> {
+    let mut header = [0u8; MESSAGE_HEADER_SIZE];
+    reader.read_exact(&mut header).await?;
+
+    let len = u32::from_be_bytes(header);
+    if len > MAX_MESSAGE_SIZE {
+        return Err(Error::Ipc(format!(
+            "message too large: {} bytes (max {})",
+            len, MAX_MESSAGE_SIZE
+        )));
+    }
+
+    let mut payload = vec![0u8; len as usize];
+    reader.read_exact(&mut payload).await?;
+
+    deserialize_message(&payload)
+}
+
 #[async_trait]
 impl PatcherIpc for UnixSocketPatcherIpc {
-    async fn start_patch(&self, patches: Vec<PatchEntry>, game_path: &Path) -> Result<(), Error> {
+    async fn start_patch(
+        &self,
+        patches: Vec<PatchEntry>,
+        game_path: &Path,
+        patch_dir: &Path,
+        unique_id: Option<&str>,
+        settings: &PatchSettings,
+    ) -> Result<u64, Error> {
+        self.require(Capabilities::DOWNLOAD, "downloading patches")?;
+
+        let job_id = self.next_job_id.fetch_add(1, Ordering::Relaxed);
         let request = PatcherRequest::StartPatch {
+            job_id,
             patches,
             game_path: game_path.to_path_buf(),
+            patch_dir: patch_dir.to_path_buf(),
+            unique_id: unique_id.map(str::to_string),
+            settings: settings.clone(),
             keep_patches: false,
         };
 
-        self.send_internal(&request).await
+        self.send_internal(&request).await?;
+        Ok(job_id)
     }
 
-    async fn receive_progress(&self) -> Result<Option<PatchProgress>, Error> {
-        // Check if child is still alive
-        if !self.check_child_alive().await {
-            return Err(Error::Ipc("patcher process died".into()));
-        }
-
-        // Use a shorter timeout for progress polling
-        let response = match self.recv_internal(Duration::from_millis(100)).await {
-            Ok(resp) => resp,
-            Err(Error::Ipc(msg)) if msg.contains("timeout") => {
-                // Timeout is expected when no progress available
-                return Ok(None);
+    async fn receive_event(&self) -> Result<Option<PatcherEvent>, Error> {
+        let response = {
+            let mut inbox = self.inbox.lock().await;
+            match inbox.pending.pop_front() {
+                Some(response) => response,
+                None => match self.next_response(&mut inbox, POLL_TIMEOUT).await {
+                    Ok(Some(response)) => response,
+                    Ok(None) => {
+                        // Nothing arrived, make sure the patcher is still there
+                        return match self.exit_status().await {
+                            Some(status) => Err(Error::PatcherExited(status)),
+                            None => Ok(None),
+                        };
+                    }
+                    Err(Error::IpcUnknownMessage(kind)) => {
+                        warn!("ignoring unknown message from patcher: {}", kind);
+                        return Ok(None);
+                    }
+                    Err(e) => return Err(e),
+                },
             }
-            Err(e) => return Err(e),
         };
 
         match response {
-            PatcherResponse::Progress {
+            PatcherResponse::Progress(report) => Ok(Some(PatcherEvent::Progress(report.into()))),
+            PatcherResponse::PatchCompleted {
+                job_id,
+                patch_index,
                 version_id,
-                repository,
-                state,
-                bytes_processed,
-                bytes_total,
-                ..
-            } => {
-                let patch = PatchEntry {
-                    version_id,
-                    url: String::new(),
-                    length: bytes_total,
-                    hash_type: None,
-                    hash_block_size: None,
-                    hashes: None,
-                    repository,
-                };
-
-                Ok(Some(PatchProgress {
-                    patch,
-                    state,
-                    bytes_downloaded: bytes_processed,
-                    bytes_total,
-                    speed_bytes_per_sec: 0.0, // Not tracked in IPC
-                }))
-            }
-            PatcherResponse::PatchCompleted { version_id, .. } => {
-                let patch = PatchEntry {
-                    version_id,
-                    url: String::new(),
-                    length: 0,
-                    hash_type: None,
-                    hash_block_size: None,
-                    hashes: None,
-                    repository: gaveloc_core::entities::Repository::Ffxiv,
-                };
-
-                Ok(Some(PatchProgress {
-                    patch,
-                    state: PatchState::Completed,
-                    bytes_downloaded: 0,
-                    bytes_total: 0,
-                    speed_bytes_per_sec: 0.0,
-                }))
+            } => Ok(Some(PatcherEvent::PatchCompleted {
+                job_id,
+                patch_index,
+                version_id,
+            })),
+            PatcherResponse::JobCompleted { job_id } => {
+                Ok(Some(PatcherEvent::JobCompleted { job_id }))
             }
-            PatcherResponse::AllCompleted => {
-                // Signal completion by returning None
-                Ok(None)
+            PatcherResponse::JobCancelled { job_id } => {
+                Ok(Some(PatcherEvent::JobCancelled { job_id }))
             }
-            PatcherResponse::Error { message } => Err(Error::ZiPatchApply(message)),
-            PatcherResponse::Cancelled => Err(Error::Cancelled),
+            PatcherResponse::Error {
+                job_id: Some(job_id),
+                message,
+            } => Ok(Some(PatcherEvent::JobFailed { job_id, message })),
+            // The patcher may fail while downloading, verifying or applying,
+            // and its message already says which
+            PatcherResponse::Error {
+                job_id: None,
+                message,
+            } => Err(Error::Other(message)),
+            // Answer to a status query that gave up waiting
+            PatcherResponse::Status { .. } => Ok(None),
             other => Err(Error::Ipc(format!("unexpected response: {:?}", other))),
         }
     }
 
+    async fn pause(&self) -> Result<(), Error> {
+        self.require(Capabilities::PAUSE, "pausing")?;
+        self.send_internal(&PatcherRequest::Pause).await
+    }
+
+    async fn resume(&self) -> Result<(), Error> {
+        self.require(Capabilities::PAUSE, "pausing")?;
+        self.send_internal(&PatcherRequest::Resume).await
+    }
+
+    async fn query_status(&self) -> Result<PatcherStatus, Error> {
+        self.require(Capabilities::STATUS, "status queries")?;
+
+        // Hold the inbox so the answer cannot be taken by receive_event
+        let mut inbox = self.inbox.lock().await;
+        self.send_internal(&PatcherRequest::QueryStatus).await?;
+
+        let deadline = tokio::time::Instant::now() + STATUS_TIMEOUT;
+        loop {
+            let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+            match self.next_response(&mut inbox, remaining).await? {
+                Some(PatcherResponse::Status {
+                    paused,
+                    current,
+                    queued_jobs,
+                }) => {
+                    return Ok(PatcherStatus {
+                        paused,
+                        current: current.map(Into::into),
+                        queued_jobs,
+                    })
+                }
+                Some(other) => inbox.pending.push_back(other),
+                None => return Err(Error::Ipc("status query timeout".into())),
+            }
+        }
+    }
+
     async fn cancel(&self) -> Result<(), Error> {
         self.send_internal(&PatcherRequest::Cancel).await
     }
@@ -407,6 +563,8 @@ impl PatcherIpc for UnixSocketPatcherIpc {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::ipc::protocol::ProgressReport;
+    use gaveloc_core::entities::{PatchState, Repository};
 
     #[test]
     fn test_socket_path_generation() {
@@ -416,6 +574,225 @@ mod tests {
         assert!(path_str.ends_with(".sock"));
     }
 
+    /// Client connected to a socket on which the test plays the patcher
+    async fn fake_patcher(
+        name: &str,
+        child: Option<Child>,
+        capabilities: Capabilities,
+    ) -> (UnixSocketPatcherIpc, Stream) {
+        let socket_path = std::env::temp_dir().join(format!(
+            "gaveloc_patcher_test_{}_{}.sock",
+            std::process::id(),
+            name
+        ));
+        std::fs::remove_file(&socket_path).ok();
+
+        let socket_name = || {
+            socket_path
+                .clone()
+                .to_fs_name::<GenericFilePath>()
+                .unwrap()
+        };
+        let listener = ListenerOptions::new()
+            .name(socket_name())
+            .create_tokio()
+            .unwrap();
+        let (launcher, patcher) = tokio::join!(listener.accept(), Stream::connect(socket_name()));
+
+        let mut ipc = UnixSocketPatcherIpc::connect(socket_path, launcher.unwrap(), child);
+        ipc.capabilities = capabilities;
+        (ipc, patcher.unwrap())
+    }
+
+    /// Child process that has already exited with `code`
+    fn exited_child(code: i32) -> Child {
+        let mut child = Command::new("sh")
+            .args(["-c", &format!("exit {}", code)])
+            .spawn()
+            .unwrap();
+        child.wait().unwrap();
+        child
+    }
+
+    async fn send_response(patcher: &mut Stream, response: &PatcherResponse) {
+        patcher
+            .write_all(&serialize_message(response).unwrap())
+            .await
+            .unwrap();
+    }
+
+    async fn recv_request(patcher: &mut Stream) -> PatcherRequest {
+        let mut header = [0u8; MESSAGE_HEADER_SIZE];
+        patcher.read_exact(&mut header).await.unwrap();
+        let mut payload = vec![0u8; u32::from_be_bytes(header) as usize];
+        patcher.read_exact(&mut payload).await.unwrap();
+        deserialize_message(&payload).unwrap()
+    }
+
+    fn progress(job_id: u64) -> PatcherResponse {
+        PatcherResponse::Progress(ProgressReport {
+            job_id,
+            patch_index: 0,
+            total_patches: 1,
+            version_id: "2024.07.23.0000.0001".to_string(),
+            repository: Repository::Ffxiv,
+            state: PatchState::Downloading,
+            bytes_processed: 512,
+            bytes_total: 1024,
+            bytes_per_sec: 256.0,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_receive_event_reports_exited_patcher() {
+        let (ipc, patcher) =
+            fake_patcher("exited", Some(exited_child(3)), Capabilities::SUPPORTED).await;
+        drop(patcher);
+
+        match ipc.receive_event().await {
+            Err(Error::PatcherExited(status)) => assert!(status.contains('3')),
+            other => panic!("expected PatcherExited, got {:?}", other),
+        }
+        assert!(!ipc.is_running());
+    }
+
+    #[tokio::test]
+    async fn test_closed_connection_reports_exited_patcher() {
+        let (ipc, _patcher) =
+            fake_patcher("closed", Some(exited_child(4)), Capabilities::SUPPORTED).await;
+
+        let closed = std::io::Error::from(ErrorKind::UnexpectedEof);
+        assert!(matches!(
+            ipc.connection_error("read header error", closed).await,
+            Error::PatcherExited(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_requests_require_capabilities() {
+        let (ipc, _patcher) = fake_patcher("capabilities", None, Capabilities::NONE).await;
+
+        let err = ipc
+            .start_patch(
+                Vec::new(),
+                Path::new("/game"),
+                Path::new("/patches"),
+                None,
+                &PatchSettings::default(),
+            )
+            .await
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("does not support downloading patches"));
+        assert!(ipc.pause().await.is_err());
+        assert!(ipc.query_status().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_jobs_get_increasing_ids() {
+        let (ipc, mut patcher) = fake_patcher("jobs", None, Capabilities::SUPPORTED).await;
+
+        for expected in 1..=2 {
+            let job_id = ipc
+                .start_patch(
+                    Vec::new(),
+                    Path::new("/game"),
+                    Path::new("/patches"),
+                    None,
+                    &PatchSettings::default(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(job_id, expected);
+
+            match recv_request(&mut patcher).await {
+                PatcherRequest::StartPatch { job_id, .. } => assert_eq!(job_id, expected),
+                other => panic!("expected StartPatch, got {:?}", other),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_query_status_keeps_events() {
+        let (ipc, mut patcher) = fake_patcher("status", None, Capabilities::SUPPORTED).await;
+
+        let fake = tokio::spawn(async move {
+            // An event already on its way when the query is sent
+            send_response(&mut patcher, &progress(1)).await;
+            assert!(matches!(
+                recv_request(&mut patcher).await,
+                PatcherRequest::QueryStatus
+            ));
+            let status = PatcherResponse::Status {
+                paused: true,
+                current: match progress(1) {
+                    PatcherResponse::Progress(report) => Some(report),
+                    _ => unreachable!(),
+                },
+                queued_jobs: vec![2],
+            };
+            send_response(&mut patcher, &status).await;
+            send_response(&mut patcher, &PatcherResponse::JobCompleted { job_id: 1 }).await;
+            patcher
+        });
+
+        let status = ipc.query_status().await.unwrap();
+        assert!(status.paused);
+        assert_eq!(status.current.unwrap().job_id, 1);
+        assert_eq!(status.queued_jobs, vec![2]);
+
+        match ipc.receive_event().await.unwrap() {
+            Some(PatcherEvent::Progress(progress)) => {
+                assert_eq!(progress.job_id, 1);
+                assert_eq!(progress.progress.bytes_downloaded, 512);
+                assert_eq!(progress.progress.speed_bytes_per_sec, 256.0);
+            }
+            other => panic!("expected progress, got {:?}", other),
+        }
+        assert!(matches!(
+            ipc.receive_event().await.unwrap(),
+            Some(PatcherEvent::JobCompleted { job_id: 1 })
+        ));
+        assert!(ipc.receive_event().await.unwrap().is_none());
+
+        fake.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_receive_event_maps_errors() {
+        let (ipc, mut patcher) = fake_patcher("errors", None, Capabilities::SUPPORTED).await;
+
+        send_response(
+            &mut patcher,
+            &PatcherResponse::Error {
+                job_id: Some(4),
+                message: "patch verification failed".to_string(),
+            },
+        )
+        .await;
+        send_response(
+            &mut patcher,
+            &PatcherResponse::Error {
+                job_id: None,
+                message: "unsupported request: Rewind".to_string(),
+            },
+        )
+        .await;
+
+        match ipc.receive_event().await.unwrap() {
+            Some(PatcherEvent::JobFailed { job_id, message }) => {
+                assert_eq!(job_id, 4);
+                assert_eq!(message, "patch verification failed");
+            }
+            other => panic!("expected job failure, got {:?}", other),
+        }
+        assert!(matches!(
+            ipc.receive_event().await,
+            Err(Error::Other(message)) if message.contains("Rewind")
+        ));
+    }
+
     #[test]
     fn test_find_patcher_binary_error() {
         // This will fail in test environment since binary doesn't exist
@@ -9,6 +9,6 @@ mod protocol;
 
 pub use client::UnixSocketPatcherIpc;
 pub use protocol::{
-    deserialize_message, serialize_message, PatcherRequest, PatcherResponse, MAX_MESSAGE_SIZE,
-    MESSAGE_HEADER_SIZE,
+    deserialize_message, serialize_message, Capabilities, Message, PatcherRequest, PatcherResponse,
+    ProgressReport, MAX_MESSAGE_SIZE, MESSAGE_HEADER_SIZE, PROTOCOL_VERSION,
 };
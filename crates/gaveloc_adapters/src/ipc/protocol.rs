@@ -3,97 +3,318 @@
 //! The patcher runs as a separate process to isolate patch application from the
 //! main launcher UI. Communication happens over Unix domain sockets using bincode
 //! serialization with length-prefixed messages.
+//!
+//! Every message is wrapped in an [`Envelope`] carrying the protocol version and
+//! the message kind, so a launcher and patcher from incompatible builds reject
+//! each other instead of misdecoding, and a message kind added by a newer build
+//! is reported by name. Optional features are negotiated with [`Capabilities`]
+//! during the `Ready`/`Hello` handshake.
+//!
+//! Adding a message variant at the end of an enum keeps the protocol
+//! compatible; changing an existing variant requires a new [`PROTOCOL_VERSION`].
 
 use std::path::PathBuf;
 
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
-use gaveloc_core::entities::{PatchEntry, PatchState, Repository};
+use gaveloc_core::config::PatchSettings;
+use gaveloc_core::entities::{
+    PatchEntry, PatchProgress, PatchState, PatcherJobProgress, Repository,
+};
+use gaveloc_core::error::Error;
+
+/// Version of the wire format, bumped on incompatible changes
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Marks a versioned message, so messages of pre-versioning builds are rejected
+const ENVELOPE_MAGIC: [u8; 4] = *b"GVLP";
+
+/// Optional features supported by a launcher or patcher build
+///
+/// Each side announces its set in the handshake and only features both
+/// support are used. Bits unknown to this build are ignored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Capabilities(u32);
+
+impl Capabilities {
+    pub const NONE: Self = Self(0);
+    /// The patcher downloads and verifies patches itself
+    pub const DOWNLOAD: Self = Self(1 << 0);
+    /// Running jobs can be paused and resumed
+    pub const PAUSE: Self = Self(1 << 1);
+    /// The patcher answers status queries
+    pub const STATUS: Self = Self(1 << 2);
+    /// Jobs started while another runs are queued behind it
+    pub const QUEUE: Self = Self(1 << 3);
+    /// Everything this build supports
+    pub const SUPPORTED: Self = Self::DOWNLOAD
+        .union(Self::PAUSE)
+        .union(Self::STATUS)
+        .union(Self::QUEUE);
+
+    pub const fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    /// Features supported by both sides
+    pub const fn intersection(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+}
 
 /// Messages sent from the launcher to the patcher process
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum PatcherRequest {
-    /// Initial handshake - patcher should respond with Ready
+    /// Handshake reply to Ready
     Hello {
         /// Process ID of the parent launcher
         parent_pid: u32,
+        /// Features supported by the launcher
+        capabilities: Capabilities,
     },
 
-    /// Start applying a batch of patches
+    /// Queue a job downloading, verifying and applying a batch of patches
+    ///
+    /// Patch files already present in `patch_dir` are resumed or reused after
+    /// verification, so the launcher may also download them itself.
     StartPatch {
+        /// ID chosen by the launcher, reported back with the job's progress
+        job_id: u64,
         /// List of patches to apply in order
         patches: Vec<PatchEntry>,
         /// Path to game installation directory
         game_path: PathBuf,
+        /// Directory patch files are downloaded to
+        patch_dir: PathBuf,
+        /// Session ID for game patch downloads (None for boot patches)
+        unique_id: Option<String>,
+        /// Download rate cap and window
+        settings: PatchSettings,
         /// Keep patch files after successful application (for debugging)
         keep_patches: bool,
     },
 
-    /// Cancel the current patch operation
+    /// Cancel the running job and all queued jobs
     Cancel,
 
-    /// Graceful shutdown - patcher should exit after responding
+    /// Graceful shutdown - running and queued jobs are cancelled first
     Shutdown,
+
+    /// Pause the running job at its next safe point; queued jobs wait too
+    Pause,
+
+    /// Resume after Pause
+    Resume,
+
+    /// Ask for a Status response
+    QueryStatus,
 }
 
 /// Messages sent from the patcher back to the launcher
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum PatcherResponse {
-    /// Patcher is ready to receive commands
-    Ready,
-
-    /// Progress update for current patch operation
-    Progress {
-        /// Index of current patch in the batch (0-based)
-        patch_index: usize,
-        /// Total number of patches in the batch
-        total_patches: usize,
-        /// Version ID of the current patch
-        version_id: String,
-        /// Repository being patched
-        repository: Repository,
-        /// Current state of the patch operation
-        state: PatchState,
-        /// Bytes processed for current patch
-        bytes_processed: u64,
-        /// Total bytes for current patch file
-        bytes_total: u64,
+    /// Patcher is ready to receive commands, sent once after connecting
+    Ready {
+        /// Features supported by the patcher
+        capabilities: Capabilities,
     },
 
-    /// A single patch was successfully applied
+    /// Progress update for the running job
+    Progress(ProgressReport),
+
+    /// A patch of a job was applied and its version file updated
     PatchCompleted {
+        /// Job the patch belongs to
+        job_id: u64,
         /// Index of the completed patch
         patch_index: usize,
         /// Version ID of the completed patch
         version_id: String,
     },
 
-    /// All patches in the batch were successfully applied
-    AllCompleted,
+    /// All patches of a job were successfully applied
+    JobCompleted {
+        /// ID of the completed job
+        job_id: u64,
+    },
 
-    /// An error occurred during patch application
+    /// An error occurred
     Error {
+        /// Job that failed, `None` for errors not tied to a job such as an
+        /// unsupported request
+        job_id: Option<u64>,
         /// Human-readable error message
         message: String,
     },
 
-    /// Operation was cancelled in response to Cancel request
-    Cancelled,
+    /// A job was cancelled in response to a Cancel or Shutdown request
+    JobCancelled {
+        /// ID of the cancelled job
+        job_id: u64,
+    },
+
+    /// Answer to QueryStatus
+    Status {
+        /// Whether patching is paused
+        paused: bool,
+        /// Latest progress of the running job, `None` while idle
+        current: Option<ProgressReport>,
+        /// Jobs waiting to run, in order
+        queued_jobs: Vec<u64>,
+    },
+}
+
+/// Progress of the running job
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProgressReport {
+    /// Job being run
+    pub job_id: u64,
+    /// Index of current patch in the job (0-based)
+    pub patch_index: usize,
+    /// Total number of patches in the job
+    pub total_patches: usize,
+    /// Version ID of the current patch
+    pub version_id: String,
+    /// Repository being patched
+    pub repository: Repository,
+    /// Current state of the patch operation
+    pub state: PatchState,
+    /// Bytes processed for current patch
+    pub bytes_processed: u64,
+    /// Total bytes for current patch file
+    pub bytes_total: u64,
+    /// Download speed, 0 outside of downloading
+    pub bytes_per_sec: f64,
+}
+
+impl From<ProgressReport> for PatcherJobProgress {
+    fn from(report: ProgressReport) -> Self {
+        let patch = PatchEntry {
+            version_id: report.version_id,
+            url: String::new(),
+            length: report.bytes_total,
+            hash_type: None,
+            hash_block_size: None,
+            hashes: None,
+            repository: report.repository,
+        };
+
+        Self {
+            job_id: report.job_id,
+            patch_index: report.patch_index,
+            total_patches: report.total_patches,
+            progress: PatchProgress {
+                patch,
+                state: report.state,
+                bytes_downloaded: report.bytes_processed,
+                bytes_total: report.bytes_total,
+                speed_bytes_per_sec: report.bytes_per_sec,
+            },
+        }
+    }
+}
+
+impl Message for PatcherRequest {
+    const KINDS: &'static [&'static str] = &[
+        "Hello",
+        "StartPatch",
+        "Cancel",
+        "Shutdown",
+        "Pause",
+        "Resume",
+        "QueryStatus",
+    ];
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::Hello { .. } => "Hello",
+            Self::StartPatch { .. } => "StartPatch",
+            Self::Cancel => "Cancel",
+            Self::Shutdown => "Shutdown",
+            Self::Pause => "Pause",
+            Self::Resume => "Resume",
+            Self::QueryStatus => "QueryStatus",
+        }
+    }
+}
+
+impl Message for PatcherResponse {
+    const KINDS: &'static [&'static str] = &[
+        "Ready",
+        "Progress",
+        "PatchCompleted",
+        "JobCompleted",
+        "Error",
+        "JobCancelled",
+        "Status",
+    ];
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::Ready { .. } => "Ready",
+            Self::Progress(_) => "Progress",
+            Self::PatchCompleted { .. } => "PatchCompleted",
+            Self::JobCompleted { .. } => "JobCompleted",
+            Self::Error { .. } => "Error",
+            Self::JobCancelled { .. } => "JobCancelled",
+            Self::Status { .. } => "Status",
+        }
+    }
+}
+
+/// A message type that can be sent over the patcher connection
+pub trait Message: Serialize + DeserializeOwned {
+    /// Names of all message kinds known to this build
+    const KINDS: &'static [&'static str];
+
+    /// Name of this message's kind
+    fn kind(&self) -> &'static str;
+}
+
+/// Versioned wrapper around every message
+///
+/// The layout of this struct must never change, since it is how builds of
+/// different protocol versions recognise each other.
+#[derive(Debug, Serialize, Deserialize)]
+struct Envelope {
+    magic: [u8; 4],
+    protocol_version: u32,
+    kind: String,
+    payload: Vec<u8>,
 }
 
 /// Wire format for IPC messages - length-prefixed bincode
 ///
 /// Message format:
 /// - 4 bytes: message length (u32 big-endian)
-/// - N bytes: bincode-serialized message
+/// - N bytes: bincode-serialized [`Envelope`] holding the bincode-serialized message
 pub const MESSAGE_HEADER_SIZE: usize = 4;
 
 /// Maximum message size to prevent memory exhaustion (16 MB)
 pub const MAX_MESSAGE_SIZE: u32 = 16 * 1024 * 1024;
 
 /// Serialize a message to bytes with length prefix
-pub fn serialize_message<T: Serialize>(msg: &T) -> Result<Vec<u8>, bincode::Error> {
-    let payload = bincode::serialize(msg)?;
+pub fn serialize_message<T: Message>(msg: &T) -> Result<Vec<u8>, Error> {
+    let envelope = Envelope {
+        magic: ENVELOPE_MAGIC,
+        protocol_version: PROTOCOL_VERSION,
+        kind: msg.kind().to_string(),
+        payload: bincode::serialize(msg).map_err(encoding_error)?,
+    };
+    let payload = bincode::serialize(&envelope).map_err(encoding_error)?;
     let len = payload.len() as u32;
 
     let mut buf = Vec::with_capacity(MESSAGE_HEADER_SIZE + payload.len());
@@ -104,17 +325,55 @@ pub fn serialize_message<T: Serialize>(msg: &T) -> Result<Vec<u8>, bincode::Erro
 }
 
 /// Deserialize a message from bytes (without length prefix)
-pub fn deserialize_message<T: for<'de> Deserialize<'de>>(data: &[u8]) -> Result<T, bincode::Error> {
-    bincode::deserialize(data)
+///
+/// Fails with [`Error::IpcProtocolMismatch`] for a message of another protocol
+/// version and with [`Error::IpcUnknownMessage`] for a message kind this build
+/// does not know.
+pub fn deserialize_message<T: Message>(data: &[u8]) -> Result<T, Error> {
+    if !data.starts_with(&ENVELOPE_MAGIC) {
+        return Err(Error::Ipc(
+            "received an unversioned message; the other side is from an incompatible build".into(),
+        ));
+    }
+
+    let envelope: Envelope = bincode::deserialize(data).map_err(encoding_error)?;
+    if envelope.protocol_version != PROTOCOL_VERSION {
+        return Err(Error::IpcProtocolMismatch {
+            ours: PROTOCOL_VERSION,
+            theirs: envelope.protocol_version,
+        });
+    }
+    if !T::KINDS.contains(&envelope.kind.as_str()) {
+        return Err(Error::IpcUnknownMessage(envelope.kind));
+    }
+
+    let msg: T = bincode::deserialize(&envelope.payload).map_err(encoding_error)?;
+    if msg.kind() != envelope.kind {
+        return Err(Error::Ipc(format!(
+            "message labelled {} decoded as {}",
+            envelope.kind,
+            msg.kind()
+        )));
+    }
+
+    Ok(msg)
+}
+
+fn encoding_error(e: bincode::Error) -> Error {
+    Error::Ipc(format!("message encoding error: {}", e))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use gaveloc_core::config::{DownloadWindow, TimeOfDay};
 
     #[test]
     fn test_serialize_deserialize_request() {
-        let request = PatcherRequest::Hello { parent_pid: 12345 };
+        let request = PatcherRequest::Hello {
+            parent_pid: 12345,
+            capabilities: Capabilities::SUPPORTED,
+        };
         let bytes = serialize_message(&request).unwrap();
 
         // Check length prefix
@@ -125,14 +384,20 @@ mod tests {
         // Deserialize payload (skip length prefix)
         let decoded: PatcherRequest = deserialize_message(&bytes[MESSAGE_HEADER_SIZE..]).unwrap();
         match decoded {
-            PatcherRequest::Hello { parent_pid } => assert_eq!(parent_pid, 12345),
+            PatcherRequest::Hello {
+                parent_pid,
+                capabilities,
+            } => {
+                assert_eq!(parent_pid, 12345);
+                assert_eq!(capabilities, Capabilities::SUPPORTED);
+            }
             _ => panic!("unexpected variant"),
         }
     }
 
-    #[test]
-    fn test_serialize_deserialize_response() {
-        let response = PatcherResponse::Progress {
+    fn progress_report() -> ProgressReport {
+        ProgressReport {
+            job_id: 7,
             patch_index: 0,
             total_patches: 5,
             version_id: "2024.07.23.0000.0001".to_string(),
@@ -140,33 +405,69 @@ mod tests {
             state: PatchState::Installing,
             bytes_processed: 1024,
             bytes_total: 4096,
+            bytes_per_sec: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_serialize_deserialize_response() {
+        let response = PatcherResponse::Progress(progress_report());
+
+        let bytes = serialize_message(&response).unwrap();
+        let decoded: PatcherResponse = deserialize_message(&bytes[MESSAGE_HEADER_SIZE..]).unwrap();
+
+        match decoded {
+            PatcherResponse::Progress(report) => {
+                assert_eq!(report.job_id, 7);
+                assert_eq!(report.patch_index, 0);
+                assert_eq!(report.total_patches, 5);
+                assert_eq!(report.version_id, "2024.07.23.0000.0001");
+                assert_eq!(report.repository, Repository::Ffxiv);
+                assert_eq!(report.state, PatchState::Installing);
+                assert_eq!(report.bytes_processed, 1024);
+                assert_eq!(report.bytes_total, 4096);
+            }
+            _ => panic!("unexpected variant"),
+        }
+    }
+
+    #[test]
+    fn test_serialize_status() {
+        let response = PatcherResponse::Status {
+            paused: true,
+            current: Some(progress_report()),
+            queued_jobs: vec![8, 9],
         };
 
         let bytes = serialize_message(&response).unwrap();
         let decoded: PatcherResponse = deserialize_message(&bytes[MESSAGE_HEADER_SIZE..]).unwrap();
 
         match decoded {
-            PatcherResponse::Progress {
-                patch_index,
-                total_patches,
-                version_id,
-                repository,
-                state,
-                bytes_processed,
-                bytes_total,
+            PatcherResponse::Status {
+                paused,
+                current,
+                queued_jobs,
             } => {
-                assert_eq!(patch_index, 0);
-                assert_eq!(total_patches, 5);
-                assert_eq!(version_id, "2024.07.23.0000.0001");
-                assert_eq!(repository, Repository::Ffxiv);
-                assert_eq!(state, PatchState::Installing);
-                assert_eq!(bytes_processed, 1024);
-                assert_eq!(bytes_total, 4096);
+                assert!(paused);
+                assert_eq!(current.unwrap().job_id, 7);
+                assert_eq!(queued_jobs, vec![8, 9]);
             }
             _ => panic!("unexpected variant"),
         }
     }
 
+    #[test]
+    fn test_progress_report_into_job_progress() {
+        let progress = PatcherJobProgress::from(progress_report());
+
+        assert_eq!(progress.job_id, 7);
+        assert_eq!(progress.total_patches, 5);
+        assert_eq!(progress.progress.patch.version_id, "2024.07.23.0000.0001");
+        assert_eq!(progress.progress.state, PatchState::Installing);
+        assert_eq!(progress.progress.bytes_downloaded, 1024);
+        assert_eq!(progress.progress.bytes_total, 4096);
+    }
+
     #[test]
     fn test_serialize_start_patch() {
         let patches = vec![PatchEntry {
@@ -179,9 +480,21 @@ mod tests {
             repository: Repository::Ffxiv,
         }];
 
+        let settings = PatchSettings {
+            max_download_rate: Some(512),
+            download_window: Some(DownloadWindow {
+                start: TimeOfDay::new(1, 0).unwrap(),
+                end: TimeOfDay::new(6, 30).unwrap(),
+            }),
+        };
+
         let request = PatcherRequest::StartPatch {
+            job_id: 3,
             patches,
             game_path: PathBuf::from("/home/user/ffxiv"),
+            patch_dir: PathBuf::from("/tmp/gaveloc_patches"),
+            unique_id: Some("session".to_string()),
+            settings: settings.clone(),
             keep_patches: false,
         };
 
@@ -190,13 +503,22 @@ mod tests {
 
         match decoded {
             PatcherRequest::StartPatch {
+                job_id,
                 patches,
                 game_path,
+                patch_dir,
+                unique_id,
+                settings: decoded_settings,
                 keep_patches,
             } => {
+                assert_eq!(job_id, 3);
                 assert_eq!(patches.len(), 1);
                 assert_eq!(patches[0].version_id, "2024.07.23.0000.0001");
+                assert_eq!(patches[0].url, "http://example.com/patch.patch");
                 assert_eq!(game_path, PathBuf::from("/home/user/ffxiv"));
+                assert_eq!(patch_dir, PathBuf::from("/tmp/gaveloc_patches"));
+                assert_eq!(unique_id.as_deref(), Some("session"));
+                assert_eq!(decoded_settings, settings);
                 assert!(!keep_patches);
             }
             _ => panic!("unexpected variant"),
@@ -207,19 +529,32 @@ mod tests {
     fn test_all_request_variants() {
         // Test all variants can be serialized/deserialized
         let variants: Vec<PatcherRequest> = vec![
-            PatcherRequest::Hello { parent_pid: 1 },
+            PatcherRequest::Hello {
+                parent_pid: 1,
+                capabilities: Capabilities::NONE,
+            },
             PatcherRequest::StartPatch {
+                job_id: 1,
                 patches: vec![],
                 game_path: PathBuf::from("/test"),
+                patch_dir: PathBuf::from("/test/patches"),
+                unique_id: None,
+                settings: PatchSettings::default(),
                 keep_patches: true,
             },
             PatcherRequest::Cancel,
             PatcherRequest::Shutdown,
+            PatcherRequest::Pause,
+            PatcherRequest::Resume,
+            PatcherRequest::QueryStatus,
         ];
 
         for request in variants {
             let bytes = serialize_message(&request).unwrap();
-            let _: PatcherRequest = deserialize_message(&bytes[MESSAGE_HEADER_SIZE..]).unwrap();
+            let decoded: PatcherRequest =
+                deserialize_message(&bytes[MESSAGE_HEADER_SIZE..]).unwrap();
+            assert_eq!(decoded.kind(), request.kind());
+            assert!(PatcherRequest::KINDS.contains(&request.kind()));
         }
     }
 
@@ -227,30 +562,112 @@ mod tests {
     fn test_all_response_variants() {
         // Test all variants can be serialized/deserialized
         let variants: Vec<PatcherResponse> = vec![
-            PatcherResponse::Ready,
-            PatcherResponse::Progress {
-                patch_index: 0,
-                total_patches: 1,
-                version_id: "test".to_string(),
-                repository: Repository::Boot,
-                state: PatchState::Pending,
-                bytes_processed: 0,
-                bytes_total: 100,
+            PatcherResponse::Ready {
+                capabilities: Capabilities::SUPPORTED,
             },
+            PatcherResponse::Progress(progress_report()),
             PatcherResponse::PatchCompleted {
+                job_id: 1,
                 patch_index: 0,
                 version_id: "test".to_string(),
             },
-            PatcherResponse::AllCompleted,
+            PatcherResponse::JobCompleted { job_id: 1 },
             PatcherResponse::Error {
+                job_id: None,
                 message: "test error".to_string(),
             },
-            PatcherResponse::Cancelled,
+            PatcherResponse::JobCancelled { job_id: 1 },
+            PatcherResponse::Status {
+                paused: false,
+                current: None,
+                queued_jobs: Vec::new(),
+            },
         ];
 
         for response in variants {
             let bytes = serialize_message(&response).unwrap();
-            let _: PatcherResponse = deserialize_message(&bytes[MESSAGE_HEADER_SIZE..]).unwrap();
+            let decoded: PatcherResponse =
+                deserialize_message(&bytes[MESSAGE_HEADER_SIZE..]).unwrap();
+            assert_eq!(decoded.kind(), response.kind());
+            assert!(PatcherResponse::KINDS.contains(&response.kind()));
+        }
+    }
+
+    /// Encode a message the way a build speaking `protocol_version` would
+    fn envelope_bytes(protocol_version: u32, kind: &str, payload: Vec<u8>) -> Vec<u8> {
+        bincode::serialize(&Envelope {
+            magic: ENVELOPE_MAGIC,
+            protocol_version,
+            kind: kind.to_string(),
+            payload,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_rejects_other_protocol_version() {
+        let payload = bincode::serialize(&PatcherRequest::Cancel).unwrap();
+        let bytes = envelope_bytes(PROTOCOL_VERSION + 1, "Cancel", payload);
+
+        match deserialize_message::<PatcherRequest>(&bytes) {
+            Err(Error::IpcProtocolMismatch { ours, theirs }) => {
+                assert_eq!(ours, PROTOCOL_VERSION);
+                assert_eq!(theirs, PROTOCOL_VERSION + 1);
+            }
+            other => panic!("expected protocol mismatch, got {:?}", other),
         }
     }
+
+    #[test]
+    fn test_rejects_unversioned_message() {
+        // Builds before protocol versioning sent bare bincode enums
+        let bytes = bincode::serialize(&PatcherResponse::JobCompleted { job_id: 1 }).unwrap();
+
+        let err = deserialize_message::<PatcherResponse>(&bytes).unwrap_err();
+        assert!(err.to_string().contains("unversioned message"));
+    }
+
+    #[test]
+    fn test_unknown_message_kind_from_newer_build() {
+        // A newer build appends a variant this build cannot decode
+        let payload = bincode::serialize(&42u32).unwrap();
+        let bytes = envelope_bytes(PROTOCOL_VERSION, "Rewind", payload);
+
+        match deserialize_message::<PatcherRequest>(&bytes) {
+            Err(Error::IpcUnknownMessage(kind)) => assert_eq!(kind, "Rewind"),
+            other => panic!("expected unknown message, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_rejects_mislabelled_message() {
+        let payload = bincode::serialize(&PatcherRequest::Shutdown).unwrap();
+        let bytes = envelope_bytes(PROTOCOL_VERSION, "Cancel", payload);
+
+        assert!(matches!(
+            deserialize_message::<PatcherRequest>(&bytes),
+            Err(Error::Ipc(_))
+        ));
+    }
+
+    #[test]
+    fn test_capability_negotiation() {
+        // A newer peer may announce features this build has never heard of
+        let newer = Capabilities::from_bits(Capabilities::SUPPORTED.bits() | 1 << 31);
+        let negotiated = Capabilities::SUPPORTED.intersection(newer);
+        assert_eq!(negotiated, Capabilities::SUPPORTED);
+        assert!(negotiated.contains(Capabilities::DOWNLOAD));
+
+        // An older peer without pause support
+        let older = Capabilities::DOWNLOAD;
+        let negotiated = Capabilities::SUPPORTED.intersection(older);
+        assert!(negotiated.contains(Capabilities::DOWNLOAD));
+        assert!(!negotiated.contains(Capabilities::PAUSE));
+        assert!(!negotiated.contains(Capabilities::DOWNLOAD.union(Capabilities::STATUS)));
+        assert!(negotiated.contains(Capabilities::NONE));
+        assert_eq!(
+            Capabilities::NONE.union(Capabilities::DOWNLOAD),
+            Capabilities::DOWNLOAD
+        );
+    }
 }
@@ -21,6 +21,7 @@ pub mod zipatch;
 pub use accounts::FileAccountRepository;
 pub use config_repository::FileConfigRepository;
 pub use credentials::KeyringCredentialStore;
+pub use fs::LocalDiskSpace;
 pub use game_detection::{detect_game_installations, get_default_install_path, is_valid_game_path, validate_game_path, ValidationResult};
 pub use integrity::GoatcorpIntegrityChecker;
 pub use ipc::UnixSocketPatcherIpc;
@@ -28,8 +29,8 @@ pub use news::HttpNewsRepository;
 pub use network::{build_oauth_client, build_patch_client};
 pub use oauth::SquareEnixAuthenticator;
 pub use otp_listener::HttpOtpListener;
-pub use patch::{FileVersionRepository, HttpPatchDownloader, SquareEnixPatchServer};
+pub use patch::{FileVersionRepository, HttpPatchDownloader, SquareEnixPatchHistory, SquareEnixPatchServer};
 pub use prefix::LinuxPrefixManager;
 pub use process::LinuxProcessLauncher;
 pub use runner::{LinuxRunnerDetector, LinuxRunnerManager};
-pub use zipatch::ZiPatchParser;
+pub use zipatch::{ZiPatchParser, ZiPatchWriter};
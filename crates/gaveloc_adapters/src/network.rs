@@ -3,10 +3,14 @@
 //! Provides factory functions for creating properly configured HTTP clients
 //! with appropriate timeouts, user agents, and settings for different use cases.
 
+use std::sync::Mutex;
 use std::time::Duration;
 
+use chrono::Timelike;
+use gaveloc_core::config::{DownloadWindow, TimeOfDay};
 use gaveloc_core::Error;
 use reqwest::Client;
+use tokio::time::Instant;
 
 /// Default timeout for HTTP requests (30 seconds)
 pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
@@ -47,6 +51,87 @@ pub fn build_patch_client() -> Result<Client, Error> {
         .map_err(|e| Error::Network(format!("failed to create patch HTTP client: {}", e)))
 }
 
+/// Build a configured HTTP client for downloading patch files.
+///
+/// Unlike [`build_patch_client`] there is no overall request timeout, since a
+/// large or rate limited patch can take far longer than any fixed limit.
+/// A read timeout still fails a download whose connection stalls.
+pub fn build_patch_download_client() -> Result<Client, Error> {
+    Client::builder()
+        .user_agent(PATCHER_USER_AGENT)
+        .connect_timeout(DEFAULT_CONNECT_TIMEOUT)
+        .read_timeout(DEFAULT_TIMEOUT)
+        .build()
+        .map_err(|e| Error::Network(format!("failed to create patch HTTP client: {}", e)))
+}
+
+/// Caps the combined rate of every download sharing it.
+///
+/// Callers report bytes after receiving them and are delayed until the
+/// configured rate has caught up.
+#[derive(Debug)]
+pub struct RateLimiter {
+    bytes_per_sec: u64,
+    /// Time at which everything reported so far has been paid for
+    next_free: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec: bytes_per_sec.max(1),
+            next_free: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub fn bytes_per_sec(&self) -> u64 {
+        self.bytes_per_sec
+    }
+
+    /// Account for `bytes` just received, sleeping if that exceeds the rate
+    pub async fn consume(&self, bytes: u64) {
+        let cost = Duration::from_secs_f64(bytes as f64 / self.bytes_per_sec as f64);
+        let until = {
+            let mut next_free = self.next_free.lock().unwrap();
+            *next_free = (*next_free).max(Instant::now()) + cost;
+            *next_free
+        };
+        tokio::time::sleep_until(until).await;
+    }
+}
+
+/// Time of day of a clock reading, dropping the seconds
+fn time_of_day<T: Timelike>(time: &T) -> TimeOfDay {
+    TimeOfDay::new(time.hour() as u8, time.minute() as u8)
+        .expect("chrono returns a valid time of day")
+}
+
+/// Whether `window` is open at the current local time
+pub fn download_window_open(window: &DownloadWindow) -> bool {
+    window.contains(time_of_day(&chrono::Local::now()))
+}
+
+/// Wait until `window` is open, returning immediately if it already is
+pub async fn wait_for_download_window(window: &DownloadWindow) {
+    loop {
+        let now = chrono::Local::now();
+        let minutes = window.minutes_until_open(time_of_day(&now));
+        if minutes == 0 {
+            return;
+        }
+
+        tracing::info!(
+            "Outside download window {}, waiting until {}",
+            window,
+            window.start
+        );
+        // Sleep to the start of the opening minute, then check again in case
+        // the clock changed in the meantime
+        let secs = minutes as u64 * 60 - now.second().min(59) as u64;
+        tokio::time::sleep(Duration::from_secs(secs)).await;
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -63,6 +148,33 @@ mod tests {
         assert!(client.is_ok());
     }
 
+    #[test]
+    fn test_build_patch_download_client() {
+        assert!(build_patch_download_client().is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rate_limiter_delays_to_rate() {
+        let limiter = RateLimiter::new(1000);
+        let start = Instant::now();
+
+        limiter.consume(500).await;
+        limiter.consume(1500).await;
+
+        assert_eq!(start.elapsed(), Duration::from_secs(2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rate_limiter_does_not_bank_idle_time() {
+        let limiter = RateLimiter::new(1000);
+        tokio::time::sleep(Duration::from_secs(10)).await;
+
+        let start = Instant::now();
+        limiter.consume(1000).await;
+
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+    }
+
     #[test]
     fn test_timeout_constants() {
         assert_eq!(DEFAULT_TIMEOUT, Duration::from_secs(30));
@@ -29,6 +29,12 @@ impl HttpNewsRepository {
     }
 }
 
+impl Default for HttpNewsRepository {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[async_trait]
 impl NewsRepository for HttpNewsRepository {
     async fn get_headlines(&self, language: &str) -> Result<Headlines, Error> {
@@ -2,90 +2,460 @@
 //!
 //! Downloads patch files with progress reporting and hash verification.
 
-use std::path::Path;
-use std::sync::Arc;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use async_trait::async_trait;
 use futures_util::StreamExt;
-use reqwest::Client;
+use reqwest::header::RANGE;
+use reqwest::{Client, StatusCode};
 use sha1::{Digest, Sha1};
-use tokio::fs::File;
-use tokio::io::AsyncWriteExt;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::task::JoinSet;
+use tokio::time::Instant;
 use tracing::instrument;
 
-use gaveloc_core::entities::PatchEntry;
+use gaveloc_core::config::{DownloadWindow, PatchSettings};
+use gaveloc_core::control::UpdateControl;
+use gaveloc_core::entities::{PatchEntry, PatchVerificationReport};
 use gaveloc_core::error::Error;
 use gaveloc_core::ports::PatchDownloader;
 
-use crate::network::build_patch_client;
+use crate::network::{
+    build_patch_download_client, download_window_open, wait_for_download_window, RateLimiter,
+};
+
+/// Buffer size used when hashing patch blocks
+const HASH_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Connections used for segmented downloads unless configured otherwise
+const DEFAULT_CONNECTIONS: usize = 4;
+
+/// Times a block is fetched before its hash mismatch fails the download
+const MAX_BLOCK_ATTEMPTS: u32 = 3;
+
+/// How often a running transfer checks whether the download window closed
+const WINDOW_CHECK_INTERVAL: Duration = Duration::from_secs(1);
 
 /// HTTP-based patch downloader
+///
+/// Patches with block hashes are fetched as several block-aligned ranges in
+/// parallel, verifying every block as it arrives. Other patches, and servers
+/// that ignore range requests, use a single resumable stream.
+///
+/// An optional rate cap is shared by all connections, and an optional daily
+/// download window pauses transfers while it is closed.
+#[derive(Clone)]
 pub struct HttpPatchDownloader {
     client: Client,
+    connections: usize,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    download_window: Option<DownloadWindow>,
+}
+
+/// A run of consecutive hash blocks fetched with one range request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Segment {
+    first_block: usize,
+    block_count: usize,
+    attempt: u32,
+}
+
+/// State shared by the workers of a segmented download
+struct SegmentedDownload {
+    client: Client,
+    url: String,
+    unique_id: Option<String>,
+    dest_path: PathBuf,
+    length: u64,
+    block_size: u64,
+    hashes: Vec<String>,
+    queue: Mutex<VecDeque<Segment>>,
+    downloaded: AtomicU64,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    download_window: Option<DownloadWindow>,
+    control: UpdateControl,
+}
+
+/// Applies the rate cap, download window and pause/cancel requests to one
+/// running transfer
+struct Throttle<'a> {
+    rate_limiter: Option<&'a RateLimiter>,
+    download_window: Option<&'a DownloadWindow>,
+    control: &'a UpdateControl,
+    next_window_check: Instant,
+}
+
+impl<'a> Throttle<'a> {
+    fn new(
+        rate_limiter: Option<&'a RateLimiter>,
+        download_window: Option<&'a DownloadWindow>,
+        control: &'a UpdateControl,
+    ) -> Self {
+        Self {
+            rate_limiter,
+            download_window,
+            control,
+            next_window_check: Instant::now() + WINDOW_CHECK_INTERVAL,
+        }
+    }
+
+    /// Account for `bytes` just received
+    ///
+    /// Returns `Ok(false)` once the transfer is paused or the download window
+    /// has closed, and `Error::Cancelled` once it is cancelled.
+    async fn after_chunk(&mut self, bytes: u64) -> Result<bool, Error> {
+        if let Some(limiter) = self.rate_limiter {
+            limiter.consume(bytes).await;
+        }
+
+        if self.control.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        if self.control.is_paused() {
+            return Ok(false);
+        }
+
+        Ok(match self.download_window {
+            Some(window) if Instant::now() >= self.next_window_check => {
+                self.next_window_check = Instant::now() + WINDOW_CHECK_INTERVAL;
+                download_window_open(window)
+            }
+            _ => true,
+        })
+    }
 }
 
 impl HttpPatchDownloader {
     pub fn new() -> Result<Self, Error> {
-        let client = build_patch_client()?;
-        Ok(Self { client })
+        let client = build_patch_download_client()?;
+        Ok(Self {
+            client,
+            connections: DEFAULT_CONNECTIONS,
+            rate_limiter: None,
+            download_window: None,
+        })
+    }
+
+    /// Set how many connections a segmented download may use
+    ///
+    /// A value of 1 always downloads patches as a single stream.
+    pub fn with_connections(mut self, connections: usize) -> Self {
+        self.connections = connections.max(1);
+        self
+    }
+
+    /// Apply the rate cap and download window from the patch settings
+    pub fn with_patch_settings(mut self, settings: &PatchSettings) -> Self {
+        self.rate_limiter = settings
+            .max_download_bytes_per_sec()
+            .map(|rate| Arc::new(RateLimiter::new(rate)));
+        self.download_window = settings.download_window;
+        self
+    }
+
+    /// Wait until the download may (re)start
+    ///
+    /// Waits for the download window to open, if one is configured, and for a
+    /// paused download to be resumed.
+    async fn wait_until_allowed(&self, control: &UpdateControl) -> Result<(), Error> {
+        if let Some(window) = &self.download_window {
+            wait_for_download_window(window).await;
+        }
+        control.checkpoint().await
+    }
+
+    /// Hash the next `len` bytes of a file, returning `None` if it ends early
+    async fn hash_block(file: &mut File, len: u64) -> Result<Option<String>, Error> {
+        let mut hasher = Sha1::new();
+        let mut buf = vec![0u8; HASH_BUFFER_SIZE];
+        let mut remaining = len;
+
+        while remaining > 0 {
+            let n = remaining.min(buf.len() as u64) as usize;
+            let read = file.read(&mut buf[..n]).await?;
+            if read == 0 {
+                return Ok(None);
+            }
+            hasher.update(&buf[..read]);
+            remaining -= read as u64;
+        }
+
+        Ok(Some(hex::encode(hasher.finalize())))
     }
 
-    /// Verify a downloaded file against block hashes
-    async fn verify_file_blocks(
+    /// Check a patch file block by block without reading it all into memory
+    ///
+    /// Blocks that extend past the end of the file are reported as failed.
+    async fn verify_blocks(
+        patch: &PatchEntry,
         file_path: &Path,
-        hashes: &[String],
-        block_size: u64,
-    ) -> Result<bool, Error> {
-        let file_data = tokio::fs::read(file_path).await?;
-        let mut current_offset = 0;
-        let mut block_index = 0;
-
-        while current_offset < file_data.len() && block_index < hashes.len() {
-            let end = (current_offset + block_size as usize).min(file_data.len());
-            let block = &file_data[current_offset..end];
-
-            let mut hasher = Sha1::new();
-            hasher.update(block);
-            let actual_hash = hex::encode(hasher.finalize());
-
-            if actual_hash != hashes[block_index] {
-                tracing::warn!(
-                    "Block {} hash mismatch: expected {}, got {}",
-                    block_index,
-                    hashes[block_index],
-                    actual_hash
-                );
-                return Ok(false);
+        control: &UpdateControl,
+    ) -> Result<PatchVerificationReport, Error> {
+        let actual_size = match tokio::fs::metadata(file_path).await {
+            Ok(metadata) => Some(metadata.len()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut report = PatchVerificationReport {
+            expected_size: patch.length,
+            actual_size,
+            total_blocks: 0,
+            failed_blocks: Vec::new(),
+        };
+
+        let (hashes, block_size) = match (&patch.hashes, patch.hash_block_size) {
+            (Some(hashes), Some(block_size)) if block_size > 0 => (hashes, block_size),
+            _ => return Ok(report),
+        };
+        report.total_blocks = hashes.len();
+
+        let existing = actual_size.unwrap_or(0);
+        let mut file = match actual_size {
+            Some(_) => Some(File::open(file_path).await?),
+            None => None,
+        };
+
+        for (index, expected) in hashes.iter().enumerate() {
+            control.checkpoint().await?;
+
+            let start = index as u64 * block_size;
+            let end = (start + block_size).min(patch.length);
+
+            let intact = match file.as_mut().filter(|_| end <= existing) {
+                Some(file) => matches!(
+                    Self::hash_block(file, end - start).await?,
+                    Some(actual) if actual.eq_ignore_ascii_case(expected)
+                ),
+                None => false,
+            };
+            if !intact {
+                tracing::debug!("Patch block {} is missing or does not match", index);
+                report.failed_blocks.push(index);
             }
+        }
+
+        Ok(report)
+    }
+
+    /// Determine how much of a previous download at `dest_path` can be kept
+    ///
+    /// Only the blocks before the first failed one are kept.
+    async fn resume_offset(
+        patch: &PatchEntry,
+        dest_path: &Path,
+        control: &UpdateControl,
+    ) -> Result<u64, Error> {
+        let existing = match tokio::fs::metadata(dest_path).await {
+            Ok(metadata) => metadata.len(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e.into()),
+        };
 
-            current_offset = end;
-            block_index += 1;
+        if existing > patch.length {
+            return Ok(0);
         }
 
-        Ok(true)
+        match patch.hash_block_size {
+            Some(block_size) if block_size > 0 && patch.hashes.is_some() => {
+                let report = Self::verify_blocks(patch, dest_path, control).await?;
+                Ok(report
+                    .failed_blocks
+                    .first()
+                    .map_or(existing, |&block| (block as u64 * block_size).min(existing)))
+            }
+            _ => Ok(existing),
+        }
     }
-}
 
+    /// Group pending blocks into contiguous segments spread over `connections`
+    fn plan_segments(pending: &[usize], connections: usize) -> VecDeque<Segment> {
+        let per_segment = pending.len().div_ceil(connections.max(1)).max(1);
+        let mut segments: VecDeque<Segment> = VecDeque::new();
 
-#[async_trait]
-impl PatchDownloader for HttpPatchDownloader {
-    #[instrument(skip(self, progress))]
-    async fn download_patch<F>(
+        for &block in pending {
+            match segments.back_mut() {
+                Some(segment)
+                    if segment.first_block + segment.block_count == block
+                        && segment.block_count < per_segment =>
+                {
+                    segment.block_count += 1;
+                }
+                _ => segments.push_back(Segment {
+                    first_block: block,
+                    block_count: 1,
+                    attempt: 0,
+                }),
+            }
+        }
+
+        segments
+    }
+
+    /// Block hashes and block size of a patch that can be segmented
+    fn segment_layout(patch: &PatchEntry) -> Option<(&[String], u64)> {
+        let (hashes, block_size) = (patch.hashes.as_ref()?, patch.hash_block_size?);
+        let expected_blocks = patch.length.div_ceil(block_size.max(1));
+        (block_size > 0 && expected_blocks > 1 && hashes.len() as u64 == expected_blocks)
+            .then_some((hashes.as_slice(), block_size))
+    }
+
+    /// Download a hashed patch over several connections
+    ///
+    /// Returns `Ok(false)` if the patch has no usable block hashes or the
+    /// server does not honour range requests, in which case the caller falls
+    /// back to a single stream.
+    async fn download_segmented<F>(
         &self,
         patch: &PatchEntry,
         dest_path: &Path,
         unique_id: Option<&str>,
-        progress: F,
+        control: &UpdateControl,
+        progress: Arc<F>,
+    ) -> Result<bool, Error>
+    where
+        F: Fn(u64, u64) + Send + Sync + 'static,
+    {
+        let Some((hashes, block_size)) = Self::segment_layout(patch) else {
+            return Ok(false);
+        };
+
+        let pending = Self::verify_blocks(patch, dest_path, control)
+            .await?
+            .failed_blocks;
+        let pending_bytes: u64 = pending
+            .iter()
+            .map(|&block| {
+                let start = block as u64 * block_size;
+                (start + block_size).min(patch.length) - start
+            })
+            .sum();
+
+        if pending.is_empty() {
+            tracing::info!("Patch {} already downloaded", patch.version_id);
+            progress(patch.length, patch.length);
+            return Ok(true);
+        }
+
+        let segments = Self::plan_segments(&pending, self.connections);
+        tracing::info!(
+            "Downloading patch {} ({} bytes, {} of {} blocks in {} segments)",
+            patch.version_id,
+            patch.length,
+            pending.len(),
+            hashes.len(),
+            segments.len()
+        );
+
+        if let Some(parent) = dest_path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(dest_path)
+            .await?;
+        file.set_len(patch.length).await?;
+        drop(file);
+
+        let workers = self.connections.min(segments.len());
+        let job = Arc::new(SegmentedDownload {
+            client: self.client.clone(),
+            url: patch.url.clone(),
+            unique_id: unique_id.map(str::to_string),
+            dest_path: dest_path.to_path_buf(),
+            length: patch.length,
+            block_size,
+            hashes: hashes.to_vec(),
+            queue: Mutex::new(segments),
+            downloaded: AtomicU64::new(patch.length - pending_bytes),
+            rate_limiter: self.rate_limiter.clone(),
+            download_window: self.download_window,
+            control: control.clone(),
+        });
+        progress(patch.length - pending_bytes, patch.length);
+
+        let mut tasks = JoinSet::new();
+        for _ in 0..workers {
+            tasks.spawn(job.clone().run_worker(progress.clone()));
+        }
+
+        let mut ranges_honoured = true;
+        while let Some(result) = tasks.join_next().await {
+            match result {
+                Ok(Ok(honoured)) => ranges_honoured &= honoured,
+                Ok(Err(e)) => return Err(e),
+                Err(e) => return Err(Error::PatchDownload(e.to_string())),
+            }
+        }
+
+        if ranges_honoured {
+            progress(patch.length, patch.length);
+            tracing::info!("Download complete: {}", patch.version_id);
+        } else {
+            tracing::warn!("Server ignored range requests, downloading as one stream");
+        }
+        Ok(ranges_honoured)
+    }
+
+    /// Download a patch as one stream, resuming after any verified prefix
+    async fn download_stream<F>(
+        &self,
+        patch: &PatchEntry,
+        dest_path: &Path,
+        unique_id: Option<&str>,
+        control: &UpdateControl,
+        progress: Arc<F>,
     ) -> Result<(), Error>
     where
         F: Fn(u64, u64) + Send + Sync + 'static,
     {
-        let progress = Arc::new(progress);
+        loop {
+            self.wait_until_allowed(control).await?;
+            if self
+                .stream_once(patch, dest_path, unique_id, control, progress.as_ref())
+                .await?
+            {
+                return Ok(());
+            }
+            tracing::info!("Pausing download of {}", patch.version_id);
+        }
+    }
+
+    /// Stream a patch until it completes, is paused or the download window
+    /// closes
+    ///
+    /// Returns `Ok(false)` if the download was paused.
+    async fn stream_once<F>(
+        &self,
+        patch: &PatchEntry,
+        dest_path: &Path,
+        unique_id: Option<&str>,
+        control: &UpdateControl,
+        progress: &F,
+    ) -> Result<bool, Error>
+    where
+        F: Fn(u64, u64),
+    {
+        // Keep whatever a previous attempt already downloaded correctly
+        let resume_from = Self::resume_offset(patch, dest_path, control).await?;
+        if resume_from > 0 && resume_from == patch.length {
+            tracing::info!("Patch {} already downloaded", patch.version_id);
+            progress(patch.length, patch.length);
+            return Ok(true);
+        }
 
         tracing::info!(
-            "Downloading patch {} ({} bytes)",
+            "Downloading patch {} ({} bytes, resuming at {})",
             patch.version_id,
-            patch.length
+            patch.length,
+            resume_from
         );
 
         // Build request
@@ -96,6 +466,10 @@ impl PatchDownloader for HttpPatchDownloader {
             request = request.header("X-Patch-Unique-Id", uid);
         }
 
+        if resume_from > 0 {
+            request = request.header(RANGE, format!("bytes={}-", resume_from));
+        }
+
         let response = request
             .send()
             .await
@@ -108,18 +482,40 @@ impl PatchDownloader for HttpPatchDownloader {
             )));
         }
 
+        // A server that ignores the Range header sends the whole file again
+        let start = if response.status() == StatusCode::PARTIAL_CONTENT {
+            resume_from
+        } else {
+            0
+        };
+
         // Get content length from response or use patch length
-        let total_size = response.content_length().unwrap_or(patch.length);
+        let total_size = start
+            + response
+                .content_length()
+                .unwrap_or(patch.length.saturating_sub(start));
 
-        // Create destination file
+        // Open destination file, dropping anything past the resume point
         if let Some(parent) = dest_path.parent() {
             tokio::fs::create_dir_all(parent).await?;
         }
-        let mut file = File::create(dest_path).await?;
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(dest_path)
+            .await?;
+        file.set_len(start).await?;
+        file.seek(std::io::SeekFrom::Start(start)).await?;
 
         // Download with progress reporting
-        let mut downloaded: u64 = 0;
+        let mut downloaded: u64 = start;
         let mut stream = response.bytes_stream();
+        let mut throttle = Throttle::new(
+            self.rate_limiter.as_deref(),
+            self.download_window.as_ref(),
+            control,
+        );
 
         while let Some(chunk_result) = stream.next().await {
             let chunk = chunk_result.map_err(|e| Error::PatchDownload(e.to_string()))?;
@@ -128,15 +524,235 @@ impl PatchDownloader for HttpPatchDownloader {
 
             downloaded += chunk.len() as u64;
             progress(downloaded, total_size);
+
+            let keep_going = throttle.after_chunk(chunk.len() as u64).await;
+            if !matches!(keep_going, Ok(true)) {
+                file.flush().await?;
+                return keep_going;
+            }
         }
 
         file.flush().await?;
 
         tracing::info!("Download complete: {}", patch.version_id);
 
+        Ok(true)
+    }
+}
+
+impl SegmentedDownload {
+    /// Byte range covered by a hash block (end exclusive)
+    fn block_range(&self, block: usize) -> (u64, u64) {
+        let start = block as u64 * self.block_size;
+        (start, (start + self.block_size).min(self.length))
+    }
+
+    fn next_segment(&self) -> Option<Segment> {
+        self.queue.lock().unwrap().pop_front()
+    }
+
+    /// Take back the `received` bytes of a block and queue it again
+    ///
+    /// Fails once the block has been attempted `MAX_BLOCK_ATTEMPTS` times.
+    fn retry_block(
+        &self,
+        block: usize,
+        attempt: u32,
+        received: u64,
+        actual: Option<String>,
+    ) -> Result<(), Error> {
+        self.downloaded.fetch_sub(received, Ordering::Relaxed);
+
+        if attempt + 1 >= MAX_BLOCK_ATTEMPTS {
+            return Err(match actual {
+                Some(actual) => Error::PatchBlockVerificationFailed {
+                    block,
+                    expected: self.hashes[block].clone(),
+                    actual,
+                },
+                None => Error::PatchDownload(format!(
+                    "connection closed before block {} was complete",
+                    block
+                )),
+            });
+        }
+
+        tracing::warn!(
+            "Block {} failed on attempt {}, fetching it again",
+            block,
+            attempt + 1
+        );
+        self.queue.lock().unwrap().push_back(Segment {
+            first_block: block,
+            block_count: 1,
+            attempt: attempt + 1,
+        });
         Ok(())
     }
 
+    /// Fetch queued segments until none are left
+    ///
+    /// Returns `Ok(false)` as soon as the server answers a range request with
+    /// the whole file.
+    async fn run_worker<F>(self: Arc<Self>, progress: Arc<F>) -> Result<bool, Error>
+    where
+        F: Fn(u64, u64) + Send + Sync + 'static,
+    {
+        let mut file = OpenOptions::new().write(true).open(&self.dest_path).await?;
+
+        while let Some(segment) = self.next_segment() {
+            if let Some(window) = &self.download_window {
+                wait_for_download_window(window).await;
+            }
+            self.control.checkpoint().await?;
+            if !self
+                .fetch_segment(&mut file, segment, progress.as_ref())
+                .await?
+            {
+                return Ok(false);
+            }
+        }
+
+        file.flush().await?;
+        Ok(true)
+    }
+
+    /// Fetch one segment, verifying each block as soon as its last byte lands
+    async fn fetch_segment<F>(
+        &self,
+        file: &mut File,
+        segment: Segment,
+        progress: &F,
+    ) -> Result<bool, Error>
+    where
+        F: Fn(u64, u64),
+    {
+        let last_block = segment.first_block + segment.block_count;
+        let (start, _) = self.block_range(segment.first_block);
+        let (_, end) = self.block_range(last_block - 1);
+
+        let mut request = self
+            .client
+            .get(&self.url)
+            .header(RANGE, format!("bytes={}-{}", start, end - 1));
+        if let Some(uid) = &self.unique_id {
+            request = request.header("X-Patch-Unique-Id", uid);
+        }
+
+        let response = request
+            .send()
+            .await
+            .map_err(|e| Error::PatchDownload(e.to_string()))?;
+
+        if response.status() != StatusCode::PARTIAL_CONTENT {
+            if response.status().is_success() {
+                return Ok(false);
+            }
+            return Err(Error::PatchDownload(format!(
+                "Download failed with status: {}",
+                response.status()
+            )));
+        }
+
+        file.seek(std::io::SeekFrom::Start(start)).await?;
+
+        let mut block = segment.first_block;
+        let mut block_remaining = end.min(start + self.block_size) - start;
+        let mut hasher = Sha1::new();
+        let mut stream = response.bytes_stream();
+        let mut throttle = Throttle::new(
+            self.rate_limiter.as_deref(),
+            self.download_window.as_ref(),
+            &self.control,
+        );
+
+        while let Some(chunk_result) = stream.next().await {
+            let chunk = chunk_result.map_err(|e| Error::PatchDownload(e.to_string()))?;
+            let mut data = &chunk[..];
+
+            // Anything past the requested range is ignored
+            while !data.is_empty() && block < last_block {
+                let n = block_remaining.min(data.len() as u64) as usize;
+                file.write_all(&data[..n]).await?;
+                hasher.update(&data[..n]);
+                data = &data[n..];
+                block_remaining -= n as u64;
+
+                let downloaded = self.downloaded.fetch_add(n as u64, Ordering::Relaxed) + n as u64;
+                progress(downloaded, self.length);
+
+                if block_remaining == 0 {
+                    let actual = hex::encode(hasher.finalize_reset());
+                    let (block_start, block_end) = self.block_range(block);
+                    if !actual.eq_ignore_ascii_case(&self.hashes[block]) {
+                        let received = block_end - block_start;
+                        self.retry_block(block, segment.attempt, received, Some(actual))?;
+                    }
+                    block += 1;
+                    if block < last_block {
+                        let (next_start, next_end) = self.block_range(block);
+                        block_remaining = next_end - next_start;
+                    }
+                }
+            }
+
+            if !throttle.after_chunk(chunk.len() as u64).await? && block < last_block {
+                // Paused or the download window closed, so hand back the rest
+                // of the segment without using up an attempt
+                let (block_start, block_end) = self.block_range(block);
+                let received = block_end - block_start - block_remaining;
+                self.downloaded.fetch_sub(received, Ordering::Relaxed);
+                self.queue.lock().unwrap().push_front(Segment {
+                    first_block: block,
+                    block_count: last_block - block,
+                    attempt: segment.attempt,
+                });
+                return Ok(true);
+            }
+        }
+
+        // The connection ended early, so the rest of the segment is fetched again
+        if block < last_block {
+            let (block_start, block_end) = self.block_range(block);
+            let received = block_end - block_start - block_remaining;
+            self.retry_block(block, segment.attempt, received, None)?;
+            for remaining in block + 1..last_block {
+                self.retry_block(remaining, segment.attempt, 0, None)?;
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+#[async_trait]
+impl PatchDownloader for HttpPatchDownloader {
+    #[instrument(skip(self, progress))]
+    async fn download_patch<F>(
+        &self,
+        patch: &PatchEntry,
+        dest_path: &Path,
+        unique_id: Option<&str>,
+        control: &UpdateControl,
+        progress: F,
+    ) -> Result<(), Error>
+    where
+        F: Fn(u64, u64) + Send + Sync + 'static,
+    {
+        let progress = Arc::new(progress);
+
+        if self.connections > 1
+            && self
+                .download_segmented(patch, dest_path, unique_id, control, progress.clone())
+                .await?
+        {
+            return Ok(());
+        }
+
+        self.download_stream(patch, dest_path, unique_id, control, progress)
+            .await
+    }
+
     #[instrument(skip(self))]
     async fn verify_patch(&self, patch: &PatchEntry, file_path: &Path) -> Result<bool, Error> {
         // Check file exists
@@ -156,23 +772,469 @@ impl PatchDownloader for HttpPatchDownloader {
             return Ok(false);
         }
 
-        // If we have block hashes, verify them
-        if let (Some(hashes), Some(block_size)) = (&patch.hashes, patch.hash_block_size) {
-            return Self::verify_file_blocks(file_path, hashes, block_size).await;
+        let report = Self::verify_blocks(patch, file_path, &UpdateControl::new()).await?;
+        if report.total_blocks == 0 {
+            // No hashes available, assume OK if size matches
+            tracing::debug!("No block hashes available, size check passed");
+        } else if !report.failed_blocks.is_empty() {
+            tracing::warn!(
+                "{} of {} blocks failed verification: {:?}",
+                report.failed_blocks.len(),
+                report.total_blocks,
+                report.failed_blocks
+            );
         }
 
-        // No hashes available, assume OK if size matches
-        tracing::debug!("No block hashes available, size check passed");
-        Ok(true)
+        Ok(report.is_valid())
+    }
+
+    #[instrument(skip(self))]
+    async fn verify_patch_blocks(
+        &self,
+        patch: &PatchEntry,
+        file_path: &Path,
+        control: &UpdateControl,
+    ) -> Result<PatchVerificationReport, Error> {
+        Self::verify_blocks(patch, file_path, control).await
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::sync::Mutex;
     use tempfile::tempdir;
     use tokio::fs::write;
 
+    use axum::http::{header, HeaderMap};
+    use axum::response::IntoResponse;
+
+    /// Range headers received by the local patch server
+    type SeenRanges = Arc<Mutex<Vec<Option<String>>>>;
+
+    /// Parse a `bytes=start-` or `bytes=start-end` range (end inclusive)
+    fn parse_range(value: &str, len: u64) -> Option<(u64, u64)> {
+        let spec = value.strip_prefix("bytes=")?;
+        let (start, end) = spec.split_once('-')?;
+        let start: u64 = start.parse().ok()?;
+        let end = if end.is_empty() {
+            len - 1
+        } else {
+            end.parse::<u64>().ok()?.min(len - 1)
+        };
+        (start <= end).then_some((start, end))
+    }
+
+    /// Start a local stand-in for the patch CDN serving `data` at `/patch`
+    ///
+    /// Range requests are honoured when `honour_range` is set, otherwise the
+    /// whole file is always returned.
+    async fn serve_patch(data: Vec<u8>, honour_range: bool) -> (String, SeenRanges) {
+        let data = Arc::new(data);
+        let seen: SeenRanges = Arc::new(Mutex::new(Vec::new()));
+
+        let seen_clone = seen.clone();
+        let app = axum::Router::new().route(
+            "/patch",
+            axum::routing::get(move |headers: HeaderMap| {
+                let data = data.clone();
+                let seen = seen_clone.clone();
+                async move {
+                    let range = headers
+                        .get(header::RANGE)
+                        .and_then(|v| v.to_str().ok())
+                        .map(str::to_string);
+                    seen.lock().unwrap().push(range.clone());
+
+                    let len = data.len() as u64;
+                    match range.filter(|_| honour_range) {
+                        Some(range) => match parse_range(&range, len) {
+                            Some((start, end)) => (
+                                axum::http::StatusCode::PARTIAL_CONTENT,
+                                [(
+                                    header::CONTENT_RANGE,
+                                    format!("bytes {}-{}/{}", start, end, len),
+                                )],
+                                data[start as usize..=end as usize].to_vec(),
+                            )
+                                .into_response(),
+                            None => axum::http::StatusCode::RANGE_NOT_SATISFIABLE.into_response(),
+                        },
+                        None => data.to_vec().into_response(),
+                    }
+                }
+            }),
+        );
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+
+        (format!("http://{}/patch", addr), seen)
+    }
+
+    /// Deterministic patch contents of `len` bytes
+    fn patch_data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    /// Build a patch entry with SHA1 hashes for `data` in blocks of `block_size`
+    fn hashed_patch_entry(url: String, data: &[u8], block_size: u64) -> PatchEntry {
+        let hashes = data
+            .chunks(block_size as usize)
+            .map(|block| hex::encode(Sha1::digest(block)))
+            .collect();
+        PatchEntry {
+            version_id: "test".to_string(),
+            url,
+            length: data.len() as u64,
+            hash_type: Some("sha1".to_string()),
+            hash_block_size: Some(block_size),
+            hashes: Some(hashes),
+            repository: gaveloc_core::entities::Repository::Ffxiv,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_download_patch_from_scratch() {
+        let data = patch_data(10_000);
+        let (url, seen) = serve_patch(data.clone(), true).await;
+        let dir = tempdir().unwrap();
+        let dest = dir.path().join("test.patch");
+        let patch = hashed_patch_entry(url, &data, 4096);
+
+        let last = Arc::new(Mutex::new((0, 0)));
+        let last_clone = last.clone();
+        HttpPatchDownloader::new()
+            .unwrap()
+            .with_connections(1)
+            .download_patch(
+                &patch,
+                &dest,
+                None,
+                &UpdateControl::new(),
+                move |done, total| {
+                    *last_clone.lock().unwrap() = (done, total);
+                },
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(tokio::fs::read(&dest).await.unwrap(), data);
+        assert_eq!(*last.lock().unwrap(), (10_000, 10_000));
+        assert_eq!(*seen.lock().unwrap(), vec![None]);
+    }
+
+    #[tokio::test]
+    async fn test_download_patch_resumes_after_verified_blocks() {
+        let data = patch_data(10_000);
+        let (url, seen) = serve_patch(data.clone(), true).await;
+        let dir = tempdir().unwrap();
+        let dest = dir.path().join("test.patch");
+        let patch = hashed_patch_entry(url, &data, 4096);
+
+        // Two good blocks followed by part of a third
+        write(&dest, &data[..9000]).await.unwrap();
+
+        HttpPatchDownloader::new()
+            .unwrap()
+            .with_connections(1)
+            .download_patch(&patch, &dest, None, &UpdateControl::new(), |_, _| {})
+            .await
+            .unwrap();
+
+        assert_eq!(tokio::fs::read(&dest).await.unwrap(), data);
+        assert_eq!(*seen.lock().unwrap(), vec![Some("bytes=8192-".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn test_download_patch_resumes_at_first_bad_block() {
+        let data = patch_data(10_000);
+        let (url, seen) = serve_patch(data.clone(), true).await;
+        let dir = tempdir().unwrap();
+        let dest = dir.path().join("test.patch");
+        let patch = hashed_patch_entry(url, &data, 4096);
+
+        let mut partial = data[..9000].to_vec();
+        partial[5000] ^= 0xFF;
+        write(&dest, &partial).await.unwrap();
+
+        HttpPatchDownloader::new()
+            .unwrap()
+            .with_connections(1)
+            .download_patch(&patch, &dest, None, &UpdateControl::new(), |_, _| {})
+            .await
+            .unwrap();
+
+        assert_eq!(tokio::fs::read(&dest).await.unwrap(), data);
+        assert_eq!(*seen.lock().unwrap(), vec![Some("bytes=4096-".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn test_download_patch_server_ignoring_range() {
+        let data = patch_data(10_000);
+        let (url, seen) = serve_patch(data.clone(), false).await;
+        let dir = tempdir().unwrap();
+        let dest = dir.path().join("test.patch");
+        let patch = hashed_patch_entry(url, &data, 4096);
+
+        write(&dest, &data[..5000]).await.unwrap();
+
+        HttpPatchDownloader::new()
+            .unwrap()
+            .with_connections(1)
+            .download_patch(&patch, &dest, None, &UpdateControl::new(), |_, _| {})
+            .await
+            .unwrap();
+
+        assert_eq!(tokio::fs::read(&dest).await.unwrap(), data);
+        assert_eq!(seen.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_download_patch_skips_complete_file() {
+        let data = patch_data(10_000);
+        let (url, seen) = serve_patch(data.clone(), true).await;
+        let dir = tempdir().unwrap();
+        let dest = dir.path().join("test.patch");
+        let patch = hashed_patch_entry(url, &data, 4096);
+
+        write(&dest, &data).await.unwrap();
+
+        HttpPatchDownloader::new()
+            .unwrap()
+            .download_patch(&patch, &dest, None, &UpdateControl::new(), |_, _| {})
+            .await
+            .unwrap();
+
+        assert!(seen.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_plan_segments() {
+        let segments = HttpPatchDownloader::plan_segments(&[0, 1, 2, 3, 5, 6, 7], 2);
+        let spans: Vec<_> = segments
+            .iter()
+            .map(|s| (s.first_block, s.block_count))
+            .collect();
+        // Four blocks per segment at most, split wherever blocks are missing
+        assert_eq!(spans, vec![(0, 4), (5, 3)]);
+
+        let segments = HttpPatchDownloader::plan_segments(&[0, 1, 2, 3, 4, 5, 6, 7, 8, 9], 4);
+        let spans: Vec<_> = segments
+            .iter()
+            .map(|s| (s.first_block, s.block_count))
+            .collect();
+        assert_eq!(spans, vec![(0, 3), (3, 3), (6, 3), (9, 1)]);
+    }
+
+    #[tokio::test]
+    async fn test_download_patch_segmented() {
+        let data = patch_data(10_000);
+        let (url, seen) = serve_patch(data.clone(), true).await;
+        let dir = tempdir().unwrap();
+        let dest = dir.path().join("test.patch");
+        let patch = hashed_patch_entry(url, &data, 1024);
+
+        let last = Arc::new(Mutex::new((0, 0)));
+        let last_clone = last.clone();
+        HttpPatchDownloader::new()
+            .unwrap()
+            .with_connections(4)
+            .download_patch(
+                &patch,
+                &dest,
+                None,
+                &UpdateControl::new(),
+                move |done, total| {
+                    *last_clone.lock().unwrap() = (done, total);
+                },
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(tokio::fs::read(&dest).await.unwrap(), data);
+        assert_eq!(*last.lock().unwrap(), (10_000, 10_000));
+
+        let mut ranges: Vec<_> = seen.lock().unwrap().iter().flatten().cloned().collect();
+        ranges.sort();
+        assert_eq!(
+            ranges,
+            vec![
+                "bytes=0-3071",
+                "bytes=3072-6143",
+                "bytes=6144-9215",
+                "bytes=9216-9999",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_download_patch_segmented_only_fetches_bad_blocks() {
+        let data = patch_data(10_000);
+        let (url, seen) = serve_patch(data.clone(), true).await;
+        let dir = tempdir().unwrap();
+        let dest = dir.path().join("test.patch");
+        let patch = hashed_patch_entry(url, &data, 1024);
+
+        // Block 2 is corrupt and the download stopped partway through block 8
+        let mut partial = data[..8500].to_vec();
+        partial[2100] ^= 0xFF;
+        write(&dest, &partial).await.unwrap();
+
+        HttpPatchDownloader::new()
+            .unwrap()
+            .with_connections(4)
+            .download_patch(&patch, &dest, None, &UpdateControl::new(), |_, _| {})
+            .await
+            .unwrap();
+
+        assert_eq!(tokio::fs::read(&dest).await.unwrap(), data);
+
+        let mut ranges: Vec<_> = seen.lock().unwrap().iter().flatten().cloned().collect();
+        ranges.sort();
+        assert_eq!(
+            ranges,
+            vec!["bytes=2048-3071", "bytes=8192-9215", "bytes=9216-9999"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_download_patch_segmented_falls_back_without_ranges() {
+        let data = patch_data(10_000);
+        let (url, _) = serve_patch(data.clone(), false).await;
+        let dir = tempdir().unwrap();
+        let dest = dir.path().join("test.patch");
+        let patch = hashed_patch_entry(url, &data, 1024);
+
+        HttpPatchDownloader::new()
+            .unwrap()
+            .with_connections(4)
+            .download_patch(&patch, &dest, None, &UpdateControl::new(), |_, _| {})
+            .await
+            .unwrap();
+
+        assert_eq!(tokio::fs::read(&dest).await.unwrap(), data);
+    }
+
+    #[tokio::test]
+    async fn test_download_patch_segmented_block_mismatch() {
+        let data = patch_data(10_000);
+        let (url, seen) = serve_patch(data.clone(), true).await;
+        let dir = tempdir().unwrap();
+        let dest = dir.path().join("test.patch");
+        let mut patch = hashed_patch_entry(url, &data, 1024);
+        patch.hashes.as_mut().unwrap()[3] = "0".repeat(40);
+
+        let result = HttpPatchDownloader::new()
+            .unwrap()
+            .with_connections(4)
+            .download_patch(&patch, &dest, None, &UpdateControl::new(), |_, _| {})
+            .await;
+
+        match result {
+            Err(Error::PatchBlockVerificationFailed { block, .. }) => assert_eq!(block, 3),
+            other => panic!("Expected block verification failure, got {:?}", other),
+        }
+        let retries = seen
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|r| r.as_deref() == Some("bytes=3072-4095"))
+            .count();
+        assert_eq!(retries, (MAX_BLOCK_ATTEMPTS - 1) as usize);
+    }
+
+    #[tokio::test]
+    async fn test_download_patch_rate_limited() {
+        let data = patch_data(8192);
+        let (url, _) = serve_patch(data.clone(), true).await;
+        let dir = tempdir().unwrap();
+        let dest = dir.path().join("test.patch");
+        let patch = hashed_patch_entry(url, &data, 1024);
+
+        let settings = PatchSettings {
+            max_download_rate: Some(8),
+            download_window: None,
+        };
+        let start = std::time::Instant::now();
+        HttpPatchDownloader::new()
+            .unwrap()
+            .with_patch_settings(&settings)
+            .download_patch(&patch, &dest, None, &UpdateControl::new(), |_, _| {})
+            .await
+            .unwrap();
+
+        // 8 KiB at 8 KiB/s across all connections
+        assert!(start.elapsed() >= Duration::from_millis(900));
+        assert_eq!(tokio::fs::read(&dest).await.unwrap(), data);
+    }
+
+    #[tokio::test]
+    async fn test_download_patch_cancelled_is_resumable() {
+        let data = patch_data(10_000);
+        let (url, seen) = serve_patch(data.clone(), true).await;
+        let dir = tempdir().unwrap();
+        let dest = dir.path().join("test.patch");
+        let patch = hashed_patch_entry(url, &data, 4096);
+        let downloader = HttpPatchDownloader::new().unwrap().with_connections(1);
+
+        // Nothing is requested once the download is cancelled
+        let control = UpdateControl::new();
+        control.cancel();
+        let result = downloader
+            .download_patch(&patch, &dest, None, &control, |_, _| {})
+            .await;
+        assert!(matches!(result, Err(Error::Cancelled)));
+        assert!(seen.lock().unwrap().is_empty());
+
+        // Cancelling mid-stream stops after the current chunk
+        let control = UpdateControl::new();
+        let handle = control.clone();
+        let result = downloader
+            .download_patch(&patch, &dest, None, &control, move |_, _| handle.cancel())
+            .await;
+        assert!(matches!(result, Err(Error::Cancelled)));
+
+        downloader
+            .download_patch(&patch, &dest, None, &UpdateControl::new(), |_, _| {})
+            .await
+            .unwrap();
+        assert_eq!(tokio::fs::read(&dest).await.unwrap(), data);
+    }
+
+    #[tokio::test]
+    async fn test_download_patch_waits_while_paused() {
+        let data = patch_data(10_000);
+        let (url, seen) = serve_patch(data.clone(), true).await;
+        let dir = tempdir().unwrap();
+        let dest = dir.path().join("test.patch");
+        let patch = hashed_patch_entry(url, &data, 4096);
+
+        let control = UpdateControl::new();
+        control.pause();
+        let download = tokio::spawn({
+            let control = control.clone();
+            let dest = dest.clone();
+            async move {
+                HttpPatchDownloader::new()
+                    .unwrap()
+                    .download_patch(&patch, &dest, None, &control, |_, _| {})
+                    .await
+            }
+        });
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!download.is_finished());
+        assert!(seen.lock().unwrap().is_empty());
+
+        control.resume();
+        download.await.unwrap().unwrap();
+        assert_eq!(tokio::fs::read(&dest).await.unwrap(), data);
+    }
+
     #[tokio::test]
     async fn test_verify_patch_size_check() {
         let dir = tempdir().unwrap();
@@ -246,6 +1308,56 @@ mod tests {
             .unwrap());
     }
 
+    #[tokio::test]
+    async fn test_verify_patch_blocks_reports_failed_blocks() {
+        let data = patch_data(10_000);
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("test.patch");
+        let patch = hashed_patch_entry("http://example.com".to_string(), &data, 1024);
+        let downloader = HttpPatchDownloader::new().unwrap();
+
+        let mut partial = data[..8500].to_vec();
+        partial[4100] ^= 0xFF;
+        write(&file_path, &partial).await.unwrap();
+
+        let report = downloader
+            .verify_patch_blocks(&patch, &file_path, &UpdateControl::new())
+            .await
+            .unwrap();
+        assert_eq!(report.actual_size, Some(8500));
+        assert_eq!(report.total_blocks, 10);
+        // Block 4 is corrupt, blocks 8 and 9 are missing or incomplete
+        assert_eq!(report.failed_blocks, vec![4, 8, 9]);
+        assert!(!report.is_valid());
+
+        write(&file_path, &data).await.unwrap();
+        let report = downloader
+            .verify_patch_blocks(&patch, &file_path, &UpdateControl::new())
+            .await
+            .unwrap();
+        assert!(report.failed_blocks.is_empty());
+        assert!(report.is_valid());
+    }
+
+    #[tokio::test]
+    async fn test_verify_patch_blocks_missing_file() {
+        let data = patch_data(3000);
+        let dir = tempdir().unwrap();
+        let patch = hashed_patch_entry("http://example.com".to_string(), &data, 1024);
+
+        let report = HttpPatchDownloader::new()
+            .unwrap()
+            .verify_patch_blocks(
+                &patch,
+                &dir.path().join("missing.patch"),
+                &UpdateControl::new(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(report.actual_size, None);
+        assert_eq!(report.failed_blocks, vec![0, 1, 2]);
+    }
+
     #[tokio::test]
     async fn test_verify_patch_missing_file() {
         let dir = tempdir().unwrap();
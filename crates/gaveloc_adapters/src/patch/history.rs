@@ -0,0 +1,255 @@
+//! Patch history from the Square Enix patch servers
+//!
+//! Asking the version servers for the patches of an installation at the base
+//! version returns the same full chain a fresh install downloads: the history
+//! patches followed by every incremental patch since.
+
+use async_trait::async_trait;
+use reqwest::Client;
+use tracing::instrument;
+
+use gaveloc_core::entities::{GameVersion, PatchChain, PatchEntry, Repository};
+use gaveloc_core::error::Error;
+use gaveloc_core::ports::PatchHistory;
+
+use super::server::{SquareEnixPatchServer, BOOT_VERSION_URL, GAME_VERSION_URL};
+use crate::network::build_patch_client;
+
+/// Version of an installation no patch has been applied to
+const BASE_VERSION: &str = "2012.01.01.0000.0000";
+
+/// Patch chains as served to a fresh install
+pub struct SquareEnixPatchHistory {
+    client: Client,
+    /// Login session, required for the game repositories
+    session_id: Option<String>,
+}
+
+impl SquareEnixPatchHistory {
+    /// History source for boot files only
+    pub fn new() -> Result<Self, Error> {
+        Ok(Self {
+            client: build_patch_client()?,
+            session_id: None,
+        })
+    }
+
+    /// History source for all repositories, using a login session
+    pub fn with_session(session_id: impl Into<String>) -> Result<Self, Error> {
+        Ok(Self {
+            session_id: Some(session_id.into()),
+            ..Self::new()?
+        })
+    }
+
+    async fn boot_chain(&self) -> Result<PatchChain, Error> {
+        let url = format!("{}/{}", BOOT_VERSION_URL, BASE_VERSION);
+        let response = self
+            .client
+            .get(&url)
+            .send()
+            .await
+            .map_err(|e| Error::Network(e.to_string()))?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(Error::PatchServer(format!(
+                "Boot patch history request failed with status: {}",
+                status
+            )));
+        }
+
+        let body = response
+            .text()
+            .await
+            .map_err(|e| Error::Network(e.to_string()))?;
+
+        Ok(PatchChain {
+            patches: SquareEnixPatchServer::parse_patch_list(&body, Repository::Boot)?,
+            unique_id: None,
+        })
+    }
+
+    async fn game_chain(&self, repository: Repository) -> Result<PatchChain, Error> {
+        let session_id = self.session_id.as_deref().ok_or_else(|| {
+            Error::PatchServer("the game patch history requires a login session".to_string())
+        })?;
+
+        // Report every repository up to the requested one as unpatched
+        let expansion = match repository {
+            Repository::Ex1 => 1,
+            Repository::Ex2 => 2,
+            Repository::Ex3 => 3,
+            Repository::Ex4 => 4,
+            Repository::Ex5 => 5,
+            _ => 0,
+        };
+        let version_report = Repository::game_repos_up_to(expansion)
+            .iter()
+            .map(|repo| format!("{}/{}", repo_report_name(*repo), BASE_VERSION))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let url = format!("{}/{}/{}", GAME_VERSION_URL, BASE_VERSION, session_id);
+        let response = self
+            .client
+            .post(&url)
+            .header("X-Hash-Check", "enabled")
+            .body(version_report)
+            .send()
+            .await
+            .map_err(|e| Error::Network(e.to_string()))?;
+
+        let unique_id = response
+            .headers()
+            .get("X-Patch-Unique-Id")
+            .and_then(|v| v.to_str().ok())
+            .map(|s| s.to_string())
+            .unwrap_or_else(|| session_id.to_string());
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(Error::PatchServer(format!(
+                "Game patch history request failed with status: {}",
+                status
+            )));
+        }
+
+        let body = response
+            .text()
+            .await
+            .map_err(|e| Error::Network(e.to_string()))?;
+
+        Ok(PatchChain {
+            patches: SquareEnixPatchServer::parse_game_patch_list(&body)?,
+            unique_id: Some(unique_id),
+        })
+    }
+}
+
+#[async_trait]
+impl PatchHistory for SquareEnixPatchHistory {
+    #[instrument(skip(self))]
+    async fn patch_chain(
+        &self,
+        repository: Repository,
+        version: &GameVersion,
+    ) -> Result<PatchChain, Error> {
+        let mut chain = match repository {
+            Repository::Boot => self.boot_chain().await?,
+            _ => self.game_chain(repository).await?,
+        };
+
+        chain.patches = chain_up_to(chain.patches, repository, version);
+        if chain.patches.is_empty() {
+            return Err(Error::PatchServer(format!(
+                "no patch history available for {}",
+                repository
+            )));
+        }
+
+        Ok(chain)
+    }
+}
+
+/// Name of a repository in a version report
+fn repo_report_name(repository: Repository) -> &'static str {
+    match repository {
+        Repository::Ex1 => "ex1",
+        Repository::Ex2 => "ex2",
+        Repository::Ex3 => "ex3",
+        Repository::Ex4 => "ex4",
+        Repository::Ex5 => "ex5",
+        _ => "ffxiv",
+    }
+}
+
+/// Version a patch updates to, ignoring the `H`/`D` prefix and part suffix of
+/// history patches
+fn patch_version(patch: &PatchEntry) -> Option<GameVersion> {
+    GameVersion::parse(
+        patch
+            .version_id
+            .trim_start_matches(|c: char| c.is_ascii_alphabetic())
+            .trim_end_matches(|c: char| c.is_ascii_alphabetic()),
+    )
+    .ok()
+}
+
+/// Patches of `repository` that lead up to `version`, in order
+fn chain_up_to(
+    patches: Vec<PatchEntry>,
+    repository: Repository,
+    version: &GameVersion,
+) -> Vec<PatchEntry> {
+    patches
+        .into_iter()
+        .filter(|patch| patch.repository == repository)
+        .filter(|patch| match patch_version(patch) {
+            Some(patch_version) => patch_version <= *version,
+            None => {
+                tracing::warn!("skipping patch with unknown version {}", patch.version_id);
+                false
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patch(version_id: &str, repository: Repository) -> PatchEntry {
+        PatchEntry {
+            version_id: version_id.to_string(),
+            url: format!("http://example.com/{}.patch", version_id),
+            length: 1024,
+            hash_type: None,
+            hash_block_size: None,
+            hashes: None,
+            repository,
+        }
+    }
+
+    #[test]
+    fn test_patch_version_ignores_history_markers() {
+        let version = patch_version(&patch("H2017.06.06.0000.0001a", Repository::Ffxiv)).unwrap();
+        assert_eq!(version.as_str(), "2017.06.06.0000.0001");
+        assert!(patch_version(&patch("latest", Repository::Ffxiv)).is_none());
+    }
+
+    #[test]
+    fn test_chain_up_to_filters_repository_and_version() {
+        let patches = vec![
+            patch("H2017.06.06.0000.0001a", Repository::Ffxiv),
+            patch("H2017.06.06.0000.0001b", Repository::Ffxiv),
+            patch("2017.07.11.0000.0000", Repository::Ex1),
+            patch("2023.07.26.0000.0000", Repository::Ffxiv),
+            patch("2024.07.23.0000.0001", Repository::Ffxiv),
+        ];
+        let version = GameVersion::parse("2023.07.26.0000.0000").unwrap();
+
+        let chain = chain_up_to(patches, Repository::Ffxiv, &version);
+        let ids: Vec<_> = chain.iter().map(|p| p.version_id.as_str()).collect();
+        assert_eq!(
+            ids,
+            vec![
+                "H2017.06.06.0000.0001a",
+                "H2017.06.06.0000.0001b",
+                "2023.07.26.0000.0000"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_game_chain_requires_session() {
+        let history = SquareEnixPatchHistory::new().unwrap();
+        let version = GameVersion::parse("2024.07.23.0000.0001").unwrap();
+
+        let err = history
+            .patch_chain(Repository::Ex2, &version)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("login session"));
+    }
+}
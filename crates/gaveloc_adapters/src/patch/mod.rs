@@ -1,9 +1,11 @@
 //! Patching adapters for version checking and patch management
 
 mod downloader;
+mod history;
 mod server;
 mod version;
 
 pub use downloader::HttpPatchDownloader;
+pub use history::SquareEnixPatchHistory;
 pub use server::SquareEnixPatchServer;
 pub use version::FileVersionRepository;
@@ -18,11 +18,13 @@ use crate::network::build_patch_client;
 
 /// Boot version check URL
 /// Format: http://patch-bootver.ffxiv.com/http/win32/ffxivneo_release_boot/{version}
-const BOOT_VERSION_URL: &str = "http://patch-bootver.ffxiv.com/http/win32/ffxivneo_release_boot";
+pub(super) const BOOT_VERSION_URL: &str =
+    "http://patch-bootver.ffxiv.com/http/win32/ffxivneo_release_boot";
 
 /// Game version check/session registration URL
 /// Format: https://patch-gamever.ffxiv.com/http/win32/ffxivneo_release_game/{version}/{session_id}
-const GAME_VERSION_URL: &str = "https://patch-gamever.ffxiv.com/http/win32/ffxivneo_release_game";
+pub(super) const GAME_VERSION_URL: &str =
+    "https://patch-gamever.ffxiv.com/http/win32/ffxivneo_release_game";
 
 /// Square Enix patch server client
 pub struct SquareEnixPatchServer {
@@ -53,7 +55,10 @@ impl SquareEnixPatchServer {
     /// ```text
     /// Content-Length: 0
     /// ```
-    fn parse_patch_list(body: &str, repository: Repository) -> Result<Vec<PatchEntry>, Error> {
+    pub(super) fn parse_patch_list(
+        body: &str,
+        repository: Repository,
+    ) -> Result<Vec<PatchEntry>, Error> {
         let mut patches = Vec::new();
 
         for line in body.lines() {
@@ -102,6 +107,38 @@ impl SquareEnixPatchServer {
 
         Ok(patches)
     }
+
+    /// Parse a game patch list, which mixes patches of every game repository
+    pub(super) fn parse_game_patch_list(body: &str) -> Result<Vec<PatchEntry>, Error> {
+        let mut all_patches = Vec::new();
+
+        for line in body.lines() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            // Determine repository from URL
+            let repo = if line.contains("/ex1/") {
+                Repository::Ex1
+            } else if line.contains("/ex2/") {
+                Repository::Ex2
+            } else if line.contains("/ex3/") {
+                Repository::Ex3
+            } else if line.contains("/ex4/") {
+                Repository::Ex4
+            } else if line.contains("/ex5/") {
+                Repository::Ex5
+            } else {
+                Repository::Ffxiv
+            };
+
+            let patches = Self::parse_patch_list(line, repo)?;
+            all_patches.extend(patches);
+        }
+
+        Ok(all_patches)
+    }
 }
 
 
@@ -237,36 +274,7 @@ impl PatchServer for SquareEnixPatchServer {
             return Ok((unique_id, Vec::new()));
         }
 
-        // Parse patches - they may be for different repositories
-        let mut all_patches = Vec::new();
-
-        // The response groups patches by repository
-        // We'll parse them all as FFXIV repository for now
-        // and let the URL determine the actual target
-        for line in body.lines() {
-            let line = line.trim();
-            if line.is_empty() {
-                continue;
-            }
-
-            // Determine repository from URL
-            let repo = if line.contains("/ex1/") {
-                Repository::Ex1
-            } else if line.contains("/ex2/") {
-                Repository::Ex2
-            } else if line.contains("/ex3/") {
-                Repository::Ex3
-            } else if line.contains("/ex4/") {
-                Repository::Ex4
-            } else if line.contains("/ex5/") {
-                Repository::Ex5
-            } else {
-                Repository::Ffxiv
-            };
-
-            let patches = Self::parse_patch_list(line, repo)?;
-            all_patches.extend(patches);
-        }
+        let all_patches = Self::parse_game_patch_list(&body)?;
 
         Ok((unique_id, all_patches))
     }
@@ -0,0 +1,750 @@
+//! ZiPatch chunk application
+//!
+//! Writes parsed SQPK commands into the SqPack files of a game installation.
+
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, Read, Seek, SeekFrom, Take, Write};
+use std::path::{Path, PathBuf};
+
+use flate2::read::DeflateDecoder;
+use gaveloc_core::error::Error;
+use gaveloc_core::zipatch::*;
+use sha1::{Digest, Sha1};
+
+use super::journal::{JournalRecord, PatchJournal};
+use crate::integrity::invalidate_hashes;
+
+/// Size of a SqPack block; all dat offsets and lengths are multiples of this
+pub(crate) const SQPACK_BLOCK_SIZE: u64 = 1 << 7;
+
+/// Buffer size used when zero-filling regions of a file
+const WIPE_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Offsets of the index header fields, relative to the start of the file
+const INDEX_DATA_OFFSET_POS: usize = SQPACK_HEADER_SIZE as usize + 0x08;
+const INDEX_DATA_SIZE_POS: usize = SQPACK_HEADER_SIZE as usize + 0x0C;
+const INDEX_DATA_HASH_POS: usize = SQPACK_HEADER_SIZE as usize + 0x10;
+
+/// SHA1 of the index header, covering the header up to this field
+const INDEX_HEADER_HASH_POS: usize = SQPACK_HEADER_SIZE as usize + 0x3C0;
+
+/// Offsets of the segments stored after the hash table (synonyms, empty blocks,
+/// directories); they move when the hash table grows or shrinks
+const INDEX_SEGMENT_OFFSET_POS: [usize; 3] = [
+    SQPACK_HEADER_SIZE as usize + 0x54,
+    SQPACK_HEADER_SIZE as usize + 0x9C,
+    SQPACK_HEADER_SIZE as usize + 0xE4,
+];
+
+/// The index header fills the second 1024 bytes of the file
+const INDEX_HEADER_MIN_LEN: usize = 2 * SQPACK_HEADER_SIZE as usize;
+
+/// State carried across chunks while a single patch file is applied
+pub(crate) struct ApplyContext<'a> {
+    /// Root of the installation the patch targets (e.g. `{install}/game`)
+    game_path: &'a Path,
+    /// Platform used to build SqPack file names, set by the TargetInfo command
+    pub platform: Platform,
+    /// Patch file being applied, read for command payloads
+    patch: BufReader<File>,
+    /// Journal receiving the original contents of everything modified, if any
+    journal: Option<PatchJournal>,
+    /// Files and directories written or removed so far
+    touched: HashSet<PathBuf>,
+}
+
+impl<'a> ApplyContext<'a> {
+    pub fn new(game_path: &'a Path, patch: File) -> Self {
+        Self {
+            game_path,
+            platform: Platform::default(),
+            patch: BufReader::new(patch),
+            journal: None,
+            touched: HashSet::new(),
+        }
+    }
+
+    /// Record every modification in `journal` before it is made
+    pub fn with_journal(mut self, journal: PatchJournal) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    /// Record that the first `committed_chunks` chunks are fully applied
+    pub fn commit(&mut self, committed_chunks: u64) -> Result<(), Error> {
+        match self.journal.as_mut() {
+            Some(journal) => journal.commit(committed_chunks),
+            None => Ok(()),
+        }
+    }
+
+    /// Record that the whole patch was applied
+    pub fn finish(&mut self) -> Result<(), Error> {
+        match self.journal.as_mut() {
+            Some(journal) => journal.finish(),
+            None => Ok(()),
+        }
+    }
+
+    /// Drop the cached integrity hashes of everything written so far
+    pub fn invalidate_hashes(&self) -> Result<(), Error> {
+        invalidate_hashes(self.game_path, self.touched.iter().map(PathBuf::as_path))
+    }
+
+    /// Journal the original contents of `len` bytes of `path` at `offset`
+    fn record_region(&mut self, path: &Path, offset: u64, len: u64) -> Result<(), Error> {
+        self.touched.insert(path.to_path_buf());
+        match self.journal.as_mut() {
+            Some(journal) => journal.record_region(path, offset, len),
+            None => Ok(()),
+        }
+    }
+
+    /// Create a directory and its missing parents, journaling each one created
+    fn create_dir_all(&mut self, path: &Path) -> Result<(), Error> {
+        if let Some(journal) = self.journal.as_mut() {
+            let missing: Vec<_> = path.ancestors().take_while(|p| !p.exists()).collect();
+            for dir in missing.into_iter().rev() {
+                journal.append(&JournalRecord::CreatedDir {
+                    path: dir.to_path_buf(),
+                })?;
+            }
+        }
+
+        std::fs::create_dir_all(path)?;
+        Ok(())
+    }
+
+    /// Remove a file, moving it into the journal's backups if there is a journal
+    fn remove_file(&mut self, path: &Path) -> Result<(), Error> {
+        self.touched.insert(path.to_path_buf());
+        match self.journal.as_mut() {
+            Some(journal) => journal.back_up_file(path),
+            None => std::fs::remove_file(path)
+                .map_err(|e| Error::ZiPatchApply(format!("failed to delete {:?}: {}", path, e))),
+        }
+    }
+
+    /// Reader over `len` bytes of the patch file starting at `offset`
+    fn patch_data(&mut self, offset: u64, len: u64) -> Result<Take<&mut BufReader<File>>, Error> {
+        self.patch.seek(SeekFrom::Start(offset))?;
+        Ok((&mut self.patch).take(len))
+    }
+
+    /// Copy `len` bytes of the patch file at `offset` into `file`
+    fn copy_patch_data(&mut self, offset: u64, len: u64, file: &mut File) -> Result<(), Error> {
+        let copied = std::io::copy(&mut self.patch_data(offset, len)?, file)?;
+        if copied != len {
+            return Err(Error::ZiPatchApply(format!(
+                "patch data at {:#x} truncated ({} of {} bytes)",
+                offset, copied, len
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Resolve a patch-relative path against the installation root
+    pub fn resolve(&self, relative: &str) -> PathBuf {
+        self.game_path.join(normalize_relative(relative))
+    }
+
+    /// Installation-relative path of the single file an SQPK command writes
+    ///
+    /// `None` for commands that write no file or a whole folder (RemoveAll).
+    pub fn command_target(&self, sqpk: &SqpkChunk) -> Option<String> {
+        let relative = match sqpk {
+            SqpkChunk::AddData(cmd) => cmd.target_file.dat_path(self.platform),
+            SqpkChunk::DeleteData(cmd) => cmd.target_file.dat_path(self.platform),
+            SqpkChunk::ExpandData(cmd) => cmd.target_file.dat_path(self.platform),
+            SqpkChunk::Header(cmd) => match cmd.file_kind {
+                SqpkFileKind::Dat => cmd.target_file.dat_path(self.platform),
+                SqpkFileKind::Index => cmd
+                    .target_file
+                    .index_path(self.platform, cmd.target_file.index_type().ok()?),
+                SqpkFileKind::Unknown(_) => return None,
+            },
+            SqpkChunk::Index(cmd) => cmd
+                .target_file
+                .index_path(self.platform, cmd.target_file.index_type().ok()?),
+            SqpkChunk::File(cmd)
+                if matches!(
+                    cmd.operation,
+                    SqpkFileOperation::AddFile | SqpkFileOperation::DeleteFile
+                ) =>
+            {
+                cmd.file_path.clone()
+            }
+            _ => return None,
+        };
+
+        Some(normalize_relative(&relative))
+    }
+
+    /// Open a file to modify `len` bytes at `offset`, creating it and its parent
+    /// directories if needed
+    ///
+    /// This is the single point through which files are modified, so the affected
+    /// region is journaled here.
+    pub fn open_for_write(&mut self, relative: &str, offset: u64, len: u64) -> Result<File, Error> {
+        let path = self.resolve(relative);
+        // Directories are journaled first so they are removed after the file on undo
+        if let Some(parent) = path.parent() {
+            self.create_dir_all(parent)?;
+        }
+        self.record_region(&path, offset, len)?;
+
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(|e| Error::ZiPatchApply(format!("failed to open {:?}: {}", path, e)))
+    }
+
+    /// Apply an ADIR chunk
+    pub fn add_directory(&mut self, relative: &str) -> Result<(), Error> {
+        let path = self.resolve(relative);
+        tracing::debug!("Creating directory: {:?}", path);
+        self.create_dir_all(&path)
+    }
+
+    /// Apply a DELD chunk, journaling every file and directory removed
+    pub fn delete_directory(&mut self, relative: &str) -> Result<(), Error> {
+        let path = self.resolve(relative);
+        tracing::debug!("Deleting directory: {:?}", path);
+        if !path.exists() {
+            return Ok(());
+        }
+
+        self.touched.insert(path.clone());
+        if let Some(journal) = self.journal.as_mut() {
+            // Listed up front, as files are moved out while the tree is walked
+            let entries = walkdir::WalkDir::new(&path)
+                .into_iter()
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| Error::ZiPatchApply(e.to_string()))?;
+            for entry in entries {
+                if entry.file_type().is_dir() {
+                    journal.append(&JournalRecord::RemovedDir {
+                        path: entry.path().to_path_buf(),
+                    })?;
+                } else {
+                    journal.back_up_file(entry.path())?;
+                }
+            }
+            journal.sync()?;
+        }
+
+        std::fs::remove_dir_all(&path)?;
+        Ok(())
+    }
+
+    /// Apply an SQPK Add Data command
+    pub fn apply_add_data(&mut self, cmd: &SqpkAddData) -> Result<(), Error> {
+        let mut file = self.open_for_write(
+            &cmd.target_file.dat_path(self.platform),
+            cmd.block_offset,
+            cmd.block_number + cmd.block_delete_number,
+        )?;
+
+        file.seek(SeekFrom::Start(cmd.block_offset))?;
+        self.copy_patch_data(cmd.data_source_offset, cmd.block_number, &mut file)?;
+
+        // The region following the new data is released and must be zeroed
+        wipe(&mut file, cmd.block_delete_number)?;
+
+        Ok(())
+    }
+
+    /// Apply an SQPK Delete Data command
+    pub fn apply_delete_data(&mut self, cmd: &SqpkDeleteData) -> Result<(), Error> {
+        let mut file = self.open_for_write(
+            &cmd.target_file.dat_path(self.platform),
+            cmd.block_offset,
+            cmd.block_number,
+        )?;
+        write_empty_file_block_at(&mut file, cmd.block_offset, cmd.block_number)
+    }
+
+    /// Apply an SQPK Expand Data command
+    pub fn apply_expand_data(&mut self, cmd: &SqpkExpandData) -> Result<(), Error> {
+        let mut file = self.open_for_write(
+            &cmd.target_file.dat_path(self.platform),
+            cmd.block_offset,
+            cmd.block_number,
+        )?;
+        write_empty_file_block_at(&mut file, cmd.block_offset, cmd.block_number)
+    }
+
+    /// Apply an SQPK Header command
+    pub fn apply_header(&mut self, cmd: &SqpkHeader) -> Result<(), Error> {
+        let relative = match cmd.file_kind {
+            SqpkFileKind::Dat => cmd.target_file.dat_path(self.platform),
+            SqpkFileKind::Index => cmd
+                .target_file
+                .index_path(self.platform, cmd.target_file.index_type()?),
+            SqpkFileKind::Unknown(kind) => {
+                return Err(Error::ZiPatchApply(format!(
+                    "unknown header file kind {:#x} for {}",
+                    kind, cmd.target_file
+                )));
+            }
+        };
+
+        if let SqpkHeaderKind::Unknown(kind) = cmd.header_kind {
+            return Err(Error::ZiPatchApply(format!(
+                "unknown header kind {:#x} for {}",
+                kind, relative
+            )));
+        }
+
+        let offset = cmd.header_kind.file_offset();
+        let mut file = self.open_for_write(&relative, offset, cmd.header_data.len() as u64)?;
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(&cmd.header_data)?;
+
+        Ok(())
+    }
+
+    /// Apply an SQPK File command
+    pub fn apply_file(&mut self, cmd: &SqpkFile) -> Result<(), Error> {
+        match cmd.operation {
+            SqpkFileOperation::AddFile => self.add_file(cmd),
+            SqpkFileOperation::RemoveAll => self.remove_all(cmd.expansion_id),
+            SqpkFileOperation::DeleteFile => {
+                let path = self.resolve(&cmd.file_path);
+                if !path.exists() {
+                    tracing::debug!("File to delete does not exist: {:?}", path);
+                    return Ok(());
+                }
+                self.remove_file(&path)
+            }
+            SqpkFileOperation::MakeDir => self.create_dir_all(&self.resolve(&cmd.file_path)),
+            SqpkFileOperation::Unknown(op) => Err(Error::ZiPatchApply(format!(
+                "unknown file operation {:#x} for {}",
+                op, cmd.file_path
+            ))),
+        }
+    }
+
+    /// Write the blocks of an AddFile operation, decompressing them as needed
+    fn add_file(&mut self, cmd: &SqpkFile) -> Result<(), Error> {
+        // Writing from the start replaces the whole file
+        if cmd.file_offset == 0 {
+            let path = self.resolve(&cmd.file_path);
+            if path.is_file() {
+                self.remove_file(&path)?;
+            }
+        }
+
+        let len = cmd
+            .blocks
+            .iter()
+            .map(|b| u64::from(b.decompressed_size))
+            .sum();
+        let mut file = self.open_for_write(&cmd.file_path, cmd.file_offset, len)?;
+        file.seek(SeekFrom::Start(cmd.file_offset))?;
+
+        for block in &cmd.blocks {
+            let data_size = u64::from(block.data_size);
+            if block.is_compressed {
+                let data = self.patch_data(block.data_source_offset, data_size)?;
+                let mut decoder = DeflateDecoder::new(data);
+                let written = std::io::copy(&mut decoder, &mut file).map_err(|e| {
+                    Error::ZiPatchApply(format!(
+                        "failed to decompress block for {}: {}",
+                        cmd.file_path, e
+                    ))
+                })?;
+                if written != u64::from(block.decompressed_size) {
+                    return Err(Error::ZiPatchApply(format!(
+                        "block for {} decompressed to {} bytes, expected {}",
+                        cmd.file_path, written, block.decompressed_size
+                    )));
+                }
+            } else {
+                self.copy_patch_data(block.data_source_offset, data_size, &mut file)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Delete every file of an expansion's sqpack folder except `.var` files
+    fn remove_all(&mut self, expansion_id: u16) -> Result<(), Error> {
+        let dir = self
+            .game_path
+            .join("sqpack")
+            .join(expansion_folder(expansion_id));
+        if !dir.exists() {
+            return Ok(());
+        }
+
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_file() && path.extension().is_none_or(|ext| ext != "var") {
+                self.remove_file(&path)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Apply an SQPK Index command by adding or removing a hash table entry
+    ///
+    /// The hash table is kept sorted by hash, as the game looks entries up with
+    /// a binary search. Segments stored after the table are moved along with it.
+    pub fn apply_index(&mut self, cmd: &SqpkIndex) -> Result<(), Error> {
+        let index_type = cmd.target_file.index_type()?;
+        let path = self.resolve(&cmd.target_file.index_path(self.platform, index_type));
+        let mut contents = std::fs::read(&path)
+            .map_err(|e| Error::ZiPatchApply(format!("failed to read {:?}: {}", path, e)))?;
+
+        let mut table = IndexTable::read(&contents, index_type)
+            .map_err(|e| Error::ZiPatchApply(format!("{:?}: {}", path, e)))?;
+
+        match cmd.command {
+            SqpkIndexCommand::Add => {
+                let file_id = cmd.target_file.file_id & 0x7;
+                let data = (cmd.block_offset << 4) | (file_id << 1) | u32::from(cmd.is_synonym);
+                table.insert(cmd.file_hash, data);
+            }
+            SqpkIndexCommand::Delete => table.remove(cmd.file_hash),
+            SqpkIndexCommand::Unknown(op) => {
+                return Err(Error::ZiPatchApply(format!(
+                    "unknown index operation {:#x} for {:?}",
+                    op, path
+                )));
+            }
+        }
+
+        table.write(&mut contents);
+        self.record_region(&path, 0, u64::MAX)?;
+        std::fs::write(&path, &contents)
+            .map_err(|e| Error::ZiPatchApply(format!("failed to write {:?}: {}", path, e)))?;
+
+        Ok(())
+    }
+}
+
+/// In-memory copy of the hash table of a `.index` or `.index2` file
+struct IndexTable {
+    index_type: IndexType,
+    /// Position of the table in the file
+    data_offset: usize,
+    /// Size of the table as currently stored in the file
+    data_size: usize,
+    /// Entries as (hash, packed data), sorted by hash
+    entries: Vec<(u64, u32)>,
+}
+
+impl IndexTable {
+    fn entry_size(index_type: IndexType) -> usize {
+        match index_type {
+            IndexType::Index => 16,
+            IndexType::Index2 => 8,
+        }
+    }
+
+    fn read(contents: &[u8], index_type: IndexType) -> Result<Self, String> {
+        if contents.len() < INDEX_HEADER_MIN_LEN {
+            return Err(format!("index file too short ({} bytes)", contents.len()));
+        }
+
+        let data_offset = read_u32_le(contents, INDEX_DATA_OFFSET_POS) as usize;
+        let data_size = read_u32_le(contents, INDEX_DATA_SIZE_POS) as usize;
+        let entry_size = Self::entry_size(index_type);
+
+        if data_offset < INDEX_HEADER_MIN_LEN
+            || data_offset + data_size > contents.len()
+            || !data_size.is_multiple_of(entry_size)
+        {
+            return Err(format!(
+                "invalid hash table at {:#x} ({} bytes)",
+                data_offset, data_size
+            ));
+        }
+
+        let mut entries: Vec<(u64, u32)> = contents[data_offset..data_offset + data_size]
+            .chunks_exact(entry_size)
+            .map(|entry| match index_type {
+                IndexType::Index => (
+                    u64::from_le_bytes(entry[0..8].try_into().unwrap()),
+                    read_u32_le(entry, 8),
+                ),
+                IndexType::Index2 => (u64::from(read_u32_le(entry, 0)), read_u32_le(entry, 4)),
+            })
+            .collect();
+        entries.sort_by_key(|&(hash, _)| hash);
+
+        Ok(Self {
+            index_type,
+            data_offset,
+            data_size,
+            entries,
+        })
+    }
+
+    fn key(&self, hash: u64) -> u64 {
+        match self.index_type {
+            IndexType::Index => hash,
+            IndexType::Index2 => hash & 0xFFFF_FFFF,
+        }
+    }
+
+    /// Add an entry, replacing any existing entry with the same hash
+    fn insert(&mut self, hash: u64, data: u32) {
+        let key = self.key(hash);
+        match self.entries.binary_search_by_key(&key, |&(h, _)| h) {
+            Ok(i) => self.entries[i].1 = data,
+            Err(i) => self.entries.insert(i, (key, data)),
+        }
+    }
+
+    fn remove(&mut self, hash: u64) {
+        let key = self.key(hash);
+        if let Ok(i) = self.entries.binary_search_by_key(&key, |&(h, _)| h) {
+            self.entries.remove(i);
+        }
+    }
+
+    /// Replace the table in `contents` and fix up the header, including its hashes
+    fn write(&self, contents: &mut Vec<u8>) {
+        let entry_size = Self::entry_size(self.index_type);
+        let mut table = Vec::with_capacity(self.entries.len() * entry_size);
+        for &(hash, data) in &self.entries {
+            match self.index_type {
+                IndexType::Index => {
+                    table.extend_from_slice(&hash.to_le_bytes());
+                    table.extend_from_slice(&data.to_le_bytes());
+                    table.extend_from_slice(&[0u8; 4]);
+                }
+                IndexType::Index2 => {
+                    table.extend_from_slice(&(hash as u32).to_le_bytes());
+                    table.extend_from_slice(&data.to_le_bytes());
+                }
+            }
+        }
+
+        let old_end = self.data_offset + self.data_size;
+        let delta = table.len() as i64 - self.data_size as i64;
+        contents.splice(self.data_offset..old_end, table.iter().copied());
+
+        write_u32_le(contents, INDEX_DATA_SIZE_POS, table.len() as u32);
+        for pos in INDEX_SEGMENT_OFFSET_POS {
+            let offset = read_u32_le(contents, pos) as usize;
+            if offset >= old_end {
+                write_u32_le(contents, pos, (offset as i64 + delta) as u32);
+            }
+        }
+
+        // The header hash covers the table hash, so it is updated last
+        let table_hash = Sha1::digest(&table);
+        contents[INDEX_DATA_HASH_POS..INDEX_DATA_HASH_POS + table_hash.len()]
+            .copy_from_slice(&table_hash);
+        let header_hash =
+            Sha1::digest(&contents[SQPACK_HEADER_SIZE as usize..INDEX_HEADER_HASH_POS]);
+        contents[INDEX_HEADER_HASH_POS..INDEX_HEADER_HASH_POS + header_hash.len()]
+            .copy_from_slice(&header_hash);
+    }
+}
+
+/// Patch paths may use either separator and start with one
+pub(crate) fn normalize_relative(relative: &str) -> String {
+    relative.trim_start_matches(['/', '\\']).replace('\\', "/")
+}
+
+fn read_u32_le(buf: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(buf[pos..pos + 4].try_into().unwrap())
+}
+
+fn write_u32_le(buf: &mut [u8], pos: usize, value: u32) {
+    buf[pos..pos + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Write `len` zero bytes at the current position of the file
+pub(crate) fn wipe(file: &mut File, len: u64) -> Result<(), Error> {
+    let zeros = [0u8; WIPE_BUFFER_SIZE];
+    let mut remaining = len;
+
+    while remaining > 0 {
+        let n = remaining.min(WIPE_BUFFER_SIZE as u64) as usize;
+        file.write_all(&zeros[..n])?;
+        remaining -= n as u64;
+    }
+
+    Ok(())
+}
+
+/// Zero out `len` bytes at `offset` and mark the region as an empty file block
+///
+/// `len` is in bytes and must be a multiple of [`SQPACK_BLOCK_SIZE`]. The empty
+/// block header records the block size and the number of blocks following it,
+/// which is what the game expects for unused space inside a dat file.
+pub(crate) fn write_empty_file_block_at(
+    file: &mut File,
+    offset: u64,
+    len: u64,
+) -> Result<(), Error> {
+    if len < SQPACK_BLOCK_SIZE || !len.is_multiple_of(SQPACK_BLOCK_SIZE) {
+        return Err(Error::ZiPatchApply(format!(
+            "invalid empty block length {} at offset {}",
+            len, offset
+        )));
+    }
+
+    file.seek(SeekFrom::Start(offset))?;
+    wipe(file, len)?;
+
+    let block_count = (len / SQPACK_BLOCK_SIZE) as u32;
+
+    file.seek(SeekFrom::Start(offset))?;
+    let mut header = [0u8; 20];
+    header[0..4].copy_from_slice(&(SQPACK_BLOCK_SIZE as u32).to_le_bytes());
+    // Bytes 4..12 (unknown, file size) stay zero
+    header[12..16].copy_from_slice(&(block_count - 1).to_le_bytes());
+    // Bytes 16..20 (used block count) stay zero
+    file.write_all(&header)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_write_empty_file_block_header() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.dat");
+        std::fs::write(&path, vec![0xAAu8; 512]).unwrap();
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        write_empty_file_block_at(&mut file, 128, 256).unwrap();
+
+        let mut data = Vec::new();
+        File::open(&path).unwrap().read_to_end(&mut data).unwrap();
+
+        assert!(data[..128].iter().all(|&b| b == 0xAA));
+        assert_eq!(&data[128..132], &128u32.to_le_bytes());
+        assert_eq!(&data[140..144], &1u32.to_le_bytes());
+        assert!(data[148..384].iter().all(|&b| b == 0));
+        assert!(data[384..].iter().all(|&b| b == 0xAA));
+    }
+
+    #[test]
+    fn test_write_empty_file_block_rejects_unaligned_length() {
+        let dir = tempdir().unwrap();
+        let mut file = File::create(dir.path().join("test.dat")).unwrap();
+        assert!(write_empty_file_block_at(&mut file, 0, 100).is_err());
+        assert!(write_empty_file_block_at(&mut file, 0, 0).is_err());
+    }
+
+    /// Build an index file with the hash table at 0x800 followed by one segment
+    fn build_index_file(entry_size: usize, entries: &[Vec<u8>]) -> Vec<u8> {
+        let table_offset = 0x800usize;
+        let table_size = entries.len() * entry_size;
+        let mut data = vec![0u8; table_offset];
+        write_u32_le(&mut data, INDEX_DATA_OFFSET_POS, table_offset as u32);
+        write_u32_le(&mut data, INDEX_DATA_SIZE_POS, table_size as u32);
+        write_u32_le(
+            &mut data,
+            INDEX_SEGMENT_OFFSET_POS[0],
+            (table_offset + table_size) as u32,
+        );
+        for entry in entries {
+            data.extend_from_slice(entry);
+        }
+        data.extend_from_slice(&[0xEEu8; 16]);
+        data
+    }
+
+    #[test]
+    fn test_index_table_insert_and_remove() {
+        let entry = |hash: u32, data: u32| {
+            let mut e = hash.to_le_bytes().to_vec();
+            e.extend_from_slice(&data.to_le_bytes());
+            e
+        };
+        let mut contents = build_index_file(8, &[entry(10, 1), entry(30, 3)]);
+
+        let mut table = IndexTable::read(&contents, IndexType::Index2).unwrap();
+        table.insert(20, 2);
+        table.insert(0xFFFF_FFFF_0000_001E, 4); // replaces hash 30
+        table.remove(10);
+        table.write(&mut contents);
+
+        let table = IndexTable::read(&contents, IndexType::Index2).unwrap();
+        assert_eq!(table.entries, vec![(20, 2), (30, 4)]);
+
+        // Segment after the table keeps its contents and its offset is unchanged
+        let segment = read_u32_le(&contents, INDEX_SEGMENT_OFFSET_POS[0]) as usize;
+        assert_eq!(segment, 0x800 + 16);
+        assert_eq!(&contents[segment..], &[0xEEu8; 16]);
+    }
+
+    #[test]
+    fn test_index_table_write_shifts_segments() {
+        let mut contents = build_index_file(16, &[]);
+        let mut table = IndexTable::read(&contents, IndexType::Index).unwrap();
+        table.insert(0x0000_0001_0000_0002, 0x40);
+        table.write(&mut contents);
+
+        assert_eq!(read_u32_le(&contents, INDEX_DATA_SIZE_POS), 16);
+        assert_eq!(
+            read_u32_le(&contents, INDEX_SEGMENT_OFFSET_POS[0]),
+            0x800 + 16
+        );
+        // Segments that are not present stay at zero
+        assert_eq!(read_u32_le(&contents, INDEX_SEGMENT_OFFSET_POS[1]), 0);
+        assert_eq!(
+            &contents[0x800..0x808],
+            &0x0000_0001_0000_0002u64.to_le_bytes()
+        );
+        assert_eq!(&contents[0x810..], &[0xEEu8; 16]);
+    }
+
+    #[test]
+    fn test_index_table_write_updates_hashes() {
+        let mut contents = build_index_file(16, &[]);
+        let mut table = IndexTable::read(&contents, IndexType::Index).unwrap();
+        table.insert(0x10, 0x40);
+        table.write(&mut contents);
+
+        assert_eq!(
+            &contents[INDEX_DATA_HASH_POS..INDEX_DATA_HASH_POS + 20],
+            Sha1::digest(&contents[0x800..0x810]).as_slice()
+        );
+        assert_eq!(
+            &contents[INDEX_HEADER_HASH_POS..INDEX_HEADER_HASH_POS + 20],
+            Sha1::digest(&contents[0x400..INDEX_HEADER_HASH_POS]).as_slice()
+        );
+    }
+
+    #[test]
+    fn test_index_table_rejects_truncated_file() {
+        assert!(IndexTable::read(&[0u8; 64], IndexType::Index).is_err());
+    }
+
+    #[test]
+    fn test_resolve_strips_leading_separators() {
+        let ctx = ApplyContext::new(Path::new("/game"), tempfile::tempfile().unwrap());
+        assert_eq!(
+            ctx.resolve("/sqpack/ffxiv"),
+            PathBuf::from("/game/sqpack/ffxiv")
+        );
+        assert_eq!(
+            ctx.resolve(r"\boot\ffxivboot.exe"),
+            PathBuf::from("/game/boot/ffxivboot.exe")
+        );
+    }
+}
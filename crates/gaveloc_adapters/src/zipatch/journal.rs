@@ -0,0 +1,517 @@
+//! Patch application journal
+//!
+//! Before a patch modifies a file, the original contents of the affected region
+//! are appended to a journal next to the patch file. After each chunk a commit
+//! record is written, so an interrupted application can either be resumed from
+//! the last committed chunk or rolled back to the pre-patch state.
+//!
+//! The journal is a sequence of records, each a little-endian `u32` length
+//! followed by the bincode-encoded record. Region records are followed by the
+//! saved bytes, which are streamed rather than held in memory. A torn record at
+//! the end of the file (from a crash mid-write) is ignored.
+//!
+//! Files that are removed or replaced as a whole are not copied into the
+//! journal; they are moved into a backup directory next to it instead. The
+//! journal is synced to disk before the files it describes are modified.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use gaveloc_core::error::Error;
+use gaveloc_core::zipatch::JournalState;
+
+/// A single journal entry
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum JournalRecord {
+    /// First record: the installation root the patch is applied to
+    Begin { game_path: PathBuf },
+    /// Original contents of a file region about to be modified, followed in the
+    /// journal by `data_len` bytes of saved data
+    ///
+    /// `original_len` is `None` if the file did not exist yet.
+    Region {
+        path: PathBuf,
+        offset: u64,
+        original_len: Option<u64>,
+        data_len: u64,
+    },
+    /// A directory that is about to be created
+    CreatedDir { path: PathBuf },
+    /// A directory that is about to be removed
+    RemovedDir { path: PathBuf },
+    /// A file about to be removed or replaced, moved aside to `backup`
+    Moved { path: PathBuf, backup: PathBuf },
+    /// The first `committed_chunks` chunks of the patch are fully applied
+    Commit { committed_chunks: u64 },
+    /// Every chunk of the patch was applied
+    Finished,
+}
+
+/// Records read back from an existing journal
+pub(crate) struct JournalContents {
+    pub game_path: PathBuf,
+    /// File position just past the `Begin` record
+    begin_end: u64,
+    /// Records after `Begin`, with the file position just past each one
+    /// (including any saved data)
+    pub records: Vec<(u64, JournalRecord)>,
+    /// Journal file, read again for saved data when undoing
+    file: File,
+}
+
+impl JournalContents {
+    /// Read a journal, returning `None` if it does not exist or has no `Begin` record
+    pub fn read(path: &Path) -> Result<Option<Self>, Error> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let file_len = file.metadata()?.len();
+        let mut reader = BufReader::new(&file);
+        let mut records = Vec::new();
+        let mut position = 0u64;
+        let mut header = [0u8; 4];
+        loop {
+            // A short read or undecodable record marks the end of the valid journal
+            if reader.read_exact(&mut header).is_err() {
+                break;
+            }
+            let len = u32::from_le_bytes(header) as usize;
+            let mut payload = vec![0u8; len];
+            if reader.read_exact(&mut payload).is_err() {
+                break;
+            }
+            let Ok(record) = bincode::deserialize::<JournalRecord>(&payload) else {
+                break;
+            };
+            position += 4 + len as u64;
+
+            if let JournalRecord::Region { data_len, .. } = record {
+                if position + data_len > file_len {
+                    break;
+                }
+                reader.seek_relative(data_len as i64)?;
+                position += data_len;
+            }
+
+            records.push((position, record));
+        }
+        drop(reader);
+
+        let mut records = records.into_iter();
+        match records.next() {
+            Some((begin_end, JournalRecord::Begin { game_path })) => Ok(Some(Self {
+                game_path,
+                begin_end,
+                records: records.collect(),
+                file,
+            })),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn state(&self) -> JournalState {
+        if self
+            .records
+            .iter()
+            .any(|(_, r)| matches!(r, JournalRecord::Finished))
+        {
+            JournalState::Completed
+        } else {
+            JournalState::InProgress {
+                committed_chunks: self.committed_chunks(),
+            }
+        }
+    }
+
+    /// Number of chunks covered by the last commit record
+    pub fn committed_chunks(&self) -> u64 {
+        self.records
+            .iter()
+            .rev()
+            .find_map(|(_, r)| match r {
+                JournalRecord::Commit { committed_chunks } => Some(*committed_chunks),
+                _ => None,
+            })
+            .unwrap_or(0)
+    }
+
+    /// Undo every record after the last commit, returning the journal length to keep
+    pub fn undo_uncommitted(&mut self) -> Result<u64, Error> {
+        let last_commit = self
+            .records
+            .iter()
+            .rposition(|(_, r)| matches!(r, JournalRecord::Commit { .. }));
+        let (keep_len, tail) = match last_commit {
+            Some(i) => (self.records[i].0, &self.records[i + 1..]),
+            None => (self.begin_end, &self.records[..]),
+        };
+
+        undo(&mut self.file, tail)?;
+        Ok(keep_len)
+    }
+
+    /// Undo every record, restoring the pre-patch state
+    pub fn undo_all(&mut self) -> Result<(), Error> {
+        undo(&mut self.file, &self.records)
+    }
+}
+
+/// Undo records in reverse order, reading saved data from the journal file
+fn undo(journal: &mut File, records: &[(u64, JournalRecord)]) -> Result<(), Error> {
+    for (end, record) in records.iter().rev() {
+        match record {
+            JournalRecord::Region {
+                path,
+                offset,
+                original_len,
+                data_len,
+            } => {
+                journal.seek(SeekFrom::Start(end - data_len))?;
+                let mut data = journal.take(*data_len);
+                restore_region(path, *offset, *original_len, &mut data)?;
+            }
+            JournalRecord::CreatedDir { path } => {
+                // Only succeeds once everything created inside has been undone
+                let _ = std::fs::remove_dir(path);
+            }
+            JournalRecord::RemovedDir { path } => std::fs::create_dir_all(path)?,
+            JournalRecord::Moved { path, backup } => {
+                // The journal is synced before the move, so it may not have happened
+                if backup.exists() {
+                    if let Some(parent) = path.parent() {
+                        std::fs::create_dir_all(parent)?;
+                    }
+                    move_file(backup, path).map_err(|e| {
+                        Error::ZiPatchApply(format!(
+                            "failed to restore {:?} during rollback: {}",
+                            path, e
+                        ))
+                    })?;
+                }
+            }
+            JournalRecord::Begin { .. }
+            | JournalRecord::Commit { .. }
+            | JournalRecord::Finished => {}
+        }
+    }
+
+    Ok(())
+}
+
+fn restore_region(
+    path: &Path,
+    offset: u64,
+    original_len: Option<u64>,
+    data: &mut impl Read,
+) -> Result<(), Error> {
+    let Some(original_len) = original_len else {
+        return match std::fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(Error::ZiPatchApply(
+                format!("failed to remove {:?} during rollback: {}", path, e),
+            )),
+            _ => Ok(()),
+        };
+    };
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+        .map_err(|e| {
+            Error::ZiPatchApply(format!(
+                "failed to restore {:?} during rollback: {}",
+                path, e
+            ))
+        })?;
+    file.set_len(original_len)?;
+    file.seek(SeekFrom::Start(offset))?;
+    std::io::copy(data, &mut file)?;
+
+    Ok(())
+}
+
+/// Move a file, copying it if the destination is on another filesystem
+///
+/// The copy is written under a temporary name first, so `to` only ever holds
+/// the complete file.
+fn move_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    match std::fs::rename(from, to) {
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+            let mut partial = to.as_os_str().to_owned();
+            partial.push(".partial");
+            std::fs::copy(from, &partial)?;
+            std::fs::rename(&partial, to)?;
+            std::fs::remove_file(from)
+        }
+        result => result,
+    }
+}
+
+/// Directory holding the files moved aside by the journal at `journal_path`
+pub(crate) fn journal_backup_dir(journal_path: &Path) -> PathBuf {
+    let mut path = journal_path.as_os_str().to_owned();
+    path.push(".backup");
+    PathBuf::from(path)
+}
+
+/// Remove a journal and its backup directory
+pub(crate) fn remove_journal(journal_path: &Path) -> Result<(), Error> {
+    match std::fs::remove_dir_all(journal_backup_dir(journal_path)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    match std::fs::remove_file(journal_path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Journal being written while a patch is applied
+pub(crate) struct PatchJournal {
+    file: File,
+    /// Directory receiving the files moved aside by [`PatchJournal::back_up_file`]
+    backup_dir: PathBuf,
+}
+
+impl PatchJournal {
+    /// Start a new journal, replacing any existing one
+    pub fn create(path: &Path, game_path: &Path) -> Result<Self, Error> {
+        remove_journal(path)?;
+        let file = File::create(path).map_err(|e| {
+            Error::ZiPatchApply(format!("failed to create journal {:?}: {}", path, e))
+        })?;
+        let mut journal = Self {
+            file,
+            backup_dir: journal_backup_dir(path),
+        };
+        journal.append(&JournalRecord::Begin {
+            game_path: game_path.to_path_buf(),
+        })?;
+        Ok(journal)
+    }
+
+    /// Reopen an existing journal for appending, discarding anything past `len`
+    pub fn reopen(path: &Path, len: u64) -> Result<Self, Error> {
+        let mut file = OpenOptions::new().write(true).open(path).map_err(|e| {
+            Error::ZiPatchApply(format!("failed to open journal {:?}: {}", path, e))
+        })?;
+        file.set_len(len)?;
+        file.seek(SeekFrom::End(0))?;
+        Ok(Self {
+            file,
+            backup_dir: journal_backup_dir(path),
+        })
+    }
+
+    pub fn append(&mut self, record: &JournalRecord) -> Result<(), Error> {
+        let payload = bincode::serialize(record)
+            .map_err(|e| Error::ZiPatchApply(format!("failed to encode journal record: {}", e)))?;
+
+        let mut buf = Vec::with_capacity(4 + payload.len());
+        buf.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&payload);
+        self.file.write_all(&buf)?;
+
+        Ok(())
+    }
+
+    /// Flush the records written so far to disk
+    ///
+    /// Called before the files they describe are modified, so a crash never
+    /// leaves a change the journal cannot undo.
+    pub fn sync(&mut self) -> Result<(), Error> {
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    /// Record the original contents of `len` bytes of `path` at `offset`
+    ///
+    /// `len` may extend past the end of the file; only existing bytes are saved.
+    pub fn record_region(&mut self, path: &Path, offset: u64, len: u64) -> Result<(), Error> {
+        let mut source = match File::open(path) {
+            Ok(file) => Some(file),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+
+        let original_len = match &source {
+            Some(file) => Some(file.metadata()?.len()),
+            None => None,
+        };
+        let data_len = original_len.map_or(0, |file_len| len.min(file_len.saturating_sub(offset)));
+
+        self.append(&JournalRecord::Region {
+            path: path.to_path_buf(),
+            offset,
+            original_len,
+            data_len,
+        })?;
+
+        if let Some(file) = source.as_mut() {
+            if data_len > 0 {
+                file.seek(SeekFrom::Start(offset))?;
+                let copied = std::io::copy(&mut file.take(data_len), &mut self.file)?;
+                if copied != data_len {
+                    return Err(Error::ZiPatchApply(format!(
+                        "{:?} changed while being journaled",
+                        path
+                    )));
+                }
+            }
+        }
+
+        self.sync()
+    }
+
+    /// Move a file that is about to be removed or replaced into the backup
+    /// directory, leaving nothing at `path`
+    pub fn back_up_file(&mut self, path: &Path) -> Result<(), Error> {
+        std::fs::create_dir_all(&self.backup_dir)?;
+        // Uncommitted records are undone before being discarded, so the journal
+        // position is never reused while its backup still exists
+        let backup = self
+            .backup_dir
+            .join(self.file.stream_position()?.to_string());
+
+        self.append(&JournalRecord::Moved {
+            path: path.to_path_buf(),
+            backup: backup.clone(),
+        })?;
+        self.sync()?;
+
+        move_file(path, &backup)
+            .map_err(|e| Error::ZiPatchApply(format!("failed to back up {:?}: {}", path, e)))
+    }
+
+    pub fn commit(&mut self, committed_chunks: u64) -> Result<(), Error> {
+        self.append(&JournalRecord::Commit { committed_chunks })
+    }
+
+    /// Mark the application as complete and flush the journal to disk
+    pub fn finish(&mut self) -> Result<(), Error> {
+        self.append(&JournalRecord::Finished)?;
+        self.file.sync_all()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_journal_round_trip_and_state() {
+        let dir = tempdir().unwrap();
+        let journal_path = dir.path().join("test.patch.journal");
+
+        let mut journal = PatchJournal::create(&journal_path, dir.path()).unwrap();
+        journal.commit(1).unwrap();
+        journal.commit(2).unwrap();
+
+        let contents = JournalContents::read(&journal_path).unwrap().unwrap();
+        assert_eq!(contents.game_path, dir.path());
+        assert_eq!(
+            contents.state(),
+            JournalState::InProgress {
+                committed_chunks: 2
+            }
+        );
+
+        journal.finish().unwrap();
+        let contents = JournalContents::read(&journal_path).unwrap().unwrap();
+        assert_eq!(contents.state(), JournalState::Completed);
+    }
+
+    #[test]
+    fn test_journal_ignores_torn_record() {
+        let dir = tempdir().unwrap();
+        let journal_path = dir.path().join("test.patch.journal");
+
+        let mut journal = PatchJournal::create(&journal_path, dir.path()).unwrap();
+        journal.commit(3).unwrap();
+        drop(journal);
+
+        // Simulate a crash while writing the next record
+        let mut file = OpenOptions::new().append(true).open(&journal_path).unwrap();
+        file.write_all(&[200, 0, 0, 0, 1, 2]).unwrap();
+
+        let contents = JournalContents::read(&journal_path).unwrap().unwrap();
+        assert_eq!(contents.committed_chunks(), 3);
+    }
+
+    #[test]
+    fn test_journal_missing_returns_none() {
+        let dir = tempdir().unwrap();
+        assert!(JournalContents::read(&dir.path().join("none.journal"))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_undo_restores_regions_in_reverse() {
+        let dir = tempdir().unwrap();
+        let journal_path = dir.path().join("test.patch.journal");
+        let target = dir.path().join("data/file.bin");
+        let created = dir.path().join("new.bin");
+        std::fs::create_dir_all(target.parent().unwrap()).unwrap();
+        std::fs::write(&target, b"original").unwrap();
+
+        let mut journal = PatchJournal::create(&journal_path, dir.path()).unwrap();
+        journal.record_region(&target, 4, 100).unwrap();
+        std::fs::write(&target, b"origXXXXXXXXXX").unwrap();
+        journal.record_region(&target, 0, u64::MAX).unwrap();
+        std::fs::write(&target, b"Y").unwrap();
+        journal.record_region(&created, 0, u64::MAX).unwrap();
+        std::fs::write(&created, b"new").unwrap();
+
+        let mut contents = JournalContents::read(&journal_path).unwrap().unwrap();
+        contents.undo_all().unwrap();
+
+        assert_eq!(std::fs::read(&target).unwrap(), b"original");
+        assert!(!created.exists());
+    }
+
+    #[test]
+    fn test_undo_restores_backed_up_files() {
+        let dir = tempdir().unwrap();
+        let journal_path = dir.path().join("test.patch.journal");
+        let target = dir.path().join("game/data/file.bin");
+        std::fs::create_dir_all(target.parent().unwrap()).unwrap();
+        std::fs::write(&target, b"original").unwrap();
+
+        let mut journal = PatchJournal::create(&journal_path, dir.path()).unwrap();
+        journal.back_up_file(&target).unwrap();
+        assert!(!target.exists());
+        assert_eq!(
+            std::fs::read_dir(journal_backup_dir(&journal_path))
+                .unwrap()
+                .count(),
+            1
+        );
+
+        // Replace the file, then remove its directory altogether
+        journal.record_region(&target, 0, u64::MAX).unwrap();
+        std::fs::write(&target, b"replacement").unwrap();
+        journal.back_up_file(&target).unwrap();
+        std::fs::remove_dir(target.parent().unwrap()).unwrap();
+
+        let mut contents = JournalContents::read(&journal_path).unwrap().unwrap();
+        contents.undo_all().unwrap();
+        assert_eq!(std::fs::read(&target).unwrap(), b"original");
+
+        remove_journal(&journal_path).unwrap();
+        assert!(!journal_path.exists());
+        assert!(!journal_backup_dir(&journal_path).exists());
+    }
+}
@@ -1,7 +1,11 @@
 //! ZiPatch file parser and applier implementation
 //!
-//! This module implements the ZiPatch binary format parser for FFXIV patch files.
+//! This module implements the ZiPatch binary format parser and writer for FFXIV patch files.
 
+mod apply;
+mod journal;
 mod parser;
+mod writer;
 
-pub use parser::ZiPatchParser;
+pub use parser::{ZiPatchChunks, ZiPatchParser};
+pub use writer::ZiPatchWriter;
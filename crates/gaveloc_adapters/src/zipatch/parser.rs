@@ -2,18 +2,33 @@
 //!
 //! Parses ZiPatch binary files and extracts chunks for patch application.
 
+use std::collections::HashSet;
 use std::fs::File;
 use std::io::{BufReader, Read, Seek, SeekFrom};
 use std::path::Path;
 
-use byteorder::{BigEndian, ReadBytesExt};
+use byteorder::{BigEndian, LittleEndian, ReadBytesExt};
 use crc32fast::Hasher;
 use tracing::instrument;
 
+use gaveloc_core::control::UpdateControl;
 use gaveloc_core::error::Error;
 use gaveloc_core::ports::ZiPatchApplier;
 use gaveloc_core::zipatch::*;
 
+use super::apply::{normalize_relative, ApplyContext};
+use super::journal::{remove_journal, JournalContents, JournalRecord, PatchJournal};
+use crate::integrity::invalidate_hashes;
+
+/// Compressed size value marking a file block as stored uncompressed
+pub(super) const UNCOMPRESSED_BLOCK_MARKER: u32 = 32000;
+
+/// File blocks (header included) are padded to this alignment
+pub(super) const FILE_BLOCK_ALIGNMENT: usize = 128;
+
+/// Buffer size used when hashing chunk payloads that are not kept in memory
+const SKIP_BUFFER_SIZE: usize = 64 * 1024;
+
 /// ZiPatch file parser
 pub struct ZiPatchParser {
     /// Whether to verify CRC32 checksums on chunks
@@ -43,6 +58,45 @@ impl ZiPatchParser {
             .to_string())
     }
 
+    /// Advance past `len` bytes, feeding them to the CRC hasher if verifying
+    fn skip_bytes(
+        reader: &mut BufReader<File>,
+        len: u64,
+        hasher: &mut Option<Hasher>,
+    ) -> Result<(), Error> {
+        if let Some(ref mut h) = hasher {
+            let mut buf = vec![0u8; SKIP_BUFFER_SIZE.min(len as usize)];
+            let mut remaining = len;
+            while remaining > 0 {
+                let n = remaining.min(buf.len() as u64) as usize;
+                reader.read_exact(&mut buf[..n])?;
+                h.update(&buf[..n]);
+                remaining -= n as u64;
+            }
+        } else if len > 0 {
+            reader.seek_relative(len as i64)?;
+        }
+
+        Ok(())
+    }
+
+    /// Open a patch file and iterate over its chunks without loading their payloads
+    ///
+    /// Data-heavy commands reference their payload through a `data_source_offset`
+    /// into the patch file, so memory use does not depend on the patch size.
+    pub fn chunks(&self, patch_path: &Path) -> Result<ZiPatchChunks<'_>, Error> {
+        let file = File::open(patch_path)?;
+        let mut reader = BufReader::new(file);
+
+        Self::read_magic_header(&mut reader)?;
+
+        Ok(ZiPatchChunks {
+            parser: self,
+            reader,
+            finished: false,
+        })
+    }
+
     /// Read the magic header and verify it
     fn read_magic_header(reader: &mut impl Read) -> Result<(), Error> {
         let mut magic = [0u8; 12];
@@ -89,35 +143,13 @@ impl ZiPatchParser {
             "SQPK" => self.parse_sqpk_chunk(reader, offset, size, &mut crc_hasher)?,
             "EOF_" => {
                 // Advance past any remaining data
-                if let Some(ref mut hasher) = crc_hasher {
-                    let mut buf = vec![0u8; data_size.saturating_sub(4)];
-                    if !buf.is_empty() {
-                        reader.read_exact(&mut buf)?;
-                        hasher.update(&buf);
-                    }
-                } else {
-                    let remaining = data_size.saturating_sub(4);
-                    if remaining > 0 {
-                        reader.seek(SeekFrom::Current(remaining as i64))?;
-                    }
-                }
+                Self::skip_bytes(reader, data_size.saturating_sub(4) as u64, &mut crc_hasher)?;
                 ZiPatchChunk::EndOfFile
             }
             _ => {
                 // Unknown chunk - skip it
                 tracing::warn!("Unknown chunk type: {}", chunk_type);
-                if let Some(ref mut hasher) = crc_hasher {
-                    let mut buf = vec![0u8; data_size.saturating_sub(4)];
-                    if !buf.is_empty() {
-                        reader.read_exact(&mut buf)?;
-                        hasher.update(&buf);
-                    }
-                } else {
-                    let remaining = data_size.saturating_sub(4);
-                    if remaining > 0 {
-                        reader.seek(SeekFrom::Current(remaining as i64))?;
-                    }
-                }
+                Self::skip_bytes(reader, data_size.saturating_sub(4) as u64, &mut crc_hasher)?;
                 ZiPatchChunk::Unknown {
                     chunk_type,
                     offset,
@@ -130,14 +162,7 @@ impl ZiPatchParser {
         let expected_crc_pos = data_start + data_size as u64;
         let current_pos = reader.stream_position()?;
         if current_pos < expected_crc_pos {
-            let skip = expected_crc_pos - current_pos;
-            if let Some(ref mut hasher) = crc_hasher {
-                let mut buf = vec![0u8; skip as usize];
-                reader.read_exact(&mut buf)?;
-                hasher.update(&buf);
-            } else {
-                reader.seek(SeekFrom::Start(expected_crc_pos))?;
-            }
+            Self::skip_bytes(reader, expected_crc_pos - current_pos, &mut crc_hasher)?;
         }
 
         // Read and verify CRC32
@@ -174,7 +199,7 @@ impl ZiPatchParser {
             buf.extend_from_slice(patch_type.as_bytes());
             // Pad to 4 bytes if needed
             let pad_len = 4usize.saturating_sub(patch_type.len());
-            buf.extend(std::iter::repeat(0u8).take(pad_len));
+            buf.extend(std::iter::repeat_n(0u8, pad_len));
             buf.extend_from_slice(&entry_files.to_be_bytes());
             h.update(&buf);
         }
@@ -182,15 +207,7 @@ impl ZiPatchParser {
         // Skip remaining header data
         let read_so_far = 12; // 2 + 2 + 4 + 4
         let remaining = (size as usize).saturating_sub(read_so_far + 4); // +4 for chunk type already read
-        if remaining > 0 {
-            if let Some(ref mut h) = hasher {
-                let mut buf = vec![0u8; remaining];
-                reader.read_exact(&mut buf)?;
-                h.update(&buf);
-            } else {
-                reader.seek(SeekFrom::Current(remaining as i64))?;
-            }
-        }
+        Self::skip_bytes(reader, remaining as u64, hasher)?;
 
         Ok(ZiPatchChunk::FileHeader(FileHeaderChunk {
             version,
@@ -249,7 +266,10 @@ impl ZiPatchParser {
             h.update(&buf);
         }
 
-        Ok(ZiPatchChunk::AddDirectory(AddDirectoryChunk { path, offset }))
+        Ok(ZiPatchChunk::AddDirectory(AddDirectoryChunk {
+            path,
+            offset,
+        }))
     }
 
     /// Parse delete directory chunk
@@ -324,20 +344,14 @@ impl ZiPatchParser {
             "D" => self.parse_sqpk_delete_data(reader, offset, hasher)?,
             "E" => self.parse_sqpk_expand_data(reader, offset, hasher)?,
             "H" => self.parse_sqpk_header(reader, offset, data_size, hasher)?,
-            "I" => self.parse_sqpk_index(reader, offset, data_size, hasher)?,
+            "I" => self.parse_sqpk_index(reader, offset, hasher)?,
             "F" => self.parse_sqpk_file(reader, offset, data_size, hasher)?,
             "X" => self.parse_sqpk_patch_info(reader, offset, hasher)?,
             "T" => self.parse_sqpk_target_info(reader, offset, hasher)?,
             _ => {
                 tracing::warn!("Unknown SQPK command: {}", command);
                 // Skip the data
-                if let Some(ref mut h) = hasher {
-                    let mut buf = vec![0u8; data_size];
-                    reader.read_exact(&mut buf)?;
-                    h.update(&buf);
-                } else {
-                    reader.seek(SeekFrom::Current(data_size as i64))?;
-                }
+                Self::skip_bytes(reader, data_size as u64, hasher)?;
                 SqpkChunk::Unknown { command, offset }
             }
         };
@@ -397,28 +411,25 @@ impl ZiPatchParser {
             h.update(&((block_delete_number >> 7) as u32).to_be_bytes());
         }
 
-        // Block data
+        // Block data, left in the patch file and referenced by offset
         let data_source_offset = reader.stream_position()?;
-        let block_data_len = block_number as usize;
 
-        // Calculate how much we've read so far: 3 + 8 + 12 = 23
-        // Data should be remaining
-        let expected_data_len = data_size.saturating_sub(23);
-        let actual_read_len = block_data_len.min(expected_data_len);
-
-        let mut block_data = vec![0u8; actual_read_len];
-        reader.read_exact(&mut block_data)?;
-
-        if let Some(ref mut h) = hasher {
-            h.update(&block_data);
+        // Header is 3 + 8 + 12 = 23 bytes, data is the remainder
+        let expected_data_len = data_size.saturating_sub(23) as u64;
+        if block_number > expected_data_len {
+            return Err(Error::ZiPatchParse(format!(
+                "AddData at {} has {} bytes of data, expected {}",
+                offset, expected_data_len, block_number
+            )));
         }
 
+        Self::skip_bytes(reader, block_number, hasher)?;
+
         Ok(SqpkChunk::AddData(SqpkAddData {
             target_file,
             block_offset,
             block_number,
             block_delete_number,
-            block_data,
             data_source_offset,
             offset,
         }))
@@ -509,13 +520,7 @@ impl ZiPatchParser {
         data_size: usize,
         hasher: &mut Option<Hasher>,
     ) -> Result<SqpkChunk, Error> {
-        // 3 bytes alignment
-        let mut align = [0u8; 3];
-        reader.read_exact(&mut align)?;
-        if let Some(ref mut h) = hasher {
-            h.update(&align);
-        }
-
+        // File kind, header kind and 1 byte alignment
         let file_kind = reader.read_u8()?;
         let header_kind = reader.read_u8()?;
         let _pad = reader.read_u8()?;
@@ -527,7 +532,7 @@ impl ZiPatchParser {
         let target_file = self.parse_sqpack_file_target(reader, hasher)?;
 
         // Header data is remaining bytes
-        let header_len = data_size.saturating_sub(14); // 3 + 3 + 8
+        let header_len = data_size.saturating_sub(11); // 3 + 8
         let mut header_data = vec![0u8; header_len];
         reader.read_exact(&mut header_data)?;
 
@@ -549,65 +554,35 @@ impl ZiPatchParser {
         &self,
         reader: &mut BufReader<File>,
         offset: u64,
-        data_size: usize,
         hasher: &mut Option<Hasher>,
     ) -> Result<SqpkChunk, Error> {
-        // 3 bytes alignment
-        let mut align = [0u8; 3];
-        reader.read_exact(&mut align)?;
-        if let Some(ref mut h) = hasher {
-            h.update(&align);
-        }
-
-        let index_cmd = reader.read_u8()?;
-        let is_synonym = (index_cmd & 0x10) != 0;
-        let index_type = if (index_cmd & 0x0F) == 0 {
-            IndexType::Index
-        } else {
-            IndexType::Index2
-        };
-
-        if let Some(ref mut h) = hasher {
-            h.update(&[index_cmd]);
-        }
-
-        // 2 bytes padding
-        let mut pad = [0u8; 2];
-        reader.read_exact(&mut pad)?;
+        // Operation (1 byte), synonym flag (1 byte) and 1 byte alignment
+        let mut header = [0u8; 3];
+        reader.read_exact(&mut header)?;
         if let Some(ref mut h) = hasher {
-            h.update(&pad);
+            h.update(&header);
         }
 
         let target_file = self.parse_sqpack_file_target(reader, hasher)?;
 
-        // Index entries
-        let entries_size = data_size.saturating_sub(14); // 3 + 1 + 2 + 8
-        let entry_count = entries_size / 16; // Each entry is 16 bytes
-
-        let mut index_data = Vec::with_capacity(entry_count);
-        for _ in 0..entry_count {
-            let file_hash = reader.read_u64::<BigEndian>()?;
-            let block_offset = reader.read_u32::<BigEndian>()?;
-            let block_number = reader.read_u32::<BigEndian>()?;
-
-            if let Some(ref mut h) = hasher {
-                h.update(&file_hash.to_be_bytes());
-                h.update(&block_offset.to_be_bytes());
-                h.update(&block_number.to_be_bytes());
-            }
+        // A single index entry
+        let file_hash = reader.read_u64::<BigEndian>()?;
+        let block_offset = reader.read_u32::<BigEndian>()?;
+        let block_number = reader.read_u32::<BigEndian>()?;
 
-            index_data.push(SqpkIndexData {
-                file_hash,
-                block_offset,
-                block_number,
-            });
+        if let Some(ref mut h) = hasher {
+            h.update(&file_hash.to_be_bytes());
+            h.update(&block_offset.to_be_bytes());
+            h.update(&block_number.to_be_bytes());
         }
 
         Ok(SqpkChunk::Index(SqpkIndex {
-            index_type,
-            is_synonym,
+            command: SqpkIndexCommand::from(header[0]),
+            is_synonym: header[1] != 0,
             target_file,
-            index_data,
+            file_hash,
+            block_offset,
+            block_number,
             offset,
         }))
     }
@@ -620,25 +595,31 @@ impl ZiPatchParser {
         data_size: usize,
         hasher: &mut Option<Hasher>,
     ) -> Result<SqpkChunk, Error> {
-        // 3 bytes alignment
-        let mut align = [0u8; 3];
-        reader.read_exact(&mut align)?;
+        // Operation (1 byte) + 2 bytes alignment
+        let mut header = [0u8; 3];
+        reader.read_exact(&mut header)?;
         if let Some(ref mut h) = hasher {
-            h.update(&align);
+            h.update(&header);
         }
+        let operation = header[0];
 
-        let operation = reader.read_u8()?;
-        let _pad = reader.read_u8()?;
-        let expansion_id = reader.read_u8()?;
-
+        // Target offset and total size of the file (8 bytes each)
+        let file_offset = reader.read_u64::<BigEndian>()?;
+        let file_size = reader.read_u64::<BigEndian>()?;
         if let Some(ref mut h) = hasher {
-            h.update(&[operation, _pad, expansion_id]);
+            h.update(&file_offset.to_be_bytes());
+            h.update(&file_size.to_be_bytes());
         }
 
-        // File path length (4 bytes)
+        // File path length (4 bytes), expansion ID (2 bytes) + 2 bytes padding
         let path_len = reader.read_u32::<BigEndian>()?;
+        let expansion_id = reader.read_u16::<BigEndian>()?;
+        let mut pad = [0u8; 2];
+        reader.read_exact(&mut pad)?;
         if let Some(ref mut h) = hasher {
             h.update(&path_len.to_be_bytes());
+            h.update(&expansion_id.to_be_bytes());
+            h.update(&pad);
         }
 
         // File path
@@ -649,25 +630,85 @@ impl ZiPatchParser {
             h.update(&buf);
         }
 
-        // File data (remaining bytes)
-        let header_len = 3 + 3 + 4 + path_len as usize;
-        let file_data_len = data_size.saturating_sub(header_len);
-        let mut file_data = vec![0u8; file_data_len];
-        reader.read_exact(&mut file_data)?;
-
-        if let Some(ref mut h) = hasher {
-            h.update(&file_data);
+        // File contents as a sequence of blocks (remaining bytes)
+        let header_len = 3 + 8 + 8 + 4 + 4 + path_len as usize;
+        let mut remaining = data_size.saturating_sub(header_len);
+        let mut blocks = Vec::new();
+
+        while remaining > 0 {
+            let (block, block_size) = Self::parse_sqpk_compressed_block(reader, hasher)?;
+            if block_size > remaining {
+                return Err(Error::ZiPatchParse(format!(
+                    "file block at {} overruns SQPK chunk",
+                    offset
+                )));
+            }
+            remaining -= block_size;
+            blocks.push(block);
         }
 
         Ok(SqpkChunk::File(SqpkFile {
             operation: SqpkFileOperation::from(operation),
             expansion_id,
+            file_offset,
+            file_size,
             file_path,
-            file_data,
+            blocks,
             offset,
         }))
     }
 
+    /// Parse a single block of an SQPK File command
+    ///
+    /// Returns the block and the number of bytes it occupies in the chunk,
+    /// including its header and alignment padding.
+    fn parse_sqpk_compressed_block(
+        reader: &mut BufReader<File>,
+        hasher: &mut Option<Hasher>,
+    ) -> Result<(SqpkCompressedBlock, usize), Error> {
+        let mut header = [0u8; 16];
+        reader.read_exact(&mut header)?;
+        if let Some(ref mut h) = hasher {
+            h.update(&header);
+        }
+
+        let mut cursor = &header[..];
+        let header_size = cursor.read_u32::<LittleEndian>()? as usize;
+        let _pad = cursor.read_u32::<LittleEndian>()?;
+        let compressed_size = cursor.read_u32::<LittleEndian>()?;
+        let decompressed_size = cursor.read_u32::<LittleEndian>()?;
+
+        if header_size != header.len() {
+            return Err(Error::ZiPatchParse(format!(
+                "unexpected file block header size {}",
+                header_size
+            )));
+        }
+
+        let is_compressed = compressed_size != UNCOMPRESSED_BLOCK_MARKER;
+        let data_len = if is_compressed {
+            compressed_size
+        } else {
+            decompressed_size
+        } as usize;
+
+        // Blocks are padded to a 128-byte boundary
+        let block_size = (header_size + data_len).next_multiple_of(FILE_BLOCK_ALIGNMENT);
+
+        let data_source_offset = reader.stream_position()?;
+        Self::skip_bytes(reader, (block_size - header_size) as u64, hasher)?;
+
+        Ok((
+            SqpkCompressedBlock {
+                is_compressed,
+                decompressed_size,
+                data_source_offset,
+                data_size: data_len as u32,
+            },
+            block_size,
+        ))
+    }
+
     /// Parse SQPK PatchInfo command
     fn parse_sqpk_patch_info(
         &self,
@@ -675,21 +716,14 @@ impl ZiPatchParser {
         offset: u64,
         hasher: &mut Option<Hasher>,
     ) -> Result<SqpkChunk, Error> {
-        // 3 bytes alignment
-        let mut align = [0u8; 3];
-        reader.read_exact(&mut align)?;
-        if let Some(ref mut h) = hasher {
-            h.update(&align);
-        }
-
+        // Status, version and 1 byte alignment
         let status = reader.read_u8()?;
         let version = reader.read_u8()?;
         let _pad = reader.read_u8()?;
-        let _pad2 = reader.read_u8()?;
         let install_size = reader.read_u64::<BigEndian>()?;
 
         if let Some(ref mut h) = hasher {
-            h.update(&[status, version, _pad, _pad2]);
+            h.update(&[status, version, _pad]);
             h.update(&install_size.to_be_bytes());
         }
 
@@ -708,15 +742,15 @@ impl ZiPatchParser {
         offset: u64,
         hasher: &mut Option<Hasher>,
     ) -> Result<SqpkChunk, Error> {
-        // 3 bytes alignment + padding
-        let mut header = [0u8; 3];
-        reader.read_exact(&mut header)?;
+        // 3 reserved bytes
+        let mut reserved = [0u8; 3];
+        reader.read_exact(&mut reserved)?;
         if let Some(ref mut h) = hasher {
-            h.update(&header);
+            h.update(&reserved);
         }
 
-        let platform_byte = reader.read_u8()?;
-        let platform = match platform_byte {
+        let platform_id = reader.read_u16::<BigEndian>()?;
+        let platform = match platform_id {
             0 => Platform::Win32,
             1 => Platform::Ps3,
             2 => Platform::Ps4,
@@ -724,35 +758,30 @@ impl ZiPatchParser {
         };
 
         let region = reader.read_u16::<BigEndian>()?;
-        let is_debug = reader.read_u8()? != 0;
+        let debug = reader.read_u16::<BigEndian>()?;
         let version = reader.read_u16::<BigEndian>()?;
 
         if let Some(ref mut h) = hasher {
-            h.update(&[platform_byte]);
+            h.update(&platform_id.to_be_bytes());
             h.update(&region.to_be_bytes());
-            h.update(&[if is_debug { 1 } else { 0 }]);
+            h.update(&debug.to_be_bytes());
             h.update(&version.to_be_bytes());
         }
 
-        // Padding
-        let mut pad = [0u8; 1];
-        reader.read_exact(&mut pad)?;
-        if let Some(ref mut h) = hasher {
-            h.update(&pad);
-        }
-
-        let deleted_data_size = reader.read_u64::<BigEndian>()?;
-        let seek_count = reader.read_u64::<BigEndian>()?;
+        // Unlike the rest of the command, the sizes are little-endian
+        let deleted_data_size = reader.read_u64::<LittleEndian>()?;
+        let seek_count = reader.read_u64::<LittleEndian>()?;
 
         if let Some(ref mut h) = hasher {
-            h.update(&deleted_data_size.to_be_bytes());
-            h.update(&seek_count.to_be_bytes());
+            h.update(&deleted_data_size.to_le_bytes());
+            h.update(&seek_count.to_le_bytes());
         }
 
+        // The rest of the chunk is reserved and skipped by read_chunk
         Ok(SqpkChunk::TargetInfo(SqpkTargetInfo {
             platform,
             region,
-            is_debug,
+            is_debug: debug != 0,
             version,
             deleted_data_size,
             seek_count,
@@ -770,38 +799,189 @@ impl Default for ZiPatchParser {
 impl ZiPatchApplier for ZiPatchParser {
     #[instrument(skip(self))]
     fn parse_patch(&self, patch_path: &Path) -> Result<Vec<ZiPatchChunk>, Error> {
-        let file = File::open(patch_path)?;
-        let mut reader = BufReader::new(file);
+        self.chunks(patch_path)?.collect()
+    }
 
-        // Read and verify magic header
-        Self::read_magic_header(&mut reader)?;
+    #[instrument(skip(self))]
+    fn apply_patch(&self, patch_path: &Path, game_path: &Path) -> Result<(), Error> {
+        tracing::info!("Applying patch {:?} to {:?}", patch_path, game_path);
+        self.apply_chunks(patch_path, game_path, None, 0, None)
+    }
+
+    #[instrument(skip(self))]
+    fn apply_patch_journaled(
+        &self,
+        patch_path: &Path,
+        game_path: &Path,
+        journal_path: &Path,
+        control: &UpdateControl,
+    ) -> Result<(), Error> {
+        let (journal, skip_chunks) = match JournalContents::read(journal_path)? {
+            Some(mut contents) => {
+                if contents.game_path != game_path {
+                    return Err(Error::ZiPatchApply(format!(
+                        "journal {:?} was started for {:?}, not {:?}",
+                        journal_path, contents.game_path, game_path
+                    )));
+                }
+
+                match contents.state() {
+                    JournalState::Completed => {
+                        tracing::info!("Patch {:?} already applied, skipping", patch_path);
+                        return Ok(());
+                    }
+                    JournalState::InProgress { committed_chunks } => {
+                        tracing::info!(
+                            "Resuming patch {:?} after {} chunks",
+                            patch_path,
+                            committed_chunks
+                        );
+                        let keep_len = contents.undo_uncommitted()?;
+                        (
+                            PatchJournal::reopen(journal_path, keep_len)?,
+                            committed_chunks,
+                        )
+                    }
+                }
+            }
+            None => {
+                tracing::info!("Applying patch {:?} to {:?}", patch_path, game_path);
+                (PatchJournal::create(journal_path, game_path)?, 0)
+            }
+        };
 
-        // Read all chunks
-        let mut chunks = Vec::new();
-        loop {
-            let chunk = self.read_chunk(&mut reader)?;
-            let is_eof = matches!(chunk, ZiPatchChunk::EndOfFile);
-            chunks.push(chunk);
+        self.apply_chunks(
+            patch_path,
+            game_path,
+            Some(journal),
+            skip_chunks,
+            Some(control),
+        )
+    }
 
-            if is_eof {
-                break;
+    #[instrument(skip(self))]
+    fn rollback_patch(&self, journal_path: &Path) -> Result<(), Error> {
+        if let Some(mut contents) = JournalContents::read(journal_path)? {
+            tracing::info!("Rolling back patch in {:?}", contents.game_path);
+            contents.undo_all()?;
+
+            let restored = contents
+                .records
+                .iter()
+                .filter_map(|(_, record)| match record {
+                    JournalRecord::Region { path, .. }
+                    | JournalRecord::CreatedDir { path }
+                    | JournalRecord::RemovedDir { path }
+                    | JournalRecord::Moved { path, .. } => Some(path.as_path()),
+                    _ => None,
+                });
+            if let Err(e) = invalidate_hashes(&contents.game_path, restored) {
+                tracing::warn!(
+                    "failed to invalidate cached hashes in {:?}: {}",
+                    contents.game_path,
+                    e
+                );
             }
         }
 
-        Ok(chunks)
+        remove_journal(journal_path)
+    }
+
+    fn discard_journal(&self, journal_path: &Path) -> Result<(), Error> {
+        remove_journal(journal_path)
+    }
+
+    fn journal_state(&self, journal_path: &Path) -> Result<Option<JournalState>, Error> {
+        Ok(JournalContents::read(journal_path)?.map(|contents| contents.state()))
     }
 
     #[instrument(skip(self))]
-    fn apply_patch(&self, patch_path: &Path, game_path: &Path) -> Result<(), Error> {
-        let chunks = self.parse_patch(patch_path)?;
+    fn read_space_info(&self, patch_path: &Path) -> Result<Option<PatchSpaceInfo>, Error> {
+        let mut install_size = None;
+        let mut deleted_data_size = 0;
+        let mut overwritten_bytes = 0;
+
+        // Only command headers are needed, so payloads are skipped unchecked
+        for chunk in Self::without_checksum_verification().chunks(patch_path)? {
+            match chunk? {
+                ZiPatchChunk::Sqpk(SqpkChunk::TargetInfo(info)) => {
+                    deleted_data_size = info.deleted_data_size;
+                }
+                ZiPatchChunk::Sqpk(SqpkChunk::PatchInfo(info)) => {
+                    install_size = Some(info.install_size);
+                }
+                ZiPatchChunk::Sqpk(sqpk) => {
+                    // The header commands come first, so the rest is not needed without them
+                    if install_size.is_none() {
+                        break;
+                    }
+                    overwritten_bytes += sqpk.overwritten_bytes();
+                }
+                _ => {}
+            }
+        }
 
-        tracing::info!(
-            "Applying patch with {} chunks to {:?}",
-            chunks.len(),
-            game_path
-        );
+        Ok(install_size.map(|install_size| PatchSpaceInfo {
+            install_size,
+            deleted_data_size,
+            journal_size: overwritten_bytes + deleted_data_size,
+        }))
+    }
+}
+
+impl ZiPatchParser {
+    /// Apply the chunks of a patch, skipping the first `skip_chunks`
+    ///
+    /// With a journal, a commit record is written after every chunk. The
+    /// control is checked once each chunk is committed.
+    fn apply_chunks(
+        &self,
+        patch_path: &Path,
+        game_path: &Path,
+        journal: Option<PatchJournal>,
+        skip_chunks: u64,
+        control: Option<&UpdateControl>,
+    ) -> Result<(), Error> {
+        let chunks = self.chunks(patch_path)?;
+
+        // Payloads are read through a separate handle while the chunks are streamed
+        let mut ctx = ApplyContext::new(game_path, File::open(patch_path)?);
+        if let Some(journal) = journal {
+            ctx = ctx.with_journal(journal);
+        }
+
+        let result = Self::apply_remaining(&mut ctx, chunks, skip_chunks, control);
+
+        // Even a failed or interrupted application may have modified files
+        if let Err(e) = ctx.invalidate_hashes() {
+            tracing::warn!(
+                "failed to invalidate cached hashes in {:?}: {}",
+                game_path,
+                e
+            );
+        }
+
+        result
+    }
+
+    /// Apply the chunks after the first `skip_chunks`, committing each one
+    fn apply_remaining(
+        ctx: &mut ApplyContext<'_>,
+        chunks: ZiPatchChunks<'_>,
+        skip_chunks: u64,
+        control: Option<&UpdateControl>,
+    ) -> Result<(), Error> {
+        for (index, chunk) in chunks.enumerate() {
+            let chunk = chunk?;
+
+            if (index as u64) < skip_chunks {
+                // Already applied, but the target platform is still needed
+                if let ZiPatchChunk::Sqpk(SqpkChunk::TargetInfo(info)) = &chunk {
+                    ctx.platform = info.platform;
+                }
+                continue;
+            }
 
-        for chunk in chunks {
             match chunk {
                 ZiPatchChunk::FileHeader(fh) => {
                     tracing::debug!(
@@ -814,34 +994,203 @@ impl ZiPatchApplier for ZiPatchParser {
                 ZiPatchChunk::ApplyOption(opt) => {
                     tracing::debug!("Apply option: {:?} = {}", opt.option, opt.value);
                 }
-                ZiPatchChunk::AddDirectory(dir) => {
-                    let full_path = game_path.join(&dir.path.trim_start_matches('/'));
-                    tracing::debug!("Creating directory: {:?}", full_path);
-                    std::fs::create_dir_all(&full_path)?;
+                ZiPatchChunk::AddDirectory(dir) => ctx.add_directory(&dir.path)?,
+                ZiPatchChunk::DeleteDirectory(dir) => ctx.delete_directory(&dir.path)?,
+                ZiPatchChunk::Sqpk(sqpk) => Self::apply_sqpk(ctx, &sqpk)?,
+                ZiPatchChunk::EndOfFile => {
+                    tracing::debug!("End of patch file");
+                }
+                _ => {}
+            }
+
+            ctx.commit(index as u64 + 1)?;
+
+            if let Some(control) = control {
+                control.checkpoint_blocking()?;
+            }
+        }
+
+        ctx.finish()
+    }
+}
+
+impl ZiPatchParser {
+    /// Replay only the commands of a patch that write one of `files`
+    ///
+    /// `files` are relative to `game_path` and separated by `/`. Replaying a
+    /// repository's whole patch chain this way into an empty directory
+    /// rebuilds just those files. Returns the files the patch wrote or deleted.
+    #[instrument(skip(self, files, control))]
+    pub fn replay_files(
+        &self,
+        patch_path: &Path,
+        game_path: &Path,
+        files: &HashSet<String>,
+        control: &UpdateControl,
+    ) -> Result<HashSet<String>, Error> {
+        let chunks = self.chunks(patch_path)?;
+        let mut ctx = ApplyContext::new(game_path, File::open(patch_path)?);
+        let mut touched = HashSet::new();
+
+        for chunk in chunks {
+            match chunk? {
+                ZiPatchChunk::Sqpk(SqpkChunk::TargetInfo(info)) => ctx.platform = info.platform,
+                ZiPatchChunk::Sqpk(SqpkChunk::File(cmd))
+                    if cmd.operation == SqpkFileOperation::RemoveAll =>
+                {
+                    // Like the full application, `.var` files survive
+                    let folder = format!("sqpack/{}/", expansion_folder(cmd.expansion_id));
+                    let removed = files
+                        .iter()
+                        .filter(|file| file.starts_with(&folder) && !file.ends_with(".var"));
+                    Self::remove_replayed(&ctx, removed, &mut touched)?;
                 }
                 ZiPatchChunk::DeleteDirectory(dir) => {
-                    let full_path = game_path.join(&dir.path.trim_start_matches('/'));
-                    tracing::debug!("Deleting directory: {:?}", full_path);
-                    if full_path.exists() {
-                        std::fs::remove_dir_all(&full_path)?;
-                    }
+                    let dir = format!("{}/", normalize_relative(&dir.path).trim_end_matches('/'));
+                    let removed = files.iter().filter(|file| file.starts_with(&dir));
+                    Self::remove_replayed(&ctx, removed, &mut touched)?;
                 }
                 ZiPatchChunk::Sqpk(sqpk) => {
-                    // TODO: Implement SQPK command application
-                    // This requires implementing the SqPack file format handling
-                    tracing::debug!("SQPK command: {}", sqpk.command());
-                }
-                ZiPatchChunk::EndOfFile => {
-                    tracing::debug!("End of patch file");
+                    if let Some(target) = ctx.command_target(&sqpk) {
+                        if files.contains(&target) {
+                            Self::apply_sqpk(&mut ctx, &sqpk)?;
+                            touched.insert(target);
+                        }
+                    }
                 }
+                // Directories are created as files are written
                 _ => {}
             }
+
+            control.checkpoint_blocking()?;
         }
 
+        Ok(touched)
+    }
+
+    /// Remove replayed files deleted by a patch
+    fn remove_replayed<'f>(
+        ctx: &ApplyContext<'_>,
+        files: impl Iterator<Item = &'f String>,
+        touched: &mut HashSet<String>,
+    ) -> Result<(), Error> {
+        for file in files {
+            let path = ctx.resolve(file);
+            if path.exists() {
+                std::fs::remove_file(&path)?;
+            }
+            touched.insert(file.clone());
+        }
         Ok(())
     }
 }
 
+/// Iterator over the chunks of a patch file, created by [`ZiPatchParser::chunks`]
+///
+/// Chunks are read one at a time; iteration stops after the EOF chunk or the
+/// first error.
+pub struct ZiPatchChunks<'a> {
+    parser: &'a ZiPatchParser,
+    reader: BufReader<File>,
+    finished: bool,
+}
+
+impl Iterator for ZiPatchChunks<'_> {
+    type Item = Result<ZiPatchChunk, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
+        let result = self.parser.read_chunk(&mut self.reader);
+        if matches!(result, Ok(ZiPatchChunk::EndOfFile) | Err(_)) {
+            self.finished = true;
+        }
+
+        Some(result)
+    }
+}
+
+impl ZiPatchParser {
+    /// Apply a single SQPK command to the installation
+    fn apply_sqpk(ctx: &mut ApplyContext<'_>, sqpk: &SqpkChunk) -> Result<(), Error> {
+        match sqpk {
+            SqpkChunk::AddData(cmd) => {
+                tracing::trace!(
+                    "AddData {} at {:#x} ({} bytes)",
+                    cmd.target_file,
+                    cmd.block_offset,
+                    cmd.block_number
+                );
+                ctx.apply_add_data(cmd)
+            }
+            SqpkChunk::DeleteData(cmd) => {
+                tracing::trace!(
+                    "DeleteData {} at {:#x} ({} bytes)",
+                    cmd.target_file,
+                    cmd.block_offset,
+                    cmd.block_number
+                );
+                ctx.apply_delete_data(cmd)
+            }
+            SqpkChunk::ExpandData(cmd) => {
+                tracing::trace!(
+                    "ExpandData {} at {:#x} ({} bytes)",
+                    cmd.target_file,
+                    cmd.block_offset,
+                    cmd.block_number
+                );
+                ctx.apply_expand_data(cmd)
+            }
+            SqpkChunk::Header(cmd) => {
+                tracing::trace!(
+                    "Header {:?}/{:?} {} ({} bytes)",
+                    cmd.file_kind,
+                    cmd.header_kind,
+                    cmd.target_file,
+                    cmd.header_data.len()
+                );
+                ctx.apply_header(cmd)
+            }
+            SqpkChunk::Index(cmd) => {
+                tracing::trace!(
+                    "Index {:?} {} hash {:#x}",
+                    cmd.command,
+                    cmd.target_file,
+                    cmd.file_hash
+                );
+                ctx.apply_index(cmd)
+            }
+            SqpkChunk::File(cmd) => {
+                tracing::trace!(
+                    "File {:?} {} at {:#x} ({} blocks)",
+                    cmd.operation,
+                    cmd.file_path,
+                    cmd.file_offset,
+                    cmd.blocks.len()
+                );
+                ctx.apply_file(cmd)
+            }
+            SqpkChunk::TargetInfo(info) => {
+                tracing::debug!(
+                    "Target platform: {}, region: {}, version: {}",
+                    info.platform,
+                    info.region,
+                    info.version
+                );
+                ctx.platform = info.platform;
+                Ok(())
+            }
+            other => {
+                // PatchInfo and unknown commands carry nothing to apply
+                tracing::debug!("Skipping SQPK command: {}", other.command());
+                Ok(())
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -987,22 +1336,18 @@ mod tests {
         // SQPK structure: inner_size(4) + command(1) + data
         let mut sqpk_data = Vec::new();
 
-        // Inner size (data after this field)
-        // 1 (command) + 3 (align) + 4 (status/version/pad) + 8 (install_size) = 16
+        // Inner size (covers itself)
+        // 4 (size) + 1 (command) + 3 (status/version/align) + 8 (install_size) = 16
         let inner_size: i32 = 16;
         sqpk_data.extend_from_slice(&inner_size.to_be_bytes());
 
         // Command: 'X' for PatchInfo
         sqpk_data.push(b'X');
 
-        // Alignment (3 bytes)
-        sqpk_data.extend_from_slice(&[0u8; 3]);
-
-        // Status, version, padding
+        // Status, version, alignment
         sqpk_data.push(1); // status
         sqpk_data.push(2); // version
-        sqpk_data.push(0); // pad
-        sqpk_data.push(0); // pad2
+        sqpk_data.push(0); // alignment
 
         // Install size (8 bytes)
         sqpk_data.extend_from_slice(&1024u64.to_be_bytes());
@@ -1014,40 +1359,164 @@ mod tests {
     fn build_sqpk_target_info_chunk() -> Vec<u8> {
         let mut sqpk_data = Vec::new();
 
-        // Inner size: 1 (cmd) + 3 (align) + 1 (platform) + 2 (region) + 1 (is_debug) + 2 (version) + 1 (pad) + 8 + 8 = 27
-        let inner_size: i32 = 27;
+        // Inner size: 4 (size) + 1 (cmd) + 3 (reserved) + 2 (platform) + 2 (region)
+        // + 2 (is_debug) + 2 (version) + 8 + 8 + 96 (reserved) = 128
+        let inner_size: i32 = 128;
         sqpk_data.extend_from_slice(&inner_size.to_be_bytes());
 
         // Command: 'T'
         sqpk_data.push(b'T');
 
-        // Alignment (3 bytes)
+        // Reserved (3 bytes)
         sqpk_data.extend_from_slice(&[0u8; 3]);
 
         // Platform (0 = Win32)
-        sqpk_data.push(0);
+        sqpk_data.extend_from_slice(&0u16.to_be_bytes());
 
         // Region
         sqpk_data.extend_from_slice(&1u16.to_be_bytes());
 
         // Is debug
-        sqpk_data.push(0);
+        sqpk_data.extend_from_slice(&0u16.to_be_bytes());
 
         // Version
         sqpk_data.extend_from_slice(&100u16.to_be_bytes());
 
-        // Padding
-        sqpk_data.push(0);
+        // Deleted data size and seek count, little-endian
+        sqpk_data.extend_from_slice(&512u64.to_le_bytes());
+        sqpk_data.extend_from_slice(&256u64.to_le_bytes());
 
-        // Deleted data size
-        sqpk_data.extend_from_slice(&512u64.to_be_bytes());
+        // Reserved
+        sqpk_data.extend_from_slice(&[0u8; 96]);
 
-        // Seek count
-        sqpk_data.extend_from_slice(&256u64.to_be_bytes());
+        build_chunk(b"SQPK", &sqpk_data)
+    }
 
+    /// Append a SqPack target file specification (main_id, sub_id, file_id)
+    fn push_target(data: &mut Vec<u8>, main_id: u16, sub_id: u16, file_id: u32) {
+        data.extend_from_slice(&main_id.to_be_bytes());
+        data.extend_from_slice(&sub_id.to_be_bytes());
+        data.extend_from_slice(&file_id.to_be_bytes());
+    }
+
+    /// Build a SQPK chunk from a command byte and its payload
+    /// Inner size covers itself (4) + command (1) + payload
+    fn build_sqpk_chunk(command: u8, payload: &[u8]) -> Vec<u8> {
+        let mut sqpk_data = Vec::new();
+        let inner_size = (4 + 1 + payload.len()) as i32;
+        sqpk_data.extend_from_slice(&inner_size.to_be_bytes());
+        sqpk_data.push(command);
+        sqpk_data.extend_from_slice(payload);
         build_chunk(b"SQPK", &sqpk_data)
     }
 
+    /// Build a SQPK Add Data (A command) chunk targeting 040000.win32.dat0
+    /// Offsets and lengths are in 128-byte blocks; data must be block-aligned
+    fn build_sqpk_add_data_chunk(block_offset: u32, data: &[u8], delete_blocks: u32) -> Vec<u8> {
+        assert_eq!(data.len() % 128, 0);
+        let mut payload = vec![0u8; 3]; // alignment
+        push_target(&mut payload, 0x04, 0x0000, 0);
+        payload.extend_from_slice(&block_offset.to_be_bytes());
+        payload.extend_from_slice(&((data.len() >> 7) as u32).to_be_bytes());
+        payload.extend_from_slice(&delete_blocks.to_be_bytes());
+        payload.extend_from_slice(data);
+        build_sqpk_chunk(b'A', &payload)
+    }
+
+    /// Build a SQPK Delete Data (D) or Expand Data (E) chunk targeting 040000.win32.dat0
+    fn build_sqpk_block_chunk(command: u8, block_offset: u32, block_count: u32) -> Vec<u8> {
+        let mut payload = vec![0u8; 3]; // alignment
+        push_target(&mut payload, 0x04, 0x0000, 0);
+        payload.extend_from_slice(&block_offset.to_be_bytes());
+        payload.extend_from_slice(&block_count.to_be_bytes());
+        payload.extend_from_slice(&[0u8; 4]); // padding
+        build_sqpk_chunk(command, &payload)
+    }
+
+    /// Build a SQPK Header (H command) chunk targeting 040000 with file ID 0
+    fn build_sqpk_header_chunk(file_kind: u8, header_kind: u8, header: &[u8]) -> Vec<u8> {
+        let mut payload = vec![file_kind, header_kind, 0]; // kinds, alignment
+        push_target(&mut payload, 0x04, 0x0000, 0);
+        payload.extend_from_slice(header);
+        build_sqpk_chunk(b'H', &payload)
+    }
+
+    /// Build a SQPK Index (I command) chunk targeting 040000.win32.index
+    fn build_sqpk_index_chunk(op: u8, hash: u64, block_offset: u32) -> Vec<u8> {
+        let mut payload = vec![op, 0, 0]; // operation, synonym, alignment
+        push_target(&mut payload, 0x04, 0x0000, 0);
+        payload.extend_from_slice(&hash.to_be_bytes());
+        payload.extend_from_slice(&block_offset.to_be_bytes());
+        payload.extend_from_slice(&1u32.to_be_bytes());
+        build_sqpk_chunk(b'I', &payload)
+    }
+
+    /// Build a file block, DEFLATE-compressing the data if requested
+    fn build_file_block(data: &[u8], compress: bool) -> Vec<u8> {
+        use flate2::write::DeflateEncoder;
+        use std::io::Write;
+
+        let (compressed_size, body) = if compress {
+            let mut encoder = DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(data).unwrap();
+            let body = encoder.finish().unwrap();
+            (body.len() as u32, body)
+        } else {
+            (32000, data.to_vec())
+        };
+
+        let mut block = Vec::new();
+        block.extend_from_slice(&16u32.to_le_bytes()); // header size
+        block.extend_from_slice(&0u32.to_le_bytes()); // padding
+        block.extend_from_slice(&compressed_size.to_le_bytes());
+        block.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        block.extend_from_slice(&body);
+        block.resize(block.len().next_multiple_of(128), 0);
+        block
+    }
+
+    /// Build a SQPK File (F command) chunk from pre-built blocks
+    fn build_sqpk_file_chunk(
+        op: u8,
+        expansion_id: u16,
+        file_offset: u64,
+        path: &str,
+        blocks: &[Vec<u8>],
+    ) -> Vec<u8> {
+        let mut path_bytes = path.as_bytes().to_vec();
+        path_bytes.push(0);
+
+        let mut payload = vec![op, 0, 0]; // operation, alignment
+        payload.extend_from_slice(&file_offset.to_be_bytes());
+        payload.extend_from_slice(&0u64.to_be_bytes()); // file size
+        payload.extend_from_slice(&(path_bytes.len() as u32).to_be_bytes());
+        payload.extend_from_slice(&expansion_id.to_be_bytes());
+        payload.extend_from_slice(&[0u8; 2]); // padding
+        payload.extend_from_slice(&path_bytes);
+        for block in blocks {
+            payload.extend_from_slice(block);
+        }
+        build_sqpk_chunk(b'F', &payload)
+    }
+
+    /// Wrap chunks in a patch file with magic, FHDR and EOF_
+    fn build_patch(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let mut patch = Vec::new();
+        patch.extend_from_slice(&ZIPATCH_MAGIC);
+        patch.extend_from_slice(&build_fhdr_chunk());
+        for chunk in chunks {
+            patch.extend_from_slice(chunk);
+        }
+        patch.extend_from_slice(&build_eof_chunk());
+        patch
+    }
+
+    /// Relative path of the dat file targeted by the SQPK fixtures
+    const TEST_DAT_PATH: &str = "sqpack/ffxiv/040000.win32.dat0";
+
+    /// Relative path of the index file targeted by the SQPK fixtures
+    const TEST_INDEX_PATH: &str = "sqpack/ffxiv/040000.win32.index";
+
     /// Build a complete minimal patch file
     fn build_minimal_patch() -> Vec<u8> {
         let mut patch = Vec::new();
@@ -1350,6 +1819,39 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_read_space_info() {
+        let patch = build_patch(&[
+            build_sqpk_target_info_chunk(),
+            build_sqpk_patch_info_chunk(),
+            build_sqpk_add_data_chunk(0, &[0x22u8; 128], 0),
+        ]);
+        let temp_file = create_temp_patch(&patch);
+
+        let info = ZiPatchParser::new()
+            .read_space_info(temp_file.path())
+            .unwrap();
+        assert_eq!(
+            info,
+            Some(PatchSpaceInfo {
+                install_size: 1024,
+                deleted_data_size: 512,
+                journal_size: 128 + 512,
+            })
+        );
+    }
+
+    #[test]
+    fn test_read_space_info_without_patch_info() {
+        let patch = build_patch(&[build_sqpk_add_data_chunk(0, &[0x22u8; 128], 0)]);
+        let temp_file = create_temp_patch(&patch);
+
+        let info = ZiPatchParser::new()
+            .read_space_info(temp_file.path())
+            .unwrap();
+        assert_eq!(info, None);
+    }
+
     // ==========================================================================
     // Unknown Chunk Handling Tests
     // ==========================================================================
@@ -1368,7 +1870,10 @@ mod tests {
         let chunks = parser.parse_patch(temp_file.path()).unwrap();
 
         assert_eq!(chunks.len(), 3);
-        if let ZiPatchChunk::Unknown { chunk_type, size, .. } = &chunks[1] {
+        if let ZiPatchChunk::Unknown {
+            chunk_type, size, ..
+        } = &chunks[1]
+        {
             assert_eq!(chunk_type, "UNKN");
             assert_eq!(*size, 9); // data only (not including type)
         } else {
@@ -1474,7 +1979,9 @@ mod tests {
         let game_dir = tempfile::tempdir().unwrap();
 
         let parser = ZiPatchParser::new();
-        parser.apply_patch(temp_file.path(), game_dir.path()).unwrap();
+        parser
+            .apply_patch(temp_file.path(), game_dir.path())
+            .unwrap();
 
         // Check that directory was created
         assert!(game_dir.path().join("test_subdir").exists());
@@ -1496,7 +2003,9 @@ mod tests {
         let temp_file = create_temp_patch(&patch);
 
         let parser = ZiPatchParser::new();
-        parser.apply_patch(temp_file.path(), game_dir.path()).unwrap();
+        parser
+            .apply_patch(temp_file.path(), game_dir.path())
+            .unwrap();
 
         // Check that directory was deleted
         assert!(!dir_to_delete.exists());
@@ -1562,4 +2071,775 @@ mod tests {
             panic!("Expected SQPK chunk");
         }
     }
+
+    // ==========================================================================
+    // SQPK Data Command Application Tests
+    // ==========================================================================
+
+    #[test]
+    fn test_parse_sqpk_add_data() {
+        let data = vec![0x5Au8; 256];
+        let patch = build_patch(&[build_sqpk_add_data_chunk(2, &data, 1)]);
+        let temp_file = create_temp_patch(&patch);
+
+        let chunks = ZiPatchParser::new().parse_patch(temp_file.path()).unwrap();
+
+        if let ZiPatchChunk::Sqpk(SqpkChunk::AddData(add)) = &chunks[1] {
+            assert_eq!(add.target_file.dat_path(Platform::Win32), TEST_DAT_PATH);
+            assert_eq!(add.block_offset, 256);
+            assert_eq!(add.block_number, 256);
+            assert_eq!(add.block_delete_number, 128);
+            let start = add.data_source_offset as usize;
+            assert_eq!(&patch[start..start + data.len()], &data[..]);
+        } else {
+            panic!("Expected SQPK AddData chunk, got {:?}", chunks[1]);
+        }
+    }
+
+    #[test]
+    fn test_apply_add_data_creates_dat() {
+        let data: Vec<u8> = (0..256).map(|i| i as u8).collect();
+        let patch = build_patch(&[build_sqpk_add_data_chunk(1, &data, 0)]);
+        let temp_file = create_temp_patch(&patch);
+        let game_dir = tempfile::tempdir().unwrap();
+
+        ZiPatchParser::new()
+            .apply_patch(temp_file.path(), game_dir.path())
+            .unwrap();
+
+        let dat = std::fs::read(game_dir.path().join(TEST_DAT_PATH)).unwrap();
+        assert_eq!(dat.len(), 128 + 256);
+        assert!(dat[..128].iter().all(|&b| b == 0));
+        assert_eq!(&dat[128..], &data[..]);
+    }
+
+    #[test]
+    fn test_apply_add_data_wipes_deleted_blocks() {
+        let game_dir = tempfile::tempdir().unwrap();
+        let dat_path = game_dir.path().join(TEST_DAT_PATH);
+        std::fs::create_dir_all(dat_path.parent().unwrap()).unwrap();
+        std::fs::write(&dat_path, vec![0xFFu8; 128 * 5]).unwrap();
+
+        let data = vec![0x11u8; 128];
+        let patch = build_patch(&[build_sqpk_add_data_chunk(1, &data, 2)]);
+        let temp_file = create_temp_patch(&patch);
+
+        ZiPatchParser::new()
+            .apply_patch(temp_file.path(), game_dir.path())
+            .unwrap();
+
+        let dat = std::fs::read(&dat_path).unwrap();
+        assert_eq!(dat.len(), 128 * 5);
+        assert!(dat[..128].iter().all(|&b| b == 0xFF));
+        assert!(dat[128..256].iter().all(|&b| b == 0x11));
+        assert!(dat[256..512].iter().all(|&b| b == 0));
+        assert!(dat[512..].iter().all(|&b| b == 0xFF));
+    }
+
+    #[test]
+    fn test_apply_delete_data_writes_empty_block() {
+        let game_dir = tempfile::tempdir().unwrap();
+        let dat_path = game_dir.path().join(TEST_DAT_PATH);
+        std::fs::create_dir_all(dat_path.parent().unwrap()).unwrap();
+        std::fs::write(&dat_path, vec![0xFFu8; 128 * 4]).unwrap();
+
+        let patch = build_patch(&[build_sqpk_block_chunk(b'D', 1, 2)]);
+        let temp_file = create_temp_patch(&patch);
+
+        ZiPatchParser::new()
+            .apply_patch(temp_file.path(), game_dir.path())
+            .unwrap();
+
+        let dat = std::fs::read(&dat_path).unwrap();
+        assert!(dat[..128].iter().all(|&b| b == 0xFF));
+        assert_eq!(&dat[128..132], &128u32.to_le_bytes());
+        assert_eq!(&dat[140..144], &1u32.to_le_bytes());
+        assert!(dat[148..384].iter().all(|&b| b == 0));
+        assert!(dat[384..].iter().all(|&b| b == 0xFF));
+    }
+
+    #[test]
+    fn test_apply_expand_data_grows_dat() {
+        let game_dir = tempfile::tempdir().unwrap();
+        let dat_path = game_dir.path().join(TEST_DAT_PATH);
+        std::fs::create_dir_all(dat_path.parent().unwrap()).unwrap();
+        std::fs::write(&dat_path, vec![0xFFu8; 128]).unwrap();
+
+        let patch = build_patch(&[build_sqpk_block_chunk(b'E', 1, 3)]);
+        let temp_file = create_temp_patch(&patch);
+
+        ZiPatchParser::new()
+            .apply_patch(temp_file.path(), game_dir.path())
+            .unwrap();
+
+        let dat = std::fs::read(&dat_path).unwrap();
+        assert_eq!(dat.len(), 128 * 4);
+        assert!(dat[..128].iter().all(|&b| b == 0xFF));
+        assert_eq!(&dat[128..132], &128u32.to_le_bytes());
+        assert_eq!(&dat[140..144], &2u32.to_le_bytes());
+    }
+
+    #[test]
+    fn test_apply_data_commands_in_sequence() {
+        let first = vec![0x22u8; 384];
+        let second = vec![0x33u8; 128];
+        let patch = build_patch(&[
+            build_sqpk_add_data_chunk(0, &first, 0),
+            build_sqpk_block_chunk(b'D', 1, 2),
+            build_sqpk_add_data_chunk(1, &second, 1),
+        ]);
+        let temp_file = create_temp_patch(&patch);
+        let game_dir = tempfile::tempdir().unwrap();
+
+        ZiPatchParser::new()
+            .apply_patch(temp_file.path(), game_dir.path())
+            .unwrap();
+
+        let dat = std::fs::read(game_dir.path().join(TEST_DAT_PATH)).unwrap();
+        assert_eq!(dat.len(), 384);
+        assert!(dat[..128].iter().all(|&b| b == 0x22));
+        assert!(dat[128..256].iter().all(|&b| b == 0x33));
+        assert!(dat[256..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn test_parse_sqpk_index() {
+        // Index command as laid out in the game's patches
+        let payload = [
+            b'D', 1, 0, // operation, synonym, alignment
+            0x00, 0x0a, 0x01, 0x00, 0x00, 0x00, 0x00, 0x02, // 0a0100 file 2
+            0x9c, 0x5d, 0x4b, 0x0f, 0x3e, 0x21, 0x77, 0x08, // file hash
+            0x00, 0x01, 0x23, 0x45, // block offset
+            0x00, 0x00, 0x00, 0x03, // block number
+        ];
+        let patch = build_patch(&[build_sqpk_chunk(b'I', &payload)]);
+        let temp_file = create_temp_patch(&patch);
+
+        let chunks = ZiPatchParser::new().parse_patch(temp_file.path()).unwrap();
+
+        if let ZiPatchChunk::Sqpk(SqpkChunk::Index(cmd)) = &chunks[1] {
+            assert_eq!(cmd.command, SqpkIndexCommand::Delete);
+            assert!(cmd.is_synonym);
+            assert_eq!(
+                cmd.target_file,
+                SqpackFileTarget {
+                    main_id: 0x0a,
+                    sub_id: 0x0100,
+                    file_id: 2,
+                }
+            );
+            assert_eq!(cmd.target_file.index_type().unwrap(), IndexType::Index2);
+            assert_eq!(cmd.file_hash, 0x9c5d_4b0f_3e21_7708);
+            assert_eq!(cmd.block_offset, 0x12345);
+            assert_eq!(cmd.block_number, 3);
+        } else {
+            panic!("Expected SQPK Index chunk");
+        }
+    }
+
+    #[test]
+    fn test_parse_sqpk_header_layout() {
+        // Header command as laid out in the game's patches
+        let mut payload = vec![
+            b'I', b'D', 0, // file kind, header kind, alignment
+            0x00, 0x0a, 0x01, 0x00, 0x00, 0x00, 0x00, 0x01, // 0a0100 file 1
+        ];
+        payload.extend_from_slice(&[0x5Au8; 1024]);
+        let patch = build_patch(&[build_sqpk_chunk(b'H', &payload)]);
+        let temp_file = create_temp_patch(&patch);
+
+        let chunks = ZiPatchParser::new().parse_patch(temp_file.path()).unwrap();
+
+        if let ZiPatchChunk::Sqpk(SqpkChunk::Header(cmd)) = &chunks[1] {
+            assert_eq!(cmd.file_kind, SqpkFileKind::Index);
+            assert_eq!(cmd.header_kind, SqpkHeaderKind::Data);
+            assert_eq!(
+                cmd.target_file,
+                SqpackFileTarget {
+                    main_id: 0x0a,
+                    sub_id: 0x0100,
+                    file_id: 1,
+                }
+            );
+            assert_eq!(cmd.header_data, vec![0x5Au8; 1024]);
+        } else {
+            panic!("Expected SQPK Header chunk");
+        }
+    }
+
+    #[test]
+    fn test_apply_header_writes_at_header_offset() {
+        let header = vec![0x5Au8; 1024];
+        let patch = build_patch(&[
+            build_sqpk_header_chunk(b'D', b'V', &header),
+            build_sqpk_header_chunk(b'I', b'I', &header),
+        ]);
+        let temp_file = create_temp_patch(&patch);
+        let game_dir = tempfile::tempdir().unwrap();
+
+        ZiPatchParser::new()
+            .apply_patch(temp_file.path(), game_dir.path())
+            .unwrap();
+
+        let dat = std::fs::read(game_dir.path().join(TEST_DAT_PATH)).unwrap();
+        assert_eq!(dat, header);
+
+        let index = std::fs::read(game_dir.path().join(TEST_INDEX_PATH)).unwrap();
+        assert_eq!(index.len(), 2048);
+        assert!(index[..1024].iter().all(|&b| b == 0));
+        assert_eq!(&index[1024..], &header[..]);
+    }
+
+    #[test]
+    fn test_apply_index_adds_and_removes_entries() {
+        let game_dir = tempfile::tempdir().unwrap();
+        let index_path = game_dir.path().join(TEST_INDEX_PATH);
+        std::fs::create_dir_all(index_path.parent().unwrap()).unwrap();
+
+        // Empty hash table at 0x800, followed by a 16-byte segment
+        let mut index = vec![0u8; 0x800];
+        index[0x408..0x40C].copy_from_slice(&0x800u32.to_le_bytes());
+        index[0x454..0x458].copy_from_slice(&0x800u32.to_le_bytes());
+        index.extend_from_slice(&[0xEEu8; 16]);
+        std::fs::write(&index_path, &index).unwrap();
+
+        let patch = build_patch(&[
+            build_sqpk_index_chunk(b'A', 0x30, 3),
+            build_sqpk_index_chunk(b'A', 0x10, 1),
+            build_sqpk_index_chunk(b'A', 0x20, 2),
+            build_sqpk_index_chunk(b'D', 0x20, 0),
+        ]);
+        let temp_file = create_temp_patch(&patch);
+
+        ZiPatchParser::new()
+            .apply_patch(temp_file.path(), game_dir.path())
+            .unwrap();
+
+        let index = std::fs::read(&index_path).unwrap();
+        assert_eq!(&index[0x40C..0x410], &32u32.to_le_bytes());
+        assert_eq!(&index[0x454..0x458], &(0x800u32 + 32).to_le_bytes());
+
+        let entry = |i: usize| {
+            let e = &index[0x800 + i * 16..0x800 + (i + 1) * 16];
+            (
+                u64::from_le_bytes(e[..8].try_into().unwrap()),
+                u32::from_le_bytes(e[8..12].try_into().unwrap()),
+            )
+        };
+        assert_eq!(entry(0), (0x10, 1 << 4));
+        assert_eq!(entry(1), (0x30, 3 << 4));
+        assert_eq!(&index[0x820..], &[0xEEu8; 16]);
+    }
+
+    #[test]
+    fn test_apply_index_missing_file_fails() {
+        let patch = build_patch(&[build_sqpk_index_chunk(b'A', 0x10, 1)]);
+        let temp_file = create_temp_patch(&patch);
+        let game_dir = tempfile::tempdir().unwrap();
+
+        let result = ZiPatchParser::new().apply_patch(temp_file.path(), game_dir.path());
+        assert!(matches!(result, Err(Error::ZiPatchApply(_))));
+    }
+
+    #[test]
+    fn test_parse_sqpk_file_blocks() {
+        let compressed = vec![0x41u8; 1000];
+        let raw = b"uncompressed".to_vec();
+        let patch = build_patch(&[build_sqpk_file_chunk(
+            b'A',
+            0,
+            0,
+            "ffxivboot.exe",
+            &[
+                build_file_block(&compressed, true),
+                build_file_block(&raw, false),
+            ],
+        )]);
+        let temp_file = create_temp_patch(&patch);
+
+        let chunks = ZiPatchParser::new().parse_patch(temp_file.path()).unwrap();
+
+        if let ZiPatchChunk::Sqpk(SqpkChunk::File(cmd)) = &chunks[1] {
+            assert_eq!(cmd.operation, SqpkFileOperation::AddFile);
+            assert_eq!(cmd.file_path, "ffxivboot.exe");
+            assert_eq!(cmd.blocks.len(), 2);
+            assert!(cmd.blocks[0].is_compressed);
+            assert_eq!(cmd.blocks[0].decompressed_size, 1000);
+            assert!(!cmd.blocks[1].is_compressed);
+            assert_eq!(cmd.blocks[1].data_size as usize, raw.len());
+            let start = cmd.blocks[1].data_source_offset as usize;
+            assert_eq!(&patch[start..start + raw.len()], &raw[..]);
+        } else {
+            panic!("Expected SQPK File chunk");
+        }
+    }
+
+    #[test]
+    fn test_parse_sqpk_file_header_layout() {
+        // File command header as laid out in the game's patches
+        let mut payload = vec![
+            b'A', 0, 0, // operation, alignment
+            0, 0, 0, 0, 0, 0, 0x01, 0x80, // file offset
+            0, 0, 0, 0, 0, 0, 0x02, 0x00, // file size
+            0, 0, 0, 0x0e, // path length, including the terminator
+            0, 0x02, 0, 0, // expansion ID, padding
+        ];
+        payload.extend_from_slice(b"ffxivboot.exe\0");
+        payload.extend_from_slice(&build_file_block(b"boot", false));
+        let patch = build_patch(&[build_sqpk_chunk(b'F', &payload)]);
+        let temp_file = create_temp_patch(&patch);
+
+        let chunks = ZiPatchParser::new().parse_patch(temp_file.path()).unwrap();
+
+        if let ZiPatchChunk::Sqpk(SqpkChunk::File(cmd)) = &chunks[1] {
+            assert_eq!(cmd.operation, SqpkFileOperation::AddFile);
+            assert_eq!(cmd.file_offset, 0x180);
+            assert_eq!(cmd.file_size, 0x200);
+            assert_eq!(cmd.expansion_id, 2);
+            assert_eq!(cmd.file_path, "ffxivboot.exe");
+            assert_eq!(cmd.blocks.len(), 1);
+            assert_eq!(cmd.blocks[0].decompressed_size, 4);
+        } else {
+            panic!("Expected SQPK File chunk");
+        }
+    }
+
+    #[test]
+    fn test_apply_add_file_decompresses_blocks() {
+        let first: Vec<u8> = (0..5000).map(|i| (i % 251) as u8).collect();
+        let second = b"tail".to_vec();
+        let patch = build_patch(&[build_sqpk_file_chunk(
+            b'A',
+            0,
+            0,
+            "ffxivboot.exe",
+            &[
+                build_file_block(&first, true),
+                build_file_block(&second, false),
+            ],
+        )]);
+        let temp_file = create_temp_patch(&patch);
+        let game_dir = tempfile::tempdir().unwrap();
+        std::fs::write(game_dir.path().join("ffxivboot.exe"), vec![0xFFu8; 10000]).unwrap();
+
+        ZiPatchParser::new()
+            .apply_patch(temp_file.path(), game_dir.path())
+            .unwrap();
+
+        let written = std::fs::read(game_dir.path().join("ffxivboot.exe")).unwrap();
+        assert_eq!(written.len(), first.len() + second.len());
+        assert_eq!(&written[..first.len()], &first[..]);
+        assert_eq!(&written[first.len()..], &second[..]);
+    }
+
+    #[test]
+    fn test_apply_add_file_at_offset_keeps_existing_data() {
+        let patch = build_patch(&[build_sqpk_file_chunk(
+            b'A',
+            0,
+            4,
+            "data/file.bin",
+            &[build_file_block(b"new", false)],
+        )]);
+        let temp_file = create_temp_patch(&patch);
+        let game_dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(game_dir.path().join("data")).unwrap();
+        std::fs::write(game_dir.path().join("data/file.bin"), b"old-content").unwrap();
+
+        ZiPatchParser::new()
+            .apply_patch(temp_file.path(), game_dir.path())
+            .unwrap();
+
+        let written = std::fs::read(game_dir.path().join("data/file.bin")).unwrap();
+        assert_eq!(written, b"old-newtent");
+    }
+
+    #[test]
+    fn test_apply_remove_all_keeps_var_files() {
+        let game_dir = tempfile::tempdir().unwrap();
+        let ex1 = game_dir.path().join("sqpack/ex1");
+        let ffxiv = game_dir.path().join("sqpack/ffxiv");
+        std::fs::create_dir_all(&ex1).unwrap();
+        std::fs::create_dir_all(&ffxiv).unwrap();
+        std::fs::write(ex1.join("020100.win32.dat0"), b"dat").unwrap();
+        std::fs::write(ex1.join("020100.win32.index"), b"index").unwrap();
+        std::fs::write(ex1.join("ex1.ver"), b"ver").unwrap();
+        std::fs::write(ex1.join("020100.win32.var"), b"var").unwrap();
+        std::fs::write(ffxiv.join("000000.win32.dat0"), b"dat").unwrap();
+
+        let patch = build_patch(&[build_sqpk_file_chunk(b'R', 1, 0, "", &[])]);
+        let temp_file = create_temp_patch(&patch);
+
+        ZiPatchParser::new()
+            .apply_patch(temp_file.path(), game_dir.path())
+            .unwrap();
+
+        let mut remaining: Vec<_> = std::fs::read_dir(&ex1)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        remaining.sort();
+        assert_eq!(remaining, vec!["020100.win32.var"]);
+        assert!(ffxiv.join("000000.win32.dat0").exists());
+    }
+
+    #[test]
+    fn test_apply_delete_file_and_make_dir_tree() {
+        let game_dir = tempfile::tempdir().unwrap();
+        std::fs::write(game_dir.path().join("old.dll"), b"old").unwrap();
+
+        let patch = build_patch(&[
+            build_sqpk_file_chunk(b'D', 0, 0, "old.dll", &[]),
+            build_sqpk_file_chunk(b'D', 0, 0, "missing.dll", &[]),
+            build_sqpk_file_chunk(b'M', 0, 0, "sqpack/ex5", &[]),
+        ]);
+        let temp_file = create_temp_patch(&patch);
+
+        ZiPatchParser::new()
+            .apply_patch(temp_file.path(), game_dir.path())
+            .unwrap();
+
+        assert!(!game_dir.path().join("old.dll").exists());
+        assert!(game_dir.path().join("sqpack/ex5").is_dir());
+    }
+
+    #[test]
+    fn test_replay_files_applies_only_requested_files() {
+        let patch = build_patch(&[
+            build_sqpk_file_chunk(
+                b'A',
+                0,
+                0,
+                "ffxivboot.exe",
+                &[build_file_block(b"boot", false)],
+            ),
+            build_sqpk_file_chunk(
+                b'A',
+                0,
+                0,
+                "ffxivlauncher.exe",
+                &[build_file_block(b"x", false)],
+            ),
+            build_sqpk_add_data_chunk(0, &[0xAB; 128], 0),
+            build_sqpk_index_chunk(b'A', 0x10, 1),
+        ]);
+        let temp_file = create_temp_patch(&patch);
+        let game_dir = tempfile::tempdir().unwrap();
+        let files: HashSet<String> = ["ffxivboot.exe", TEST_DAT_PATH]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+
+        let touched = ZiPatchParser::new()
+            .replay_files(
+                temp_file.path(),
+                game_dir.path(),
+                &files,
+                &UpdateControl::new(),
+            )
+            .unwrap();
+
+        assert_eq!(touched, files);
+        assert_eq!(
+            std::fs::read(game_dir.path().join("ffxivboot.exe")).unwrap(),
+            b"boot"
+        );
+        assert!(game_dir.path().join(TEST_DAT_PATH).exists());
+        assert!(!game_dir.path().join("ffxivlauncher.exe").exists());
+        assert!(!game_dir.path().join(TEST_INDEX_PATH).exists());
+    }
+
+    #[test]
+    fn test_replay_files_follows_removals() {
+        let game_dir = tempfile::tempdir().unwrap();
+        let dat = "sqpack/ex1/020100.win32.dat0";
+        std::fs::create_dir_all(game_dir.path().join("sqpack/ex1")).unwrap();
+        std::fs::write(game_dir.path().join(dat), b"replayed earlier").unwrap();
+
+        let patch = build_patch(&[build_sqpk_file_chunk(b'R', 1, 0, "", &[])]);
+        let temp_file = create_temp_patch(&patch);
+        let files: HashSet<String> = [dat.to_string(), "ffxivboot.exe".to_string()].into();
+
+        let touched = ZiPatchParser::new()
+            .replay_files(
+                temp_file.path(),
+                game_dir.path(),
+                &files,
+                &UpdateControl::new(),
+            )
+            .unwrap();
+
+        assert_eq!(touched, HashSet::from([dat.to_string()]));
+        assert!(!game_dir.path().join(dat).exists());
+    }
+
+    #[test]
+    fn test_chunks_iterates_lazily() {
+        let patch = build_multi_chunk_patch();
+        let temp_file = create_temp_patch(&patch);
+        let parser = ZiPatchParser::new();
+
+        let mut chunks = parser.chunks(temp_file.path()).unwrap();
+        assert!(matches!(
+            chunks.next(),
+            Some(Ok(ZiPatchChunk::FileHeader(_)))
+        ));
+
+        let rest: Vec<_> = chunks.collect::<Result<_, _>>().unwrap();
+        assert!(matches!(rest.last(), Some(ZiPatchChunk::EndOfFile)));
+        assert_eq!(
+            rest.len() + 1,
+            parser.parse_patch(temp_file.path()).unwrap().len()
+        );
+    }
+
+    #[test]
+    fn test_chunks_stops_after_error() {
+        let mut patch = build_patch(&[build_apfs_chunk(0)]);
+        // Corrupt the APFS CRC
+        let crc_pos =
+            ZIPATCH_MAGIC.len() + build_fhdr_chunk().len() + build_apfs_chunk(0).len() - 1;
+        patch[crc_pos] ^= 0xFF;
+        let temp_file = create_temp_patch(&patch);
+        let parser = ZiPatchParser::new();
+
+        let results: Vec<_> = parser.chunks(temp_file.path()).unwrap().collect();
+        assert_eq!(results.len(), 2);
+        assert!(results[0].is_ok());
+        assert!(matches!(
+            results[1],
+            Err(Error::ZiPatchChecksumMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_parse_add_data_overrunning_chunk_fails() {
+        let mut payload = vec![0u8; 3]; // alignment
+        push_target(&mut payload, 0x04, 0x0000, 0);
+        payload.extend_from_slice(&0u32.to_be_bytes());
+        payload.extend_from_slice(&2u32.to_be_bytes()); // claims 256 bytes
+        payload.extend_from_slice(&0u32.to_be_bytes());
+        payload.extend_from_slice(&[0u8; 128]);
+        let patch = build_patch(&[build_sqpk_chunk(b'A', &payload)]);
+        let temp_file = create_temp_patch(&patch);
+
+        let result = ZiPatchParser::new().parse_patch(temp_file.path());
+        assert!(matches!(result, Err(Error::ZiPatchParse(_))));
+    }
+
+    #[test]
+    fn test_apply_journaled_completes_journal() {
+        let data = vec![0x22u8; 128];
+        let patch = build_patch(&[build_sqpk_add_data_chunk(0, &data, 0)]);
+        let temp_file = create_temp_patch(&patch);
+        let game_dir = tempfile::tempdir().unwrap();
+        let journal_path = patch_journal_path(temp_file.path());
+        let parser = ZiPatchParser::new();
+        let control = UpdateControl::new();
+
+        assert_eq!(parser.journal_state(&journal_path).unwrap(), None);
+
+        parser
+            .apply_patch_journaled(temp_file.path(), game_dir.path(), &journal_path, &control)
+            .unwrap();
+        assert_eq!(
+            parser.journal_state(&journal_path).unwrap(),
+            Some(JournalState::Completed)
+        );
+
+        // A completed journal makes a second apply a no-op
+        std::fs::remove_file(game_dir.path().join(TEST_DAT_PATH)).unwrap();
+        parser
+            .apply_patch_journaled(temp_file.path(), game_dir.path(), &journal_path, &control)
+            .unwrap();
+        assert!(!game_dir.path().join(TEST_DAT_PATH).exists());
+    }
+
+    #[test]
+    fn test_rollback_restores_pre_patch_state() {
+        let game_dir = tempfile::tempdir().unwrap();
+        let dat_path = game_dir.path().join(TEST_DAT_PATH);
+        std::fs::create_dir_all(dat_path.parent().unwrap()).unwrap();
+        std::fs::write(&dat_path, vec![0x11u8; 256]).unwrap();
+        std::fs::write(game_dir.path().join("old.dll"), b"old").unwrap();
+
+        let patch = build_patch(&[
+            build_sqpk_add_data_chunk(1, &[0x22u8; 256], 1),
+            build_sqpk_file_chunk(b'A', 0, 0, "bin/new.exe", &[build_file_block(b"new", true)]),
+            build_sqpk_file_chunk(b'D', 0, 0, "old.dll", &[]),
+            build_adir_chunk("movie/ex1"),
+        ]);
+        let temp_file = create_temp_patch(&patch);
+        let journal_path = patch_journal_path(temp_file.path());
+        let parser = ZiPatchParser::new();
+        let control = UpdateControl::new();
+
+        parser
+            .apply_patch_journaled(temp_file.path(), game_dir.path(), &journal_path, &control)
+            .unwrap();
+        assert_eq!(std::fs::read(&dat_path).unwrap().len(), 512);
+        assert!(game_dir.path().join("bin/new.exe").exists());
+
+        parser.rollback_patch(&journal_path).unwrap();
+
+        assert_eq!(std::fs::read(&dat_path).unwrap(), vec![0x11u8; 256]);
+        assert!(!game_dir.path().join("bin").exists());
+        assert!(!game_dir.path().join("movie").exists());
+        assert_eq!(
+            std::fs::read(game_dir.path().join("old.dll")).unwrap(),
+            b"old"
+        );
+        assert!(!journal_path.exists());
+        assert!(!super::super::journal::journal_backup_dir(&journal_path).exists());
+    }
+
+    #[test]
+    fn test_rollback_after_failed_apply() {
+        let game_dir = tempfile::tempdir().unwrap();
+        let patch = build_patch(&[
+            build_sqpk_add_data_chunk(0, &[0x22u8; 128], 0),
+            // Zero-length delete is rejected
+            build_sqpk_block_chunk(b'D', 0, 0),
+        ]);
+        let temp_file = create_temp_patch(&patch);
+        let journal_path = patch_journal_path(temp_file.path());
+        let parser = ZiPatchParser::new();
+        let control = UpdateControl::new();
+
+        assert!(parser
+            .apply_patch_journaled(temp_file.path(), game_dir.path(), &journal_path, &control)
+            .is_err());
+        assert_eq!(
+            parser.journal_state(&journal_path).unwrap(),
+            Some(JournalState::InProgress {
+                committed_chunks: 2
+            })
+        );
+
+        parser.rollback_patch(&journal_path).unwrap();
+        assert!(!game_dir.path().join(TEST_DAT_PATH).exists());
+    }
+
+    #[test]
+    fn test_apply_journaled_resumes_after_last_commit() {
+        use super::super::journal::{JournalContents, JournalRecord};
+
+        let game_dir = tempfile::tempdir().unwrap();
+        let patch = build_patch(&[
+            build_sqpk_add_data_chunk(0, &[0x22u8; 128], 0),
+            build_sqpk_add_data_chunk(1, &[0x33u8; 128], 0),
+        ]);
+        let temp_file = create_temp_patch(&patch);
+        let journal_path = patch_journal_path(temp_file.path());
+        let parser = ZiPatchParser::new();
+        let control = UpdateControl::new();
+
+        parser
+            .apply_patch_journaled(temp_file.path(), game_dir.path(), &journal_path, &control)
+            .unwrap();
+
+        // Simulate a crash after the second AddData was written but not committed
+        let contents = JournalContents::read(&journal_path).unwrap().unwrap();
+        let (crash_len, _) = contents
+            .records
+            .iter()
+            .find(|(_, r)| {
+                matches!(
+                    r,
+                    JournalRecord::Commit {
+                        committed_chunks: 2
+                    }
+                )
+            })
+            .unwrap();
+        let journal = std::fs::OpenOptions::new()
+            .write(true)
+            .open(&journal_path)
+            .unwrap();
+        journal.set_len(*crash_len).unwrap();
+
+        // Committed chunks must not be reapplied on resume
+        let dat_path = game_dir.path().join(TEST_DAT_PATH);
+        let mut dat = std::fs::read(&dat_path).unwrap();
+        dat[..128].fill(0xEE);
+        std::fs::write(&dat_path, &dat).unwrap();
+
+        parser
+            .apply_patch_journaled(temp_file.path(), game_dir.path(), &journal_path, &control)
+            .unwrap();
+
+        let dat = std::fs::read(&dat_path).unwrap();
+        assert!(dat[..128].iter().all(|&b| b == 0xEE));
+        assert!(dat[128..].iter().all(|&b| b == 0x33));
+        assert_eq!(
+            parser.journal_state(&journal_path).unwrap(),
+            Some(JournalState::Completed)
+        );
+    }
+
+    #[test]
+    fn test_apply_journaled_cancel_keeps_journal_for_resume() {
+        let game_dir = tempfile::tempdir().unwrap();
+        let patch = build_patch(&[
+            build_sqpk_add_data_chunk(0, &[0x22u8; 128], 0),
+            build_sqpk_add_data_chunk(1, &[0x33u8; 128], 0),
+        ]);
+        let temp_file = create_temp_patch(&patch);
+        let journal_path = patch_journal_path(temp_file.path());
+        let parser = ZiPatchParser::new();
+
+        // Cancelled up front, so application stops after the first chunk
+        let control = UpdateControl::new();
+        control.cancel();
+        let result = parser.apply_patch_journaled(
+            temp_file.path(),
+            game_dir.path(),
+            &journal_path,
+            &control,
+        );
+        assert!(matches!(result, Err(Error::Cancelled)));
+        assert_eq!(
+            parser.journal_state(&journal_path).unwrap(),
+            Some(JournalState::InProgress {
+                committed_chunks: 1
+            })
+        );
+
+        parser
+            .apply_patch_journaled(
+                temp_file.path(),
+                game_dir.path(),
+                &journal_path,
+                &UpdateControl::new(),
+            )
+            .unwrap();
+
+        let dat = std::fs::read(game_dir.path().join(TEST_DAT_PATH)).unwrap();
+        assert!(dat[..128].iter().all(|&b| b == 0x22));
+        assert!(dat[128..].iter().all(|&b| b == 0x33));
+        assert_eq!(
+            parser.journal_state(&journal_path).unwrap(),
+            Some(JournalState::Completed)
+        );
+    }
+
+    #[test]
+    fn test_apply_journaled_rejects_other_install() {
+        let patch = build_patch(&[]);
+        let temp_file = create_temp_patch(&patch);
+        let journal_path = patch_journal_path(temp_file.path());
+        let first = tempfile::tempdir().unwrap();
+        let second = tempfile::tempdir().unwrap();
+
+        let journal = PatchJournal::create(&journal_path, first.path()).unwrap();
+        drop(journal);
+
+        let result = ZiPatchParser::new().apply_patch_journaled(
+            temp_file.path(),
+            second.path(),
+            &journal_path,
+            &UpdateControl::new(),
+        );
+        assert!(matches!(result, Err(Error::ZiPatchApply(_))));
+    }
 }
@@ -0,0 +1,1058 @@
+//! ZiPatch file writer
+//!
+//! Serialises chunks in the layout read by the parser, computing chunk sizes
+//! and CRC32 checksums.
+
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crc32fast::Hasher;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+
+use gaveloc_core::error::Error;
+use gaveloc_core::zipatch::*;
+
+use super::parser::{FILE_BLOCK_ALIGNMENT, UNCOMPRESSED_BLOCK_MARKER};
+
+/// Size of the header preceding the data of each file block
+const FILE_BLOCK_HEADER_SIZE: usize = 16;
+
+/// Unit of the offsets and sizes of AddData, DeleteData and ExpandData commands
+const SQPK_BLOCK_SIZE: u64 = 128;
+
+/// Amount of file data carried by each block of the AddFile commands we write
+const FILE_BLOCK_DATA_SIZE: usize = 16000;
+
+/// Reserved bytes ending an SQPK TargetInfo command
+const TARGET_INFO_RESERVED_SIZE: usize = 96;
+
+/// ZiPatch file writer
+///
+/// Chunks are written in order after the magic header, and [`finish`](Self::finish)
+/// terminates the patch with an `EOF_` chunk. The `offset` fields of written
+/// chunks are ignored.
+pub struct ZiPatchWriter<W: Write> {
+    writer: W,
+    position: u64,
+}
+
+impl ZiPatchWriter<BufWriter<File>> {
+    /// Create a patch file, replacing any existing file
+    pub fn create(patch_path: &Path) -> Result<Self, Error> {
+        Self::new(BufWriter::new(File::create(patch_path)?))
+    }
+}
+
+impl<W: Write> ZiPatchWriter<W> {
+    /// Create a writer, writing the magic header
+    pub fn new(mut writer: W) -> Result<Self, Error> {
+        writer.write_all(&ZIPATCH_MAGIC)?;
+
+        Ok(Self {
+            writer,
+            position: ZIPATCH_MAGIC.len() as u64,
+        })
+    }
+
+    /// Offset in the patch at which the next chunk will be written
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Write a chunk, returning its offset in the patch
+    ///
+    /// Parsed AddData commands and AddFile blocks reference their data in the
+    /// patch they were read from, so chunks carrying data must be written with
+    /// [`copy_chunk`](Self::copy_chunk) or built with [`add_data`](Self::add_data)
+    /// and [`add_file`](Self::add_file).
+    pub fn write_chunk(&mut self, chunk: &ZiPatchChunk) -> Result<u64, Error> {
+        self.write_chunk_with(chunk, |offset, len| {
+            Err(Error::ZiPatchWrite(format!(
+                "{} bytes of chunk data at {} are not available",
+                len, offset
+            )))
+        })
+    }
+
+    /// Write a chunk parsed from another patch, copying its data from `source`
+    pub fn copy_chunk<R: Read + Seek>(
+        &mut self,
+        chunk: &ZiPatchChunk,
+        source: &mut R,
+    ) -> Result<u64, Error> {
+        self.write_chunk_with(chunk, |offset, len| {
+            let mut data = vec![0u8; len];
+            source.seek(SeekFrom::Start(offset))?;
+            source.read_exact(&mut data)?;
+            Ok(data)
+        })
+    }
+
+    /// Write an SQPK AddData command storing `data` at `block_offset` in a dat file
+    ///
+    /// `block_delete_number` bytes following the data are zeroed. Offsets and
+    /// sizes are in bytes and must be multiples of 128.
+    pub fn add_data(
+        &mut self,
+        target_file: SqpackFileTarget,
+        block_offset: u64,
+        data: &[u8],
+        block_delete_number: u64,
+    ) -> Result<u64, Error> {
+        let chunk = ZiPatchChunk::Sqpk(SqpkChunk::AddData(SqpkAddData {
+            target_file,
+            block_offset,
+            block_number: data.len() as u64,
+            block_delete_number,
+            data_source_offset: 0,
+            offset: 0,
+        }));
+
+        self.write_chunk_with(&chunk, |_, _| Ok(data.to_vec()))
+    }
+
+    /// Write an SQPK AddFile command storing `data` at `file_offset` in a file
+    ///
+    /// The data is split into blocks, each DEFLATE-compressed when that makes
+    /// it smaller. Writing at offset 0 replaces the whole file.
+    pub fn add_file(
+        &mut self,
+        expansion_id: u16,
+        file_path: &str,
+        file_offset: u64,
+        data: &[u8],
+    ) -> Result<u64, Error> {
+        let mut blocks = Vec::new();
+        let mut payloads = Vec::new();
+
+        for block_data in data.chunks(FILE_BLOCK_DATA_SIZE) {
+            let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(block_data)?;
+            let compressed = encoder.finish()?;
+
+            // A compressed size equal to the marker would read back as stored data
+            let is_compressed = compressed.len() < block_data.len()
+                && compressed.len() != UNCOMPRESSED_BLOCK_MARKER as usize;
+            let payload = if is_compressed {
+                compressed
+            } else {
+                block_data.to_vec()
+            };
+
+            blocks.push(SqpkCompressedBlock {
+                is_compressed,
+                decompressed_size: block_data.len() as u32,
+                data_source_offset: 0,
+                data_size: payload.len() as u32,
+            });
+            payloads.push(payload);
+        }
+
+        let chunk = ZiPatchChunk::Sqpk(SqpkChunk::File(SqpkFile {
+            operation: SqpkFileOperation::AddFile,
+            expansion_id,
+            file_offset,
+            file_size: file_offset + data.len() as u64,
+            file_path: file_path.to_string(),
+            blocks,
+            offset: 0,
+        }));
+
+        // Block data is requested in order
+        let mut payloads = payloads.into_iter();
+        self.write_chunk_with(&chunk, |_, _| {
+            payloads
+                .next()
+                .ok_or_else(|| Error::ZiPatchWrite("missing file block data".to_string()))
+        })
+    }
+
+    /// Write the `EOF_` chunk and flush, returning the underlying writer
+    pub fn finish(mut self) -> Result<W, Error> {
+        self.write_raw_chunk("EOF_", &[])?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    /// Serialise a chunk, obtaining data payloads from `read_data`
+    ///
+    /// `read_data` is called with the `data_source_offset` and length of each
+    /// payload, in the order they appear in the chunk.
+    fn write_chunk_with(
+        &mut self,
+        chunk: &ZiPatchChunk,
+        mut read_data: impl FnMut(u64, usize) -> Result<Vec<u8>, Error>,
+    ) -> Result<u64, Error> {
+        let mut body = Vec::new();
+
+        match chunk {
+            ZiPatchChunk::FileHeader(header) => {
+                let patch_type = header.patch_type.as_bytes();
+                if patch_type.len() > 4 {
+                    return Err(Error::ZiPatchWrite(format!(
+                        "patch type {:?} is longer than 4 bytes",
+                        header.patch_type
+                    )));
+                }
+
+                body.extend_from_slice(&header.version.to_be_bytes());
+                body.extend_from_slice(&[0u8; 2]);
+                body.extend_from_slice(patch_type);
+                body.resize(body.len() + 4 - patch_type.len(), 0);
+                body.extend_from_slice(&header.entry_files.to_be_bytes());
+            }
+            ZiPatchChunk::ApplyOption(apply) => {
+                body.extend_from_slice(&u32::from(apply.option).to_be_bytes());
+                body.extend_from_slice(&apply.value.to_be_bytes());
+                body.extend_from_slice(&[0u8; 4]);
+            }
+            ZiPatchChunk::AddDirectory(AddDirectoryChunk { path, .. })
+            | ZiPatchChunk::DeleteDirectory(DeleteDirectoryChunk { path, .. }) => {
+                // The path fills the chunk except for 4 trailing bytes
+                body.extend_from_slice(path.as_bytes());
+                body.push(0);
+                body.extend_from_slice(&[0u8; 4]);
+            }
+            ZiPatchChunk::ApplyFreeSpace(apfs) => {
+                body.extend_from_slice(&apfs.alloc_size.to_be_bytes());
+            }
+            ZiPatchChunk::Sqpk(sqpk) => {
+                body = Self::sqpk_body(sqpk, &mut read_data)?;
+            }
+            ZiPatchChunk::EndOfFile => {}
+            ZiPatchChunk::Unknown { size, .. } => {
+                // Unknown chunks are parsed without their contents
+                body.resize(*size as usize, 0);
+            }
+        }
+
+        self.write_raw_chunk(chunk.chunk_type(), &body)
+    }
+
+    /// Serialise the data of an SQPK chunk, from its inner size onwards
+    fn sqpk_body(
+        sqpk: &SqpkChunk,
+        read_data: &mut impl FnMut(u64, usize) -> Result<Vec<u8>, Error>,
+    ) -> Result<Vec<u8>, Error> {
+        let command = match sqpk.command().as_bytes() {
+            [command] => *command,
+            _ => {
+                return Err(Error::ZiPatchWrite(format!(
+                    "SQPK command {:?} is not a single byte",
+                    sqpk.command()
+                )))
+            }
+        };
+
+        let mut payload = Vec::new();
+
+        match sqpk {
+            SqpkChunk::AddData(cmd) => {
+                payload.extend_from_slice(&[0u8; 3]); // alignment
+                push_target(&mut payload, &cmd.target_file);
+                for value in [cmd.block_offset, cmd.block_number, cmd.block_delete_number] {
+                    payload.extend_from_slice(&block_units(value)?.to_be_bytes());
+                }
+                if cmd.block_number > 0 {
+                    let data = read_data(cmd.data_source_offset, cmd.block_number as usize)?;
+                    payload.extend_from_slice(&data);
+                }
+            }
+            SqpkChunk::DeleteData(SqpkDeleteData {
+                target_file,
+                block_offset,
+                block_number,
+                ..
+            })
+            | SqpkChunk::ExpandData(SqpkExpandData {
+                target_file,
+                block_offset,
+                block_number,
+                ..
+            }) => {
+                payload.extend_from_slice(&[0u8; 3]); // alignment
+                push_target(&mut payload, target_file);
+                payload.extend_from_slice(&block_units(*block_offset)?.to_be_bytes());
+                payload.extend_from_slice(&block_units(*block_number)?.to_be_bytes());
+                payload.extend_from_slice(&[0u8; 4]);
+            }
+            SqpkChunk::Header(cmd) => {
+                payload.extend_from_slice(&[cmd.file_kind.into(), cmd.header_kind.into(), 0]);
+                push_target(&mut payload, &cmd.target_file);
+                payload.extend_from_slice(&cmd.header_data);
+            }
+            SqpkChunk::Index(cmd) => {
+                payload.extend_from_slice(&[cmd.command.into(), u8::from(cmd.is_synonym), 0]);
+                push_target(&mut payload, &cmd.target_file);
+                payload.extend_from_slice(&cmd.file_hash.to_be_bytes());
+                payload.extend_from_slice(&cmd.block_offset.to_be_bytes());
+                payload.extend_from_slice(&cmd.block_number.to_be_bytes());
+            }
+            SqpkChunk::File(cmd) => {
+                let path_len = cmd.file_path.len() + 1;
+
+                payload.extend_from_slice(&[cmd.operation.into(), 0, 0]);
+                payload.extend_from_slice(&cmd.file_offset.to_be_bytes());
+                payload.extend_from_slice(&cmd.file_size.to_be_bytes());
+                payload.extend_from_slice(&(path_len as u32).to_be_bytes());
+                payload.extend_from_slice(&cmd.expansion_id.to_be_bytes());
+                payload.extend_from_slice(&[0u8; 2]);
+                payload.extend_from_slice(cmd.file_path.as_bytes());
+                payload.push(0);
+
+                for block in &cmd.blocks {
+                    push_file_block(&mut payload, block, read_data)?;
+                }
+            }
+            SqpkChunk::PatchInfo(info) => {
+                payload.extend_from_slice(&[info.status, info.version, 0]);
+                payload.extend_from_slice(&info.install_size.to_be_bytes());
+            }
+            SqpkChunk::TargetInfo(info) => {
+                let platform: u16 = match info.platform {
+                    Platform::Win32 => 0,
+                    Platform::Ps3 => 1,
+                    Platform::Ps4 => 2,
+                };
+
+                payload.extend_from_slice(&[0u8; 3]); // reserved
+                payload.extend_from_slice(&platform.to_be_bytes());
+                payload.extend_from_slice(&info.region.to_be_bytes());
+                payload.extend_from_slice(&u16::from(info.is_debug).to_be_bytes());
+                payload.extend_from_slice(&info.version.to_be_bytes());
+                payload.extend_from_slice(&info.deleted_data_size.to_le_bytes());
+                payload.extend_from_slice(&info.seek_count.to_le_bytes());
+                payload.extend_from_slice(&[0u8; TARGET_INFO_RESERVED_SIZE]);
+            }
+            // Unknown commands are parsed without their contents
+            SqpkChunk::Unknown { .. } => {}
+        }
+
+        // The inner size covers itself, the command byte and the payload
+        let inner_size = i32::try_from(payload.len() + 5)
+            .map_err(|_| Error::ZiPatchWrite("SQPK command is too large".to_string()))?;
+
+        let mut body = Vec::with_capacity(payload.len() + 5);
+        body.extend_from_slice(&inner_size.to_be_bytes());
+        body.push(command);
+        body.extend_from_slice(&payload);
+        Ok(body)
+    }
+
+    /// Write a chunk with its size prefix and CRC32 suffix
+    fn write_raw_chunk(&mut self, chunk_type: &str, body: &[u8]) -> Result<u64, Error> {
+        if chunk_type.len() != 4 {
+            return Err(Error::ZiPatchWrite(format!(
+                "chunk type {:?} is not 4 bytes",
+                chunk_type
+            )));
+        }
+        let size = u32::try_from(body.len())
+            .map_err(|_| Error::ZiPatchWrite(format!("{} chunk is too large", chunk_type)))?;
+
+        // The checksum covers the chunk type and data
+        let mut hasher = Hasher::new();
+        hasher.update(chunk_type.as_bytes());
+        hasher.update(body);
+
+        self.writer.write_all(&size.to_be_bytes())?;
+        self.writer.write_all(chunk_type.as_bytes())?;
+        self.writer.write_all(body)?;
+        self.writer.write_all(&hasher.finalize().to_be_bytes())?;
+
+        let offset = self.position;
+        self.position += 12 + body.len() as u64;
+        Ok(offset)
+    }
+}
+
+/// Append a SqPack target file specification
+fn push_target(payload: &mut Vec<u8>, target: &SqpackFileTarget) {
+    payload.extend_from_slice(&target.main_id.to_be_bytes());
+    payload.extend_from_slice(&target.sub_id.to_be_bytes());
+    payload.extend_from_slice(&target.file_id.to_be_bytes());
+}
+
+/// Convert a byte offset or size of a data command to 128-byte blocks
+fn block_units(value: u64) -> Result<u32, Error> {
+    if !value.is_multiple_of(SQPK_BLOCK_SIZE) {
+        return Err(Error::ZiPatchWrite(format!(
+            "{} is not a multiple of {} bytes",
+            value, SQPK_BLOCK_SIZE
+        )));
+    }
+
+    u32::try_from(value / SQPK_BLOCK_SIZE)
+        .map_err(|_| Error::ZiPatchWrite(format!("{} bytes exceed the block range", value)))
+}
+
+/// Append a file block with its header, padded to the block alignment
+fn push_file_block(
+    payload: &mut Vec<u8>,
+    block: &SqpkCompressedBlock,
+    read_data: &mut impl FnMut(u64, usize) -> Result<Vec<u8>, Error>,
+) -> Result<(), Error> {
+    // Stored blocks are read back using their decompressed size
+    let compressed_size = if block.is_compressed {
+        if block.data_size == UNCOMPRESSED_BLOCK_MARKER {
+            return Err(Error::ZiPatchWrite(format!(
+                "compressed block size {} is reserved for stored blocks",
+                block.data_size
+            )));
+        }
+        block.data_size
+    } else {
+        if block.data_size != block.decompressed_size {
+            return Err(Error::ZiPatchWrite(format!(
+                "stored block has {} bytes of data but a size of {}",
+                block.data_size, block.decompressed_size
+            )));
+        }
+        UNCOMPRESSED_BLOCK_MARKER
+    };
+
+    let start = payload.len();
+    payload.extend_from_slice(&(FILE_BLOCK_HEADER_SIZE as u32).to_le_bytes());
+    payload.extend_from_slice(&0u32.to_le_bytes());
+    payload.extend_from_slice(&compressed_size.to_le_bytes());
+    payload.extend_from_slice(&block.decompressed_size.to_le_bytes());
+    if block.data_size > 0 {
+        let data = read_data(block.data_source_offset, block.data_size as usize)?;
+        payload.extend_from_slice(&data);
+    }
+
+    let block_size = (payload.len() - start).next_multiple_of(FILE_BLOCK_ALIGNMENT);
+    payload.resize(start + block_size, 0);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zipatch::ZiPatchParser;
+    use gaveloc_core::ports::ZiPatchApplier;
+    use proptest::prelude::*;
+    use tempfile::{NamedTempFile, TempDir};
+
+    /// Parse a patch held in memory
+    fn parse(patch: &[u8]) -> Vec<ZiPatchChunk> {
+        let file = NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), patch).unwrap();
+        ZiPatchParser::new().parse_patch(file.path()).unwrap()
+    }
+
+    /// Clear the fields that depend on where a chunk sits in its patch
+    fn without_offsets(chunk: ZiPatchChunk) -> ZiPatchChunk {
+        match chunk {
+            ZiPatchChunk::FileHeader(c) => {
+                ZiPatchChunk::FileHeader(FileHeaderChunk { offset: 0, ..c })
+            }
+            ZiPatchChunk::ApplyOption(c) => {
+                ZiPatchChunk::ApplyOption(ApplyOptionChunk { offset: 0, ..c })
+            }
+            ZiPatchChunk::AddDirectory(c) => {
+                ZiPatchChunk::AddDirectory(AddDirectoryChunk { offset: 0, ..c })
+            }
+            ZiPatchChunk::DeleteDirectory(c) => {
+                ZiPatchChunk::DeleteDirectory(DeleteDirectoryChunk { offset: 0, ..c })
+            }
+            ZiPatchChunk::ApplyFreeSpace(c) => {
+                ZiPatchChunk::ApplyFreeSpace(ApplyFreeSpaceChunk { offset: 0, ..c })
+            }
+            ZiPatchChunk::Sqpk(sqpk) => ZiPatchChunk::Sqpk(match sqpk {
+                SqpkChunk::AddData(c) => SqpkChunk::AddData(SqpkAddData {
+                    data_source_offset: 0,
+                    offset: 0,
+                    ..c
+                }),
+                SqpkChunk::DeleteData(c) => {
+                    SqpkChunk::DeleteData(SqpkDeleteData { offset: 0, ..c })
+                }
+                SqpkChunk::ExpandData(c) => {
+                    SqpkChunk::ExpandData(SqpkExpandData { offset: 0, ..c })
+                }
+                SqpkChunk::Header(c) => SqpkChunk::Header(SqpkHeader { offset: 0, ..c }),
+                SqpkChunk::Index(c) => SqpkChunk::Index(SqpkIndex { offset: 0, ..c }),
+                SqpkChunk::File(c) => SqpkChunk::File(SqpkFile {
+                    blocks: c
+                        .blocks
+                        .into_iter()
+                        .map(|b| SqpkCompressedBlock {
+                            data_source_offset: 0,
+                            ..b
+                        })
+                        .collect(),
+                    offset: 0,
+                    ..c
+                }),
+                SqpkChunk::PatchInfo(c) => SqpkChunk::PatchInfo(SqpkPatchInfo { offset: 0, ..c }),
+                SqpkChunk::TargetInfo(c) => {
+                    SqpkChunk::TargetInfo(SqpkTargetInfo { offset: 0, ..c })
+                }
+                SqpkChunk::Unknown { command, .. } => SqpkChunk::Unknown { command, offset: 0 },
+            }),
+            ZiPatchChunk::EndOfFile => ZiPatchChunk::EndOfFile,
+            ZiPatchChunk::Unknown {
+                chunk_type, size, ..
+            } => ZiPatchChunk::Unknown {
+                chunk_type,
+                offset: 0,
+                size,
+            },
+        }
+    }
+
+    /// Serialise chunks, returning the bytes between the magic header and `EOF_`
+    fn written_bytes(
+        write: impl FnOnce(&mut ZiPatchWriter<Vec<u8>>) -> Result<u64, Error>,
+    ) -> Vec<u8> {
+        let mut writer = ZiPatchWriter::new(Vec::new()).unwrap();
+        write(&mut writer).unwrap();
+        let end = writer.position() as usize;
+        writer.finish().unwrap()[ZIPATCH_MAGIC.len()..end].to_vec()
+    }
+
+    /// Parse the single chunk held in `bytes`
+    fn parse_chunk(bytes: &[u8]) -> ZiPatchChunk {
+        let mut patch = ZiPatchWriter::new(Vec::new()).unwrap().finish().unwrap();
+        let start = ZIPATCH_MAGIC.len();
+        patch.splice(start..start, bytes.iter().copied());
+
+        let mut chunks = parse(&patch);
+        assert_eq!(chunks.len(), 2);
+        without_offsets(chunks.remove(0))
+    }
+
+    /// Target of 040000.win32.dat0 and 040000.win32.index
+    fn test_target() -> SqpackFileTarget {
+        SqpackFileTarget {
+            main_id: 0x04,
+            sub_id: 0x0000,
+            file_id: 0,
+        }
+    }
+
+    fn target_strategy() -> impl Strategy<Value = SqpackFileTarget> {
+        (any::<u16>(), any::<u16>(), any::<u32>()).prop_map(|(main_id, sub_id, file_id)| {
+            SqpackFileTarget {
+                main_id,
+                sub_id,
+                file_id,
+            }
+        })
+    }
+
+    /// Byte offsets and sizes expressible in 128-byte blocks
+    fn block_strategy() -> impl Strategy<Value = u64> {
+        any::<u32>().prop_map(|blocks| u64::from(blocks) << 7)
+    }
+
+    /// SQPK commands that carry no data outside the chunk structure
+    fn sqpk_strategy() -> impl Strategy<Value = SqpkChunk> {
+        prop_oneof![
+            (target_strategy(), block_strategy(), block_strategy()).prop_map(
+                |(target_file, block_offset, block_delete_number)| {
+                    SqpkChunk::AddData(SqpkAddData {
+                        target_file,
+                        block_offset,
+                        block_number: 0,
+                        block_delete_number,
+                        data_source_offset: 0,
+                        offset: 0,
+                    })
+                }
+            ),
+            (target_strategy(), block_strategy(), block_strategy()).prop_map(
+                |(target_file, block_offset, block_number)| {
+                    SqpkChunk::DeleteData(SqpkDeleteData {
+                        target_file,
+                        block_offset,
+                        block_number,
+                        offset: 0,
+                    })
+                }
+            ),
+            (target_strategy(), block_strategy(), block_strategy()).prop_map(
+                |(target_file, block_offset, block_number)| {
+                    SqpkChunk::ExpandData(SqpkExpandData {
+                        target_file,
+                        block_offset,
+                        block_number,
+                        offset: 0,
+                    })
+                }
+            ),
+            (
+                any::<u8>(),
+                any::<u8>(),
+                target_strategy(),
+                prop::collection::vec(any::<u8>(), 0..64)
+            )
+                .prop_map(|(file_kind, header_kind, target_file, header_data)| {
+                    SqpkChunk::Header(SqpkHeader {
+                        file_kind: file_kind.into(),
+                        header_kind: header_kind.into(),
+                        target_file,
+                        header_data,
+                        offset: 0,
+                    })
+                }),
+            (
+                any::<u8>(),
+                any::<bool>(),
+                target_strategy(),
+                any::<u64>(),
+                any::<u32>(),
+                any::<u32>()
+            )
+                .prop_map(
+                    |(command, is_synonym, target_file, file_hash, block_offset, block_number)| {
+                        SqpkChunk::Index(SqpkIndex {
+                            command: command.into(),
+                            is_synonym,
+                            target_file,
+                            file_hash,
+                            block_offset,
+                            block_number,
+                            offset: 0,
+                        })
+                    }
+                ),
+            (
+                any::<u8>(),
+                any::<u16>(),
+                any::<u64>(),
+                any::<u64>(),
+                "[a-z0-9/._]{0,32}"
+            )
+                .prop_map(
+                    |(operation, expansion_id, file_offset, file_size, file_path)| {
+                        SqpkChunk::File(SqpkFile {
+                            operation: operation.into(),
+                            expansion_id,
+                            file_offset,
+                            file_size,
+                            file_path,
+                            blocks: Vec::new(),
+                            offset: 0,
+                        })
+                    }
+                ),
+            (any::<u8>(), any::<u8>(), any::<u64>()).prop_map(|(status, version, install_size)| {
+                SqpkChunk::PatchInfo(SqpkPatchInfo {
+                    status,
+                    version,
+                    install_size,
+                    offset: 0,
+                })
+            }),
+            (
+                prop_oneof![
+                    Just(Platform::Win32),
+                    Just(Platform::Ps3),
+                    Just(Platform::Ps4)
+                ],
+                any::<u16>(),
+                any::<bool>(),
+                any::<u16>(),
+                any::<u64>(),
+                any::<u64>()
+            )
+                .prop_map(
+                    |(platform, region, is_debug, version, deleted_data_size, seek_count)| {
+                        SqpkChunk::TargetInfo(SqpkTargetInfo {
+                            platform,
+                            region,
+                            is_debug,
+                            version,
+                            deleted_data_size,
+                            seek_count,
+                            offset: 0,
+                        })
+                    }
+                ),
+            "[a-z]".prop_map(|command| SqpkChunk::Unknown { command, offset: 0 }),
+        ]
+    }
+
+    /// Chunks that carry no data outside the chunk structure, except `EOF_`
+    fn chunk_strategy() -> impl Strategy<Value = ZiPatchChunk> {
+        prop_oneof![
+            (any::<u16>(), "[A-Z]{0,4}", any::<u32>()).prop_map(
+                |(version, patch_type, entry_files)| {
+                    ZiPatchChunk::FileHeader(FileHeaderChunk {
+                        version,
+                        patch_type,
+                        entry_files,
+                        offset: 0,
+                    })
+                }
+            ),
+            (any::<u32>(), any::<u32>()).prop_map(|(option, value)| {
+                ZiPatchChunk::ApplyOption(ApplyOptionChunk {
+                    option: option.into(),
+                    value,
+                    offset: 0,
+                })
+            }),
+            "[a-z0-9/._]{0,32}"
+                .prop_map(|path| ZiPatchChunk::AddDirectory(AddDirectoryChunk { path, offset: 0 })),
+            "[a-z0-9/._]{0,32}".prop_map(|path| {
+                ZiPatchChunk::DeleteDirectory(DeleteDirectoryChunk { path, offset: 0 })
+            }),
+            any::<u64>().prop_map(|alloc_size| {
+                ZiPatchChunk::ApplyFreeSpace(ApplyFreeSpaceChunk {
+                    alloc_size,
+                    offset: 0,
+                })
+            }),
+            sqpk_strategy().prop_map(ZiPatchChunk::Sqpk),
+            ("[a-z]{4}", 0u32..64).prop_map(|(chunk_type, size)| ZiPatchChunk::Unknown {
+                chunk_type,
+                offset: 0,
+                size,
+            }),
+        ]
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn test_written_chunks_parse_back_equal(
+            chunks in prop::collection::vec(chunk_strategy(), 0..16)
+        ) {
+            let mut writer = ZiPatchWriter::new(Vec::new()).unwrap();
+            let mut offsets = Vec::new();
+            for chunk in &chunks {
+                offsets.push(writer.write_chunk(chunk).unwrap());
+            }
+            offsets.push(writer.position());
+            let patch = writer.finish().unwrap();
+
+            let parsed = parse(&patch);
+            prop_assert_eq!(parsed.len(), chunks.len() + 1);
+            prop_assert_eq!(parsed.last(), Some(&ZiPatchChunk::EndOfFile));
+            for (parsed, chunk) in parsed.into_iter().zip(&chunks) {
+                prop_assert_eq!(&without_offsets(parsed), chunk);
+            }
+        }
+    }
+
+    // Chunks laid out by hand as they appear in game patches
+
+    #[test]
+    fn test_add_data_matches_game_layout() {
+        let expected = [
+            &[0x00, 0x00, 0x00, 0x9c][..], // chunk size
+            b"SQPK",
+            &[0x00, 0x00, 0x00, 0x9c], // inner size
+            b"A",
+            &[0x00, 0x00, 0x00],                               // alignment
+            &[0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // 040000.win32.dat0
+            &[0x00, 0x00, 0x00, 0x02],                         // block offset
+            &[0x00, 0x00, 0x00, 0x01],                         // block number
+            &[0x00, 0x00, 0x00, 0x01],                         // blocks to delete
+            &[0x11; 128],
+            &[0x08, 0xe3, 0xa9, 0x6b], // CRC32
+        ]
+        .concat();
+
+        let written = written_bytes(|w| w.add_data(test_target(), 256, &[0x11; 128], 128));
+        assert_eq!(written, expected);
+        assert_eq!(
+            parse_chunk(&expected),
+            ZiPatchChunk::Sqpk(SqpkChunk::AddData(SqpkAddData {
+                target_file: test_target(),
+                block_offset: 256,
+                block_number: 128,
+                block_delete_number: 128,
+                data_source_offset: 0,
+                offset: 0,
+            }))
+        );
+    }
+
+    #[test]
+    fn test_add_file_matches_game_layout() {
+        let expected = [
+            &[0x00, 0x00, 0x00, 0xa6][..], // chunk size
+            b"SQPK",
+            &[0x00, 0x00, 0x00, 0xa6], // inner size
+            b"F",
+            &[b'A', 0x00, 0x00], // operation, alignment
+            &[0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // file offset
+            &[0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02], // file size
+            &[0x00, 0x00, 0x00, 0x06], // path length
+            &[0x00, 0x01, 0x00, 0x00], // expansion ID, padding
+            b"a.txt\0",
+            &[0x10, 0x00, 0x00, 0x00], // block header size
+            &[0x00, 0x00, 0x00, 0x00],
+            &[0x00, 0x7d, 0x00, 0x00], // stored uncompressed
+            &[0x02, 0x00, 0x00, 0x00], // decompressed size
+            b"hi",
+            &[0x00; 110],              // block alignment
+            &[0xef, 0xb0, 0xcf, 0xe5], // CRC32
+        ]
+        .concat();
+
+        let written = written_bytes(|w| w.add_file(1, "a.txt", 0, b"hi"));
+        assert_eq!(written, expected);
+        assert_eq!(
+            parse_chunk(&expected),
+            ZiPatchChunk::Sqpk(SqpkChunk::File(SqpkFile {
+                operation: SqpkFileOperation::AddFile,
+                expansion_id: 1,
+                file_offset: 0,
+                file_size: 2,
+                file_path: "a.txt".to_string(),
+                blocks: vec![SqpkCompressedBlock {
+                    is_compressed: false,
+                    decompressed_size: 2,
+                    data_source_offset: 0,
+                    data_size: 2,
+                }],
+                offset: 0,
+            }))
+        );
+    }
+
+    #[test]
+    fn test_index_matches_game_layout() {
+        let expected = [
+            &[0x00, 0x00, 0x00, 0x20][..], // chunk size
+            b"SQPK",
+            &[0x00, 0x00, 0x00, 0x20], // inner size
+            b"I",
+            &[b'D', 0x01, 0x00], // command, synonym, alignment
+            &[0x00, 0x0a, 0x01, 0x00, 0x00, 0x00, 0x00, 0x02], // 0a0100.win32.index2
+            &[0x9c, 0x5d, 0x4b, 0x0f, 0x3e, 0x21, 0x77, 0x08], // file hash
+            &[0x00, 0x01, 0x23, 0x45], // block offset
+            &[0x00, 0x00, 0x00, 0x03], // block number
+            &[0x2d, 0x93, 0x97, 0xdd], // CRC32
+        ]
+        .concat();
+
+        let chunk = ZiPatchChunk::Sqpk(SqpkChunk::Index(SqpkIndex {
+            command: SqpkIndexCommand::Delete,
+            is_synonym: true,
+            target_file: SqpackFileTarget {
+                main_id: 0x0a,
+                sub_id: 0x0100,
+                file_id: 2,
+            },
+            file_hash: 0x9c5d_4b0f_3e21_7708,
+            block_offset: 0x12345,
+            block_number: 3,
+            offset: 0,
+        }));
+        assert_eq!(written_bytes(|w| w.write_chunk(&chunk)), expected);
+        assert_eq!(parse_chunk(&expected), chunk);
+    }
+
+    #[test]
+    fn test_header_matches_game_layout() {
+        let expected = [
+            &[0x00, 0x00, 0x04, 0x10][..], // chunk size
+            b"SQPK",
+            &[0x00, 0x00, 0x04, 0x10], // inner size
+            b"H",
+            &[b'D', b'V', 0x00], // file kind, header kind, alignment
+            &[0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // 040000.win32.dat0
+            &[0x5a; 1024],
+            &[0xb9, 0xd8, 0x73, 0x48], // CRC32
+        ]
+        .concat();
+
+        let chunk = ZiPatchChunk::Sqpk(SqpkChunk::Header(SqpkHeader {
+            file_kind: SqpkFileKind::Dat,
+            header_kind: SqpkHeaderKind::Version,
+            target_file: test_target(),
+            header_data: vec![0x5a; 1024],
+            offset: 0,
+        }));
+        assert_eq!(written_bytes(|w| w.write_chunk(&chunk)), expected);
+        assert_eq!(parse_chunk(&expected), chunk);
+    }
+
+    #[test]
+    fn test_patch_info_matches_game_layout() {
+        let expected = [
+            &[0x00, 0x00, 0x00, 0x10][..], // chunk size
+            b"SQPK",
+            &[0x00, 0x00, 0x00, 0x10], // inner size
+            b"X",
+            &[0x01, 0x02, 0x00], // status, version, alignment
+            &[0x00, 0x00, 0x00, 0x00, 0x12, 0x34, 0x56, 0x78], // install size
+            &[0xa0, 0x4e, 0x83, 0xc9], // CRC32
+        ]
+        .concat();
+
+        let chunk = ZiPatchChunk::Sqpk(SqpkChunk::PatchInfo(SqpkPatchInfo {
+            status: 1,
+            version: 2,
+            install_size: 0x1234_5678,
+            offset: 0,
+        }));
+        assert_eq!(written_bytes(|w| w.write_chunk(&chunk)), expected);
+        assert_eq!(parse_chunk(&expected), chunk);
+    }
+
+    #[test]
+    fn test_target_info_matches_game_layout() {
+        let expected = [
+            &[0x00, 0x00, 0x00, 0x80][..], // chunk size
+            b"SQPK",
+            &[0x00, 0x00, 0x00, 0x80], // inner size
+            b"T",
+            &[0x00, 0x00, 0x00],                               // reserved
+            &[0x00, 0x00],                                     // platform
+            &[0xff, 0xff],                                     // region
+            &[0x00, 0x00],                                     // debug
+            &[0x00, 0x00],                                     // version
+            &[0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // deleted data size
+            &[0x20, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // seek count
+            &[0x00; 96],                                       // reserved
+            &[0x15, 0x74, 0x54, 0xc7],                         // CRC32
+        ]
+        .concat();
+
+        let chunk = ZiPatchChunk::Sqpk(SqpkChunk::TargetInfo(SqpkTargetInfo {
+            platform: Platform::Win32,
+            region: 0xffff,
+            is_debug: false,
+            version: 0,
+            deleted_data_size: 0x1000,
+            seek_count: 0x20,
+            offset: 0,
+        }));
+        assert_eq!(written_bytes(|w| w.write_chunk(&chunk)), expected);
+        assert_eq!(parse_chunk(&expected), chunk);
+    }
+
+    #[test]
+    fn test_write_chunk_returns_offsets() {
+        let mut writer = ZiPatchWriter::new(Vec::new()).unwrap();
+        assert_eq!(writer.position(), ZIPATCH_MAGIC.len() as u64);
+
+        let apfs = ZiPatchChunk::ApplyFreeSpace(ApplyFreeSpaceChunk {
+            alloc_size: 1,
+            offset: 0,
+        });
+        assert_eq!(writer.write_chunk(&apfs).unwrap(), 12);
+        // Size, type and CRC around 8 bytes of data
+        assert_eq!(writer.write_chunk(&apfs).unwrap(), 32);
+
+        let patch = writer.finish().unwrap();
+        let parsed = parse(&patch);
+        assert!(matches!(
+            parsed[1],
+            ZiPatchChunk::ApplyFreeSpace(ApplyFreeSpaceChunk { offset: 32, .. })
+        ));
+    }
+
+    #[test]
+    fn test_added_data_and_files_apply() {
+        let target = test_target();
+        let block_data: Vec<u8> = (0..256).map(|i| i as u8).collect();
+        // Compressible and incompressible data spanning several file blocks
+        let text = b"gaveloc ".repeat(5000);
+        let mut state = 0x2545_f491_u32;
+        let noise: Vec<u8> = (0..20000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+
+        let patch = NamedTempFile::new().unwrap();
+        let mut writer = ZiPatchWriter::create(patch.path()).unwrap();
+        writer.add_data(target, 128, &block_data, 128).unwrap();
+        writer.add_file(0, "text.txt", 0, &text).unwrap();
+        writer.add_file(0, "noise.bin", 0, &noise).unwrap();
+        writer.finish().unwrap();
+
+        let chunks = ZiPatchParser::new().parse_patch(patch.path()).unwrap();
+        let blocks = chunks
+            .iter()
+            .filter_map(|chunk| match chunk {
+                ZiPatchChunk::Sqpk(SqpkChunk::File(file)) => Some(&file.blocks),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].len(), 3);
+        assert!(blocks[0].iter().all(|b| b.is_compressed));
+        assert_eq!(blocks[1].len(), 2);
+        assert!(blocks[1].iter().all(|b| !b.is_compressed));
+
+        let game = TempDir::new().unwrap();
+        ZiPatchParser::new()
+            .apply_patch(patch.path(), game.path())
+            .unwrap();
+
+        let dat = std::fs::read(game.path().join("sqpack/ffxiv/040000.win32.dat0")).unwrap();
+        assert_eq!(dat.len(), 128 + 256 + 128);
+        assert_eq!(&dat[128..384], &block_data[..]);
+        assert_eq!(std::fs::read(game.path().join("text.txt")).unwrap(), text);
+        assert_eq!(std::fs::read(game.path().join("noise.bin")).unwrap(), noise);
+    }
+
+    #[test]
+    fn test_copied_chunks_reproduce_patch() {
+        let target = test_target();
+        let mut writer = ZiPatchWriter::new(Vec::new()).unwrap();
+        writer.add_data(target, 0, &[7u8; 384], 0).unwrap();
+        writer
+            .add_file(1, "sqpack/ex1/data.bin", 64, &[3u8; 1000])
+            .unwrap();
+        let original = writer.finish().unwrap();
+
+        let mut source = std::io::Cursor::new(&original);
+        let mut writer = ZiPatchWriter::new(Vec::new()).unwrap();
+        for chunk in parse(&original) {
+            if chunk != ZiPatchChunk::EndOfFile {
+                writer.copy_chunk(&chunk, &mut source).unwrap();
+            }
+        }
+
+        assert_eq!(writer.finish().unwrap(), original);
+    }
+
+    #[test]
+    fn test_write_chunk_without_data_fails() {
+        let mut writer = ZiPatchWriter::new(Vec::new()).unwrap();
+        let chunk = ZiPatchChunk::Sqpk(SqpkChunk::AddData(SqpkAddData {
+            target_file: test_target(),
+            block_offset: 0,
+            block_number: 128,
+            block_delete_number: 0,
+            data_source_offset: 4096,
+            offset: 0,
+        }));
+
+        assert!(matches!(
+            writer.write_chunk(&chunk),
+            Err(Error::ZiPatchWrite(_))
+        ));
+    }
+
+    #[test]
+    fn test_unaligned_data_is_rejected() {
+        let target = test_target();
+        let mut writer = ZiPatchWriter::new(Vec::new()).unwrap();
+
+        assert!(matches!(
+            writer.add_data(target.clone(), 0, &[0u8; 100], 0),
+            Err(Error::ZiPatchWrite(_))
+        ));
+        assert!(matches!(
+            writer.add_data(target, 64, &[0u8; 128], 0),
+            Err(Error::ZiPatchWrite(_))
+        ));
+    }
+}
@@ -1,7 +1,12 @@
+use std::collections::BTreeMap;
+use std::fmt;
 use std::path::PathBuf;
+use std::str::FromStr;
 
 use serde::{Deserialize, Serialize};
 
+use crate::error::Error;
+
 /// Region for Square Enix login servers (internal use only)
 /// Global accounts use Europe region by default
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
@@ -50,7 +55,11 @@ impl Language {
 pub struct Settings {
     pub game: GameSettings,
     pub wine: WineSettings,
+    pub patch: PatchSettings,
+    pub integrity: IntegritySettings,
     pub log_level: String,
+    /// Where patches are downloaded to (None for the user cache directory)
+    pub patch_dir: Option<PathBuf>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Default)]
@@ -86,12 +95,178 @@ pub struct WineSettings {
     pub dxvk_hud: Option<String>,
 }
 
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct PatchSettings {
+    /// Download rate cap in KiB/s (None for unlimited)
+    pub max_download_rate: Option<u64>,
+    /// Only download patches during this time of day
+    pub download_window: Option<DownloadWindow>,
+}
+
+impl PatchSettings {
+    /// Download rate cap in bytes per second, if any
+    pub fn max_download_bytes_per_sec(&self) -> Option<u64> {
+        self.max_download_rate
+            .filter(|&rate| rate > 0)
+            .map(|rate| rate * 1024)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct IntegritySettings {
+    /// Where manifests are read from, tried in order
+    pub manifest_sources: Vec<ManifestSourceSettings>,
+    /// Expected SHA-256 of the manifest JSON, by game version
+    pub pinned_manifests: BTreeMap<String, String>,
+    /// Where fetched manifests are cached (None for the user cache directory)
+    pub manifest_cache_dir: Option<PathBuf>,
+}
+
+/// A place integrity manifests are read from
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ManifestSourceSettings {
+    /// The goatcorp community manifests
+    Goatcorp,
+    /// An HTTP mirror serving `<base_url>/<version>.json`
+    Mirror { base_url: String },
+    /// A local directory of `<version>.json` files
+    Directory { path: PathBuf },
+}
+
+/// Local time of day with minute precision, written as `HH:MM`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct TimeOfDay {
+    minutes: u16,
+}
+
+impl TimeOfDay {
+    pub const MINUTES_PER_DAY: u16 = 24 * 60;
+
+    pub fn new(hour: u8, minute: u8) -> Option<Self> {
+        (hour < 24 && minute < 60).then_some(Self {
+            minutes: hour as u16 * 60 + minute as u16,
+        })
+    }
+
+    pub fn hour(self) -> u8 {
+        (self.minutes / 60) as u8
+    }
+
+    pub fn minute(self) -> u8 {
+        (self.minutes % 60) as u8
+    }
+
+    pub fn minutes_since_midnight(self) -> u16 {
+        self.minutes
+    }
+}
+
+impl FromStr for TimeOfDay {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidDownloadWindow(format!("expected HH:MM, got '{}'", s));
+        let (hour, minute) = s.trim().split_once(':').ok_or_else(invalid)?;
+        let hour = hour.parse().map_err(|_| invalid())?;
+        let minute = minute.parse().map_err(|_| invalid())?;
+        Self::new(hour, minute).ok_or_else(invalid)
+    }
+}
+
+impl TryFrom<String> for TimeOfDay {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<TimeOfDay> for String {
+    fn from(time: TimeOfDay) -> Self {
+        time.to_string()
+    }
+}
+
+impl fmt::Display for TimeOfDay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}:{:02}", self.hour(), self.minute())
+    }
+}
+
+/// Daily period in which patches may be downloaded
+///
+/// A window whose end is before its start runs past midnight, and one whose
+/// start and end are equal covers the whole day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DownloadWindow {
+    pub start: TimeOfDay,
+    pub end: TimeOfDay,
+}
+
+impl DownloadWindow {
+    /// Whether downloads are allowed at `now`
+    pub fn contains(&self, now: TimeOfDay) -> bool {
+        let (start, end, now) = (self.start.minutes, self.end.minutes, now.minutes);
+        match start.cmp(&end) {
+            std::cmp::Ordering::Less => start <= now && now < end,
+            std::cmp::Ordering::Greater => now >= start || now < end,
+            std::cmp::Ordering::Equal => true,
+        }
+    }
+
+    /// Minutes from `now` until the window opens (0 if it is open)
+    pub fn minutes_until_open(&self, now: TimeOfDay) -> u16 {
+        if self.contains(now) {
+            return 0;
+        }
+        (self.start.minutes + TimeOfDay::MINUTES_PER_DAY - now.minutes) % TimeOfDay::MINUTES_PER_DAY
+    }
+}
+
+/// Parses `HH:MM-HH:MM`
+impl FromStr for DownloadWindow {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s.split_once('-').ok_or_else(|| {
+            Error::InvalidDownloadWindow(format!("expected HH:MM-HH:MM, got '{}'", s))
+        })?;
+        Ok(Self {
+            start: start.parse()?,
+            end: end.parse()?,
+        })
+    }
+}
+
+impl fmt::Display for DownloadWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.start, self.end)
+    }
+}
+
 impl Default for Settings {
     fn default() -> Self {
         Self {
             game: GameSettings::default(),
             wine: WineSettings::default(),
+            patch: PatchSettings::default(),
+            integrity: IntegritySettings::default(),
             log_level: "info".to_string(),
+            patch_dir: None,
+        }
+    }
+}
+
+impl Default for IntegritySettings {
+    fn default() -> Self {
+        Self {
+            manifest_sources: vec![ManifestSourceSettings::Goatcorp],
+            pinned_manifests: BTreeMap::new(),
+            manifest_cache_dir: None,
         }
     }
 }
@@ -155,6 +330,89 @@ mod tests {
         assert_eq!(settings.wine.dxvk_hud, None);
     }
 
+    #[rstest]
+    #[case("00:00", 0, 0)]
+    #[case("7:05", 7, 5)]
+    #[case("23:59", 23, 59)]
+    fn test_time_of_day_parse(#[case] input: &str, #[case] hour: u8, #[case] minute: u8) {
+        let time: TimeOfDay = input.parse().unwrap();
+        assert_eq!((time.hour(), time.minute()), (hour, minute));
+    }
+
+    #[rstest]
+    #[case("24:00")]
+    #[case("12:60")]
+    #[case("noon")]
+    #[case("12")]
+    fn test_time_of_day_parse_invalid(#[case] input: &str) {
+        assert!(input.parse::<TimeOfDay>().is_err());
+    }
+
+    #[rstest]
+    #[case("01:00-07:00", "00:59", false, 1)]
+    #[case("01:00-07:00", "01:00", true, 0)]
+    #[case("01:00-07:00", "07:00", false, 18 * 60)]
+    #[case("22:30-06:00", "23:00", true, 0)]
+    #[case("22:30-06:00", "05:59", true, 0)]
+    #[case("22:30-06:00", "12:00", false, 10 * 60 + 30)]
+    #[case("08:00-08:00", "03:00", true, 0)]
+    fn test_download_window(
+        #[case] window: &str,
+        #[case] now: &str,
+        #[case] open: bool,
+        #[case] wait: u16,
+    ) {
+        let window: DownloadWindow = window.parse().unwrap();
+        let now: TimeOfDay = now.parse().unwrap();
+        assert_eq!(window.contains(now), open);
+        assert_eq!(window.minutes_until_open(now), wait);
+    }
+
+    #[test]
+    fn test_patch_settings_serde_roundtrip() {
+        let settings = PatchSettings {
+            max_download_rate: Some(512),
+            download_window: Some("22:30-06:00".parse().unwrap()),
+        };
+        let json = serde_json::to_string(&settings).unwrap();
+        assert!(json.contains(r#""start":"22:30""#));
+        assert_eq!(
+            serde_json::from_str::<PatchSettings>(&json).unwrap(),
+            settings
+        );
+        assert_eq!(settings.max_download_bytes_per_sec(), Some(512 * 1024));
+    }
+
+    #[test]
+    fn test_integrity_settings_deserialize() {
+        let settings: IntegritySettings = serde_json::from_str(
+            r#"{
+                "manifest_sources": [
+                    { "type": "directory", "path": "/srv/manifests" },
+                    { "type": "mirror", "base_url": "https://mirror.example/integrity" },
+                    { "type": "goatcorp" }
+                ],
+                "pinned_manifests": { "2024.07.23.0000.0001": "abc123" }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            settings.manifest_sources,
+            vec![
+                ManifestSourceSettings::Directory {
+                    path: PathBuf::from("/srv/manifests")
+                },
+                ManifestSourceSettings::Mirror {
+                    base_url: "https://mirror.example/integrity".to_string()
+                },
+                ManifestSourceSettings::Goatcorp,
+            ]
+        );
+        assert_eq!(settings.pinned_manifests["2024.07.23.0000.0001"], "abc123");
+        assert_eq!(settings.manifest_cache_dir, None);
+    }
+
     #[test]
     fn test_default_settings_snapshot() {
         let settings = Settings::default();
@@ -0,0 +1,216 @@
+//! Pausing and cancelling long running operations
+//!
+//! Downloads, verification and patch application check an [`UpdateControl`]
+//! at safe points (between download chunks, hash blocks or patch chunks), so
+//! stopping them never leaves a half-written file behind. A cancelled
+//! download keeps its verified data and a cancelled patch application keeps
+//! its journal, so both resume on the next run.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::watch;
+
+use crate::error::Error;
+
+/// How often blocking work re-checks a paused control
+const BLOCKING_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Requested state of a controlled operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ControlState {
+    #[default]
+    Running,
+    Paused,
+    Cancelled,
+}
+
+/// Handle for pausing, resuming and cancelling an operation
+///
+/// Clones share the same state, so one clone can be handed to the operation
+/// and another kept by whoever drives the UI.
+#[derive(Debug, Clone)]
+pub struct UpdateControl {
+    state: Arc<watch::Sender<ControlState>>,
+}
+
+impl UpdateControl {
+    pub fn new() -> Self {
+        let (state, _) = watch::channel(ControlState::Running);
+        Self {
+            state: Arc::new(state),
+        }
+    }
+
+    pub fn state(&self) -> ControlState {
+        *self.state.borrow()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state() == ControlState::Paused
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state() == ControlState::Cancelled
+    }
+
+    /// Pause at the next safe point (no effect once cancelled)
+    pub fn pause(&self) {
+        self.transition(ControlState::Running, ControlState::Paused);
+    }
+
+    /// Continue a paused operation (no effect once cancelled)
+    pub fn resume(&self) {
+        self.transition(ControlState::Paused, ControlState::Running);
+    }
+
+    /// Stop at the next safe point; this cannot be undone
+    pub fn cancel(&self) {
+        self.state.send_if_modified(|state| {
+            let changed = *state != ControlState::Cancelled;
+            *state = ControlState::Cancelled;
+            changed
+        });
+    }
+
+    fn transition(&self, from: ControlState, to: ControlState) {
+        self.state.send_if_modified(|state| {
+            let changed = *state == from;
+            if changed {
+                *state = to;
+            }
+            changed
+        });
+    }
+
+    /// Watch for state changes
+    pub fn subscribe(&self) -> watch::Receiver<ControlState> {
+        self.state.subscribe()
+    }
+
+    /// Safe point for async work: waits while paused and fails with
+    /// [`Error::Cancelled`] once cancelled
+    pub async fn checkpoint(&self) -> Result<(), Error> {
+        let mut state = self.state.subscribe();
+        let state = *state
+            .wait_for(|state| *state != ControlState::Paused)
+            .await
+            .map_err(|_| Error::Cancelled)?;
+        match state {
+            ControlState::Cancelled => Err(Error::Cancelled),
+            _ => Ok(()),
+        }
+    }
+
+    /// Safe point for blocking work such as patch application
+    pub fn checkpoint_blocking(&self) -> Result<(), Error> {
+        loop {
+            match self.state() {
+                ControlState::Running => return Ok(()),
+                ControlState::Cancelled => return Err(Error::Cancelled),
+                ControlState::Paused => std::thread::sleep(BLOCKING_POLL_INTERVAL),
+            }
+        }
+    }
+
+    /// Resolves once the operation is cancelled
+    pub async fn cancelled(&self) {
+        let mut state = self.state.subscribe();
+        let _ = state
+            .wait_for(|state| *state == ControlState::Cancelled)
+            .await;
+    }
+}
+
+impl Default for UpdateControl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_control_transitions() {
+        let control = UpdateControl::new();
+        assert_eq!(control.state(), ControlState::Running);
+
+        control.resume();
+        assert_eq!(control.state(), ControlState::Running);
+
+        control.pause();
+        assert!(control.is_paused());
+        control.resume();
+        assert_eq!(control.state(), ControlState::Running);
+
+        control.pause();
+        control.cancel();
+        assert!(control.is_cancelled());
+
+        // Cancellation is final
+        control.resume();
+        control.pause();
+        assert!(control.is_cancelled());
+    }
+
+    #[test]
+    fn test_clones_share_state() {
+        let control = UpdateControl::new();
+        let handle = control.clone();
+        handle.cancel();
+        assert!(control.is_cancelled());
+        assert!(matches!(
+            control.checkpoint_blocking(),
+            Err(Error::Cancelled)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_waits_while_paused() {
+        let control = UpdateControl::new();
+        control.checkpoint().await.unwrap();
+
+        control.pause();
+        let waiting = tokio::spawn({
+            let control = control.clone();
+            async move { control.checkpoint().await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!waiting.is_finished());
+
+        control.resume();
+        waiting.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_fails_when_cancelled_while_paused() {
+        let control = UpdateControl::new();
+        control.pause();
+        let waiting = tokio::spawn({
+            let control = control.clone();
+            async move { control.checkpoint().await }
+        });
+
+        control.cancel();
+        assert!(matches!(waiting.await.unwrap(), Err(Error::Cancelled)));
+        control.cancelled().await;
+    }
+
+    #[test]
+    fn test_checkpoint_blocking_waits_for_resume() {
+        let control = UpdateControl::new();
+        control.pause();
+
+        let waiting = std::thread::spawn({
+            let control = control.clone();
+            move || control.checkpoint_blocking()
+        });
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!waiting.is_finished());
+
+        control.resume();
+        waiting.join().unwrap().unwrap();
+    }
+}
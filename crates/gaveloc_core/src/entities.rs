@@ -113,6 +113,14 @@ impl Repository {
         }
     }
 
+    /// Get the directory patches for this repository are applied to, relative to game root
+    pub fn install_dir(&self) -> &'static str {
+        match self {
+            Repository::Boot => "boot",
+            _ => "game",
+        }
+    }
+
     /// Get repository from expansion number (0 = base game, 1-5 = expansions)
     pub fn from_expansion(expansion: u32) -> Option<Self> {
         match expansion {
@@ -136,6 +144,25 @@ impl Repository {
         }
         repos
     }
+
+    /// Get the repository whose patches write a file, from its path relative to game root
+    ///
+    /// Accepts manifest paths (`\game\sqpack\ex1\...`) as well as `/` separated ones.
+    pub fn for_game_file(relative_path: &str) -> Self {
+        let path = relative_path
+            .trim_start_matches(['/', '\\'])
+            .replace('\\', "/")
+            .to_lowercase();
+        if path.starts_with("boot/") {
+            return Repository::Boot;
+        }
+
+        path.strip_prefix("game/sqpack/ex")
+            .and_then(|rest| rest.split('/').next())
+            .and_then(|expansion| expansion.parse().ok())
+            .and_then(Self::from_expansion)
+            .unwrap_or(Repository::Ffxiv)
+    }
 }
 
 impl fmt::Display for Repository {
@@ -283,6 +310,45 @@ impl PatchEntry {
     }
 }
 
+/// Patches that build a repository from an empty installation, in order
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PatchChain {
+    /// History patches followed by the incremental patches
+    pub patches: Vec<PatchEntry>,
+    /// Session ID for downloading the patches (None for boot patches)
+    pub unique_id: Option<String>,
+}
+
+/// Result of checking a downloaded patch file against its size and block hashes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PatchVerificationReport {
+    /// Size the patch file should have
+    pub expected_size: u64,
+    /// Size of the file on disk (None if it does not exist)
+    pub actual_size: Option<u64>,
+    /// Number of hash blocks the patch is split into (0 without block hashes)
+    pub total_blocks: usize,
+    /// Indices of blocks that are missing or do not match their hash
+    pub failed_blocks: Vec<usize>,
+}
+
+impl PatchVerificationReport {
+    /// Whether the file has the expected size and every block matched
+    pub fn is_valid(&self) -> bool {
+        self.actual_size == Some(self.expected_size) && self.failed_blocks.is_empty()
+    }
+}
+
+/// Free space on the filesystem holding a path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FilesystemSpace {
+    /// Device the filesystem is mounted from, used to tell whether two paths
+    /// share their free space
+    pub device: u64,
+    /// Bytes available to unprivileged users
+    pub available_bytes: u64,
+}
+
 /// Patch download/install state for progress tracking
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum PatchState {
@@ -339,12 +405,55 @@ impl PatchProgress {
     }
 }
 
+/// Progress of a job running in the patcher process
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PatcherJobProgress {
+    /// Job the progress belongs to
+    pub job_id: u64,
+    /// Index of the current patch in the job (0-based)
+    pub patch_index: usize,
+    /// Number of patches in the job
+    pub total_patches: usize,
+    /// Progress of the current patch
+    pub progress: PatchProgress,
+}
+
+/// Something the patcher process reported about its jobs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum PatcherEvent {
+    /// A job made progress on one of its patches
+    Progress(PatcherJobProgress),
+    /// A patch of a job was applied and its version recorded
+    PatchCompleted {
+        job_id: u64,
+        patch_index: usize,
+        version_id: String,
+    },
+    /// Every patch of a job was applied
+    JobCompleted { job_id: u64 },
+    /// A job was cancelled before it finished
+    JobCancelled { job_id: u64 },
+    /// A job stopped on an error
+    JobFailed { job_id: u64, message: String },
+}
+
+/// State of the patcher process, as answered to a status query
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PatcherStatus {
+    /// Whether patching is paused (queued jobs wait as well)
+    pub paused: bool,
+    /// Latest progress of the running job, `None` while idle
+    pub current: Option<PatcherJobProgress>,
+    /// Jobs waiting to run, in the order they will run
+    pub queued_jobs: Vec<u64>,
+}
+
 // =============================================================================
 // Integrity Checking Types
 // =============================================================================
 
 /// Result of an integrity check for a single file
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct FileIntegrityResult {
     /// Relative path from game directory
     pub relative_path: String,
@@ -380,6 +489,92 @@ impl fmt::Display for IntegrityStatus {
     }
 }
 
+/// A file inside a SqPack archive that could not be extracted
+///
+/// Found by decompressing every file listed in the archive indexes. The index
+/// only keeps hashes of the game paths, so the file is identified by its hash
+/// and location.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SqPackFileDamage {
+    /// Index file listing the file, as a manifest path
+    pub index_path: String,
+    /// Dat file holding the file, as a manifest path
+    pub dat_path: String,
+    /// Path hash from the index: folder and file name CRCs in hex
+    pub hash: String,
+    /// Offset of the file in the dat file
+    pub offset: u64,
+    /// Bytes from `offset` up to the next file, or the end of the dat file
+    pub size: u64,
+    /// Why the file could not be extracted
+    pub error: String,
+}
+
+impl fmt::Display for SqPackFileDamage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} in {} at {:#x}",
+            self.hash, self.dat_path, self.offset
+        )
+    }
+}
+
+/// Result of repairing a damaged file inside a SqPack archive
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SqPackRepairResult {
+    pub file: SqPackFileDamage,
+    /// What the repair did
+    pub outcome: RepairOutcome,
+}
+
+/// Result of repairing a single file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileRepairResult {
+    /// Relative path from game directory, as in the manifest
+    pub relative_path: String,
+    /// What the repair did
+    pub outcome: RepairOutcome,
+}
+
+/// Outcome of rebuilding a file from its repository's patches
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RepairOutcome {
+    /// Rebuilt, verified against the manifest and put in place
+    Repaired,
+    /// Rebuilt, but the result does not match the manifest; the file was left untouched
+    StillMismatched {
+        /// SHA1 hash of the rebuilt file
+        actual_hash: String,
+    },
+    /// None of the repository's patches write this file
+    NotInPatches,
+    /// The file could not be rebuilt (no patch history, download or apply error)
+    Failed {
+        /// Human-readable reason
+        message: String,
+    },
+}
+
+impl RepairOutcome {
+    pub fn is_repaired(&self) -> bool {
+        matches!(self, RepairOutcome::Repaired)
+    }
+}
+
+impl fmt::Display for RepairOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RepairOutcome::Repaired => write!(f, "Repaired"),
+            RepairOutcome::StillMismatched { actual_hash } => {
+                write!(f, "Still mismatched (rebuilt as {})", actual_hash)
+            }
+            RepairOutcome::NotInPatches => write!(f, "Not in patches"),
+            RepairOutcome::Failed { message } => write!(f, "Failed: {}", message),
+        }
+    }
+}
+
 /// Remote integrity manifest from goatcorp
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct IntegrityManifest {
@@ -392,6 +587,98 @@ pub struct IntegrityManifest {
     /// Previous game version (for delta checking)
     #[serde(rename = "LastGameVersion")]
     pub last_game_version: Option<String>,
+    /// Where the manifest was read from; not part of the manifest format
+    #[serde(skip)]
+    pub source: Option<String>,
+}
+
+/// Outcome of an integrity check with the context needed to read it later
+///
+/// Reports are kept so runs can be compared, e.g. before and after a crash or
+/// a patch (see `IntegrityReport::diff`).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IntegrityReport {
+    /// Game version the manifest is for
+    pub game_version: String,
+    /// Where the manifest was read from
+    pub manifest_source: Option<String>,
+    /// Whether unchanged files were hashed again
+    pub mode: IntegrityCheckMode,
+    /// When the check started (Unix timestamp)
+    pub started_at: i64,
+    /// How long the check took, in milliseconds
+    pub duration_ms: u64,
+    /// Totals per repository, in patch order
+    pub repositories: Vec<RepositoryIntegritySummary>,
+    /// Every checked file, sorted by path
+    pub files: Vec<FileIntegrityResult>,
+    /// Damaged files inside SqPack archives, if they were checked
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sqpack_files: Vec<SqPackFileDamage>,
+}
+
+/// File totals of one repository in an integrity report
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RepositoryIntegritySummary {
+    pub repository: Repository,
+    pub total: u32,
+    pub valid: u32,
+    pub mismatch: u32,
+    pub missing: u32,
+    pub unreadable: u32,
+}
+
+impl RepositoryIntegritySummary {
+    /// Number of files that are not valid
+    pub fn problems(&self) -> u32 {
+        self.total - self.valid
+    }
+}
+
+/// Files whose results differ between two integrity reports
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IntegrityReportDiff {
+    pub old_game_version: String,
+    pub new_game_version: String,
+    /// Changed files, sorted by path
+    pub changes: Vec<FileIntegrityChange>,
+}
+
+/// A file checked differently by two integrity reports
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileIntegrityChange {
+    pub relative_path: String,
+    /// Result in the older report, `None` if it did not check the file
+    pub before: Option<FileIntegrityResult>,
+    /// Result in the newer report, `None` if it did not check the file
+    pub after: Option<FileIntegrityResult>,
+}
+
+impl FileIntegrityChange {
+    pub fn status_before(&self) -> Option<IntegrityStatus> {
+        self.before.as_ref().map(|r| r.status)
+    }
+
+    pub fn status_after(&self) -> Option<IntegrityStatus> {
+        self.after.as_ref().map(|r| r.status)
+    }
+
+    /// Whether the file is damaged now but was not before
+    pub fn is_regression(&self) -> bool {
+        let damaged =
+            |status: Option<IntegrityStatus>| status.is_some_and(|s| s != IntegrityStatus::Valid);
+        damaged(self.status_after()) && !damaged(self.status_before())
+    }
+}
+
+/// How an integrity check reads the installation
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IntegrityCheckMode {
+    /// Reuse the hashes of files unchanged since they were last hashed
+    #[default]
+    Incremental,
+    /// Hash every file again
+    Full,
 }
 
 /// Progress information for integrity checking
@@ -590,6 +877,24 @@ pub struct LoginResult {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_patch_verification_report_is_valid() {
+        let mut report = PatchVerificationReport {
+            expected_size: 100,
+            actual_size: Some(100),
+            total_blocks: 2,
+            failed_blocks: vec![],
+        };
+        assert!(report.is_valid());
+
+        report.failed_blocks.push(1);
+        assert!(!report.is_valid());
+
+        report.failed_blocks.clear();
+        report.actual_size = None;
+        assert!(!report.is_valid());
+    }
+
     #[test]
     fn test_runner_type_display() {
         assert_eq!(RunnerType::System.to_string(), "System");
@@ -678,6 +983,13 @@ mod tests {
         assert_eq!(Repository::Ex1.version_file_path(), "game/sqpack/ex1/ex1.ver");
     }
 
+    #[test]
+    fn test_repository_install_dir() {
+        assert_eq!(Repository::Boot.install_dir(), "boot");
+        assert_eq!(Repository::Ffxiv.install_dir(), "game");
+        assert_eq!(Repository::Ex5.install_dir(), "game");
+    }
+
     #[test]
     fn test_repository_from_expansion() {
         assert_eq!(Repository::from_expansion(0), Some(Repository::Ffxiv));
@@ -699,6 +1011,26 @@ mod tests {
         assert_eq!(repos.len(), 6);
     }
 
+    #[test]
+    fn test_repository_for_game_file() {
+        assert_eq!(
+            Repository::for_game_file(r"\boot\ffxivboot.exe"),
+            Repository::Boot
+        );
+        assert_eq!(
+            Repository::for_game_file(r"\game\sqpack\ex3\030000.win32.dat0"),
+            Repository::Ex3
+        );
+        assert_eq!(
+            Repository::for_game_file("game/sqpack/ffxiv/000000.win32.index"),
+            Repository::Ffxiv
+        );
+        assert_eq!(
+            Repository::for_game_file(r"\game\ffxiv_dx11.exe"),
+            Repository::Ffxiv
+        );
+    }
+
     #[test]
     fn test_game_version_parse() {
         let version = GameVersion::parse("2024.07.23.0000.0001").unwrap();
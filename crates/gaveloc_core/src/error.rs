@@ -106,12 +106,18 @@ pub enum Error {
         actual: String,
     },
 
+    #[error("invalid download window: {0}")]
+    InvalidDownloadWindow(String),
+
     #[error("zipatch parse error: {0}")]
     ZiPatchParse(String),
 
     #[error("zipatch apply error: {0}")]
     ZiPatchApply(String),
 
+    #[error("zipatch write error: {0}")]
+    ZiPatchWrite(String),
+
     #[error("zipatch checksum mismatch at offset {offset}")]
     ZiPatchChecksumMismatch { offset: u64 },
 
@@ -121,6 +127,12 @@ pub enum Error {
     #[error("zipatch unknown chunk type: {0}")]
     ZiPatchUnknownChunk(String),
 
+    #[error("sqpack error: {0}")]
+    SqPack(String),
+
+    #[error("file not found in sqpack: {0}")]
+    SqPackFileNotFound(String),
+
     #[error("integrity manifest not found for version {0}")]
     IntegrityManifestNotFound(String),
 
@@ -133,6 +145,13 @@ pub enum Error {
     #[error("not enough disk space: need {needed} bytes, have {available} bytes")]
     NotEnoughDiskSpace { needed: u64, available: u64 },
 
+    #[error("not enough disk space for the update in {path}: need {needed} bytes, have {available} bytes")]
+    InsufficientDiskSpace {
+        path: PathBuf,
+        needed: u64,
+        available: u64,
+    },
+
     #[error("game path not configured")]
     GamePathNotConfigured,
 
@@ -141,6 +160,15 @@ pub enum Error {
 
     #[error("IPC communication error: {0}")]
     Ipc(String),
+
+    #[error("patcher process exited unexpectedly ({0})")]
+    PatcherExited(String),
+
+    #[error("patcher IPC protocol mismatch: this build speaks v{ours}, the other side v{theirs}; launcher and patcher must come from compatible builds")]
+    IpcProtocolMismatch { ours: u32, theirs: u32 },
+
+    #[error("unknown IPC message: {0}")]
+    IpcUnknownMessage(String),
 }
 
 impl From<String> for Error {
@@ -247,6 +275,23 @@ mod tests {
             .to_string(),
             "not enough disk space: need 1000 bytes, have 500 bytes"
         );
+        assert_eq!(
+            Error::InsufficientDiskSpace {
+                path: PathBuf::from("/games/ffxiv"),
+                needed: 1000,
+                available: 500
+            }
+            .to_string(),
+            "not enough disk space for the update in /games/ffxiv: need 1000 bytes, have 500 bytes"
+        );
+        assert_eq!(
+            Error::PatcherExited("signal: 9 (SIGKILL)".to_string()).to_string(),
+            "patcher process exited unexpectedly (signal: 9 (SIGKILL))"
+        );
+        assert_eq!(
+            Error::IpcProtocolMismatch { ours: 1, theirs: 2 }.to_string(),
+            "patcher IPC protocol mismatch: this build speaks v1, the other side v2; launcher and patcher must come from compatible builds"
+        );
         assert_eq!(
             Error::OauthLogin(OauthError::InvalidCredentials).to_string(),
             "OAuth login failed: invalid username or password"
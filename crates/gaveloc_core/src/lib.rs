@@ -1,8 +1,11 @@
 pub mod config;
+pub mod control;
 pub mod entities;
 pub mod error;
 pub mod launch_args;
 pub mod ports;
+pub mod report;
+pub mod sqpack;
 pub mod use_cases;
 pub mod zipatch;
 
@@ -2,14 +2,16 @@ use std::path::{Path, PathBuf};
 
 use async_trait::async_trait;
 
-use crate::config::{GameSettings, Region, Settings, WineSettings};
+use crate::config::{GameSettings, PatchSettings, Region, Settings, WineSettings};
+use crate::control::UpdateControl;
 use crate::entities::{
-    Account, AccountId, CachedSession, Credentials, FileIntegrityResult, GameVersion,
-    IntegrityManifest, IntegrityProgress, OauthLoginResult, PatchEntry, PatchProgress, Repository,
-    WineRunner,
+    Account, AccountId, CachedSession, Credentials, FileIntegrityResult, FileRepairResult,
+    FilesystemSpace, GameVersion, IntegrityCheckMode, IntegrityManifest, IntegrityProgress,
+    OauthLoginResult, PatchChain, PatchEntry, PatchVerificationReport, PatcherEvent, PatcherStatus,
+    Repository, SqPackFileDamage, SqPackRepairResult, WineRunner,
 };
 use crate::error::Error;
-use crate::zipatch::ZiPatchChunk;
+use crate::zipatch::{JournalState, PatchSpaceInfo, ZiPatchChunk};
 
 // ============================================================================
 // News Ports
@@ -76,16 +78,34 @@ pub trait PatchServer: Send + Sync {
     ) -> Result<(String, Vec<PatchEntry>), Error>;
 }
 
+/// Patches a repository was built from, used to rebuild damaged files
+#[async_trait]
+pub trait PatchHistory: Send + Sync {
+    /// Patches that take `repository` from an empty installation to `version`
+    ///
+    /// The chain starts with the repository's history patches, so replaying
+    /// it in order recreates every file the repository installs.
+    async fn patch_chain(
+        &self,
+        repository: Repository,
+        version: &GameVersion,
+    ) -> Result<PatchChain, Error>;
+}
+
 /// Patch downloading with progress reporting
 #[async_trait]
 pub trait PatchDownloader: Send + Sync {
     /// Download a patch file to the destination path
     /// Progress callback receives (bytes_downloaded, bytes_total)
+    ///
+    /// Pausing or cancelling through `control` stops between chunks and keeps
+    /// the partial download for a later resume.
     async fn download_patch<F>(
         &self,
         patch: &PatchEntry,
         dest_path: &Path,
         unique_id: Option<&str>,
+        control: &UpdateControl,
         progress: F,
     ) -> Result<(), Error>
     where
@@ -93,6 +113,17 @@ pub trait PatchDownloader: Send + Sync {
 
     /// Verify patch file integrity using block hashes
     async fn verify_patch(&self, patch: &PatchEntry, file_path: &Path) -> Result<bool, Error>;
+
+    /// Verify a patch file block by block, reporting which blocks failed
+    ///
+    /// Blocks past the end of a short file are reported as failed, so a later
+    /// download only needs to fetch the blocks listed in the report.
+    async fn verify_patch_blocks(
+        &self,
+        patch: &PatchEntry,
+        file_path: &Path,
+        control: &UpdateControl,
+    ) -> Result<PatchVerificationReport, Error>;
 }
 
 /// ZiPatch file parser and applier (synchronous - runs in blocking context)
@@ -100,55 +131,181 @@ pub trait ZiPatchApplier: Send + Sync {
     /// Apply a ZiPatch file to the game installation
     fn apply_patch(&self, patch_path: &Path, game_path: &Path) -> Result<(), Error>;
 
+    /// Apply a ZiPatch file, journaling every file region before it is overwritten
+    ///
+    /// If the journal holds an interrupted application of the same patch, its
+    /// uncommitted changes are undone and application resumes after the last
+    /// committed chunk. A journal of a completed application makes this a no-op.
+    ///
+    /// `control` is checked after each committed chunk. Cancelling fails with
+    /// `Error::Cancelled` and leaves the journal in place so the application
+    /// can be resumed rather than rolled back.
+    fn apply_patch_journaled(
+        &self,
+        patch_path: &Path,
+        game_path: &Path,
+        journal_path: &Path,
+        control: &UpdateControl,
+    ) -> Result<(), Error>;
+
+    /// Restore the installation to its pre-patch state and remove the journal
+    fn rollback_patch(&self, journal_path: &Path) -> Result<(), Error>;
+
+    /// Remove the journal of a patch that no longer needs rolling back, along
+    /// with the original files it kept
+    fn discard_journal(&self, journal_path: &Path) -> Result<(), Error>;
+
+    /// Read the state of a journal, `None` if there is no journal
+    fn journal_state(&self, journal_path: &Path) -> Result<Option<JournalState>, Error>;
+
     /// Parse a ZiPatch file and return its chunks (for debugging/verification)
     fn parse_patch(&self, patch_path: &Path) -> Result<Vec<ZiPatchChunk>, Error>;
+
+    /// Read the space figures declared in the header of a patch, and the
+    /// journal size its data chunks need
+    ///
+    /// Returns `None` if the patch has no PatchInfo command in its header.
+    fn read_space_info(&self, patch_path: &Path) -> Result<Option<PatchSpaceInfo>, Error>;
+}
+
+/// Free space queries used to check an update fits before it starts
+pub trait DiskSpaceProvider: Send + Sync {
+    /// Space on the filesystem holding `path`
+    ///
+    /// `path` does not have to exist yet; its nearest existing ancestor is used.
+    fn filesystem_space(&self, path: &Path) -> Result<FilesystemSpace, Error>;
+}
+
+/// A place integrity manifests are read from
+#[async_trait]
+pub trait ManifestSource: Send + Sync {
+    /// Where the manifests come from, for logs
+    fn describe(&self) -> String;
+
+    /// Raw manifest JSON for `game_version`
+    ///
+    /// Fails with `Error::IntegrityManifestNotFound` when the source has no
+    /// manifest for that version.
+    async fn fetch_manifest(&self, game_version: &str) -> Result<Vec<u8>, Error>;
 }
 
 /// Integrity checking against community manifest
 #[async_trait]
 pub trait IntegrityChecker: Send + Sync {
-    /// Fetch integrity manifest from goatcorp for the specified version
+    /// Fetch the integrity manifest for the specified version from the first
+    /// manifest source that has it
     async fn fetch_manifest(&self, game_version: &str) -> Result<IntegrityManifest, Error>;
 
     /// Run integrity check on game files
     /// Progress callback receives current progress info
+    ///
+    /// Incremental checks trust the hashes of files that are unchanged since a
+    /// previous check and were not written by a patch since.
     async fn check_integrity<F>(
         &self,
         game_path: &Path,
         manifest: &IntegrityManifest,
+        mode: IntegrityCheckMode,
         progress: F,
     ) -> Result<Vec<FileIntegrityResult>, Error>
     where
         F: Fn(IntegrityProgress) + Send + Sync + 'static;
 
-    /// Repair a corrupted or missing file by re-downloading
+    /// Hash a known-good installation into a manifest for `game_version`
+    ///
+    /// The manifest has the same format as fetched ones, so it can be
+    /// published and used to check other installations.
+    async fn generate_manifest<F>(
+        &self,
+        game_path: &Path,
+        game_version: &str,
+        progress: F,
+    ) -> Result<IntegrityManifest, Error>
+    where
+        F: Fn(IntegrityProgress) + Send + Sync + 'static;
+
+    /// Rebuild a corrupted or missing file from the patches that created it
+    ///
+    /// The rebuilt file must match `manifest` before it replaces the local one.
+    /// `control` pauses or cancels the patch downloads and replay.
     async fn repair_file(
         &self,
         game_path: &Path,
+        manifest: &IntegrityManifest,
         relative_path: &str,
-        expected_hash: &str,
-    ) -> Result<(), Error>;
+        history: &dyn PatchHistory,
+        control: &UpdateControl,
+    ) -> Result<FileRepairResult, Error>;
 
-    /// Repair multiple files in parallel (more efficient for batch operations)
-    /// Returns (success_count, failure_count)
+    /// Rebuild multiple files, replaying each repository's patch chain once
+    /// Returns one result per file, in the order given
     async fn repair_files(
         &self,
         game_path: &Path,
+        manifest: &IntegrityManifest,
         files: &[FileIntegrityResult],
-    ) -> Result<(u32, u32), Error>;
+        history: &dyn PatchHistory,
+        control: &UpdateControl,
+    ) -> Result<Vec<FileRepairResult>, Error>;
+
+    /// Extract every file listed in the SqPack indexes to find damaged ones
+    ///
+    /// Manifests only cover whole dat files; this finds the files inside them
+    /// that are broken, without needing a manifest.
+    async fn check_sqpack_files<F>(
+        &self,
+        game_path: &Path,
+        progress: F,
+    ) -> Result<Vec<SqPackFileDamage>, Error>
+    where
+        F: Fn(IntegrityProgress) + Send + Sync + 'static;
+
+    /// Rebuild damaged SqPack files from their repositories' patches
+    ///
+    /// Only the bytes of the damaged files are rewritten, the rest of their dat
+    /// files is left untouched. Returns one result per file, in the order given
+    async fn repair_sqpack_files(
+        &self,
+        game_path: &Path,
+        files: &[SqPackFileDamage],
+        history: &dyn PatchHistory,
+        control: &UpdateControl,
+    ) -> Result<Vec<SqPackRepairResult>, Error>;
 }
 
 /// IPC communication for the separate patcher process
+///
+/// The patcher runs queued jobs one at a time while it keeps answering
+/// requests, so a running job can be paused, resumed or cancelled.
 #[async_trait]
 pub trait PatcherIpc: Send + Sync {
-    /// Send a patch job to the patcher process
-    async fn start_patch(&self, patches: Vec<PatchEntry>, game_path: &Path)
-        -> Result<(), Error>;
+    /// Queue a patch job in the patcher process and return its ID
+    ///
+    /// The patcher downloads the patches into `patch_dir`, verifies and
+    /// applies them, and updates the version files of `game_path`. Events
+    /// about the job carry the returned ID.
+    async fn start_patch(
+        &self,
+        patches: Vec<PatchEntry>,
+        game_path: &Path,
+        patch_dir: &Path,
+        unique_id: Option<&str>,
+        settings: &PatchSettings,
+    ) -> Result<u64, Error>;
+
+    /// Receive the next event from the patcher, `None` if none is pending
+    async fn receive_event(&self) -> Result<Option<PatcherEvent>, Error>;
+
+    /// Pause the running job at its next safe point; queued jobs wait too
+    async fn pause(&self) -> Result<(), Error>;
+
+    /// Resume a paused patcher
+    async fn resume(&self) -> Result<(), Error>;
 
-    /// Receive progress updates from the patcher
-    async fn receive_progress(&self) -> Result<Option<PatchProgress>, Error>;
+    /// Ask the patcher what it is doing
+    async fn query_status(&self) -> Result<PatcherStatus, Error>;
 
-    /// Cancel the current patch operation
+    /// Cancel the running job and all queued jobs
     async fn cancel(&self) -> Result<(), Error>;
 
     /// Check if the patcher process is running
@@ -0,0 +1,564 @@
+//! Integrity reports
+//!
+//! Builds an [`IntegrityReport`] from the results of a check, renders it as
+//! JSON, CSV or a standalone HTML page, and compares two reports.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::entities::{
+    FileIntegrityChange, FileIntegrityResult, IntegrityCheckMode, IntegrityManifest,
+    IntegrityReport, IntegrityReportDiff, IntegrityStatus, Repository, RepositoryIntegritySummary,
+};
+use crate::error::Error;
+
+/// File format of a rendered report
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Json,
+    Csv,
+    Html,
+}
+
+impl ReportFormat {
+    /// Format matching the extension of `path`, if it has a known one
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.extension()?.to_str()?.parse().ok()
+    }
+}
+
+impl FromStr for ReportFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Ok(ReportFormat::Json),
+            "csv" => Ok(ReportFormat::Csv),
+            "html" | "htm" => Ok(ReportFormat::Html),
+            _ => Err(Error::Other(format!("unknown report format: {}", s))),
+        }
+    }
+}
+
+impl IntegrityReport {
+    /// Report on the results of checking an installation against `manifest`
+    pub fn new(
+        manifest: &IntegrityManifest,
+        mode: IntegrityCheckMode,
+        started_at: SystemTime,
+        duration: Duration,
+        mut files: Vec<FileIntegrityResult>,
+    ) -> Self {
+        files.sort_by(|a, b| a.relative_path.cmp(&b.relative_path));
+
+        let mut repositories: Vec<RepositoryIntegritySummary> = std::iter::once(Repository::Boot)
+            .chain(Repository::game_repos_up_to(5))
+            .map(|repository| RepositoryIntegritySummary {
+                repository,
+                total: 0,
+                valid: 0,
+                mismatch: 0,
+                missing: 0,
+                unreadable: 0,
+            })
+            .collect();
+        for file in &files {
+            let repository = Repository::for_game_file(&file.relative_path);
+            if let Some(summary) = repositories.iter_mut().find(|s| s.repository == repository) {
+                summary.total += 1;
+                match file.status {
+                    IntegrityStatus::Valid => summary.valid += 1,
+                    IntegrityStatus::Mismatch => summary.mismatch += 1,
+                    IntegrityStatus::Missing => summary.missing += 1,
+                    IntegrityStatus::Unreadable => summary.unreadable += 1,
+                }
+            }
+        }
+        repositories.retain(|s| s.total > 0);
+
+        Self {
+            game_version: manifest.game_version.clone(),
+            manifest_source: manifest.source.clone(),
+            mode,
+            started_at: started_at
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs() as i64)
+                .unwrap_or(0),
+            duration_ms: duration.as_millis() as u64,
+            repositories,
+            files,
+            sqpack_files: Vec::new(),
+        }
+    }
+
+    /// Files that are not valid
+    pub fn problems(&self) -> impl Iterator<Item = &FileIntegrityResult> {
+        self.files
+            .iter()
+            .filter(|f| f.status != IntegrityStatus::Valid)
+    }
+
+    /// Render the report in `format`
+    pub fn render(&self, format: ReportFormat) -> Result<String, Error> {
+        match format {
+            ReportFormat::Json => self.to_json(),
+            ReportFormat::Csv => Ok(self.to_csv()),
+            ReportFormat::Html => Ok(self.to_html()),
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String, Error> {
+        serde_json::to_string_pretty(self)
+            .map_err(|e| Error::Other(format!("failed to serialize integrity report: {}", e)))
+    }
+
+    /// Read back a report rendered as JSON
+    pub fn from_json(data: &str) -> Result<Self, Error> {
+        serde_json::from_str(data)
+            .map_err(|e| Error::Other(format!("failed to parse integrity report: {}", e)))
+    }
+
+    /// One line per file
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("path,repository,status,expected_hash,actual_hash\n");
+        for file in &self.files {
+            let fields = [
+                file.relative_path.clone(),
+                Repository::for_game_file(&file.relative_path).to_string(),
+                file.status.to_string(),
+                file.expected_hash.clone(),
+                file.actual_hash.clone().unwrap_or_default(),
+            ];
+            let line: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+            csv.push_str(&line.join(","));
+            csv.push('\n');
+        }
+        csv
+    }
+
+    /// A single page without external resources, listing the problems first
+    pub fn to_html(&self) -> String {
+        let mut html = String::new();
+        let title = format!("Integrity report for {}", escape_html(&self.game_version));
+
+        let _ = write!(
+            html,
+            "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+             <title>{title}</title>\n<style>{HTML_STYLE}</style>\n</head>\n<body>\n\
+             <h1>{title}</h1>\n<table class=\"meta\">\n"
+        );
+        let meta = [
+            (
+                "Manifest source",
+                self.manifest_source
+                    .as_deref()
+                    .unwrap_or("unknown")
+                    .to_string(),
+            ),
+            (
+                "Mode",
+                match self.mode {
+                    IntegrityCheckMode::Incremental => "Incremental".to_string(),
+                    IntegrityCheckMode::Full => "Full".to_string(),
+                },
+            ),
+            ("Started", format_timestamp(self.started_at)),
+            (
+                "Duration",
+                format!("{:.1} s", self.duration_ms as f64 / 1000.0),
+            ),
+        ];
+        for (name, value) in meta {
+            let _ = writeln!(
+                html,
+                "<tr><th>{}</th><td>{}</td></tr>",
+                name,
+                escape_html(&value)
+            );
+        }
+        html.push_str("</table>\n");
+
+        html.push_str(
+            "<h2>Repositories</h2>\n<table>\n<tr><th>Repository</th><th>Files</th>\
+             <th>Valid</th><th>Mismatch</th><th>Missing</th><th>Unreadable</th></tr>\n",
+        );
+        for s in &self.repositories {
+            let _ = writeln!(
+                html,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                s.repository, s.total, s.valid, s.mismatch, s.missing, s.unreadable
+            );
+        }
+        html.push_str("</table>\n");
+
+        let problems: Vec<_> = self.problems().collect();
+        let _ = writeln!(html, "<h2>Problems ({})</h2>", problems.len());
+        if problems.is_empty() {
+            html.push_str("<p>All files are valid.</p>\n");
+        } else {
+            html_file_table(&mut html, problems);
+        }
+
+        if !self.sqpack_files.is_empty() {
+            let _ = writeln!(
+                html,
+                "<h2>Damaged files in SqPack archives ({})</h2>",
+                self.sqpack_files.len()
+            );
+            html.push_str(
+                "<table>\n<tr><th>Dat file</th><th>Offset</th><th>Hash</th>\
+                 <th>Error</th></tr>\n",
+            );
+            for file in &self.sqpack_files {
+                let _ = writeln!(
+                    html,
+                    "<tr class=\"Mismatch\"><td>{}</td><td class=\"hash\">{:#x}</td>\
+                     <td class=\"hash\">{}</td><td class=\"status\">{}</td></tr>",
+                    escape_html(&file.dat_path),
+                    file.offset,
+                    escape_html(&file.hash),
+                    escape_html(&file.error),
+                );
+            }
+            html.push_str("</table>\n");
+        }
+
+        let _ = writeln!(
+            html,
+            "<details>\n<summary>All files ({})</summary>",
+            self.files.len()
+        );
+        html_file_table(&mut html, self.files.iter());
+        html.push_str("</details>\n</body>\n</html>\n");
+        html
+    }
+
+    /// Files whose results differ in `newer`, a later report
+    pub fn diff(&self, newer: &IntegrityReport) -> IntegrityReportDiff {
+        let mut files: BTreeMap<
+            &str,
+            (Option<&FileIntegrityResult>, Option<&FileIntegrityResult>),
+        > = BTreeMap::new();
+        for file in &self.files {
+            files.entry(&file.relative_path).or_default().0 = Some(file);
+        }
+        for file in &newer.files {
+            files.entry(&file.relative_path).or_default().1 = Some(file);
+        }
+
+        let changes = files
+            .into_iter()
+            .filter(|(_, (before, after))| before != after)
+            .map(|(path, (before, after))| FileIntegrityChange {
+                relative_path: path.to_string(),
+                before: before.cloned(),
+                after: after.cloned(),
+            })
+            .collect();
+
+        IntegrityReportDiff {
+            old_game_version: self.game_version.clone(),
+            new_game_version: newer.game_version.clone(),
+            changes,
+        }
+    }
+}
+
+impl IntegrityReportDiff {
+    /// Files damaged in the newer report that were not before
+    pub fn regressions(&self) -> impl Iterator<Item = &FileIntegrityChange> {
+        self.changes.iter().filter(|c| c.is_regression())
+    }
+}
+
+const HTML_STYLE: &str = "body{font-family:sans-serif;margin:2em;color:#222}\
+table{border-collapse:collapse;margin-bottom:1.5em}\
+th,td{border:1px solid #ccc;padding:.3em .6em;text-align:left}\
+th{background:#f0f0f0}td.hash{font-family:monospace}\
+tr.Mismatch td.status,tr.Missing td.status{color:#b00020;font-weight:bold}\
+tr.Unreadable td.status{color:#b26a00;font-weight:bold}\
+tr.Valid td.status{color:#1b5e20}";
+
+fn html_file_table<'a>(
+    html: &mut String,
+    files: impl IntoIterator<Item = &'a FileIntegrityResult>,
+) {
+    html.push_str(
+        "<table>\n<tr><th>Path</th><th>Status</th><th>Expected</th><th>Actual</th></tr>\n",
+    );
+    for file in files {
+        let _ = writeln!(
+            html,
+            "<tr class=\"{status}\"><td>{}</td><td class=\"status\">{status}</td>\
+             <td class=\"hash\">{}</td><td class=\"hash\">{}</td></tr>",
+            escape_html(&file.relative_path),
+            escape_html(&file.expected_hash),
+            escape_html(file.actual_hash.as_deref().unwrap_or("")),
+            status = file.status,
+        );
+    }
+    html.push_str("</table>\n");
+}
+
+fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Quote a CSV field if it contains a separator, quote or line break
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+/// Format a Unix timestamp as `YYYY-MM-DD HH:MM:SS UTC`
+fn format_timestamp(timestamp: i64) -> String {
+    let days = timestamp.div_euclid(86400);
+    let secs = timestamp.rem_euclid(86400);
+
+    // Civil date from days since the epoch (proleptic Gregorian calendar)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entities::SqPackFileDamage;
+    use std::collections::HashMap;
+
+    fn result(path: &str, status: IntegrityStatus, actual: Option<&str>) -> FileIntegrityResult {
+        FileIntegrityResult {
+            relative_path: path.to_string(),
+            expected_hash: "aa".to_string(),
+            actual_hash: actual.map(str::to_string),
+            status,
+        }
+    }
+
+    fn report(files: Vec<FileIntegrityResult>) -> IntegrityReport {
+        let manifest = IntegrityManifest {
+            hashes: HashMap::new(),
+            game_version: "2024.07.23.0000.0001".to_string(),
+            last_game_version: None,
+            source: Some("https://example.com/integrity".to_string()),
+        };
+        IntegrityReport::new(
+            &manifest,
+            IntegrityCheckMode::Full,
+            UNIX_EPOCH + Duration::from_secs(1_721_736_000),
+            Duration::from_millis(1500),
+            files,
+        )
+    }
+
+    #[test]
+    fn test_new_summarizes_by_repository() {
+        let report = report(vec![
+            result(
+                r"\game\sqpack\ex1\020000.win32.dat0",
+                IntegrityStatus::Missing,
+                None,
+            ),
+            result(r"\game\ffxiv_dx11.exe", IntegrityStatus::Valid, Some("aa")),
+            result(
+                r"\boot\ffxivboot.exe",
+                IntegrityStatus::Mismatch,
+                Some("bb"),
+            ),
+            result(r"\game\ffxivgame.ver", IntegrityStatus::Unreadable, None),
+        ]);
+
+        assert_eq!(report.started_at, 1_721_736_000);
+        assert_eq!(report.duration_ms, 1500);
+        assert_eq!(
+            report.manifest_source.as_deref(),
+            Some("https://example.com/integrity")
+        );
+        assert_eq!(report.files[0].relative_path, r"\boot\ffxivboot.exe");
+
+        let repositories: Vec<_> = report
+            .repositories
+            .iter()
+            .map(|s| (s.repository, s.total, s.problems()))
+            .collect();
+        assert_eq!(
+            repositories,
+            vec![
+                (Repository::Boot, 1, 1),
+                (Repository::Ffxiv, 2, 1),
+                (Repository::Ex1, 1, 1)
+            ]
+        );
+        assert_eq!(report.problems().count(), 3);
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let report = report(vec![result(
+            r"\game\ffxiv_dx11.exe",
+            IntegrityStatus::Valid,
+            Some("aa"),
+        )]);
+        let json = report.render(ReportFormat::Json).unwrap();
+        assert!(!json.contains("sqpack_files"));
+        assert_eq!(IntegrityReport::from_json(&json).unwrap(), report);
+    }
+
+    #[test]
+    fn test_report_lists_damaged_sqpack_files() {
+        let mut report = report(Vec::new());
+        report.sqpack_files.push(SqPackFileDamage {
+            index_path: r"\game\sqpack\ffxiv\0a0000.win32.index".to_string(),
+            dat_path: r"\game\sqpack\ffxiv\0a0000.win32.dat0".to_string(),
+            hash: "e39b7999/a39e7bbf".to_string(),
+            offset: 0x1280,
+            size: 0x200,
+            error: "block at 0x1300 decompressed to 3 bytes, expected 4".to_string(),
+        });
+
+        let html = report.to_html();
+        assert!(html.contains("<h2>Damaged files in SqPack archives (1)</h2>"));
+        assert!(html.contains("<td class=\"hash\">0x1280</td>"));
+
+        let json = report.to_json().unwrap();
+        assert_eq!(IntegrityReport::from_json(&json).unwrap(), report);
+    }
+
+    #[test]
+    fn test_csv_quotes_fields() {
+        let report = report(vec![
+            result(r"\game\a,b.dat", IntegrityStatus::Missing, None),
+            result(r"\game\ffxiv_dx11.exe", IntegrityStatus::Valid, Some("aa")),
+        ]);
+
+        assert_eq!(
+            report.to_csv(),
+            "path,repository,status,expected_hash,actual_hash\n\
+             \"\\game\\a,b.dat\",FFXIV,Missing,aa,\n\
+             \\game\\ffxiv_dx11.exe,FFXIV,Valid,aa,aa\n"
+        );
+    }
+
+    #[test]
+    fn test_html_is_escaped_and_lists_problems() {
+        let report = report(vec![
+            result(r"\game\<script>.dat", IntegrityStatus::Mismatch, Some("bb")),
+            result(r"\game\ffxiv_dx11.exe", IntegrityStatus::Valid, Some("aa")),
+        ]);
+        let html = report.to_html();
+
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("Integrity report for 2024.07.23.0000.0001"));
+        assert!(html.contains("2024-07-23 12:00:00 UTC"));
+        assert!(html.contains("<h2>Problems (1)</h2>"));
+        assert!(html.contains(r"\game\&lt;script&gt;.dat"));
+        assert!(!html.contains("<script>"));
+    }
+
+    #[test]
+    fn test_diff_reports_changed_files() {
+        let before = report(vec![
+            result(r"\game\a.dat", IntegrityStatus::Valid, Some("aa")),
+            result(r"\game\b.dat", IntegrityStatus::Mismatch, Some("bb")),
+            result(r"\game\c.dat", IntegrityStatus::Valid, Some("aa")),
+            result(r"\game\removed.dat", IntegrityStatus::Valid, Some("aa")),
+        ]);
+        let after = report(vec![
+            result(r"\game\a.dat", IntegrityStatus::Missing, None),
+            result(r"\game\b.dat", IntegrityStatus::Valid, Some("aa")),
+            result(r"\game\c.dat", IntegrityStatus::Valid, Some("aa")),
+            result(r"\game\new.dat", IntegrityStatus::Mismatch, Some("cc")),
+        ]);
+
+        let diff = before.diff(&after);
+        let changes: Vec<_> = diff
+            .changes
+            .iter()
+            .map(|c| {
+                (
+                    c.relative_path.as_str(),
+                    c.status_before(),
+                    c.status_after(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                (
+                    r"\game\a.dat",
+                    Some(IntegrityStatus::Valid),
+                    Some(IntegrityStatus::Missing)
+                ),
+                (
+                    r"\game\b.dat",
+                    Some(IntegrityStatus::Mismatch),
+                    Some(IntegrityStatus::Valid)
+                ),
+                (r"\game\new.dat", None, Some(IntegrityStatus::Mismatch)),
+                (r"\game\removed.dat", Some(IntegrityStatus::Valid), None),
+            ]
+        );
+
+        let regressions: Vec<_> = diff
+            .regressions()
+            .map(|c| c.relative_path.as_str())
+            .collect();
+        assert_eq!(regressions, vec![r"\game\a.dat", r"\game\new.dat"]);
+        assert!(before.diff(&before).changes.is_empty());
+    }
+
+    #[test]
+    fn test_report_format_from_path() {
+        assert_eq!(
+            ReportFormat::from_path(Path::new("report.HTML")),
+            Some(ReportFormat::Html)
+        );
+        assert_eq!(
+            ReportFormat::from_path(Path::new("report.csv")),
+            Some(ReportFormat::Csv)
+        );
+        assert_eq!(ReportFormat::from_path(Path::new("report")), None);
+        assert!("xml".parse::<ReportFormat>().is_err());
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_timestamp(951_827_696), "2000-02-29 12:34:56 UTC");
+    }
+}
@@ -0,0 +1,1046 @@
+//! Read-only access to SqPack archives
+//!
+//! Game data lives in SqPack archives under `game/sqpack/<expansion>/`. An
+//! archive is a set of files sharing a name such as `040000.win32`:
+//! - `.index` maps the CRC of a file's folder and name to its location
+//! - `.index2` maps the CRC of its full path to the same location
+//! - `.dat0`, `.dat1`, ... hold the files, split into blocks that are
+//!   DEFLATE-compressed unless compression does not pay off
+//!
+//! Each of these starts with the same 1024-byte SqPack header. Archive names
+//! are `{category:02x}{expansion:02x}{chunk:02x}`: the category comes from the
+//! first folder of a game path (`chara/...`), the expansion from the second
+//! (`bg/ex1/...`), and large categories are split into several chunks.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use flate2::read::DeflateDecoder;
+
+use crate::error::Error;
+use crate::zipatch::{expansion_folder, IndexType, Platform, SQPACK_HEADER_SIZE};
+
+/// Magic at the start of every SqPack file
+const SQPACK_MAGIC: &[u8; 8] = b"SqPack\0\0";
+
+/// Compressed size marking a block stored without compression
+const UNCOMPRESSED_BLOCK: u32 = 32000;
+
+/// Upper bound for the size of a single block, to reject corrupt headers
+const MAX_BLOCK_SIZE: u32 = 64 * 1024;
+
+/// Size of the header put in front of an extracted model
+const MODEL_HEADER_SIZE: usize = 0x44;
+
+/// Category IDs by the first folder of a game path
+const CATEGORIES: [(&str, u8); 15] = [
+    ("common", 0x00),
+    ("bgcommon", 0x01),
+    ("bg", 0x02),
+    ("cut", 0x03),
+    ("chara", 0x04),
+    ("shader", 0x05),
+    ("ui", 0x06),
+    ("sound", 0x07),
+    ("vfx", 0x08),
+    ("ui_script", 0x09),
+    ("exd", 0x0a),
+    ("game_script", 0x0b),
+    ("music", 0x0c),
+    ("sqpack_test", 0x12),
+    ("debug", 0x13),
+];
+
+// =============================================================================
+// Headers
+// =============================================================================
+
+/// What a SqPack file contains
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SqPackKind {
+    Database,
+    Data,
+    Index,
+    Unknown(u32),
+}
+
+/// The header every SqPack file starts with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SqPackHeader {
+    pub platform: Platform,
+    pub kind: SqPackKind,
+    pub version: u32,
+}
+
+impl SqPackHeader {
+    /// Parse the header at the start of `data`
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        if data.len() < 0x18 || &data[..8] != SQPACK_MAGIC {
+            return Err(Error::SqPack("not a SqPack file".to_string()));
+        }
+
+        let platform = match data[8] {
+            0 => Platform::Win32,
+            1 => Platform::Ps3,
+            2 => Platform::Ps4,
+            other => {
+                return Err(Error::SqPack(format!("unknown platform {}", other)));
+            }
+        };
+        let kind = match le_u32(data, 0x14) {
+            0 => SqPackKind::Database,
+            1 => SqPackKind::Data,
+            2 => SqPackKind::Index,
+            other => SqPackKind::Unknown(other),
+        };
+
+        Ok(Self {
+            platform,
+            kind,
+            version: le_u32(data, 0x10),
+        })
+    }
+
+    fn read(reader: &mut impl Read) -> Result<Self, Error> {
+        let mut header = [0u8; SQPACK_HEADER_SIZE as usize];
+        reader.read_exact(&mut header)?;
+        Self::parse(&header)
+    }
+}
+
+// =============================================================================
+// Index files
+// =============================================================================
+
+/// CRC used for path hashes: CRC-32 without the final inversion
+fn path_crc(s: &str) -> u32 {
+    !crc32fast::hash(s.as_bytes())
+}
+
+/// Normalize a game path as it is hashed: lowercase, `/` separated
+fn normalize_game_path(path: &str) -> String {
+    path.trim_start_matches(['/', '\\'])
+        .replace('\\', "/")
+        .to_ascii_lowercase()
+}
+
+/// Hash of a game path in `.index` files: the folder CRC in the high and the
+/// file name CRC in the low 32 bits
+pub fn index_hash(path: &str) -> u64 {
+    let path = normalize_game_path(path);
+    let (folder, file) = path.rsplit_once('/').unwrap_or(("", &path));
+    (u64::from(path_crc(folder)) << 32) | u64::from(path_crc(file))
+}
+
+/// Hash of a game path in `.index2` files: the CRC of the full path
+pub fn index2_hash(path: &str) -> u32 {
+    path_crc(&normalize_game_path(path))
+}
+
+/// Location of a file in the dat files of an archive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndexEntry {
+    /// Path hash, see [`index_hash`] and [`index2_hash`]
+    pub hash: u64,
+    /// Which `.dat` file holds the file
+    pub data_file_id: u8,
+    /// Offset of the file's entry header in the dat file
+    pub offset: u64,
+    /// Several paths share this hash; the entry then points at the index's
+    /// synonym table instead of a file
+    pub is_synonym: bool,
+}
+
+impl IndexEntry {
+    fn new(hash: u64, data: u32) -> Self {
+        Self {
+            hash,
+            data_file_id: ((data >> 1) & 0b111) as u8,
+            offset: u64::from(data & !0xF) * 8,
+            is_synonym: data & 1 != 0,
+        }
+    }
+}
+
+/// The hash table of a `.index` or `.index2` file
+#[derive(Debug, Clone)]
+pub struct SqPackIndex {
+    index_type: IndexType,
+    /// Sorted by hash
+    entries: Vec<IndexEntry>,
+}
+
+impl SqPackIndex {
+    /// Read an index file, its type given by the extension
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let index_type = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("index") => IndexType::Index,
+            Some("index2") => IndexType::Index2,
+            _ => {
+                return Err(Error::SqPack(format!("{:?} is not an index file", path)));
+            }
+        };
+        Self::parse(&std::fs::read(path)?, index_type)
+    }
+
+    /// Parse the contents of an index file
+    pub fn parse(data: &[u8], index_type: IndexType) -> Result<Self, Error> {
+        let header = SqPackHeader::parse(data)?;
+        if header.kind != SqPackKind::Index {
+            return Err(Error::SqPack(format!(
+                "expected an index file, found {:?}",
+                header.kind
+            )));
+        }
+
+        // The index header follows the SqPack header
+        let index_header = SQPACK_HEADER_SIZE as usize;
+        if data.len() < index_header + 0x10 {
+            return Err(Error::SqPack("index header truncated".to_string()));
+        }
+        let table_offset = le_u32(data, index_header + 0x08) as usize;
+        let table_size = le_u32(data, index_header + 0x0C) as usize;
+        let entry_size = match index_type {
+            IndexType::Index => 16,
+            IndexType::Index2 => 8,
+        };
+        let table = table_offset
+            .checked_add(table_size)
+            .filter(|&end| end <= data.len() && table_size.is_multiple_of(entry_size))
+            .map(|end| &data[table_offset..end])
+            .ok_or_else(|| {
+                Error::SqPack(format!(
+                    "invalid hash table at {:#x} ({} bytes)",
+                    table_offset, table_size
+                ))
+            })?;
+
+        let mut entries: Vec<IndexEntry> = table
+            .chunks_exact(entry_size)
+            .map(|entry| match index_type {
+                IndexType::Index => IndexEntry::new(
+                    u64::from_le_bytes(entry[0..8].try_into().unwrap()),
+                    le_u32(entry, 8),
+                ),
+                IndexType::Index2 => IndexEntry::new(u64::from(le_u32(entry, 0)), le_u32(entry, 4)),
+            })
+            .collect();
+        entries.sort_by_key(|entry| entry.hash);
+
+        Ok(Self {
+            index_type,
+            entries,
+        })
+    }
+
+    pub fn index_type(&self) -> IndexType {
+        self.index_type
+    }
+
+    /// Every entry, sorted by hash
+    pub fn entries(&self) -> &[IndexEntry] {
+        &self.entries
+    }
+
+    /// Entry with the given hash
+    pub fn get(&self, hash: u64) -> Option<&IndexEntry> {
+        self.entries
+            .binary_search_by_key(&hash, |entry| entry.hash)
+            .ok()
+            .map(|i| &self.entries[i])
+    }
+
+    /// Entry of a game path such as `exd/root.exl`
+    pub fn find(&self, path: &str) -> Option<&IndexEntry> {
+        let hash = match self.index_type {
+            IndexType::Index => index_hash(path),
+            IndexType::Index2 => u64::from(index2_hash(path)),
+        };
+        self.get(hash)
+    }
+}
+
+// =============================================================================
+// Dat files
+// =============================================================================
+
+/// How a file is stored in a dat file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SqPackFileType {
+    /// Placeholder for a deleted file
+    Empty,
+    /// Any file that is not a model or texture
+    Standard,
+    /// `.mdl` file, stored as separate sections
+    Model,
+    /// `.tex` file, stored as mipmap levels
+    Texture,
+    Unknown(u32),
+}
+
+impl From<u32> for SqPackFileType {
+    fn from(value: u32) -> Self {
+        match value {
+            1 => SqPackFileType::Empty,
+            2 => SqPackFileType::Standard,
+            3 => SqPackFileType::Model,
+            4 => SqPackFileType::Texture,
+            other => SqPackFileType::Unknown(other),
+        }
+    }
+}
+
+/// Header of a file stored in a dat file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DatEntryHeader {
+    /// Size of the header; the file's blocks follow it
+    pub header_size: u32,
+    pub file_type: SqPackFileType,
+    /// Size of the extracted file
+    pub raw_file_size: u32,
+}
+
+/// A `.dat` file of an archive
+pub struct DatFile<R> {
+    reader: R,
+    header: SqPackHeader,
+}
+
+impl DatFile<BufReader<File>> {
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read + Seek> DatFile<R> {
+    pub fn new(mut reader: R) -> Result<Self, Error> {
+        reader.seek(SeekFrom::Start(0))?;
+        let header = SqPackHeader::read(&mut reader)?;
+        if header.kind != SqPackKind::Data {
+            return Err(Error::SqPack(format!(
+                "expected a dat file, found {:?}",
+                header.kind
+            )));
+        }
+
+        Ok(Self { reader, header })
+    }
+
+    pub fn header(&self) -> &SqPackHeader {
+        &self.header
+    }
+
+    /// Header of the file stored at `offset`
+    pub fn entry_header(&mut self, offset: u64) -> Result<DatEntryHeader, Error> {
+        self.reader.seek(SeekFrom::Start(offset))?;
+        Ok(DatEntryHeader {
+            header_size: self.reader.read_u32::<LittleEndian>()?,
+            file_type: self.reader.read_u32::<LittleEndian>()?.into(),
+            raw_file_size: self.reader.read_u32::<LittleEndian>()?,
+        })
+    }
+
+    /// Extract the file stored at `offset`
+    pub fn read_file(&mut self, offset: u64) -> Result<Vec<u8>, Error> {
+        let entry = self.entry_header(offset)?;
+        match entry.file_type {
+            SqPackFileType::Empty => Ok(Vec::new()),
+            SqPackFileType::Standard => self.read_standard(offset, &entry),
+            SqPackFileType::Texture => self.read_texture(offset, &entry),
+            SqPackFileType::Model => self.read_model(offset, &entry),
+            SqPackFileType::Unknown(kind) => Err(Error::SqPack(format!(
+                "unknown file type {} at {:#x}",
+                kind, offset
+            ))),
+        }
+    }
+
+    /// Standard files are a plain sequence of blocks
+    fn read_standard(&mut self, offset: u64, entry: &DatEntryHeader) -> Result<Vec<u8>, Error> {
+        self.reader.seek(SeekFrom::Start(offset + 0x14))?;
+        let block_count = self.reader.read_u32::<LittleEndian>()?;
+
+        let mut block_offsets = Vec::new();
+        for _ in 0..block_count {
+            block_offsets.push(self.reader.read_u32::<LittleEndian>()?);
+            // Compressed and uncompressed sizes, repeated in each block header
+            self.reader.read_u32::<LittleEndian>()?;
+        }
+
+        let data_offset = offset + u64::from(entry.header_size);
+        let mut data = Vec::with_capacity(initial_capacity(entry.raw_file_size));
+        for block_offset in block_offsets {
+            self.read_block(data_offset + u64::from(block_offset), &mut data)?;
+        }
+
+        if data.len() != entry.raw_file_size as usize {
+            return Err(Error::SqPack(format!(
+                "file at {:#x} extracted to {} bytes, expected {}",
+                offset,
+                data.len(),
+                entry.raw_file_size
+            )));
+        }
+        Ok(data)
+    }
+
+    /// Textures are their header, stored as is, followed by the blocks of
+    /// each mipmap level
+    fn read_texture(&mut self, offset: u64, entry: &DatEntryHeader) -> Result<Vec<u8>, Error> {
+        self.reader.seek(SeekFrom::Start(offset + 0x14))?;
+        let lod_count = self.reader.read_u32::<LittleEndian>()?;
+
+        // (offset of the first block, number of blocks)
+        let mut lods = Vec::new();
+        for _ in 0..lod_count {
+            let compressed_offset = self.reader.read_u32::<LittleEndian>()?;
+            // Compressed size, decompressed size, index of the first block
+            for _ in 0..3 {
+                self.reader.read_u32::<LittleEndian>()?;
+            }
+            let block_count = self.reader.read_u32::<LittleEndian>()?;
+            lods.push((compressed_offset, block_count));
+        }
+
+        // Distance from each block to the next, in order across all levels
+        let total_blocks: u32 = lods.iter().map(|&(_, count)| count).sum();
+        let mut block_sizes = Vec::new();
+        for _ in 0..total_blocks {
+            block_sizes.push(self.reader.read_u16::<LittleEndian>()?);
+        }
+
+        let data_offset = offset + u64::from(entry.header_size);
+        let texture_header_size = lods.first().map_or(0, |&(first, _)| first);
+        if texture_header_size > entry.raw_file_size {
+            return Err(Error::SqPack(format!(
+                "texture at {:#x} has a {} byte header but only {} bytes",
+                offset, texture_header_size, entry.raw_file_size
+            )));
+        }
+
+        let mut data = vec![0u8; texture_header_size as usize];
+        self.reader.seek(SeekFrom::Start(data_offset))?;
+        self.reader.read_exact(&mut data)?;
+
+        let mut block_sizes = block_sizes.into_iter();
+        for (compressed_offset, block_count) in lods {
+            let mut position = data_offset + u64::from(compressed_offset);
+            for _ in 0..block_count {
+                self.read_block(position, &mut data)?;
+                position += u64::from(block_sizes.next().unwrap_or_default());
+            }
+        }
+
+        Ok(data)
+    }
+
+    /// Models are stored as separate sections; the extracted file gets a
+    /// header with the size and position of each
+    fn read_model(&mut self, offset: u64, entry: &DatEntryHeader) -> Result<Vec<u8>, Error> {
+        // Sections: stack, runtime, then vertex buffers, edge geometry and
+        // index buffers for each of the three levels of detail
+        const SECTIONS: usize = 11;
+
+        self.reader.seek(SeekFrom::Start(offset + 0x0C))?;
+        let block_count = self.reader.read_u32::<LittleEndian>()?;
+        // Number of blocks in use
+        self.reader.read_u32::<LittleEndian>()?;
+        let version = self.reader.read_u32::<LittleEndian>()?;
+
+        // Uncompressed and compressed section sizes
+        for _ in 0..SECTIONS * 2 {
+            self.reader.read_u32::<LittleEndian>()?;
+        }
+        let mut section_offsets = [0u32; SECTIONS];
+        for section_offset in &mut section_offsets {
+            *section_offset = self.reader.read_u32::<LittleEndian>()?;
+        }
+        let mut first_blocks = [0u16; SECTIONS];
+        for first_block in &mut first_blocks {
+            *first_block = self.reader.read_u16::<LittleEndian>()?;
+        }
+        let mut section_blocks = [0u16; SECTIONS];
+        for blocks in &mut section_blocks {
+            *blocks = self.reader.read_u16::<LittleEndian>()?;
+        }
+        let mesh_count = self.reader.read_u16::<LittleEndian>()?;
+        let material_count = self.reader.read_u16::<LittleEndian>()?;
+        let lod_count = self.reader.read_u8()?;
+        let index_buffer_streaming = self.reader.read_u8()?;
+        let edge_geometry = self.reader.read_u8()?;
+        self.reader.read_u8()?;
+
+        let mut block_sizes = Vec::new();
+        for _ in 0..block_count {
+            block_sizes.push(self.reader.read_u16::<LittleEndian>()?);
+        }
+
+        let data_offset = offset + u64::from(entry.header_size);
+        let mut data = vec![0u8; MODEL_HEADER_SIZE];
+        // Position and size of each section in the extracted file
+        let read_section = |this: &mut Self, section: usize, data: &mut Vec<u8>| {
+            let start = data.len();
+            let first = usize::from(first_blocks[section]);
+            let count = usize::from(section_blocks[section]);
+            let sizes = block_sizes.get(first..first + count).ok_or_else(|| {
+                Error::SqPack(format!(
+                    "model at {:#x} has {} blocks, section {} needs {}",
+                    offset,
+                    block_count,
+                    section,
+                    first + count
+                ))
+            })?;
+
+            let mut position = data_offset + u64::from(section_offsets[section]);
+            for &size in sizes {
+                this.read_block(position, data)?;
+                position += u64::from(size);
+            }
+            Ok::<_, Error>((start as u32, (data.len() - start) as u32, count > 0))
+        };
+
+        let (_, stack_size, _) = read_section(self, 0, &mut data)?;
+        let (_, runtime_size, _) = read_section(self, 1, &mut data)?;
+        let mut vertex_offsets = [0u32; 3];
+        let mut vertex_sizes = [0u32; 3];
+        let mut index_offsets = [0u32; 3];
+        let mut index_sizes = [0u32; 3];
+        for lod in 0..3 {
+            let (start, size, present) = read_section(self, 2 + lod, &mut data)?;
+            if present {
+                vertex_offsets[lod] = start;
+                vertex_sizes[lod] = size;
+            }
+            read_section(self, 5 + lod, &mut data)?;
+            let (start, size, present) = read_section(self, 8 + lod, &mut data)?;
+            if present {
+                index_offsets[lod] = start;
+                index_sizes[lod] = size;
+            }
+        }
+
+        let mut header = Vec::with_capacity(MODEL_HEADER_SIZE);
+        header.extend_from_slice(&version.to_le_bytes());
+        header.extend_from_slice(&stack_size.to_le_bytes());
+        header.extend_from_slice(&runtime_size.to_le_bytes());
+        header.extend_from_slice(&mesh_count.to_le_bytes());
+        header.extend_from_slice(&material_count.to_le_bytes());
+        for values in [vertex_offsets, index_offsets, vertex_sizes, index_sizes] {
+            for value in values {
+                header.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        header.extend_from_slice(&[lod_count, index_buffer_streaming, edge_geometry, 0]);
+        data[..MODEL_HEADER_SIZE].copy_from_slice(&header);
+
+        Ok(data)
+    }
+
+    /// Append the contents of the block at `offset` to `data`
+    fn read_block(&mut self, offset: u64, data: &mut Vec<u8>) -> Result<(), Error> {
+        self.reader.seek(SeekFrom::Start(offset))?;
+        let header_size = self.reader.read_u32::<LittleEndian>()?;
+        self.reader.read_u32::<LittleEndian>()?;
+        let compressed_size = self.reader.read_u32::<LittleEndian>()?;
+        let uncompressed_size = self.reader.read_u32::<LittleEndian>()?;
+
+        if uncompressed_size > MAX_BLOCK_SIZE
+            || (compressed_size != UNCOMPRESSED_BLOCK && compressed_size > MAX_BLOCK_SIZE)
+        {
+            return Err(Error::SqPack(format!(
+                "invalid block at {:#x} ({} bytes, {} compressed)",
+                offset, uncompressed_size, compressed_size
+            )));
+        }
+
+        self.reader
+            .seek(SeekFrom::Start(offset + u64::from(header_size)))?;
+        let start = data.len();
+        if compressed_size == UNCOMPRESSED_BLOCK {
+            data.resize(start + uncompressed_size as usize, 0);
+            self.reader.read_exact(&mut data[start..])?;
+        } else {
+            let compressed = (&mut self.reader).take(u64::from(compressed_size));
+            DeflateDecoder::new(compressed)
+                .take(u64::from(uncompressed_size) + 1)
+                .read_to_end(data)
+                .map_err(|e| {
+                    Error::SqPack(format!(
+                        "failed to decompress block at {:#x}: {}",
+                        offset, e
+                    ))
+                })?;
+        }
+
+        if data.len() - start != uncompressed_size as usize {
+            return Err(Error::SqPack(format!(
+                "block at {:#x} decompressed to {} bytes, expected {}",
+                offset,
+                data.len() - start,
+                uncompressed_size
+            )));
+        }
+        Ok(())
+    }
+}
+
+// =============================================================================
+// Archives
+// =============================================================================
+
+/// The SqPack archives of an installation, read by game path
+pub struct SqPack {
+    dir: PathBuf,
+    platform: Platform,
+    /// Indexes are large, so each is parsed once
+    indexes: Mutex<HashMap<PathBuf, Arc<SqPackIndex>>>,
+}
+
+impl SqPack {
+    /// Archives in `dir`, usually `{install}/game/sqpack`
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            platform: Platform::default(),
+            indexes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Read the archives of another platform than Windows
+    pub fn with_platform(mut self, platform: Platform) -> Self {
+        self.platform = platform;
+        self
+    }
+
+    /// Dat file and offset holding a game path such as `exd/root.exl`
+    pub fn locate(&self, path: &str) -> Result<(PathBuf, u64), Error> {
+        let path = normalize_game_path(path);
+        let (category, expansion) = archive_id(&path)?;
+        let folder = self.dir.join(expansion_folder(expansion.into()));
+
+        // Chunks are numbered without gaps
+        for chunk in 0..=u8::MAX {
+            let name = format!(
+                "{:02x}{:02x}{:02x}.{}",
+                category, expansion, chunk, self.platform
+            );
+            let index_path = folder.join(format!("{}.index", name));
+            if !index_path.exists() {
+                break;
+            }
+
+            if let Some(entry) = self.index(&index_path)?.find(&path) {
+                if entry.is_synonym {
+                    return Err(Error::SqPack(format!(
+                        "{} shares its hash with another file, which is not supported",
+                        path
+                    )));
+                }
+                let dat_path = folder.join(format!("{}.dat{}", name, entry.data_file_id));
+                return Ok((dat_path, entry.offset));
+            }
+        }
+
+        Err(Error::SqPackFileNotFound(path))
+    }
+
+    /// Extract a file by its game path
+    pub fn read_file(&self, path: &str) -> Result<Vec<u8>, Error> {
+        let (dat_path, offset) = self.locate(path)?;
+        DatFile::open(&dat_path)?.read_file(offset)
+    }
+
+    fn index(&self, path: &Path) -> Result<Arc<SqPackIndex>, Error> {
+        let mut indexes = self.indexes.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(index) = indexes.get(path) {
+            return Ok(index.clone());
+        }
+
+        let index = Arc::new(SqPackIndex::open(path)?);
+        indexes.insert(path.to_path_buf(), index.clone());
+        Ok(index)
+    }
+}
+
+/// Category and expansion of the archives that may hold a normalized game path
+fn archive_id(path: &str) -> Result<(u8, u8), Error> {
+    let mut folders = path.split('/');
+    let category = folders
+        .next()
+        .and_then(|folder| CATEGORIES.iter().find(|(name, _)| *name == folder))
+        .map(|&(_, id)| id)
+        .ok_or_else(|| Error::SqPack(format!("unknown category in {}", path)))?;
+    let expansion = folders
+        .next()
+        .and_then(|folder| folder.strip_prefix("ex"))
+        .and_then(|number| number.parse().ok())
+        .unwrap_or(0);
+
+    Ok((category, expansion))
+}
+
+fn le_u32(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap())
+}
+
+/// Buffer size to reserve for a file, bounded in case its header is corrupt
+fn initial_capacity(raw_file_size: u32) -> usize {
+    (raw_file_size as usize).min(64 * 1024 * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::DeflateEncoder;
+    use flate2::Compression;
+    use std::io::{Cursor, Write};
+
+    fn sqpack_header(kind: u32) -> Vec<u8> {
+        let mut header = vec![0u8; SQPACK_HEADER_SIZE as usize];
+        header[..8].copy_from_slice(SQPACK_MAGIC);
+        header[0x0C..0x10].copy_from_slice(&(SQPACK_HEADER_SIZE as u32).to_le_bytes());
+        header[0x10..0x14].copy_from_slice(&1u32.to_le_bytes());
+        header[0x14..0x18].copy_from_slice(&kind.to_le_bytes());
+        header
+    }
+
+    /// Packed location of a file in an index entry
+    fn packed(data_file_id: u8, offset: u64) -> u32 {
+        (offset / 8) as u32 | (u32::from(data_file_id) << 1)
+    }
+
+    fn build_index(index_type: IndexType, entries: &[(u64, u32)]) -> Vec<u8> {
+        let mut data = sqpack_header(2);
+        let mut index_header = vec![0u8; SQPACK_HEADER_SIZE as usize];
+        let entry_size = match index_type {
+            IndexType::Index => 16,
+            IndexType::Index2 => 8,
+        };
+        index_header[0x08..0x0C].copy_from_slice(&0x800u32.to_le_bytes());
+        index_header[0x0C..0x10]
+            .copy_from_slice(&((entries.len() * entry_size) as u32).to_le_bytes());
+        data.extend_from_slice(&index_header);
+
+        for &(hash, location) in entries {
+            match index_type {
+                IndexType::Index => {
+                    data.extend_from_slice(&hash.to_le_bytes());
+                    data.extend_from_slice(&location.to_le_bytes());
+                    data.extend_from_slice(&[0u8; 4]);
+                }
+                IndexType::Index2 => {
+                    data.extend_from_slice(&(hash as u32).to_le_bytes());
+                    data.extend_from_slice(&location.to_le_bytes());
+                }
+            }
+        }
+        data
+    }
+
+    fn pad_to_block(data: &mut Vec<u8>) {
+        data.resize(data.len().next_multiple_of(128), 0);
+    }
+
+    /// A block, compressed or stored as is
+    fn block(contents: &[u8], compress: bool) -> Vec<u8> {
+        let payload = if compress {
+            let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(contents).unwrap();
+            encoder.finish().unwrap()
+        } else {
+            contents.to_vec()
+        };
+        let compressed_size = if compress {
+            payload.len() as u32
+        } else {
+            UNCOMPRESSED_BLOCK
+        };
+
+        let mut block = Vec::new();
+        for value in [16, 0, compressed_size, contents.len() as u32] {
+            block.extend_from_slice(&value.to_le_bytes());
+        }
+        block.extend_from_slice(&payload);
+        pad_to_block(&mut block);
+        block
+    }
+
+    /// Entry header with the common fields, padded to `header_size`
+    fn entry(header_size: u32, file_type: u32, raw_size: u32, fields: &[u8]) -> Vec<u8> {
+        let mut entry = Vec::new();
+        for value in [header_size, file_type, raw_size] {
+            entry.extend_from_slice(&value.to_le_bytes());
+        }
+        entry.extend_from_slice(fields);
+        entry.resize(header_size as usize, 0);
+        entry
+    }
+
+    fn standard_entry(parts: &[(&[u8], bool)]) -> Vec<u8> {
+        let blocks: Vec<Vec<u8>> = parts.iter().map(|&(data, c)| block(data, c)).collect();
+        let raw_size: usize = parts.iter().map(|(data, _)| data.len()).sum();
+
+        let mut fields = vec![0u8; 8];
+        fields.extend_from_slice(&(blocks.len() as u32).to_le_bytes());
+        let mut block_offset = 0u32;
+        for (block, (data, _)) in blocks.iter().zip(parts) {
+            fields.extend_from_slice(&block_offset.to_le_bytes());
+            fields.extend_from_slice(&(block.len() as u16).to_le_bytes());
+            fields.extend_from_slice(&(data.len() as u16).to_le_bytes());
+            block_offset += block.len() as u32;
+        }
+
+        let mut entry = entry(128, 2, raw_size as u32, &fields);
+        entry.extend(blocks.concat());
+        entry
+    }
+
+    fn texture_entry(texture_header: &[u8], lods: &[&[&[u8]]]) -> Vec<u8> {
+        let mut fields = vec![0u8; 8];
+        fields.extend_from_slice(&(lods.len() as u32).to_le_bytes());
+
+        let mut body = texture_header.to_vec();
+        let mut block_sizes = Vec::new();
+        let mut raw_size = texture_header.len();
+        let mut first_block = 0u32;
+        for lod in lods {
+            let offset = body.len() as u32;
+            for part in *lod {
+                let block = block(part, true);
+                block_sizes.push(block.len() as u16);
+                body.extend_from_slice(&block);
+                raw_size += part.len();
+            }
+            for value in [offset, 0, 0, first_block, lod.len() as u32] {
+                fields.extend_from_slice(&value.to_le_bytes());
+            }
+            first_block += lod.len() as u32;
+        }
+        for size in block_sizes {
+            fields.extend_from_slice(&size.to_le_bytes());
+        }
+
+        let mut entry = entry(256, 4, raw_size as u32, &fields);
+        entry.extend(body);
+        entry
+    }
+
+    /// Dat file with the given entries, returning it and their offsets
+    fn build_dat(entries: &[Vec<u8>]) -> (Vec<u8>, Vec<u64>) {
+        let mut data = sqpack_header(1);
+        data.resize(2 * SQPACK_HEADER_SIZE as usize, 0);
+        let mut offsets = Vec::new();
+        for entry in entries {
+            offsets.push(data.len() as u64);
+            data.extend_from_slice(entry);
+            pad_to_block(&mut data);
+        }
+        (data, offsets)
+    }
+
+    #[test]
+    fn test_path_hashes() {
+        assert_eq!(index_hash("EXD/Root.exl"), index_hash("exd/root.exl"));
+        assert_eq!(
+            index_hash("exd/root.exl"),
+            (u64::from(path_crc("exd")) << 32) | u64::from(path_crc("root.exl"))
+        );
+        assert_eq!(index2_hash("\\exd\\root.exl"), path_crc("exd/root.exl"));
+        assert_eq!(path_crc(""), u32::MAX);
+    }
+
+    #[test]
+    fn test_index_lookup() {
+        let hash = index_hash("exd/root.exl");
+        let data = build_index(
+            IndexType::Index,
+            &[(hash, packed(2, 0x1280)), (1, packed(0, 0x800) | 1)],
+        );
+        let index = SqPackIndex::parse(&data, IndexType::Index).unwrap();
+
+        assert_eq!(index.entries().len(), 2);
+        assert_eq!(
+            index.find("exd/root.exl"),
+            Some(&IndexEntry {
+                hash,
+                data_file_id: 2,
+                offset: 0x1280,
+                is_synonym: false,
+            })
+        );
+        assert!(index.get(1).unwrap().is_synonym);
+        assert!(index.find("exd/missing.exh").is_none());
+
+        let hash2 = index2_hash("exd/root.exl");
+        let data = build_index(IndexType::Index2, &[(u64::from(hash2), packed(0, 0x800))]);
+        let index = SqPackIndex::parse(&data, IndexType::Index2).unwrap();
+        assert_eq!(index.find("exd/root.exl").unwrap().offset, 0x800);
+    }
+
+    #[test]
+    fn test_index_rejects_invalid_files() {
+        let dat = sqpack_header(1);
+        assert!(SqPackIndex::parse(&dat, IndexType::Index).is_err());
+        assert!(SqPackIndex::parse(b"not sqpack", IndexType::Index).is_err());
+
+        let mut index = build_index(IndexType::Index, &[(1, 0)]);
+        index.truncate(index.len() - 4);
+        assert!(SqPackIndex::parse(&index, IndexType::Index).is_err());
+    }
+
+    #[test]
+    fn test_read_standard_file() {
+        let first = vec![0x11u8; 300];
+        let second = b"stored as is".to_vec();
+        let (dat, offsets) = build_dat(&[
+            entry(128, 1, 0, &[]),
+            standard_entry(&[(&first, true), (&second, false)]),
+        ]);
+        let mut dat = DatFile::new(Cursor::new(dat)).unwrap();
+
+        assert_eq!(dat.header().platform, Platform::Win32);
+        assert_eq!(
+            dat.entry_header(offsets[1]).unwrap().file_type,
+            SqPackFileType::Standard
+        );
+        assert_eq!(dat.read_file(offsets[1]).unwrap(), [first, second].concat());
+        assert!(dat.read_file(offsets[0]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_read_texture_file() {
+        let texture_header = vec![0xAAu8; 80];
+        let mip0 = vec![0x01u8; 500];
+        let mip0_rest = vec![0x02u8; 100];
+        let mip1 = vec![0x03u8; 150];
+        let (dat, offsets) = build_dat(&[texture_entry(
+            &texture_header,
+            &[&[&mip0, &mip0_rest], &[&mip1]],
+        )]);
+        let mut dat = DatFile::new(Cursor::new(dat)).unwrap();
+
+        assert_eq!(
+            dat.read_file(offsets[0]).unwrap(),
+            [texture_header, mip0, mip0_rest, mip1].concat()
+        );
+    }
+
+    #[test]
+    fn test_read_model_file() {
+        // Stack, runtime, level 0 vertex and index buffers
+        let sections: [(usize, &[u8]); 4] = [
+            (0, b"stack"),
+            (1, b"runtime"),
+            (2, b"vertices"),
+            (8, b"indices"),
+        ];
+        let blocks: Vec<Vec<u8>> = sections.iter().map(|(_, d)| block(d, true)).collect();
+
+        let mut fields = Vec::new();
+        fields.extend_from_slice(&(blocks.len() as u32).to_le_bytes());
+        fields.extend_from_slice(&(blocks.len() as u32).to_le_bytes());
+        fields.extend_from_slice(&5u32.to_le_bytes()); // version
+        fields.extend_from_slice(&[0u8; 11 * 4 * 2]);
+        let mut section_offsets = [0u32; 11];
+        let mut first_blocks = [0u16; 11];
+        let mut section_blocks = [0u16; 11];
+        let mut offset = 0;
+        for (i, ((section, _), block)) in sections.iter().zip(&blocks).enumerate() {
+            section_offsets[*section] = offset;
+            first_blocks[*section] = i as u16;
+            section_blocks[*section] = 1;
+            offset += block.len() as u32;
+        }
+        for value in section_offsets {
+            fields.extend_from_slice(&value.to_le_bytes());
+        }
+        for value in first_blocks.iter().chain(&section_blocks) {
+            fields.extend_from_slice(&value.to_le_bytes());
+        }
+        fields.extend_from_slice(&2u16.to_le_bytes()); // meshes
+        fields.extend_from_slice(&3u16.to_le_bytes()); // materials
+        fields.extend_from_slice(&[1, 0, 0, 0]);
+        for block in &blocks {
+            fields.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        }
+
+        // The model-specific fields replace the common ones after the file size
+        let mut model = entry(256, 3, 0, &fields);
+        model.extend(blocks.concat());
+        let (dat, offsets) = build_dat(&[model]);
+        let mut dat = DatFile::new(Cursor::new(dat)).unwrap();
+        let data = dat.read_file(offsets[0]).unwrap();
+
+        let header = &data[..MODEL_HEADER_SIZE];
+        assert_eq!(le_u32(header, 0x00), 5);
+        assert_eq!(le_u32(header, 0x04), 5); // stack size
+        assert_eq!(le_u32(header, 0x08), 7); // runtime size
+        assert_eq!(&header[0x0C..0x10], &[2, 0, 3, 0]);
+        let vertex_offset = le_u32(header, 0x10) as usize;
+        let index_offset = le_u32(header, 0x1C) as usize;
+        assert_eq!(vertex_offset, MODEL_HEADER_SIZE + 12);
+        assert_eq!(le_u32(header, 0x28), 8); // vertex buffer size
+        assert_eq!(le_u32(header, 0x34), 7); // index buffer size
+        assert_eq!(header[0x40], 1);
+        assert_eq!(&data[MODEL_HEADER_SIZE..vertex_offset], b"stackruntime");
+        assert_eq!(&data[vertex_offset..index_offset], b"vertices");
+        assert_eq!(&data[index_offset..], b"indices");
+    }
+
+    #[test]
+    fn test_read_rejects_corrupt_block() {
+        let mut entry = standard_entry(&[(b"data", true)]);
+        // Claim a larger uncompressed size than the block holds
+        entry[128 + 12..128 + 16].copy_from_slice(&5u32.to_le_bytes());
+        let (dat, offsets) = build_dat(&[entry]);
+        let mut dat = DatFile::new(Cursor::new(dat)).unwrap();
+        assert!(matches!(dat.read_file(offsets[0]), Err(Error::SqPack(_))));
+    }
+
+    #[test]
+    fn test_sqpack_reads_by_game_path() {
+        let dir = std::env::temp_dir().join(format!("gaveloc-sqpack-{}", std::process::id()));
+        let folder = dir.join("ex1");
+        std::fs::create_dir_all(&folder).unwrap();
+
+        // The file is in the second chunk of the bg/ex1 archive
+        let contents = b"level data".to_vec();
+        let (dat, offsets) = build_dat(&[standard_entry(&[(&contents, true)])]);
+        std::fs::write(folder.join("020101.win32.dat1"), dat).unwrap();
+        std::fs::write(
+            folder.join("020100.win32.index"),
+            build_index(IndexType::Index, &[]),
+        )
+        .unwrap();
+        std::fs::write(
+            folder.join("020101.win32.index"),
+            build_index(
+                IndexType::Index,
+                &[(index_hash("bg/ex1/level.lvb"), packed(1, offsets[0]))],
+            ),
+        )
+        .unwrap();
+
+        let sqpack = SqPack::new(&dir);
+        assert_eq!(sqpack.read_file("BG/ex1/level.lvb").unwrap(), contents);
+        assert_eq!(
+            sqpack.locate("bg/ex1/level.lvb").unwrap(),
+            (folder.join("020101.win32.dat1"), offsets[0])
+        );
+        assert!(matches!(
+            sqpack.read_file("bg/ex1/missing.lvb"),
+            Err(Error::SqPackFileNotFound(_))
+        ));
+        assert!(matches!(
+            sqpack.read_file("nowhere/file.txt"),
+            Err(Error::SqPack(_))
+        ));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -2,4 +2,7 @@ mod login;
 mod update_game;
 
 pub use login::LoginUseCase;
-pub use update_game::{UpdateGameUseCase, UpdateProgress, UpdateStage};
+pub use update_game::{
+    DiskSpaceEstimate, DownloadActivity, DownloadPhase, PatchActivity, UpdateCheckResult,
+    UpdateGameUseCase, UpdateProgress, UpdateStage,
+};
@@ -1,47 +1,88 @@
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
+use tokio::sync::mpsc;
+
+use crate::control::{ControlState, UpdateControl};
 use crate::entities::{PatchEntry, Repository};
 use crate::error::Error;
-use crate::ports::{PatchDownloader, PatchServer, VersionRepository, ZiPatchApplier};
+use crate::ports::{
+    DiskSpaceProvider, PatchDownloader, PatchServer, VersionRepository, ZiPatchApplier,
+};
+use crate::zipatch::{patch_journal_path, JournalState};
+
+/// Verified patches that may wait for the applier while the next one downloads.
+///
+/// Together with the patch being applied and the one downloading, this bounds
+/// the number of patch files on disk at once.
+const PIPELINE_DEPTH: usize = 1;
+
+/// Patch files that can be on disk at once: the queued ones plus the one being
+/// applied and the one downloading
+const PATCH_FILES_ON_DISK: usize = PIPELINE_DEPTH + 2;
 
 /// Stage of the update process
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum UpdateStage {
     /// Checking for available patches
     CheckingPatches,
-    /// Downloading a patch file
-    Downloading {
-        patch_index: usize,
-        total_patches: usize,
-        repository: Repository,
-        version: String,
-    },
-    /// Verifying a downloaded patch
-    Verifying {
-        patch_index: usize,
-        total_patches: usize,
-    },
-    /// Applying a patch to the game
-    Applying {
-        patch_index: usize,
-        total_patches: usize,
-        repository: Repository,
-        version: String,
+    /// Downloading and applying patches
+    ///
+    /// The next patch downloads while the previous one is applied, so both
+    /// may be in progress at once.
+    Patching {
+        /// Patch being downloaded or verified
+        download: Option<DownloadActivity>,
+        /// Patch being applied to the game
+        apply: Option<PatchActivity>,
     },
+    /// Paused at a safe point, waiting to be resumed
+    Paused,
+    /// Cancelled at a safe point; partial downloads and interrupted patches
+    /// resume on the next update
+    Cancelled,
     /// Update completed successfully
     Completed,
     /// Update failed
     Failed { error: String },
 }
 
+/// A patch the update is working on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatchActivity {
+    /// 1-based position of the patch in the update
+    pub patch_index: usize,
+    pub total_patches: usize,
+    pub repository: Repository,
+    pub version: String,
+}
+
+/// A patch being fetched
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DownloadActivity {
+    pub patch: PatchActivity,
+    pub phase: DownloadPhase,
+}
+
+/// Step of fetching a patch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DownloadPhase {
+    Downloading,
+    Verifying,
+}
+
 /// Progress information for the update process
 #[derive(Debug, Clone)]
 pub struct UpdateProgress {
     pub stage: UpdateStage,
+    /// Bytes downloaded of the patch currently downloading
     pub bytes_downloaded: u64,
+    /// Size of the patch currently downloading
     pub bytes_total: u64,
+    /// Overall progress, counting downloading and applying each patch as half
     pub overall_progress: f64,
+    /// Number of patches applied and recorded so far; they finish in list order
+    pub patches_applied: usize,
 }
 
 impl UpdateProgress {
@@ -54,38 +95,137 @@ impl UpdateProgress {
     }
 }
 
+/// State shared by the download and apply halves of the patch pipeline
+struct PipelineStatus {
+    total_bytes: u64,
+    download: Option<DownloadActivity>,
+    apply: Option<PatchActivity>,
+    bytes_downloaded: u64,
+    bytes_total: u64,
+    /// Bytes of patches that finished downloading
+    fetched_bytes: u64,
+    /// Bytes of patches that finished applying
+    applied_bytes: u64,
+    patches_applied: usize,
+    /// The update is paused, which takes precedence over the activities
+    paused: bool,
+}
+
+impl PipelineStatus {
+    fn new(patches: &[PatchEntry]) -> Self {
+        Self {
+            total_bytes: patches.iter().map(|p| p.length).sum(),
+            download: None,
+            apply: None,
+            bytes_downloaded: 0,
+            bytes_total: 0,
+            fetched_bytes: 0,
+            applied_bytes: 0,
+            patches_applied: 0,
+            paused: false,
+        }
+    }
+
+    fn progress(&self) -> UpdateProgress {
+        let done = self.fetched_bytes + self.bytes_downloaded + self.applied_bytes;
+        let overall_progress = if self.total_bytes == 0 {
+            0.0
+        } else {
+            (done as f64 / (2 * self.total_bytes) as f64) * 100.0
+        };
+
+        let stage = if self.paused {
+            UpdateStage::Paused
+        } else {
+            UpdateStage::Patching {
+                download: self.download.clone(),
+                apply: self.apply.clone(),
+            }
+        };
+
+        UpdateProgress {
+            stage,
+            bytes_downloaded: self.bytes_downloaded,
+            bytes_total: self.bytes_total,
+            overall_progress,
+            patches_applied: self.patches_applied,
+        }
+    }
+
+    /// Report the current status under a different stage
+    fn report_stage<F>(status: &Mutex<Self>, progress: &F, stage: UpdateStage)
+    where
+        F: Fn(UpdateProgress),
+    {
+        let snapshot = UpdateProgress {
+            stage,
+            ..status.lock().unwrap().progress()
+        };
+        progress(snapshot);
+    }
+
+    /// Apply `update` to the shared status and report the result
+    fn report<F>(status: &Mutex<Self>, progress: &F, update: impl FnOnce(&mut Self))
+    where
+        F: Fn(UpdateProgress),
+    {
+        let snapshot = {
+            let mut status = status.lock().unwrap();
+            update(&mut status);
+            status.progress()
+        };
+        progress(snapshot);
+    }
+}
+
+/// A patch handed from the download half of the pipeline to the apply half
+struct PipelineJob {
+    index: usize,
+    patch_path: PathBuf,
+    journal_path: PathBuf,
+    /// The journal shows the patch was applied but not recorded as such
+    already_applied: bool,
+}
+
 /// Orchestrates the complete game update flow including:
 /// - Boot patch checking and application
 /// - Game patch checking via session registration
 /// - Patch downloading with verification
+/// - Disk space checks before patching starts
 /// - Patch application using ZiPatch
 /// - Version file updates
-pub struct UpdateGameUseCase<P, D, Z, V>
+pub struct UpdateGameUseCase<P, D, Z, V, S>
 where
     P: PatchServer,
     D: PatchDownloader,
     Z: ZiPatchApplier + 'static,
     V: VersionRepository,
+    S: DiskSpaceProvider,
 {
     patch_server: Arc<P>,
     downloader: Arc<D>,
     applier: Arc<Z>,
     version_repo: Arc<V>,
+    disk_space: Arc<S>,
     patch_dir: PathBuf,
+    /// Leave patch files in `patch_dir` once applied
+    keep_patches: bool,
 }
 
-impl<P, D, Z, V> UpdateGameUseCase<P, D, Z, V>
+impl<P, D, Z, V, S> UpdateGameUseCase<P, D, Z, V, S>
 where
     P: PatchServer,
     D: PatchDownloader,
     Z: ZiPatchApplier + 'static,
     V: VersionRepository,
+    S: DiskSpaceProvider,
 {
     pub fn new(
         patch_server: Arc<P>,
         downloader: Arc<D>,
         applier: Arc<Z>,
         version_repo: Arc<V>,
+        disk_space: Arc<S>,
         patch_dir: PathBuf,
     ) -> Self {
         Self {
@@ -93,14 +233,33 @@ where
             downloader,
             applier,
             version_repo,
+            disk_space,
             patch_dir,
+            keep_patches: false,
         }
     }
 
+    /// Keep patch files once they are applied instead of removing them
+    pub fn with_keep_patches(mut self, keep_patches: bool) -> Self {
+        self.keep_patches = keep_patches;
+        self
+    }
+
+    /// Where a patch is downloaded to
+    fn patch_path(&self, index: usize, patch: &PatchEntry) -> PathBuf {
+        patch_path(&self.patch_dir, index, patch)
+    }
+
     /// Check for and apply boot patches.
     ///
     /// Returns the list of patches that were applied, empty if up to date.
-    pub async fn update_boot<F>(&self, game_path: &Path, progress: F) -> Result<Vec<PatchEntry>, Error>
+    /// `control` pauses or cancels the update between safe points.
+    pub async fn update_boot<F>(
+        &self,
+        game_path: &Path,
+        control: &UpdateControl,
+        progress: F,
+    ) -> Result<Vec<PatchEntry>, Error>
     where
         F: Fn(UpdateProgress) + Send + Sync + Clone + 'static,
     {
@@ -109,6 +268,7 @@ where
             bytes_downloaded: 0,
             bytes_total: 0,
             overall_progress: 0.0,
+            patches_applied: 0,
         });
 
         // Get current boot version
@@ -129,12 +289,13 @@ where
                 bytes_downloaded: 0,
                 bytes_total: 0,
                 overall_progress: 100.0,
+                patches_applied: 0,
             });
             return Ok(vec![]);
         }
 
         // Apply each patch
-        self.apply_patches(&patches, game_path, None, progress)
+        self.apply_patches(&patches, game_path, None, control, progress)
             .await?;
 
         Ok(patches)
@@ -144,11 +305,13 @@ where
     ///
     /// Requires a valid session_id from OAuth login.
     /// Returns the list of patches that were applied, empty if up to date.
+    /// `control` pauses or cancels the update between safe points.
     pub async fn update_game<F>(
         &self,
         session_id: &str,
         game_path: &Path,
         max_expansion: u32,
+        control: &UpdateControl,
         progress: F,
     ) -> Result<(String, Vec<PatchEntry>), Error>
     where
@@ -159,6 +322,7 @@ where
             bytes_downloaded: 0,
             bytes_total: 0,
             overall_progress: 0.0,
+            patches_applied: 0,
         });
 
         // Register session and get patch list
@@ -173,139 +337,336 @@ where
                 bytes_downloaded: 0,
                 bytes_total: 0,
                 overall_progress: 100.0,
+                patches_applied: 0,
             });
             return Ok((unique_id.clone(), vec![]));
         }
 
         // Apply patches with unique_id for authentication
-        self.apply_patches(&patches, game_path, Some(&unique_id), progress)
+        self.apply_patches(&patches, game_path, Some(&unique_id), control, progress)
             .await?;
 
         Ok((unique_id, patches))
     }
 
     /// Apply a list of patches to the game installation.
-    async fn apply_patches<F>(
+    ///
+    /// Patches are downloaded ahead of the applier, so the next patch
+    /// downloads while the previous one is applied. They are still applied
+    /// one at a time in list order.
+    ///
+    /// Nothing is downloaded unless the estimated space is available.
+    ///
+    /// A cancelled update is not rolled back: the partial download and the
+    /// journal of an interrupted patch are kept so the next update resumes them.
+    pub async fn apply_patches<F>(
         &self,
         patches: &[PatchEntry],
         game_path: &Path,
         unique_id: Option<&str>,
+        control: &UpdateControl,
         progress: F,
     ) -> Result<(), Error>
+    where
+        F: Fn(UpdateProgress) + Send + Sync + Clone + 'static,
+    {
+        let estimate = self.estimate_disk_space(patches).await?;
+        estimate.ensure_fits(self.disk_space.as_ref(), &self.patch_dir, game_path)?;
+
+        let status = Arc::new(Mutex::new(PipelineStatus::new(patches)));
+        let (jobs, queue) = mpsc::channel(PIPELINE_DEPTH);
+
+        let pipeline = async {
+            tokio::join!(
+                self.download_patches(patches, unique_id, jobs, control, &status, &progress),
+                self.apply_queued(queue, patches, game_path, control, &status, &progress),
+            )
+        };
+        let (download_result, apply_result) = tokio::select! {
+            results = pipeline => results,
+            never = Self::report_pauses(control, &status, &progress) => never,
+        };
+
+        // A failed apply stops the downloads, so its error is the root cause
+        let result = apply_result.and(download_result);
+        if matches!(result, Err(Error::Cancelled)) {
+            tracing::info!("Update cancelled");
+            PipelineStatus::report_stage(&status, &progress, UpdateStage::Cancelled);
+        }
+        result?;
+
+        let total_bytes = status.lock().unwrap().total_bytes;
+        progress(UpdateProgress {
+            stage: UpdateStage::Completed,
+            bytes_downloaded: total_bytes,
+            bytes_total: total_bytes,
+            overall_progress: 100.0,
+            patches_applied: patches.len(),
+        });
+
+        Ok(())
+    }
+
+    /// Report the update as paused while `control` is paused
+    ///
+    /// Never completes, so it must be raced against the pipeline.
+    async fn report_pauses<F>(
+        control: &UpdateControl,
+        status: &Mutex<PipelineStatus>,
+        progress: &F,
+    ) -> !
+    where
+        F: Fn(UpdateProgress),
+    {
+        let mut state = control.subscribe();
+        let mut paused = false;
+        loop {
+            let now_paused = *state.borrow_and_update() == ControlState::Paused;
+            if now_paused != paused {
+                paused = now_paused;
+                tracing::info!("Update {}", if paused { "paused" } else { "resumed" });
+                PipelineStatus::report(status, progress, |s| s.paused = paused);
+            }
+            if state.changed().await.is_err() {
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+
+    /// Download half of the pipeline: fetch and verify patches in order,
+    /// queueing each one for the applier.
+    ///
+    /// Stops early, without error, once the applier has stopped.
+    async fn download_patches<F>(
+        &self,
+        patches: &[PatchEntry],
+        unique_id: Option<&str>,
+        jobs: mpsc::Sender<PipelineJob>,
+        control: &UpdateControl,
+        status: &Arc<Mutex<PipelineStatus>>,
+        progress: &F,
+    ) -> Result<(), Error>
     where
         F: Fn(UpdateProgress) + Send + Sync + Clone + 'static,
     {
         let total_patches = patches.len();
-        let total_bytes: u64 = patches.iter().map(|p| p.length).sum();
-        let mut cumulative_bytes: u64 = 0;
 
         for (index, patch) in patches.iter().enumerate() {
-            let patch_path = self.patch_dir.join(
-                patch
-                    .filename()
-                    .unwrap_or(&format!("patch_{}.patch", index)),
-            );
-
-            // Download phase
-            let progress_clone = progress.clone();
-            let patch_index = index;
-            let repo = patch.repository;
-            let version = patch.version_id.clone();
-            let cumulative = cumulative_bytes;
+            let patch_path = self.patch_path(index, patch);
+            let journal_path = patch_journal_path(&patch_path);
 
-            progress(UpdateProgress {
-                stage: UpdateStage::Downloading {
-                    patch_index: index + 1,
-                    total_patches,
-                    repository: patch.repository,
-                    version: patch.version_id.clone(),
-                },
-                bytes_downloaded: 0,
-                bytes_total: patch.length,
-                overall_progress: (cumulative_bytes as f64 / total_bytes as f64) * 100.0,
-            });
+            // A completed journal means the patch was applied but the version
+            // file was not updated before the previous run stopped
+            let already_applied =
+                self.applier.journal_state(&journal_path)? == Some(JournalState::Completed);
 
-            self.downloader
-                .download_patch(patch, &patch_path, unique_id, move |downloaded, total| {
-                    progress_clone(UpdateProgress {
-                        stage: UpdateStage::Downloading {
-                            patch_index: patch_index + 1,
+            if already_applied {
+                status.lock().unwrap().fetched_bytes += patch.length;
+            } else {
+                PipelineStatus::report(status, progress, |s| {
+                    s.download = Some(DownloadActivity {
+                        patch: PatchActivity {
+                            patch_index: index + 1,
                             total_patches,
-                            repository: repo,
-                            version: version.clone(),
+                            repository: patch.repository,
+                            version: patch.version_id.clone(),
                         },
-                        bytes_downloaded: downloaded,
-                        bytes_total: total,
-                        overall_progress: ((cumulative + downloaded) as f64 / total_bytes as f64)
-                            * 100.0,
+                        phase: DownloadPhase::Downloading,
                     });
-                })
-                .await?;
+                    s.bytes_downloaded = 0;
+                    s.bytes_total = patch.length;
+                });
+                let fetch =
+                    self.fetch_patch(patch, &patch_path, unique_id, control, status, progress);
+                tokio::select! {
+                    result = fetch => result?,
+                    _ = jobs.closed() => return Ok(()),
+                }
+            }
 
-            // Verify phase
-            progress(UpdateProgress {
-                stage: UpdateStage::Verifying {
-                    patch_index: index + 1,
-                    total_patches,
+            let job = PipelineJob {
+                index,
+                patch_path,
+                journal_path,
+                already_applied,
+            };
+            if jobs.send(job).await.is_err() {
+                return Ok(());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Download and verify a single patch
+    ///
+    /// The caller has already reported the patch as the current download.
+    async fn fetch_patch<F>(
+        &self,
+        patch: &PatchEntry,
+        patch_path: &Path,
+        unique_id: Option<&str>,
+        control: &UpdateControl,
+        status: &Arc<Mutex<PipelineStatus>>,
+        progress: &F,
+    ) -> Result<(), Error>
+    where
+        F: Fn(UpdateProgress) + Send + Sync + Clone + 'static,
+    {
+        let status_clone = status.clone();
+        let progress_clone = progress.clone();
+        self.downloader
+            .download_patch(
+                patch,
+                patch_path,
+                unique_id,
+                control,
+                move |downloaded, total| {
+                    PipelineStatus::report(&status_clone, &progress_clone, |s| {
+                        s.bytes_downloaded = downloaded;
+                        s.bytes_total = total;
+                    });
                 },
-                bytes_downloaded: patch.length,
-                bytes_total: patch.length,
-                overall_progress: ((cumulative_bytes + patch.length) as f64 / total_bytes as f64)
-                    * 100.0,
-            });
+            )
+            .await?;
 
-            let verified = self.downloader.verify_patch(patch, &patch_path).await?;
-            if !verified {
-                // Clean up failed patch file
-                let _ = tokio::fs::remove_file(&patch_path).await;
-                return Err(Error::PatchVerificationFailed);
+        PipelineStatus::report(status, progress, |s| {
+            if let Some(download) = &mut s.download {
+                download.phase = DownloadPhase::Verifying;
             }
+            s.bytes_downloaded = patch.length;
+            s.bytes_total = patch.length;
+        });
 
-            // Apply phase
-            progress(UpdateProgress {
-                stage: UpdateStage::Applying {
-                    patch_index: index + 1,
-                    total_patches,
-                    repository: patch.repository,
-                    version: patch.version_id.clone(),
-                },
-                bytes_downloaded: patch.length,
-                bytes_total: patch.length,
-                overall_progress: ((cumulative_bytes + patch.length) as f64 / total_bytes as f64)
-                    * 100.0,
-            });
+        let report = self
+            .downloader
+            .verify_patch_blocks(patch, patch_path, control)
+            .await?;
+        if !report.is_valid() {
+            // Keep the file so the next attempt only fetches the failed blocks
+            tracing::warn!(
+                "Patch {} failed verification: {} of {} blocks bad",
+                patch.version_id,
+                report.failed_blocks.len(),
+                report.total_blocks
+            );
+            return Err(Error::PatchVerificationFailed);
+        }
 
-            // ZiPatch application is synchronous, run in blocking context
-            let applier = self.applier.clone();
-            let patch_path_clone = patch_path.clone();
-            let game_path_clone = game_path.to_path_buf();
-            tokio::task::spawn_blocking(move || {
-                applier.apply_patch(&patch_path_clone, &game_path_clone)
-            })
-            .await
-            .map_err(|e| Error::ZiPatchApply(e.to_string()))??;
+        PipelineStatus::report(status, progress, |s| {
+            s.download = None;
+            s.bytes_downloaded = 0;
+            s.bytes_total = 0;
+            s.fetched_bytes += patch.length;
+        });
 
-            // Update version file after successful patch
-            self.version_repo
-                .set_version(game_path, patch.repository, &patch.version_id)
-                .await?;
+        Ok(())
+    }
 
-            // Clean up patch file after successful application
-            let _ = tokio::fs::remove_file(&patch_path).await;
+    /// Apply half of the pipeline: apply queued patches in the order they arrive
+    async fn apply_queued<F>(
+        &self,
+        mut queue: mpsc::Receiver<PipelineJob>,
+        patches: &[PatchEntry],
+        game_path: &Path,
+        control: &UpdateControl,
+        status: &Arc<Mutex<PipelineStatus>>,
+        progress: &F,
+    ) -> Result<(), Error>
+    where
+        F: Fn(UpdateProgress) + Send + Sync + Clone + 'static,
+    {
+        while let Some(job) = queue.recv().await {
+            let patch = &patches[job.index];
+            control.checkpoint().await?;
 
-            cumulative_bytes += patch.length;
+            if job.already_applied {
+                tracing::info!("Patch {} already applied, finishing up", patch.version_id);
+            } else {
+                PipelineStatus::report(status, progress, |s| {
+                    s.apply = Some(PatchActivity {
+                        patch_index: job.index + 1,
+                        total_patches: patches.len(),
+                        repository: patch.repository,
+                        version: patch.version_id.clone(),
+                    });
+                });
+
+                // ZiPatch application is synchronous, run in blocking context.
+                // An interrupted application from a previous run is resumed from its
+                // journal; a failed one is rolled back so the install stays consistent.
+                // A cancelled one keeps its journal so the next run resumes it.
+                let applier = self.applier.clone();
+                let patch_path = job.patch_path.clone();
+                let journal_path = job.journal_path.clone();
+                let install_path = game_path.join(patch.repository.install_dir());
+                let control = control.clone();
+                tokio::task::spawn_blocking(move || {
+                    applier
+                        .apply_patch_journaled(&patch_path, &install_path, &journal_path, &control)
+                        .inspect_err(|e| {
+                            if matches!(e, Error::Cancelled) {
+                                return;
+                            }
+                            tracing::error!("Failed to apply patch, rolling back: {}", e);
+                            if let Err(e) = applier.rollback_patch(&journal_path) {
+                                tracing::error!("Rollback failed: {}", e);
+                            }
+                        })
+                })
+                .await
+                .map_err(|e| Error::ZiPatchApply(e.to_string()))??;
+            }
+
+            self.finish_patch(patch, game_path, &job.patch_path, &job.journal_path)
+                .await?;
+
+            PipelineStatus::report(status, progress, |s| {
+                s.apply = None;
+                s.applied_bytes += patch.length;
+                s.patches_applied += 1;
+            });
         }
 
-        progress(UpdateProgress {
-            stage: UpdateStage::Completed,
-            bytes_downloaded: total_bytes,
-            bytes_total: total_bytes,
-            overall_progress: 100.0,
-        });
+        Ok(())
+    }
+
+    /// Record a successfully applied patch and clean up its files.
+    ///
+    /// The journal is removed only after the version file is updated, so a
+    /// crash in between is detected on the next run. The patch file is kept
+    /// if the use case keeps patches.
+    async fn finish_patch(
+        &self,
+        patch: &PatchEntry,
+        game_path: &Path,
+        patch_path: &Path,
+        journal_path: &Path,
+    ) -> Result<(), Error> {
+        self.version_repo
+            .set_version(game_path, patch.repository, &patch.version_id)
+            .await?;
+
+        if let Err(e) = self.applier.discard_journal(journal_path) {
+            tracing::warn!("failed to remove journal {:?}: {}", journal_path, e);
+        }
+        if !self.keep_patches {
+            let _ = tokio::fs::remove_file(patch_path).await;
+        }
 
         Ok(())
     }
 
+    /// Estimate the extra space a list of patches needs.
+    ///
+    /// See [`DiskSpaceEstimate::for_patches`].
+    pub async fn estimate_disk_space(
+        &self,
+        patches: &[PatchEntry],
+    ) -> Result<DiskSpaceEstimate, Error> {
+        DiskSpaceEstimate::for_patches(&self.applier, &self.patch_dir, patches).await
+    }
+
     /// Check if game needs updates without applying them.
     ///
     /// Useful for UI to show update availability before starting.
@@ -337,15 +698,33 @@ where
             .map(|p| p.length)
             .sum();
 
+        // Boot and game patches are applied one after the other
+        let boot_space = self.estimate_disk_space(&boot_patches).await?;
+        let game_space = self.estimate_disk_space(&game_patches).await?;
+        let disk_space = DiskSpaceEstimate {
+            patch_dir_bytes: boot_space.patch_dir_bytes.max(game_space.patch_dir_bytes),
+            game_dir_bytes: boot_space.game_dir_bytes + game_space.game_dir_bytes,
+        };
+
         Ok(UpdateCheckResult {
             boot_patches,
             game_patches,
             unique_id,
             total_download_size: total_size,
+            disk_space,
         })
     }
 }
 
+/// Where a patch is downloaded to in `patch_dir`
+fn patch_path(patch_dir: &Path, index: usize, patch: &PatchEntry) -> PathBuf {
+    patch_dir.join(
+        patch
+            .filename()
+            .unwrap_or(&format!("patch_{}.patch", index)),
+    )
+}
+
 /// Result of checking for updates
 #[derive(Debug)]
 pub struct UpdateCheckResult {
@@ -353,6 +732,127 @@ pub struct UpdateCheckResult {
     pub game_patches: Vec<PatchEntry>,
     pub unique_id: String,
     pub total_download_size: u64,
+    /// Extra space the update needs
+    pub disk_space: DiskSpaceEstimate,
+}
+
+/// Extra disk space an update is expected to need
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DiskSpaceEstimate {
+    /// Peak space taken by patch files waiting to be applied and the journal
+    /// of the one being applied
+    pub patch_dir_bytes: u64,
+    /// Growth of the game installation
+    pub game_dir_bytes: u64,
+}
+
+impl DiskSpaceEstimate {
+    /// Estimate the extra space a list of patches downloaded to `patch_dir`
+    /// needs.
+    ///
+    /// Patch files are removed once applied, so the patch directory only needs
+    /// room for the largest run of patches on disk at once, less what is
+    /// already downloaded, plus the largest apply journal, as patches are
+    /// applied one at a time. The game directory grows by what each patch
+    /// declares in its header; patches not downloaded yet are assumed to grow
+    /// it, and to need a journal, by their own size.
+    pub async fn for_patches<Z: ZiPatchApplier + 'static>(
+        applier: &Arc<Z>,
+        patch_dir: &Path,
+        patches: &[PatchEntry],
+    ) -> Result<Self, Error> {
+        let mut remaining_downloads = Vec::with_capacity(patches.len());
+        let mut game_dir_bytes = 0;
+        let mut journal_bytes = 0;
+
+        for (index, patch) in patches.iter().enumerate() {
+            let patch_path = patch_path(patch_dir, index, patch);
+            let journal_path = patch_journal_path(&patch_path);
+            if applier.journal_state(&journal_path)? == Some(JournalState::Completed) {
+                remaining_downloads.push(0);
+                continue;
+            }
+
+            let existing = match tokio::fs::metadata(&patch_path).await {
+                Ok(metadata) => metadata.len().min(patch.length),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+                Err(e) => return Err(e.into()),
+            };
+            remaining_downloads.push(patch.length - existing);
+
+            let space_info = if existing == patch.length {
+                let applier = applier.clone();
+                tokio::task::spawn_blocking(move || applier.read_space_info(&patch_path))
+                    .await
+                    .map_err(|e| Error::ZiPatchParse(e.to_string()))?
+                    // A damaged header is re-downloaded later, so fall back to the estimate
+                    .unwrap_or(None)
+            } else {
+                None
+            };
+            game_dir_bytes += space_info.map_or(patch.length, |info| info.growth());
+            journal_bytes =
+                journal_bytes.max(space_info.map_or(patch.length, |info| info.journal_size));
+        }
+
+        let download_bytes: u64 = remaining_downloads
+            .windows(PATCH_FILES_ON_DISK.min(remaining_downloads.len()).max(1))
+            .map(|window| window.iter().sum())
+            .max()
+            .unwrap_or(0);
+        let patch_dir_bytes = download_bytes + journal_bytes;
+
+        Ok(DiskSpaceEstimate {
+            patch_dir_bytes,
+            game_dir_bytes,
+        })
+    }
+
+    /// Space needed when the patch and game directories share a filesystem
+    pub fn total(&self) -> u64 {
+        self.patch_dir_bytes + self.game_dir_bytes
+    }
+
+    /// Fail with [`Error::InsufficientDiskSpace`] unless the estimate fits.
+    ///
+    /// When the patch and game directories share a filesystem, both amounts
+    /// must fit in its free space together.
+    pub fn ensure_fits(
+        &self,
+        disk_space: &dyn DiskSpaceProvider,
+        patch_dir: &Path,
+        game_path: &Path,
+    ) -> Result<(), Error> {
+        let patch_space = disk_space.filesystem_space(patch_dir)?;
+        let game_space = disk_space.filesystem_space(game_path)?;
+
+        let required = if patch_space.device == game_space.device {
+            vec![(game_path, self.total(), game_space.available_bytes)]
+        } else {
+            vec![
+                (patch_dir, self.patch_dir_bytes, patch_space.available_bytes),
+                (game_path, self.game_dir_bytes, game_space.available_bytes),
+            ]
+        };
+
+        for (path, needed, available) in required {
+            if needed > available {
+                tracing::warn!(
+                    "Not enough space in {:?}: need {} bytes, have {}",
+                    path,
+                    needed,
+                    available
+                );
+                return Err(Error::InsufficientDiskSpace {
+                    path: path.to_path_buf(),
+                    needed,
+                    available,
+                });
+            }
+        }
+
+        Ok(())
+    }
 }
 
 impl UpdateCheckResult {
@@ -376,19 +876,503 @@ impl UpdateCheckResult {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use async_trait::async_trait;
+
+    use crate::entities::{FilesystemSpace, GameVersion, PatchVerificationReport};
+    use crate::zipatch::{PatchSpaceInfo, ZiPatchChunk};
+
+    /// Things the mocked ports did, in order
+    type Events = Arc<Mutex<Vec<String>>>;
+
+    fn test_patch(version: &str) -> PatchEntry {
+        PatchEntry {
+            version_id: version.to_string(),
+            url: format!("http://example.com/{}.patch", version),
+            length: 100,
+            hash_type: None,
+            hash_block_size: None,
+            hashes: None,
+            repository: Repository::Boot,
+        }
+    }
+
+    fn patch_version(patch_path: &Path) -> String {
+        patch_path
+            .file_stem()
+            .unwrap()
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    struct MockServer {
+        patches: Vec<PatchEntry>,
+    }
+
+    #[async_trait]
+    impl PatchServer for MockServer {
+        async fn check_boot_version(
+            &self,
+            _game_path: &Path,
+            _boot_version: &GameVersion,
+        ) -> Result<Vec<PatchEntry>, Error> {
+            Ok(self.patches.clone())
+        }
+
+        async fn register_session(
+            &self,
+            _session_id: &str,
+            _game_path: &Path,
+            _max_expansion: u32,
+        ) -> Result<(String, Vec<PatchEntry>), Error> {
+            Ok(("unique".to_string(), self.patches.clone()))
+        }
+    }
+
+    struct MockDownloader {
+        events: Events,
+    }
+
+    #[async_trait]
+    impl PatchDownloader for MockDownloader {
+        async fn download_patch<F>(
+            &self,
+            patch: &PatchEntry,
+            _dest_path: &Path,
+            _unique_id: Option<&str>,
+            control: &UpdateControl,
+            progress: F,
+        ) -> Result<(), Error>
+        where
+            F: Fn(u64, u64) + Send + Sync + 'static,
+        {
+            control.checkpoint().await?;
+            self.events
+                .lock()
+                .unwrap()
+                .push(format!("download {}", patch.version_id));
+            progress(patch.length, patch.length);
+            Ok(())
+        }
+
+        async fn verify_patch(&self, _patch: &PatchEntry, _path: &Path) -> Result<bool, Error> {
+            Ok(true)
+        }
+
+        async fn verify_patch_blocks(
+            &self,
+            patch: &PatchEntry,
+            _file_path: &Path,
+            _control: &UpdateControl,
+        ) -> Result<PatchVerificationReport, Error> {
+            Ok(PatchVerificationReport {
+                expected_size: patch.length,
+                actual_size: Some(patch.length),
+                total_blocks: 0,
+                failed_blocks: vec![],
+            })
+        }
+    }
+
+    struct MockApplier {
+        events: Events,
+        failing_version: Option<&'static str>,
+    }
+
+    impl ZiPatchApplier for MockApplier {
+        fn apply_patch(&self, patch_path: &Path, _game_path: &Path) -> Result<(), Error> {
+            self.events
+                .lock()
+                .unwrap()
+                .push(format!("apply unjournaled {}", patch_version(patch_path)));
+            Err(Error::ZiPatchApply(
+                "the use case always journals".to_string(),
+            ))
+        }
+
+        fn apply_patch_journaled(
+            &self,
+            patch_path: &Path,
+            _game_path: &Path,
+            _journal_path: &Path,
+            control: &UpdateControl,
+        ) -> Result<(), Error> {
+            let version = patch_version(patch_path);
+            self.events
+                .lock()
+                .unwrap()
+                .push(format!("apply {}", version));
+            // Slow enough for the next download to overlap
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            control.checkpoint_blocking()?;
+            if self.failing_version == Some(version.as_str()) {
+                return Err(Error::ZiPatchApply("broken patch".to_string()));
+            }
+            self.events
+                .lock()
+                .unwrap()
+                .push(format!("applied {}", version));
+            Ok(())
+        }
+
+        fn rollback_patch(&self, journal_path: &Path) -> Result<(), Error> {
+            let patch_path = journal_path.with_extension("");
+            self.events
+                .lock()
+                .unwrap()
+                .push(format!("rollback {}", patch_version(&patch_path)));
+            Ok(())
+        }
+
+        fn discard_journal(&self, _journal_path: &Path) -> Result<(), Error> {
+            Ok(())
+        }
+
+        fn journal_state(&self, _journal_path: &Path) -> Result<Option<JournalState>, Error> {
+            Ok(None)
+        }
+
+        fn parse_patch(&self, _patch_path: &Path) -> Result<Vec<ZiPatchChunk>, Error> {
+            Ok(vec![])
+        }
+
+        fn read_space_info(&self, _patch_path: &Path) -> Result<Option<PatchSpaceInfo>, Error> {
+            Ok(Some(PatchSpaceInfo {
+                install_size: 60,
+                deleted_data_size: 10,
+                journal_size: 30,
+            }))
+        }
+    }
+
+    /// Patch and game directories on one filesystem
+    struct MockDiskSpace {
+        available_bytes: u64,
+    }
+
+    impl DiskSpaceProvider for MockDiskSpace {
+        fn filesystem_space(&self, _path: &Path) -> Result<FilesystemSpace, Error> {
+            Ok(FilesystemSpace {
+                device: 1,
+                available_bytes: self.available_bytes,
+            })
+        }
+    }
+
+    struct MockVersions {
+        events: Events,
+    }
+
+    #[async_trait]
+    impl VersionRepository for MockVersions {
+        async fn get_version(
+            &self,
+            _game_path: &Path,
+            _repo: Repository,
+        ) -> Result<GameVersion, Error> {
+            Ok(GameVersion::new(2024, 1, 1, 0, 0))
+        }
+
+        async fn set_version(
+            &self,
+            _game_path: &Path,
+            _repo: Repository,
+            version: &str,
+        ) -> Result<(), Error> {
+            self.events.lock().unwrap().push(format!("set {}", version));
+            Ok(())
+        }
+
+        async fn get_boot_version_hash(&self, _game_path: &Path) -> Result<String, Error> {
+            Ok(String::new())
+        }
+
+        async fn get_version_report(
+            &self,
+            _game_path: &Path,
+            _max_expansion: u32,
+        ) -> Result<String, Error> {
+            Ok(String::new())
+        }
+
+        async fn validate_game_installation(&self, _game_path: &Path) -> Result<bool, Error> {
+            Ok(true)
+        }
+    }
+
+    type TestUseCase =
+        UpdateGameUseCase<MockServer, MockDownloader, MockApplier, MockVersions, MockDiskSpace>;
+
+    fn use_case(
+        versions: &[&str],
+        failing_version: Option<&'static str>,
+        events: &Events,
+    ) -> TestUseCase {
+        use_case_with(
+            versions,
+            failing_version,
+            u64::MAX,
+            std::env::temp_dir().join("gaveloc-update-game-test"),
+            events,
+        )
+    }
+
+    fn use_case_with(
+        versions: &[&str],
+        failing_version: Option<&'static str>,
+        available_bytes: u64,
+        patch_dir: PathBuf,
+        events: &Events,
+    ) -> TestUseCase {
+        UpdateGameUseCase::new(
+            Arc::new(MockServer {
+                patches: versions.iter().map(|v| test_patch(v)).collect(),
+            }),
+            Arc::new(MockDownloader {
+                events: events.clone(),
+            }),
+            Arc::new(MockApplier {
+                events: events.clone(),
+                failing_version,
+            }),
+            Arc::new(MockVersions {
+                events: events.clone(),
+            }),
+            Arc::new(MockDiskSpace { available_bytes }),
+            patch_dir,
+        )
+    }
+
+    fn position(events: &[String], event: &str) -> usize {
+        events
+            .iter()
+            .position(|e| e == event)
+            .unwrap_or_else(|| panic!("missing event {:?} in {:?}", event, events))
+    }
+
+    #[tokio::test]
+    async fn test_update_downloads_next_patch_while_applying() {
+        let events = Events::default();
+        let progress_log = Arc::new(Mutex::new(Vec::new()));
+        let progress_clone = progress_log.clone();
+
+        let applied = use_case(&["p1", "p2", "p3"], None, &events)
+            .update_boot(
+                Path::new("/game"),
+                &UpdateControl::new(),
+                move |p: UpdateProgress| {
+                    progress_clone.lock().unwrap().push(p);
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(applied.len(), 3);
+
+        let events = events.lock().unwrap().clone();
+        // The second patch downloads before the first finishes applying
+        assert!(position(&events, "download p2") < position(&events, "applied p1"));
+
+        // Patches are still applied and recorded strictly in order
+        let applies: Vec<_> = events.iter().filter(|e| e.starts_with("apply ")).collect();
+        assert_eq!(applies, ["apply p1", "apply p2", "apply p3"]);
+        assert!(position(&events, "applied p1") < position(&events, "apply p2"));
+        let sets: Vec<_> = events.iter().filter(|e| e.starts_with("set ")).collect();
+        assert_eq!(sets, ["set p1", "set p2", "set p3"]);
+
+        let progress_log = progress_log.lock().unwrap();
+        assert!(progress_log.iter().any(|p| matches!(
+            &p.stage,
+            UpdateStage::Patching {
+                download: Some(_),
+                apply: Some(_)
+            }
+        )));
+        // Each applied patch is counted once, in order
+        let mut applied_counts: Vec<_> = progress_log.iter().map(|p| p.patches_applied).collect();
+        applied_counts.dedup();
+        assert_eq!(applied_counts, [0, 1, 2, 3]);
+        let last = progress_log.last().unwrap();
+        assert_eq!(last.stage, UpdateStage::Completed);
+        assert_eq!(last.overall_progress, 100.0);
+    }
+
+    #[tokio::test]
+    async fn test_update_stops_after_failed_apply() {
+        let events = Events::default();
+
+        let result = use_case(&["p1", "p2", "p3", "p4", "p5"], Some("p2"), &events)
+            .update_boot(Path::new("/game"), &UpdateControl::new(), |_| {})
+            .await;
+        assert!(matches!(result, Err(Error::ZiPatchApply(_))));
+
+        let events = events.lock().unwrap().clone();
+        assert!(events.contains(&"rollback p2".to_string()));
+        assert!(events.contains(&"set p1".to_string()));
+        assert!(!events.contains(&"set p2".to_string()));
+        assert!(!events.contains(&"apply p3".to_string()));
+        // p3 waits in the queue and p4 for space in it, so p5 is never fetched
+        assert!(!events.contains(&"download p5".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_update_cancel_keeps_interrupted_patch() {
+        let events = Events::default();
+        let control = UpdateControl::new();
+        let handle = control.clone();
+        let progress_log = Arc::new(Mutex::new(Vec::new()));
+        let progress_clone = progress_log.clone();
+
+        // Cancel while the second patch is being applied
+        let result = use_case(&["p1", "p2", "p3"], None, &events)
+            .update_boot(Path::new("/game"), &control, move |p: UpdateProgress| {
+                if let UpdateStage::Patching {
+                    apply: Some(apply), ..
+                } = &p.stage
+                {
+                    if apply.patch_index == 2 {
+                        handle.cancel();
+                    }
+                }
+                progress_clone.lock().unwrap().push(p);
+            })
+            .await;
+        assert!(matches!(result, Err(Error::Cancelled)));
+
+        let events = events.lock().unwrap().clone();
+        assert!(events.contains(&"set p1".to_string()));
+        assert!(events.contains(&"apply p2".to_string()));
+        assert!(!events.contains(&"applied p2".to_string()));
+        // The journal is kept so the next update resumes p2
+        assert!(!events.contains(&"rollback p2".to_string()));
+        assert!(!events.contains(&"apply p3".to_string()));
+
+        let last = progress_log.lock().unwrap().last().unwrap().clone();
+        assert_eq!(last.stage, UpdateStage::Cancelled);
+    }
+
+    #[tokio::test]
+    async fn test_update_waits_while_paused() {
+        let events = Events::default();
+        let control = UpdateControl::new();
+        let progress_log = Arc::new(Mutex::new(Vec::new()));
+        let progress_clone = progress_log.clone();
+
+        control.pause();
+        let update = tokio::spawn({
+            let use_case = use_case(&["p1", "p2"], None, &events);
+            let control = control.clone();
+            async move {
+                use_case
+                    .update_boot(Path::new("/game"), &control, move |p: UpdateProgress| {
+                        progress_clone.lock().unwrap().push(p);
+                    })
+                    .await
+            }
+        });
+
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert!(!update.is_finished());
+        assert!(!events
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|e| e.starts_with("apply ")));
+        assert_eq!(
+            progress_log.lock().unwrap().last().unwrap().stage,
+            UpdateStage::Paused
+        );
+
+        control.resume();
+        assert_eq!(update.await.unwrap().unwrap().len(), 2);
+        assert_eq!(
+            progress_log.lock().unwrap().last().unwrap().stage,
+            UpdateStage::Completed
+        );
+    }
+
+    #[tokio::test]
+    async fn test_estimate_disk_space() {
+        let patch_dir =
+            std::env::temp_dir().join(format!("gaveloc-disk-estimate-{}", std::process::id()));
+        std::fs::create_dir_all(&patch_dir).unwrap();
+        // p2 is fully downloaded and p4 partly
+        std::fs::write(patch_dir.join("p2.patch"), [0u8; 100]).unwrap();
+        std::fs::write(patch_dir.join("p4.patch"), [0u8; 40]).unwrap();
+
+        let events = Events::default();
+        let estimate = use_case_with(&[], None, u64::MAX, patch_dir.clone(), &events)
+            .estimate_disk_space(&["p1", "p2", "p3", "p4", "p5"].map(test_patch))
+            .await
+            .unwrap();
+        std::fs::remove_dir_all(&patch_dir).unwrap();
+
+        // Still to download: 100, 0, 100, 60, 100; at most three files at once,
+        // plus a journal as large as a patch not read yet
+        assert_eq!(estimate.patch_dir_bytes, 260 + 100);
+        // p2's header declares 50 bytes of growth, the rest count their size
+        assert_eq!(estimate.game_dir_bytes, 450);
+        assert_eq!(estimate.total(), 810);
+    }
+
+    #[tokio::test]
+    async fn test_update_refuses_without_disk_space() {
+        let events = Events::default();
+
+        let result = use_case_with(
+            &["p1", "p2"],
+            None,
+            250,
+            std::env::temp_dir().join("gaveloc-update-game-test"),
+            &events,
+        )
+        .update_boot(Path::new("/game"), &UpdateControl::new(), |_| {})
+        .await;
+
+        match result {
+            Err(Error::InsufficientDiskSpace {
+                needed, available, ..
+            }) => {
+                assert_eq!(needed, 500);
+                assert_eq!(available, 250);
+            }
+            other => panic!("expected InsufficientDiskSpace, got {:?}", other),
+        }
+        assert!(events.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_pipeline_status_overall_progress() {
+        let patches = [test_patch("p1"), test_patch("p2")];
+        let mut status = PipelineStatus::new(&patches);
+        assert_eq!(status.progress().overall_progress, 0.0);
+
+        // First patch fetched and half of the second downloaded
+        status.fetched_bytes = 100;
+        status.bytes_downloaded = 50;
+        assert_eq!(status.progress().overall_progress, 37.5);
+
+        status.applied_bytes = 100;
+        assert_eq!(status.progress().overall_progress, 62.5);
+    }
 
     #[test]
     fn test_update_progress_percent() {
         let progress = UpdateProgress {
-            stage: UpdateStage::Downloading {
-                patch_index: 1,
-                total_patches: 3,
-                repository: Repository::Ffxiv,
-                version: "2024.01.01.0000.0000".to_string(),
+            stage: UpdateStage::Patching {
+                download: Some(DownloadActivity {
+                    patch: PatchActivity {
+                        patch_index: 1,
+                        total_patches: 3,
+                        repository: Repository::Ffxiv,
+                        version: "2024.01.01.0000.0000".to_string(),
+                    },
+                    phase: DownloadPhase::Downloading,
+                }),
+                apply: None,
             },
             bytes_downloaded: 500,
             bytes_total: 1000,
             overall_progress: 25.0,
+            patches_applied: 0,
         };
 
         assert!((progress.download_percent() - 50.0).abs() < 0.01);
@@ -401,6 +1385,7 @@ mod tests {
             game_patches: vec![],
             unique_id: "test".to_string(),
             total_download_size: 0,
+            disk_space: DiskSpaceEstimate::default(),
         };
 
         assert!(!result.needs_update());
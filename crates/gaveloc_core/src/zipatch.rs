@@ -9,19 +9,27 @@
 //! - Chunks: [size: u32 BE][type: 4 ASCII][data: N bytes][crc32: u32 BE]
 //! - EOF chunk terminates the file
 
+use std::collections::BTreeMap;
 use std::fmt;
+use std::path::{Path, PathBuf};
 
 use serde::{Deserialize, Serialize};
 
+use crate::error::Error;
+
 /// ZiPatch file magic header bytes
 /// 0x91 followed by "ZIPATCH" and control chars
 pub const ZIPATCH_MAGIC: [u8; 12] = [
     0x91, 0x5A, 0x49, 0x50, 0x41, 0x54, 0x43, 0x48, 0x0D, 0x0A, 0x1A, 0x0A,
 ];
 
+/// Size of the SqPack version header and of the index/data header following it
+pub const SQPACK_HEADER_SIZE: u64 = 1024;
+
 /// Platform identifier for file paths
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Platform {
+    #[default]
     Win32,
     Ps3,
     Ps4,
@@ -37,18 +45,12 @@ impl fmt::Display for Platform {
     }
 }
 
-impl Default for Platform {
-    fn default() -> Self {
-        Platform::Win32
-    }
-}
-
 // =============================================================================
 // Chunk Types
 // =============================================================================
 
 /// A parsed ZiPatch chunk
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ZiPatchChunk {
     /// File header chunk (FHDR) - contains version and patch type info
     FileHeader(FileHeaderChunk),
@@ -96,7 +98,7 @@ impl ZiPatchChunk {
 }
 
 /// File header chunk - appears at start of patch
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct FileHeaderChunk {
     /// Patch file format version (usually 3)
     pub version: u16,
@@ -109,7 +111,7 @@ pub struct FileHeaderChunk {
 }
 
 /// Apply option chunk - patch application settings
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ApplyOptionChunk {
     /// Option type/operation
     pub option: ApplyOption,
@@ -140,8 +142,18 @@ impl From<u32> for ApplyOption {
     }
 }
 
+impl From<ApplyOption> for u32 {
+    fn from(value: ApplyOption) -> Self {
+        match value {
+            ApplyOption::IgnoreMissing => 1,
+            ApplyOption::IgnoreOldMismatch => 2,
+            ApplyOption::Unknown(value) => value,
+        }
+    }
+}
+
 /// Add directory chunk - creates a directory
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct AddDirectoryChunk {
     /// Path to create (relative to game directory)
     pub path: String,
@@ -150,7 +162,7 @@ pub struct AddDirectoryChunk {
 }
 
 /// Delete directory chunk - removes a directory
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DeleteDirectoryChunk {
     /// Path to delete (relative to game directory)
     pub path: String,
@@ -159,7 +171,7 @@ pub struct DeleteDirectoryChunk {
 }
 
 /// Apply free space chunk - legacy allocator (rarely used)
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ApplyFreeSpaceChunk {
     /// Size to allocate
     pub alloc_size: u64,
@@ -172,7 +184,7 @@ pub struct ApplyFreeSpaceChunk {
 // =============================================================================
 
 /// SQPK chunk - contains SqPack-specific patching commands
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum SqpkChunk {
     /// Add data to a dat file
     AddData(SqpkAddData),
@@ -217,10 +229,48 @@ impl SqpkChunk {
             SqpkChunk::Unknown { command, .. } => command,
         }
     }
+
+    /// Upper bound on the existing bytes the command overwrites in place
+    ///
+    /// These are the bytes the apply journal copies before the command runs.
+    /// Index commands rewrite their whole index file, which the patch does not
+    /// describe, so only the changed entry is counted for them.
+    pub fn overwritten_bytes(&self) -> u64 {
+        match self {
+            SqpkChunk::AddData(cmd) => cmd.block_number + cmd.block_delete_number,
+            SqpkChunk::DeleteData(cmd) => cmd.block_number,
+            SqpkChunk::ExpandData(cmd) => cmd.block_number,
+            SqpkChunk::Header(cmd) => cmd.header_data.len() as u64,
+            SqpkChunk::Index(cmd) => match cmd.target_file.index_type() {
+                Ok(IndexType::Index) => 16,
+                Ok(IndexType::Index2) => 8,
+                Err(_) => 0,
+            },
+            SqpkChunk::File(cmd) if cmd.operation == SqpkFileOperation::AddFile => cmd
+                .blocks
+                .iter()
+                .map(|block| u64::from(block.decompressed_size))
+                .sum(),
+            _ => 0,
+        }
+    }
+}
+
+/// Get the name of the sqpack folder holding an expansion's files
+pub fn expansion_folder(expansion_id: u16) -> &'static str {
+    match expansion_id {
+        0 => "ffxiv",
+        1 => "ex1",
+        2 => "ex2",
+        3 => "ex3",
+        4 => "ex4",
+        5 => "ex5",
+        _ => "ffxiv",
+    }
 }
 
 /// Target file specification for SqPack operations
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct SqpackFileTarget {
     /// Main ID (repository identifier)
     pub main_id: u16,
@@ -238,15 +288,7 @@ impl SqpackFileTarget {
 
     /// Get the expansion folder name
     pub fn expansion_folder(&self) -> &'static str {
-        match self.expansion_id() {
-            0 => "ffxiv",
-            1 => "ex1",
-            2 => "ex2",
-            3 => "ex3",
-            4 => "ex4",
-            5 => "ex5",
-            _ => "ffxiv",
-        }
+        expansion_folder(self.expansion_id().into())
     }
 
     /// Build the relative path for a dat file
@@ -261,6 +303,20 @@ impl SqpackFileTarget {
         )
     }
 
+    /// Index type addressed by this target when it refers to an index file
+    ///
+    /// Index targets use file ID 0 for `.index` and 2 for `.index2`.
+    pub fn index_type(&self) -> Result<IndexType, Error> {
+        match self.file_id {
+            0 => Ok(IndexType::Index),
+            2 => Ok(IndexType::Index2),
+            file_id => Err(Error::ZiPatchParse(format!(
+                "file ID {} of {:02x}{:04x} does not address an index file",
+                file_id, self.main_id, self.sub_id
+            ))),
+        }
+    }
+
     /// Build the relative path for an index file
     pub fn index_path(&self, platform: Platform, index_type: IndexType) -> String {
         let suffix = match index_type {
@@ -296,7 +352,7 @@ pub enum IndexType {
 }
 
 /// Add data command - writes data to a dat file
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SqpkAddData {
     /// Target dat file
     pub target_file: SqpackFileTarget,
@@ -306,16 +362,14 @@ pub struct SqpkAddData {
     pub block_number: u64,
     /// Number of bytes to delete after writing
     pub block_delete_number: u64,
-    /// The actual data to write
-    pub block_data: Vec<u8>,
-    /// Offset in patch file where data was found
+    /// Offset in the patch file of the `block_number` bytes to write
     pub data_source_offset: u64,
     /// Offset where chunk was found
     pub offset: u64,
 }
 
 /// Delete data command - removes data from a dat file
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SqpkDeleteData {
     /// Target dat file
     pub target_file: SqpackFileTarget,
@@ -328,7 +382,7 @@ pub struct SqpkDeleteData {
 }
 
 /// Expand data command - expands space in a dat file
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SqpkExpandData {
     /// Target dat file
     pub target_file: SqpackFileTarget,
@@ -341,7 +395,7 @@ pub struct SqpkExpandData {
 }
 
 /// Header modification command
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SqpkHeader {
     /// Type of file (dat, index, etc.)
     pub file_kind: SqpkFileKind,
@@ -373,6 +427,16 @@ impl From<u8> for SqpkFileKind {
     }
 }
 
+impl From<SqpkFileKind> for u8 {
+    fn from(value: SqpkFileKind) -> Self {
+        match value {
+            SqpkFileKind::Dat => b'D',
+            SqpkFileKind::Index => b'I',
+            SqpkFileKind::Unknown(value) => value,
+        }
+    }
+}
+
 /// Type of header operation
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SqpkHeaderKind {
@@ -393,57 +457,119 @@ impl From<u8> for SqpkHeaderKind {
     }
 }
 
-/// Index modification command
-#[derive(Debug, Clone)]
+impl From<SqpkHeaderKind> for u8 {
+    fn from(value: SqpkHeaderKind) -> Self {
+        match value {
+            SqpkHeaderKind::Version => b'V',
+            SqpkHeaderKind::Index => b'I',
+            SqpkHeaderKind::Data => b'D',
+            SqpkHeaderKind::Unknown(value) => value,
+        }
+    }
+}
+
+impl SqpkHeaderKind {
+    /// Byte offset of this header within the target file
+    pub fn file_offset(&self) -> u64 {
+        match self {
+            SqpkHeaderKind::Version => 0,
+            _ => SQPACK_HEADER_SIZE,
+        }
+    }
+}
+
+/// Index modification command, adding or removing a single entry
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SqpkIndex {
-    /// Type of index (index or index2)
-    pub index_type: IndexType,
-    /// Whether this is a data update or sync
+    /// Whether the entry is added or removed
+    pub command: SqpkIndexCommand,
+    /// Whether the entry is a synonym (hash collision)
     pub is_synonym: bool,
-    /// Target file
+    /// Target index file; the file ID selects `.index` or `.index2`
     pub target_file: SqpackFileTarget,
-    /// Index entries to modify
-    pub index_data: Vec<SqpkIndexData>,
-    /// Offset where chunk was found
-    pub offset: u64,
-}
-
-/// A single index entry modification
-#[derive(Debug, Clone)]
-pub struct SqpkIndexData {
-    /// Hash of the file path
+    /// Hash of the file path (only the low 32 bits are used by index2)
     pub file_hash: u64,
-    /// Block offset in dat file
+    /// Block offset in dat file, in 128-byte units
     pub block_offset: u32,
     /// Block number
     pub block_number: u32,
+    /// Offset where chunk was found
+    pub offset: u64,
+}
+
+/// Index entry operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SqpkIndexCommand {
+    /// Add or replace the entries
+    Add,
+    /// Remove the entries
+    Delete,
+    /// Unknown operation
+    Unknown(u8),
+}
+
+impl From<u8> for SqpkIndexCommand {
+    fn from(value: u8) -> Self {
+        match value {
+            b'A' => SqpkIndexCommand::Add,
+            b'D' => SqpkIndexCommand::Delete,
+            _ => SqpkIndexCommand::Unknown(value),
+        }
+    }
+}
+
+impl From<SqpkIndexCommand> for u8 {
+    fn from(value: SqpkIndexCommand) -> Self {
+        match value {
+            SqpkIndexCommand::Add => b'A',
+            SqpkIndexCommand::Delete => b'D',
+            SqpkIndexCommand::Unknown(value) => value,
+        }
+    }
 }
 
 /// File operation command - for creating/modifying files
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SqpkFile {
     /// Operation to perform
     pub operation: SqpkFileOperation,
     /// Expansion ID
-    pub expansion_id: u8,
+    pub expansion_id: u16,
+    /// Offset in the target file where the blocks are written
+    pub file_offset: u64,
+    /// Total size of the target file
+    pub file_size: u64,
     /// File path (relative)
     pub file_path: String,
-    /// File data (for add operations)
-    pub file_data: Vec<u8>,
+    /// File contents (for add operations)
+    pub blocks: Vec<SqpkCompressedBlock>,
     /// Offset where chunk was found
     pub offset: u64,
 }
 
+/// A block of file contents carried by an AddFile operation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SqpkCompressedBlock {
+    /// Whether the data is DEFLATE-compressed
+    pub is_compressed: bool,
+    /// Size of the data once decompressed
+    pub decompressed_size: u32,
+    /// Offset in the patch file of the block data, after its header
+    pub data_source_offset: u64,
+    /// Size of the block data in the patch file, without alignment padding
+    pub data_size: u32,
+}
+
 /// File operation type
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SqpkFileOperation {
     /// Add or overwrite a file
     AddFile,
-    /// Remove a file
+    /// Remove all files of an expansion, keeping `.var` files
     RemoveAll,
-    /// Delete a file (alias for RemoveAll in some contexts)
+    /// Delete a single file
     DeleteFile,
-    /// Make a directory
+    /// Make a directory tree
     MakeDir,
     /// Unknown operation
     Unknown(u8),
@@ -461,8 +587,20 @@ impl From<u8> for SqpkFileOperation {
     }
 }
 
+impl From<SqpkFileOperation> for u8 {
+    fn from(value: SqpkFileOperation) -> Self {
+        match value {
+            SqpkFileOperation::AddFile => b'A',
+            SqpkFileOperation::RemoveAll => b'R',
+            SqpkFileOperation::DeleteFile => b'D',
+            SqpkFileOperation::MakeDir => b'M',
+            SqpkFileOperation::Unknown(value) => value,
+        }
+    }
+}
+
 /// Patch information command - metadata about the patch
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct SqpkPatchInfo {
     /// Status code
     pub status: u8,
@@ -475,7 +613,7 @@ pub struct SqpkPatchInfo {
 }
 
 /// Target information command - platform and region info
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct SqpkTargetInfo {
     /// Target platform
     pub platform: Platform,
@@ -493,6 +631,213 @@ pub struct SqpkTargetInfo {
     pub offset: u64,
 }
 
+/// Space figures a patch declares in its SQPK PatchInfo and TargetInfo commands,
+/// along with the size of the journal needed to apply it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PatchSpaceInfo {
+    /// Install size from the PatchInfo command
+    pub install_size: u64,
+    /// Deleted data size from the TargetInfo command
+    pub deleted_data_size: u64,
+    /// Worst-case size of the apply journal: every byte overwritten in place,
+    /// plus the deleted data, which the journal keeps until the patch is done
+    pub journal_size: u64,
+}
+
+impl PatchSpaceInfo {
+    /// Bytes the game directory is expected to grow by when the patch is applied
+    pub fn growth(&self) -> u64 {
+        self.install_size.saturating_sub(self.deleted_data_size)
+    }
+}
+
+// =============================================================================
+// Inspection
+// =============================================================================
+
+/// What a patch contains and which files it writes, to audit it before it is
+/// applied
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PatchInspection {
+    pub header: Option<FileHeaderChunk>,
+    pub target_info: Option<SqpkTargetInfo>,
+    pub patch_info: Option<SqpkPatchInfo>,
+    /// Number of chunks by type (`FHDR`, `SQPK`, ...)
+    pub chunk_counts: BTreeMap<String, u64>,
+    /// Number of SQPK commands by command (`A`, `F`, ...)
+    pub sqpk_counts: BTreeMap<String, u64>,
+    /// Affected files and directories, by path relative to the patched directory
+    pub files: BTreeMap<String, PatchFileChange>,
+}
+
+/// Kind of path a patch affects
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum PatchFileKind {
+    Dat,
+    Index,
+    File,
+    Directory,
+}
+
+/// How a patch affects one file or directory
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PatchFileChange {
+    pub kind: PatchFileKind,
+    /// Number of chunks and commands affecting the path
+    pub commands: u64,
+    /// Bytes written, including blocks written empty to expand a dat file
+    pub bytes_written: u64,
+    /// Bytes cleared in a dat file
+    pub bytes_deleted: u64,
+    /// Whether the patch deletes the path (a later command may create it again)
+    pub removed: bool,
+}
+
+impl PatchInspection {
+    /// Summarize the chunks of a patch, in file order
+    pub fn from_chunks<'a>(chunks: impl IntoIterator<Item = &'a ZiPatchChunk>) -> Self {
+        let mut inspection = Self::default();
+        // Paths depend on the platform, set by a TargetInfo command
+        let mut platform = Platform::default();
+
+        for chunk in chunks {
+            *inspection
+                .chunk_counts
+                .entry(chunk.chunk_type().to_string())
+                .or_default() += 1;
+
+            match chunk {
+                ZiPatchChunk::FileHeader(header) => inspection.header = Some(header.clone()),
+                ZiPatchChunk::AddDirectory(dir) => {
+                    inspection.change(&dir.path, PatchFileKind::Directory);
+                }
+                ZiPatchChunk::DeleteDirectory(dir) => {
+                    inspection
+                        .change(&dir.path, PatchFileKind::Directory)
+                        .removed = true;
+                }
+                ZiPatchChunk::Sqpk(sqpk) => {
+                    *inspection
+                        .sqpk_counts
+                        .entry(sqpk.command().to_string())
+                        .or_default() += 1;
+                    inspection.add_sqpk(sqpk, &mut platform);
+                }
+                _ => {}
+            }
+        }
+
+        inspection
+    }
+
+    fn add_sqpk(&mut self, sqpk: &SqpkChunk, platform: &mut Platform) {
+        match sqpk {
+            SqpkChunk::TargetInfo(info) => {
+                *platform = info.platform;
+                self.target_info = Some(info.clone());
+            }
+            SqpkChunk::PatchInfo(info) => self.patch_info = Some(info.clone()),
+            SqpkChunk::AddData(cmd) => {
+                let change = self.change(&cmd.target_file.dat_path(*platform), PatchFileKind::Dat);
+                change.bytes_written += cmd.block_number;
+                change.bytes_deleted += cmd.block_delete_number;
+            }
+            SqpkChunk::DeleteData(cmd) => {
+                let change = self.change(&cmd.target_file.dat_path(*platform), PatchFileKind::Dat);
+                change.bytes_deleted += cmd.block_number;
+            }
+            SqpkChunk::ExpandData(cmd) => {
+                let change = self.change(&cmd.target_file.dat_path(*platform), PatchFileKind::Dat);
+                change.bytes_written += cmd.block_number;
+            }
+            SqpkChunk::Header(cmd) => {
+                let (path, kind) = match cmd.file_kind {
+                    SqpkFileKind::Dat => (cmd.target_file.dat_path(*platform), PatchFileKind::Dat),
+                    SqpkFileKind::Index => match cmd.target_file.index_type() {
+                        Ok(index_type) => (
+                            cmd.target_file.index_path(*platform, index_type),
+                            PatchFileKind::Index,
+                        ),
+                        Err(_) => return,
+                    },
+                    SqpkFileKind::Unknown(_) => return,
+                };
+                self.change(&path, kind).bytes_written += cmd.header_data.len() as u64;
+            }
+            SqpkChunk::Index(cmd) => {
+                let Ok(index_type) = cmd.target_file.index_type() else {
+                    return;
+                };
+                let entry_size = match index_type {
+                    IndexType::Index => 16,
+                    IndexType::Index2 => 8,
+                };
+                let path = cmd.target_file.index_path(*platform, index_type);
+                self.change(&path, PatchFileKind::Index).bytes_written += entry_size;
+            }
+            SqpkChunk::File(cmd) => match cmd.operation {
+                SqpkFileOperation::AddFile => {
+                    let change = self.change(&cmd.file_path, PatchFileKind::File);
+                    change.bytes_written += cmd
+                        .blocks
+                        .iter()
+                        .map(|block| u64::from(block.decompressed_size))
+                        .sum::<u64>();
+                    change.removed = false;
+                }
+                SqpkFileOperation::DeleteFile => {
+                    self.change(&cmd.file_path, PatchFileKind::File).removed = true;
+                }
+                SqpkFileOperation::RemoveAll => {
+                    let folder = format!("sqpack/{}", expansion_folder(cmd.expansion_id));
+                    self.change(&folder, PatchFileKind::Directory).removed = true;
+                }
+                SqpkFileOperation::MakeDir => {
+                    self.change(&cmd.file_path, PatchFileKind::Directory);
+                }
+                SqpkFileOperation::Unknown(_) => {}
+            },
+            SqpkChunk::Unknown { .. } => {}
+        }
+    }
+
+    /// Record a command affecting `path`
+    fn change(&mut self, path: &str, kind: PatchFileKind) -> &mut PatchFileChange {
+        let path = path.replace('\\', "/").trim_matches('/').to_string();
+        let change = self.files.entry(path).or_insert(PatchFileChange {
+            kind,
+            commands: 0,
+            bytes_written: 0,
+            bytes_deleted: 0,
+            removed: false,
+        });
+        change.commands += 1;
+        change
+    }
+}
+
+// =============================================================================
+// Apply Journal
+// =============================================================================
+
+/// Path of the journal recording the application of a patch file
+///
+/// The journal is kept next to the patch file so both are cleaned up together.
+pub fn patch_journal_path(patch_path: &Path) -> PathBuf {
+    let mut path = patch_path.as_os_str().to_owned();
+    path.push(".journal");
+    PathBuf::from(path)
+}
+
+/// Progress recorded in a patch application journal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JournalState {
+    /// Application was interrupted after `committed_chunks` chunks were fully applied
+    InProgress { committed_chunks: u64 },
+    /// Every chunk of the patch was applied
+    Completed,
+}
+
 // =============================================================================
 // Tests
 // =============================================================================
@@ -543,7 +888,10 @@ mod tests {
     fn test_sqpk_file_kind_from() {
         assert_eq!(SqpkFileKind::from(b'D'), SqpkFileKind::Dat);
         assert_eq!(SqpkFileKind::from(b'I'), SqpkFileKind::Index);
-        assert!(matches!(SqpkFileKind::from(b'X'), SqpkFileKind::Unknown(b'X')));
+        assert!(matches!(
+            SqpkFileKind::from(b'X'),
+            SqpkFileKind::Unknown(b'X')
+        ));
     }
 
     #[test]
@@ -553,6 +901,19 @@ mod tests {
         assert_eq!(SqpkFileOperation::from(b'M'), SqpkFileOperation::MakeDir);
     }
 
+    #[test]
+    fn test_byte_conversions_round_trip() {
+        for value in 0..=u8::MAX {
+            assert_eq!(u8::from(SqpkFileKind::from(value)), value);
+            assert_eq!(u8::from(SqpkHeaderKind::from(value)), value);
+            assert_eq!(u8::from(SqpkIndexCommand::from(value)), value);
+            assert_eq!(u8::from(SqpkFileOperation::from(value)), value);
+        }
+        for value in [0, 1, 2, 99] {
+            assert_eq!(u32::from(ApplyOption::from(value)), value);
+        }
+    }
+
     #[test]
     fn test_platform_display() {
         assert_eq!(Platform::Win32.to_string(), "win32");
@@ -582,6 +943,61 @@ mod tests {
         assert_eq!(chunk.command(), "X");
     }
 
+    #[test]
+    fn test_sqpk_overwritten_bytes() {
+        let target_file = SqpackFileTarget {
+            main_id: 0x0a,
+            sub_id: 0x0100,
+            file_id: 2,
+        };
+        let add = SqpkChunk::AddData(SqpkAddData {
+            target_file: target_file.clone(),
+            block_offset: 0,
+            block_number: 256,
+            block_delete_number: 128,
+            data_source_offset: 0,
+            offset: 0,
+        });
+        assert_eq!(add.overwritten_bytes(), 384);
+
+        let index = SqpkChunk::Index(SqpkIndex {
+            command: SqpkIndexCommand::Add,
+            is_synonym: false,
+            target_file,
+            file_hash: 0,
+            block_offset: 0,
+            block_number: 0,
+            offset: 0,
+        });
+        assert_eq!(index.overwritten_bytes(), 8);
+
+        let info = SqpkChunk::PatchInfo(SqpkPatchInfo {
+            status: 0,
+            version: 1,
+            install_size: 1 << 20,
+            offset: 0,
+        });
+        assert_eq!(info.overwritten_bytes(), 0);
+    }
+
+    #[test]
+    fn test_patch_space_info_growth() {
+        let info = PatchSpaceInfo {
+            install_size: 1000,
+            deleted_data_size: 300,
+            journal_size: 500,
+        };
+        assert_eq!(info.growth(), 700);
+
+        // A patch that frees more than it installs needs no extra space
+        let info = PatchSpaceInfo {
+            install_size: 100,
+            deleted_data_size: 300,
+            journal_size: 300,
+        };
+        assert_eq!(info.growth(), 0);
+    }
+
     // ==========================================================================
     // Additional Tests for Coverage
     // ==========================================================================
@@ -680,7 +1096,6 @@ mod tests {
                 block_offset: 0,
                 block_number: 0,
                 block_delete_number: 0,
-                block_data: vec![],
                 data_source_offset: 0,
                 offset: 0,
             })
@@ -740,14 +1155,16 @@ mod tests {
         // Index
         assert_eq!(
             SqpkChunk::Index(SqpkIndex {
-                index_type: IndexType::Index,
+                command: SqpkIndexCommand::Add,
                 is_synonym: false,
                 target_file: SqpackFileTarget {
                     main_id: 0,
                     sub_id: 0,
                     file_id: 0
                 },
-                index_data: vec![],
+                file_hash: 0,
+                block_offset: 0,
+                block_number: 0,
                 offset: 0,
             })
             .command(),
@@ -759,8 +1176,10 @@ mod tests {
             SqpkChunk::File(SqpkFile {
                 operation: SqpkFileOperation::AddFile,
                 expansion_id: 0,
+                file_offset: 0,
+                file_size: 0,
                 file_path: String::new(),
-                file_data: vec![],
+                blocks: vec![],
                 offset: 0,
             })
             .command(),
@@ -839,6 +1258,35 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_patch_journal_path() {
+        assert_eq!(
+            patch_journal_path(Path::new("/patches/D2024.01.01.0000.0000.patch")),
+            PathBuf::from("/patches/D2024.01.01.0000.0000.patch.journal")
+        );
+    }
+
+    #[test]
+    fn test_expansion_folder() {
+        assert_eq!(expansion_folder(0), "ffxiv");
+        assert_eq!(expansion_folder(3), "ex3");
+        assert_eq!(expansion_folder(0xFF), "ffxiv");
+    }
+
+    #[test]
+    fn test_sqpack_file_target_index_type() {
+        let mut target = SqpackFileTarget {
+            main_id: 0x04,
+            sub_id: 0x0000,
+            file_id: 0,
+        };
+        assert_eq!(target.index_type().unwrap(), IndexType::Index);
+        target.file_id = 2;
+        assert_eq!(target.index_type().unwrap(), IndexType::Index2);
+        target.file_id = 1;
+        assert!(matches!(target.index_type(), Err(Error::ZiPatchParse(_))));
+    }
+
     #[test]
     fn test_sqpack_file_target_display() {
         let target = SqpackFileTarget {
@@ -860,6 +1308,23 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_sqpk_header_kind_file_offset() {
+        assert_eq!(SqpkHeaderKind::Version.file_offset(), 0);
+        assert_eq!(SqpkHeaderKind::Index.file_offset(), 1024);
+        assert_eq!(SqpkHeaderKind::Data.file_offset(), 1024);
+    }
+
+    #[test]
+    fn test_sqpk_index_command_from() {
+        assert_eq!(SqpkIndexCommand::from(b'A'), SqpkIndexCommand::Add);
+        assert_eq!(SqpkIndexCommand::from(b'D'), SqpkIndexCommand::Delete);
+        assert!(matches!(
+            SqpkIndexCommand::from(b'X'),
+            SqpkIndexCommand::Unknown(b'X')
+        ));
+    }
+
     #[test]
     fn test_sqpk_file_operation_delete_file() {
         assert_eq!(SqpkFileOperation::from(b'D'), SqpkFileOperation::DeleteFile);
@@ -872,4 +1337,113 @@ mod tests {
             SqpkFileOperation::Unknown(b'Z')
         ));
     }
+
+    #[test]
+    fn test_patch_inspection_counts_chunks_and_files() {
+        let target = |sub_id| SqpackFileTarget {
+            main_id: 0x0a,
+            sub_id,
+            file_id: 0,
+        };
+        let chunks = vec![
+            ZiPatchChunk::FileHeader(FileHeaderChunk {
+                version: 3,
+                patch_type: "DIFF".to_string(),
+                entry_files: 2,
+                offset: 12,
+            }),
+            ZiPatchChunk::Sqpk(SqpkChunk::TargetInfo(SqpkTargetInfo {
+                platform: Platform::Ps4,
+                region: 0xFFFF,
+                is_debug: false,
+                version: 0,
+                deleted_data_size: 0,
+                seek_count: 0,
+                offset: 40,
+            })),
+            ZiPatchChunk::Sqpk(SqpkChunk::AddData(SqpkAddData {
+                target_file: target(0),
+                block_offset: 0,
+                block_number: 256,
+                block_delete_number: 128,
+                data_source_offset: 0,
+                offset: 100,
+            })),
+            ZiPatchChunk::Sqpk(SqpkChunk::ExpandData(SqpkExpandData {
+                target_file: target(0),
+                block_offset: 384,
+                block_number: 128,
+                offset: 500,
+            })),
+            ZiPatchChunk::Sqpk(SqpkChunk::Index(SqpkIndex {
+                command: SqpkIndexCommand::Add,
+                is_synonym: false,
+                target_file: SqpackFileTarget {
+                    file_id: 2,
+                    ..target(0x0100)
+                },
+                file_hash: 1,
+                block_offset: 0,
+                block_number: 1,
+                offset: 600,
+            })),
+            ZiPatchChunk::Sqpk(SqpkChunk::File(SqpkFile {
+                operation: SqpkFileOperation::DeleteFile,
+                expansion_id: 0,
+                file_offset: 0,
+                file_size: 0,
+                file_path: "ffxivgame.dll".to_string(),
+                blocks: Vec::new(),
+                offset: 700,
+            })),
+            ZiPatchChunk::DeleteDirectory(DeleteDirectoryChunk {
+                path: "movie/ffxiv/".to_string(),
+                offset: 800,
+            }),
+            ZiPatchChunk::EndOfFile,
+        ];
+
+        let inspection = PatchInspection::from_chunks(&chunks);
+
+        assert_eq!(inspection.header.as_ref().unwrap().patch_type, "DIFF");
+        assert_eq!(
+            inspection.target_info.as_ref().unwrap().platform,
+            Platform::Ps4
+        );
+        assert!(inspection.patch_info.is_none());
+        assert_eq!(inspection.chunk_counts["SQPK"], 5);
+        assert_eq!(inspection.chunk_counts["EOF_"], 1);
+        assert_eq!(inspection.sqpk_counts["A"], 1);
+        assert_eq!(inspection.sqpk_counts["T"], 1);
+
+        let paths: Vec<&str> = inspection.files.keys().map(String::as_str).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "ffxivgame.dll",
+                "movie/ffxiv",
+                "sqpack/ex1/0a0100.ps4.index2",
+                "sqpack/ffxiv/0a0000.ps4.dat0"
+            ]
+        );
+        assert_eq!(
+            inspection.files["sqpack/ffxiv/0a0000.ps4.dat0"],
+            PatchFileChange {
+                kind: PatchFileKind::Dat,
+                commands: 2,
+                bytes_written: 384,
+                bytes_deleted: 128,
+                removed: false,
+            }
+        );
+        assert_eq!(
+            inspection.files["sqpack/ex1/0a0100.ps4.index2"].bytes_written,
+            8
+        );
+        assert!(inspection.files["ffxivgame.dll"].removed);
+        assert_eq!(
+            inspection.files["movie/ffxiv"].kind,
+            PatchFileKind::Directory
+        );
+    }
 }